@@ -0,0 +1,100 @@
+// Runs the same programs through the tree-walking evaluator and the bytecode
+// vm and reports how long each takes, `cargo bench --bench engines [filter]`.
+
+use std::{
+    hint::black_box,
+    time::{Duration, Instant},
+};
+
+use interpreter_book_in_rust::{
+    ast::Program, compiler::Compiler, env::Env, evaluator::eval_program, lexer::Lexer,
+    parser::Parser, vm::Vm,
+};
+
+const ITERATIONS: u32 = 5;
+
+const PROGRAMS: &[(&str, &str)] = &[
+    (
+        "fib(30)",
+        "let fib = fn(n) { if (n < 2) { n } else { fib(n - 1) + fib(n - 2) } };
+        fib(30);",
+    ),
+    (
+        "arrays",
+        "let build = fn(n, acc) { if (n == 0) { acc } else { build(n - 1, push(acc, n)) } };
+        let map = fn(arr, acc, f) {
+            if (len(arr) == 0) { acc } else { map(rest(arr), push(acc, f(first(arr))), f) }
+        };
+        let sum = fn(arr, acc) {
+            if (len(arr) == 0) { acc } else { sum(rest(arr), acc + first(arr)) }
+        };
+        sum(map(build(500, []), [], fn(x) { x * 2 }), 0);",
+    ),
+    (
+        "strings",
+        "let repeat = fn(s, n, acc) { if (n == 0) { acc } else { repeat(s, n - 1, acc + s) } };
+        let join = fn(arr, sep, acc) {
+            if (len(arr) == 0) { acc } else { join(rest(arr), sep, acc + sep + first(arr)) }
+        };
+        let words = [\"let\", \"fn\", repeat(\"ab\", 200, \"\"), \"return\"];
+        len(join(words, \", \", \"\") + repeat(\"monkey \", 500, \"\"));",
+    ),
+];
+
+fn time<F: FnMut() -> String>(mut run: F) -> (Duration, String) {
+    // one untimed run to warm up caches and allocator
+    let result = run();
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        black_box(run());
+    }
+    (start.elapsed() / ITERATIONS, result)
+}
+
+fn eval(prog: &Program) -> String {
+    let mut env = Env::new();
+    match eval_program(prog, &mut env) {
+        Ok(value) => value.to_string(),
+        Err(err) => format!("error: {}", err),
+    }
+}
+
+fn run_vm(prog: &Program) -> String {
+    let mut compiler = Compiler::new();
+    if let Err(err) = compiler.compile(prog) {
+        return format!("error: {}", err);
+    }
+    match Vm::new(compiler.bytecode()).run() {
+        Ok(value) => value.to_string(),
+        Err(err) => format!("error: {}", err),
+    }
+}
+
+fn main() {
+    let filter = std::env::args().skip(1).find(|arg| !arg.starts_with("--"));
+
+    println!(
+        "{:<12} {:>14} {:>14} {:>10}",
+        "program", "evaluator", "vm", "speedup"
+    );
+    for (name, source) in PROGRAMS {
+        if filter.as_ref().is_some_and(|f| !name.contains(f.as_str())) {
+            continue;
+        }
+        let prog = Parser::new(Lexer::new(source.as_bytes()))
+            .parse()
+            .unwrap_or_else(|errors| panic!("{} failed to parse: {:?}", name, errors));
+
+        let (eval_time, eval_result) = time(|| eval(&prog));
+        let (vm_time, vm_result) = time(|| run_vm(&prog));
+        assert_eq!(eval_result, vm_result, "{}: engines disagree", name);
+
+        println!(
+            "{:<12} {:>14?} {:>14?} {:>9.2}x",
+            name,
+            eval_time,
+            vm_time,
+            eval_time.as_secs_f64() / vm_time.as_secs_f64()
+        );
+    }
+}
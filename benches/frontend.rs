@@ -0,0 +1,73 @@
+// Lexer and parser throughput on generated multi-megabyte programs,
+// `cargo bench --bench frontend`.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use interpreter_book_in_rust::{lexer::Lexer, parser::Parser};
+
+// statements in a generated program, about 4MB of source
+const STATEMENTS: usize = 50_000;
+
+// a mix of the constructs real scripts use, repeated with fresh names so the
+// source isn't just the same few bytes over and over
+fn program(statements: usize) -> String {
+    let templates = [
+        "let value{n} = {i} * (value + {i}) - other / 2;\n",
+        "let add{n} = fn(a, b) { if (a > b) { return a - b; } else { a + b } };\n",
+        "let list{n} = [1, 2 * {i}, add(3, 4), fn(x) { x }][{i} - 1];\n",
+        "puts(len(rest(push(items, {i}))), !true == false, -{i} < {i});\n",
+    ];
+    let mut source = String::new();
+    for i in 0..statements {
+        let template = templates[i % templates.len()];
+        let stmt = template
+            .replace("{i}", &i.to_string())
+            .replace("{n}", &name(i));
+        source.push_str(&stmt);
+    }
+    source
+}
+
+// identifiers can't contain digits, so number them with letters
+fn name(mut i: usize) -> String {
+    let mut name = String::new();
+    loop {
+        name.push((b'a' + (i % 26) as u8) as char);
+        i /= 26;
+        if i == 0 {
+            return name;
+        }
+    }
+}
+
+fn count_tokens(source: &str) -> u64 {
+    Lexer::new(source.as_bytes()).count() as u64
+}
+
+fn lexer(c: &mut Criterion) {
+    let source = program(STATEMENTS);
+    let mut group = c.benchmark_group("lexer");
+    group.throughput(Throughput::Elements(count_tokens(&source)));
+    group.sample_size(20);
+    group.bench_function("tokens", |b| b.iter(|| count_tokens(black_box(&source))));
+    group.finish();
+}
+
+fn parser(c: &mut Criterion) {
+    let source = program(STATEMENTS);
+    let mut group = c.benchmark_group("parser");
+    group.throughput(Throughput::Elements(STATEMENTS as u64));
+    group.sample_size(20);
+    group.bench_function("statements", |b| {
+        b.iter(|| {
+            let prog = Parser::new(Lexer::new(black_box(source.as_bytes())))
+                .parse()
+                .unwrap();
+            assert_eq!(prog.stmts.len(), STATEMENTS);
+            prog
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, lexer, parser);
+criterion_main!(benches);
@@ -0,0 +1,72 @@
+// Lexes, parses and evaluates every `examples/programs/*.mk` script,
+// `cargo bench --bench programs [filter]`. Each one leans on a different
+// part of the evaluator: `fib` on calls and arithmetic, `ackermann` on calls
+// nested in calls, `strings` on concatenation and `maps` on hash literals
+// and indexing.
+
+use std::{fs, path::Path};
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use interpreter_book_in_rust::{env::Env, evaluator::eval_program, lexer::Lexer, parser::Parser};
+
+// the scripts by name, in order
+fn programs() -> Vec<(String, String)> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("examples/programs");
+    let mut programs = fs::read_dir(dir)
+        .expect("failed to read examples/programs")
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "mk"))
+        .map(|path| {
+            let name = path.file_stem().unwrap().to_string_lossy().into_owned();
+            (name, fs::read_to_string(&path).unwrap())
+        })
+        .collect::<Vec<_>>();
+    programs.sort();
+    programs
+}
+
+fn lexer(c: &mut Criterion) {
+    let mut group = c.benchmark_group("programs/lexer");
+    for (name, source) in programs() {
+        group.throughput(Throughput::Bytes(source.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(name), &source, |b, source| {
+            b.iter(|| Lexer::new(black_box(source.as_bytes())).count())
+        });
+    }
+    group.finish();
+}
+
+fn parser(c: &mut Criterion) {
+    let mut group = c.benchmark_group("programs/parser");
+    for (name, source) in programs() {
+        group.throughput(Throughput::Bytes(source.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(name), &source, |b, source| {
+            b.iter(|| {
+                Parser::new(Lexer::new(black_box(source.as_bytes())))
+                    .parse()
+                    .unwrap()
+            })
+        });
+    }
+    group.finish();
+}
+
+fn evaluator(c: &mut Criterion) {
+    let mut group = c.benchmark_group("programs/evaluator");
+    for (name, source) in programs() {
+        let prog = Parser::new(Lexer::new(source.as_bytes()))
+            .parse()
+            .unwrap_or_else(|errors| panic!("{} failed to parse: {:?}", name, errors));
+        // a script that fails would only time how long it takes to fail
+        if let Err(err) = eval_program(&prog, &mut Env::new()) {
+            panic!("{} failed: {}", name, err);
+        }
+        group.bench_function(BenchmarkId::from_parameter(name), |b| {
+            b.iter(|| eval_program(black_box(&prog), &mut Env::new()).unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, lexer, parser, evaluator);
+criterion_main!(benches);
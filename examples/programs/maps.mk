@@ -0,0 +1,7 @@
+let entry = fn(i) { {"id": i, "square": i * i, "even": i / 2 * 2 == i, i: "by number"} };
+let entries = fn(n, acc) { if (n == 0) { acc } else { entries(n - 1, push(acc, entry(n))) } };
+let total = 0;
+for (e in entries(200, [])) {
+  if (e["even"]) { total = total + e["square"] } else { total = total + e["id"] }
+}
+total
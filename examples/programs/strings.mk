@@ -0,0 +1,10 @@
+let digits = ["zero", "one", "two", "three", "four", "five", "six", "seven", "eight", "nine"];
+let text = "";
+for (_hundreds in digits) {
+  for (_tens in digits) {
+    for (ones in digits) {
+      text = text + ones + ", ";
+    }
+  }
+}
+len(text)
@@ -0,0 +1,53 @@
+/*
+ * the C interface to the Monkey interpreter, built into the library with
+ * `cargo build --release --features ffi`
+ *
+ * strings returned are owned by the interpreter and valid until the next
+ * call to monkey_eval on it, copy them to keep them. from python:
+ *
+ *     lib = ctypes.CDLL("target/release/libinterpreter_book_in_rust.so")
+ *     lib.monkey_new.restype = ctypes.c_void_p
+ *     lib.monkey_eval.argtypes = [ctypes.c_void_p, ctypes.c_char_p]
+ *     lib.monkey_eval.restype = ctypes.c_char_p
+ *     monkey = lib.monkey_new()
+ *     lib.monkey_eval(monkey, b"1 + 2")  # b"3"
+ */
+#ifndef MONKEY_H
+#define MONKEY_H
+
+#include <stddef.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct Monkey Monkey;
+
+/* an interpreter with no bindings, free it with monkey_free */
+Monkey *monkey_new(void);
+
+/*
+ * evaluates source, a utf-8 string, keeping its bindings for later calls.
+ * returns the value printed, or NULL if it failed and monkey_last_error
+ * says why
+ */
+const char *monkey_eval(Monkey *monkey, const char *source);
+
+/* the error of the last monkey_eval, or NULL if it succeeded */
+const char *monkey_last_error(const Monkey *monkey);
+
+/*
+ * where the error of the last monkey_eval is, both counting from 1, or 0
+ * if it succeeded or the error isn't about a place in the source
+ */
+size_t monkey_last_error_line(const Monkey *monkey);
+size_t monkey_last_error_column(const Monkey *monkey);
+
+/* frees the interpreter and the strings it returned, NULL is ignored */
+void monkey_free(Monkey *monkey);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -1,77 +1,153 @@
 #![allow(unused, dead_code)]
 
-use crate::token::Token;
+use crate::{
+    intern::Symbol,
+    rc::Rc,
+    slots::Slot,
+    token::{Text, Token},
+};
 use core::fmt;
 
 // Remove heap allocation per node to single allocation per AST
 // with an arena alloc
-#[derive(Debug)]
-pub enum Expr<'a> {
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+pub enum Expr {
     Identifier {
-        token: Token<'a>,
-        value: &'a [u8],
+        token: Token,
+        #[cfg_attr(feature = "serde", serde(with = "crate::serde::text"))]
+        value: Text,
+        // the name interned, what bindings are looked up by
+        symbol: Symbol,
+        // where the evaluator keeps the binding, None for the ones it looks
+        // up by name
+        #[cfg_attr(feature = "serde", serde(default))]
+        slot: Option<Slot>,
     },
     IntLiteral {
-        token: Token<'a>,
+        token: Token,
         value: i64,
     },
+    FloatLiteral {
+        token: Token,
+        value: f64,
+    },
     BoolLiteral {
-        token: Token<'a>,
+        token: Token,
         value: bool,
     },
+    StringLiteral {
+        token: Token,
+        value: Rc<str>,
+    },
     FnLiteral {
-        token: Token<'a>,
-        parameters: Vec<Expr<'a>>,
-        block: Box<Stmt<'a>>,
+        token: Token,
+        parameters: Vec<Expr>,
+        // `...rest`, collecting the arguments past the other parameters
+        rest: Option<Box<Expr>>,
+        block: Rc<Stmt>,
     },
     Call {
-        token: Token<'a>,
-        function: Box<Expr<'a>>,
-        arguments: Vec<Expr<'a>>,
+        token: Token,
+        function: Box<Expr>,
+        arguments: Vec<Expr>,
     },
     Prefix {
-        token: Token<'a>,
-        op: &'a [u8],
-        expr: Box<Expr<'a>>,
+        token: Token,
+        #[cfg_attr(feature = "serde", serde(with = "crate::serde::text"))]
+        op: Text,
+        expr: Box<Expr>,
     },
     Infix {
-        token: Token<'a>,
-        left: Box<Expr<'a>>,
-        op: &'a [u8],
-        right: Box<Expr<'a>>,
+        token: Token,
+        left: Box<Expr>,
+        #[cfg_attr(feature = "serde", serde(with = "crate::serde::text"))]
+        op: Text,
+        right: Box<Expr>,
     },
     If {
-        token: Token<'a>,
-        condition: Box<Expr<'a>>,
-        consequence: Box<Stmt<'a>>,
-        alternative: Option<Box<Stmt<'a>>>,
+        token: Token,
+        condition: Box<Expr>,
+        consequence: Box<Stmt>,
+        alternative: Option<Box<Stmt>>,
+    },
+    ArrayLiteral {
+        token: Token,
+        elements: Vec<Expr>,
+    },
+    Index {
+        token: Token,
+        left: Box<Expr>,
+        index: Box<Expr>,
+    },
+    HashLiteral {
+        token: Token,
+        pairs: Vec<(Expr, Expr)>,
+    },
+    Match {
+        token: Token,
+        subject: Box<Expr>,
+        arms: Vec<MatchArm>,
+    },
+    // runs the handler with the error bound to the name if the body fails,
+    // the name is always an identifier
+    Try {
+        token: Token,
+        body: Box<Stmt>,
+        name: Box<Expr>,
+        handler: Box<Stmt>,
     },
+    // rebinds an existing name, the name is always an identifier
+    Assign {
+        token: Token,
+        name: Box<Expr>,
+        value: Box<Expr>,
+    },
+}
+
+// `pattern => value`, the `_` arm that matches anything has no pattern
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+pub struct MatchArm {
+    pub pattern: Option<Expr>,
+    pub value: Expr,
+}
+
+impl fmt::Display for MatchArm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.pattern {
+            Some(pattern) => write!(f, "{} => {}", pattern, self.value),
+            None => write!(f, "_ => {}", self.value),
+        }
+    }
 }
 
-impl fmt::Display for Expr<'_> {
+impl fmt::Display for Expr {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Expr::Identifier { token, .. } => {
                 write!(f, "{}", token)
             }
-            Expr::IntLiteral { token, .. } => {
-                write!(f, "{}", token)
-            }
-            Expr::BoolLiteral { token, .. } => {
-                write!(f, "{}", token)
+            Expr::IntLiteral { token, value } => write!(f, "{}", literal_text(token, value)),
+            Expr::FloatLiteral { token, value } => write!(f, "{}", literal_text(token, value)),
+            Expr::BoolLiteral { token, value } => write!(f, "{}", literal_text(token, value)),
+            Expr::StringLiteral { value, .. } => {
+                write!(f, "\"{}\"", value)
             }
             Expr::FnLiteral {
                 token,
                 parameters,
+                rest,
                 block,
             } => {
                 write!(
                     f,
-                    "{} ({}) {}",
+                    "{}({}) {}",
                     token,
                     parameters
                         .iter()
                         .map(|p| p.to_string())
+                        .chain(rest.iter().map(|rest| format!("...{}", rest)))
                         .collect::<Vec<String>>()
                         .join(", "),
                     block
@@ -100,9 +176,9 @@ impl fmt::Display for Expr<'_> {
                 condition,
             } => {
                 if let Some(alt) = alternative {
-                    write!(f, "if {} {} else {})", condition, consequence, alt)
+                    write!(f, "if ({}) {} else {}", condition, consequence, alt)
                 } else {
-                    write!(f, "if {} {})", condition, consequence)
+                    write!(f, "if ({}) {}", condition, consequence)
                 }
             }
             Expr::Prefix { expr, op, .. } => {
@@ -119,75 +195,390 @@ impl fmt::Display for Expr<'_> {
                     right
                 )
             }
+            Expr::ArrayLiteral { elements, .. } => {
+                write!(
+                    f,
+                    "[{}]",
+                    elements
+                        .iter()
+                        .map(|e| e.to_string())
+                        .collect::<Vec<String>>()
+                        .join(", ")
+                )
+            }
+            Expr::Index { left, index, .. } => {
+                write!(f, "({}[{}])", left, index)
+            }
+            Expr::Assign { name, value, .. } => {
+                write!(f, "({} = {})", name, value)
+            }
+            Expr::Try {
+                body,
+                name,
+                handler,
+                ..
+            } => write!(f, "try {} catch ({}) {}", body, name, handler),
+            Expr::Match { subject, arms, .. } => {
+                write!(
+                    f,
+                    "match ({}) {{ {} }}",
+                    subject,
+                    arms.iter()
+                        .map(|arm| arm.to_string())
+                        .collect::<Vec<String>>()
+                        .join(", ")
+                )
+            }
+            Expr::HashLiteral { pairs, .. } => {
+                write!(
+                    f,
+                    "{{{}}}",
+                    pairs
+                        .iter()
+                        .map(|(key, value)| format!("{}: {}", key, value))
+                        .collect::<Vec<String>>()
+                        .join(", ")
+                )
+            }
         }
     }
 }
 
+// how a number or boolean literal is written, the literals made by folding
+// constants have no text in the source and show their value
+pub fn literal_text(token: &Token, value: impl fmt::Debug) -> String {
+    if token.literal.is_empty() {
+        format!("{:?}", value)
+    } else {
+        token.to_string()
+    }
+}
 
-#[derive(Debug)]
-pub struct Program<'a> {
-    pub stmts: Vec<Stmt<'a>>,
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+pub struct Program {
+    pub stmts: Vec<Stmt>,
 }
 
-#[derive(Debug)]
-pub enum Stmt<'a> {
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+pub enum Stmt {
     Let {
-        token: Token<'a>,
-        name: Expr<'a>,
-        value: Expr<'a>,
+        token: Token,
+        name: Expr,
+        value: Expr,
     },
     Return {
-        token: Token<'a>,
-        value: Expr<'a>,
+        token: Token,
+        value: Expr,
     },
     Expr {
-        expr: Expr<'a>,
+        expr: Expr,
     },
     Block {
-        token: Token<'a>,
-        stmts: Vec<Stmt<'a>>,
+        token: Token,
+        stmts: Vec<Stmt>,
+    },
+    // pauses the program when it runs under the debugger
+    Debugger {
+        token: Token,
+    },
+    For {
+        token: Token,
+        name: Expr,
+        iterable: Expr,
+        body: Box<Stmt>,
+    },
+    Break {
+        token: Token,
     },
+    Continue {
+        token: Token,
+    },
+}
+
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_stmts(f, &self.stmts)
+    }
+}
+
+// expression statements get a `;` so the next statement can't be read as
+// part of them, `a (b)` would parse as a call
+fn write_stmts(f: &mut fmt::Formatter<'_>, stmts: &[Stmt]) -> fmt::Result {
+    for (i, stmt) in stmts.iter().enumerate() {
+        if i > 0 {
+            write!(f, " ")?;
+        }
+        match stmt {
+            Stmt::Expr { expr } => write!(f, "{};", expr)?,
+            stmt => write!(f, "{}", stmt)?,
+        }
+    }
+    Ok(())
 }
 
-impl fmt::Display for Stmt<'_> {
+impl fmt::Display for Stmt {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Stmt::Expr { expr } => write!(f, "{}", expr),
-            Stmt::Block { stmts, .. } => write!(
-                f,
-                "{}",
-                stmts.iter().map(|s| s.to_string()).collect::<String>()
-            ),
+            Stmt::Block { stmts, .. } if stmts.is_empty() => write!(f, "{{ }}"),
+            Stmt::Block { stmts, .. } => {
+                write!(f, "{{ ")?;
+                write_stmts(f, stmts)?;
+                write!(f, " }}")
+            }
             Stmt::Return { token, value } => {
                 write!(
                     f,
                     "{} {};",
-                    std::str::from_utf8(token.literal).unwrap(),
+                    std::str::from_utf8(&token.literal).unwrap(),
                     value
                 )
             }
             Stmt::Let { name, token, value } => write!(
                 f,
                 "{} {} = {};",
-                std::str::from_utf8(token.literal).unwrap(),
+                std::str::from_utf8(&token.literal).unwrap(),
                 name,
                 value
             ),
+            Stmt::Debugger { token } | Stmt::Break { token } | Stmt::Continue { token } => {
+                write!(f, "{};", token)
+            }
+            Stmt::For {
+                token,
+                name,
+                iterable,
+                body,
+            } => write!(f, "{} ({} in {}) {}", token, name, iterable, body),
+        }
+    }
+}
+
+impl Expr {
+    // the token the expression starts with, operators and calls sit after
+    // their first operand
+    pub fn first_token(&self) -> &Token {
+        match self {
+            Expr::Call { function: left, .. }
+            | Expr::Infix { left, .. }
+            | Expr::Index { left, .. }
+            | Expr::Assign { name: left, .. } => left.first_token(),
+            Expr::Identifier { token, .. }
+            | Expr::IntLiteral { token, .. }
+            | Expr::FloatLiteral { token, .. }
+            | Expr::BoolLiteral { token, .. }
+            | Expr::StringLiteral { token, .. }
+            | Expr::FnLiteral { token, .. }
+            | Expr::Prefix { token, .. }
+            | Expr::If { token, .. }
+            | Expr::ArrayLiteral { token, .. }
+            | Expr::HashLiteral { token, .. }
+            | Expr::Match { token, .. }
+            | Expr::Try { token, .. } => token,
+        }
+    }
+}
+
+impl Stmt {
+    pub fn first_token(&self) -> &Token {
+        match self {
+            Stmt::Expr { expr } => expr.first_token(),
+            Stmt::Let { token, .. }
+            | Stmt::Return { token, .. }
+            | Stmt::Block { token, .. }
+            | Stmt::Debugger { token }
+            | Stmt::For { token, .. }
+            | Stmt::Break { token }
+            | Stmt::Continue { token } => token,
+        }
+    }
+}
+
+// calls `f` with every statement, including the ones in blocks and function
+// bodies, each before the statements nested in it
+pub fn visit_stmts(stmts: &[Stmt], f: &mut impl FnMut(&Stmt)) {
+    for stmt in stmts {
+        visit_stmt(stmt, f);
+    }
+}
+
+fn visit_stmt(stmt: &Stmt, f: &mut impl FnMut(&Stmt)) {
+    f(stmt);
+    match stmt {
+        Stmt::Let { value: expr, .. } | Stmt::Return { value: expr, .. } | Stmt::Expr { expr } => {
+            visit_expr(expr, f)
+        }
+        Stmt::Block { stmts, .. } => visit_stmts(stmts, f),
+        Stmt::Debugger { .. } | Stmt::Break { .. } | Stmt::Continue { .. } => {}
+        Stmt::For { iterable, body, .. } => {
+            visit_expr(iterable, f);
+            visit_stmt(body, f);
+        }
+    }
+}
+
+fn visit_expr(expr: &Expr, f: &mut impl FnMut(&Stmt)) {
+    match expr {
+        Expr::Identifier { .. }
+        | Expr::IntLiteral { .. }
+        | Expr::FloatLiteral { .. }
+        | Expr::BoolLiteral { .. }
+        | Expr::StringLiteral { .. } => {}
+        Expr::FnLiteral { block, .. } => visit_stmt(block, f),
+        Expr::Call {
+            function,
+            arguments,
+            ..
+        } => {
+            visit_expr(function, f);
+            arguments.iter().for_each(|arg| visit_expr(arg, f));
+        }
+        Expr::Prefix { expr, .. } | Expr::Assign { value: expr, .. } => visit_expr(expr, f),
+        Expr::Infix { left, right, .. }
+        | Expr::Index {
+            left, index: right, ..
+        } => {
+            visit_expr(left, f);
+            visit_expr(right, f);
+        }
+        Expr::If {
+            condition,
+            consequence,
+            alternative,
+            ..
+        } => {
+            visit_expr(condition, f);
+            visit_stmt(consequence, f);
+            if let Some(alternative) = alternative {
+                visit_stmt(alternative, f);
+            }
+        }
+        Expr::Try { body, handler, .. } => {
+            visit_stmt(body, f);
+            visit_stmt(handler, f);
+        }
+        Expr::ArrayLiteral { elements, .. } => elements.iter().for_each(|e| visit_expr(e, f)),
+        Expr::HashLiteral { pairs, .. } => pairs.iter().for_each(|(key, value)| {
+            visit_expr(key, f);
+            visit_expr(value, f);
+        }),
+        Expr::Match { subject, arms, .. } => {
+            visit_expr(subject, f);
+            for arm in arms {
+                if let Some(pattern) = &arm.pattern {
+                    visit_expr(pattern, f);
+                }
+                visit_expr(&arm.value, f);
+            }
         }
     }
 }
 
-pub enum Node<'a> {
-    Stmt(Stmt<'a>),
-    Expr(Expr<'a>),
+pub enum Node {
+    Stmt(Stmt),
+    Expr(Expr),
 }
 
-impl fmt::Display for Node<'_> {
+impl fmt::Display for Node {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Node::Stmt(stmt) => format!("{stmt}"),
-            Node::Expr(expr) => format!("{expr}"),
-        };
-        Ok(())
+            Node::Stmt(stmt) => write!(f, "{stmt}"),
+            Node::Expr(expr) => write!(f, "{expr}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use crate::{lexer::Lexer, parser::Parser};
+
+    fn assert_round_trip(input: &str) {
+        let prog = Parser::new(Lexer::new(input.as_bytes())).parse().unwrap();
+        let printed = prog.to_string();
+        let reparsed = Parser::new(Lexer::new(printed.as_bytes()))
+            .parse()
+            .unwrap_or_else(|errors| panic!("{:?} for {}", errors, printed));
+        assert_eq!(reparsed, prog, "{}", printed);
+    }
+
+    #[test]
+    fn test_display_round_trip() {
+        let inputs = [
+            "if (x < y) { x } else { y }; (z)",
+            "let f = fn(a, b) { let c = a * b; return c; }; f(1, 2)",
+            "fn() { }; if (true) { } else { 1; 2 }",
+            "a; (b); [1, 2][0]; -a; !b",
+            "fn(x) { x }(1); if (a) { b }(c)",
+            "debugger; let f = fn() { debugger }",
+            "let s = \"a b\" + \"\"; s == \"c\"",
+            "{}; {\"a\": 1, 2: [fn(x) { x }]}[true]",
+            "let pi = 3.14; -0.5 * pi + 10",
+            "for (x in [1, 2]) { puts(x); for (y in []) { } } x",
+            "x = 1; (a = b = 2) + 1; f(c = 3)",
+            "let [a, b] = [1, 2]; let [] = c",
+            "fn(a, ...rest) { rest }; fn(...xs) { }",
+            "for (x in xs) { if (x) { break; } else { continue } }",
+            "match (x + 1) { 1 => a, -2 => \"b\", true => fn() { 1 }, _ => c }; match (y) { }",
+            "try { f(1) } catch (e) { puts(e); 0 } + 1; try { } catch (err) { }",
+        ];
+        for input in inputs {
+            assert_round_trip(input);
+        }
+    }
+
+    fn expr() -> impl Strategy<Value = String> {
+        let leaf = prop_oneof![
+            prop::sample::select(vec!["a", "b", "foo"]).prop_map(str::to_string),
+            (0..1000i64).prop_map(|n| n.to_string()),
+            (0..1000i64).prop_map(|n| format!("{}.25", n)),
+            any::<bool>().prop_map(|b| b.to_string()),
+            prop::sample::select(vec!["\"\"", "\"foo bar\""]).prop_map(str::to_string),
+        ];
+        leaf.prop_recursive(4, 32, 4, |inner| {
+            let ops = prop::sample::select(vec!["+", "-", "*", "/", "<", ">", "==", "!="]);
+            prop_oneof![
+                (prop::sample::select(vec!["-", "!"]), inner.clone())
+                    .prop_map(|(op, e)| format!("{}{}", op, e)),
+                (inner.clone(), ops, inner.clone(), any::<bool>()).prop_map(|(l, op, r, group)| {
+                    if group {
+                        format!("({} {} {})", l, op, r)
+                    } else {
+                        format!("{} {} {}", l, op, r)
+                    }
+                }),
+                (inner.clone(), prop::collection::vec(inner.clone(), 0..3))
+                    .prop_map(|(f, args)| format!("{}({})", f, args.join(", "))),
+                (inner.clone(), inner.clone()).prop_map(|(l, i)| format!("{}[{}]", l, i)),
+                prop::collection::vec(inner.clone(), 0..3)
+                    .prop_map(|elements| format!("[{}]", elements.join(", "))),
+                prop::collection::vec(inner.clone(), 0..3)
+                    .prop_map(|body| format!("fn(x, y) {{ {} }}", body.join("; "))),
+                (inner.clone(), inner.clone(), prop::option::of(inner)).prop_map(
+                    |(c, t, e)| match e {
+                        Some(e) => format!("if ({}) {{ {} }} else {{ {} }}", c, t, e),
+                        None => format!("if ({}) {{ {} }}", c, t),
+                    }
+                ),
+            ]
+        })
+    }
+
+    fn stmt() -> impl Strategy<Value = String> {
+        prop_oneof![
+            expr().prop_map(|e| format!("let x = {};", e)),
+            expr().prop_map(|e| format!("return {};", e)),
+            expr().prop_map(|e| format!("{};", e)),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn test_print_parse_round_trip(stmts in prop::collection::vec(stmt(), 1..4)) {
+            assert_round_trip(&stmts.join(" "));
+        }
     }
 }
@@ -0,0 +1,243 @@
+use crate::{object::Object, rc::Rc, sandbox::Capability, tasks::Channel};
+
+pub type BuiltinFn = for<'a> fn(&[Object<'a>]) -> Result<Object<'a>, &'static str>;
+
+#[derive(Debug)]
+pub struct Builtin {
+    pub name: &'static str,
+    pub func: BuiltinFn,
+    pub capability: Option<Capability>,
+}
+
+// the index of a builtin in this table is the operand of OpGetBuiltin, so
+// new entries go at the end
+pub const BUILTINS: &[Builtin] = &[
+    Builtin {
+        name: "len",
+        func: len,
+        capability: None,
+    },
+    Builtin {
+        name: "puts",
+        func: puts,
+        capability: Some(Capability::Io),
+    },
+    Builtin {
+        name: "first",
+        func: first,
+        capability: None,
+    },
+    Builtin {
+        name: "last",
+        func: last,
+        capability: None,
+    },
+    Builtin {
+        name: "rest",
+        func: rest,
+        capability: None,
+    },
+    Builtin {
+        name: "push",
+        func: push,
+        capability: None,
+    },
+    Builtin {
+        name: "channel",
+        func: channel,
+        capability: Some(Capability::Concurrency),
+    },
+    Builtin {
+        name: "send",
+        func: send,
+        capability: Some(Capability::Concurrency),
+    },
+    Builtin {
+        name: "spawn",
+        func: scheduled,
+        capability: Some(Capability::Concurrency),
+    },
+    Builtin {
+        name: "join",
+        func: scheduled,
+        capability: Some(Capability::Concurrency),
+    },
+    Builtin {
+        name: "recv",
+        func: scheduled,
+        capability: Some(Capability::Concurrency),
+    },
+];
+
+pub fn lookup(name: &[u8]) -> Option<&'static Builtin> {
+    BUILTINS.iter().find(|b| b.name.as_bytes() == name)
+}
+
+fn len<'a>(args: &[Object<'a>]) -> Result<Object<'a>, &'static str> {
+    match args {
+        [Object::Array(elements)] => Ok(Object::Integer(elements.len() as i64)),
+        [Object::Str(value)] => Ok(Object::Integer(value.chars().count() as i64)),
+        [_] => Err("argument to `len` not supported"),
+        _ => Err("wrong number of arguments"),
+    }
+}
+
+fn puts<'a>(args: &[Object<'a>]) -> Result<Object<'a>, &'static str> {
+    for arg in args {
+        println!("{}", arg);
+    }
+    Ok(Object::Null)
+}
+
+fn first<'a>(args: &[Object<'a>]) -> Result<Object<'a>, &'static str> {
+    match args {
+        [Object::Array(elements)] => Ok(elements.first().cloned().unwrap_or(Object::Null)),
+        [_] => Err("argument to `first` must be an array"),
+        _ => Err("wrong number of arguments"),
+    }
+}
+
+fn last<'a>(args: &[Object<'a>]) -> Result<Object<'a>, &'static str> {
+    match args {
+        [Object::Array(elements)] => Ok(elements.last().cloned().unwrap_or(Object::Null)),
+        [_] => Err("argument to `last` must be an array"),
+        _ => Err("wrong number of arguments"),
+    }
+}
+
+fn rest<'a>(args: &[Object<'a>]) -> Result<Object<'a>, &'static str> {
+    match args {
+        [Object::Array(elements)] if elements.is_empty() => Ok(Object::Null),
+        [Object::Array(elements)] => Ok(Object::Array(Rc::new(elements[1..].to_vec()))),
+        [_] => Err("argument to `rest` must be an array"),
+        _ => Err("wrong number of arguments"),
+    }
+}
+
+fn push<'a>(args: &[Object<'a>]) -> Result<Object<'a>, &'static str> {
+    match args {
+        [Object::Array(elements), value] => {
+            let mut elements = elements.to_vec();
+            elements.push(value.clone());
+            Ok(Object::Array(Rc::new(elements)))
+        }
+        [_, _] => Err("argument to `push` must be an array"),
+        _ => Err("wrong number of arguments"),
+    }
+}
+
+fn channel<'a>(args: &[Object<'a>]) -> Result<Object<'a>, &'static str> {
+    match args {
+        [] => Ok(Object::Channel(Rc::new(Channel::default()))),
+        _ => Err("wrong number of arguments"),
+    }
+}
+
+fn send<'a>(args: &[Object<'a>]) -> Result<Object<'a>, &'static str> {
+    match args {
+        [Object::Channel(channel), value] => {
+            channel.send(value.clone());
+            Ok(Object::Null)
+        }
+        [_, _] => Err("argument to `send` must be a channel"),
+        _ => Err("wrong number of arguments"),
+    }
+}
+
+// the engines run these themselves, see tasks::TaskOp
+fn scheduled<'a>(_: &[Object<'a>]) -> Result<Object<'a>, &'static str> {
+    Err("builtin needs a scheduler")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        compiler::Compiler, env::Env, evaluator::eval_program, lexer::Lexer, parser::Parser, vm::Vm,
+    };
+
+    // (input, expected display or error) pairs that both engines must agree on
+    const CASES: &[(&str, Result<&str, &str>)] = &[
+        ("len([])", Ok("0")),
+        ("len([1, 2, 3])", Ok("3")),
+        ("len(1)", Err("argument to `len` not supported")),
+        ("len([1], [2])", Err("wrong number of arguments")),
+        ("puts(1, [2])", Ok("nil")),
+        ("first([1, 2, 3])", Ok("1")),
+        ("first([])", Ok("nil")),
+        ("first(1)", Err("argument to `first` must be an array")),
+        ("last([1, 2, 3])", Ok("3")),
+        ("last([])", Ok("nil")),
+        ("last(1)", Err("argument to `last` must be an array")),
+        ("rest([1, 2, 3])", Ok("[2, 3]")),
+        ("rest(rest([1]))", Ok("nil")),
+        ("rest(1)", Err("argument to `rest` must be an array")),
+        ("push([], 1)", Ok("[1]")),
+        ("let a = [1]; push(a, 2); a", Ok("[1]")),
+        ("push(1, 1)", Err("argument to `push` must be an array")),
+        ("push([])", Err("wrong number of arguments")),
+        ("let len = fn(x) { 42 }; len([])", Ok("42")),
+        ("let count = fn(arr) { len(arr) }; count([1, 2])", Ok("2")),
+        (
+            "let map = fn(arr, acc, f) {
+                if (len(arr) == 0) { acc } else { map(rest(arr), push(acc, f(first(arr))), f) }
+            };
+            map([1, 2, 3], [], fn(x) { x * 2 })",
+            Ok("[2, 4, 6]"),
+        ),
+        (
+            "let ch = channel(); send(ch, 1); send(ch, [2]); [recv(ch), recv(ch)]",
+            Ok("[1, [2]]"),
+        ),
+        ("send(1, 2)", Err("argument to `send` must be a channel")),
+        ("channel(1)", Err("wrong number of arguments")),
+        ("spawn(fn() { 1 })", Ok("<task>")),
+        ("join(spawn(fn() { 1 + 2 }))", Ok("3")),
+        ("let t = spawn(fn() { 5 }); join(t) + join(t)", Ok("10")),
+        ("join(spawn(len))", Err("wrong number of arguments")),
+        ("spawn(1)", Err("argument to `spawn` must be a function")),
+        ("join(1)", Err("argument to `join` must be a task")),
+        ("recv(1)", Err("argument to `recv` must be a channel")),
+        (
+            "recv(channel())",
+            Err("recv on an empty channel with no tasks left"),
+        ),
+        (
+            "let ch = channel(); let t = spawn(fn() { join(recv(ch)) }); send(ch, t); join(t)",
+            Err("task is still running"),
+        ),
+        (
+            "let ch = channel();
+            let produce = fn(n) { if (n > 0) { send(ch, n); produce(n - 1) } };
+            spawn(fn() { produce(3) });
+            let sum = fn(n, acc) { if (n == 0) { acc } else { sum(n - 1, acc + recv(ch)) } };
+            sum(3, 0)",
+            Ok("6"),
+        ),
+        // tasks nothing waited on run when the program ends
+        (
+            "spawn(fn() { 1 + true }); 1",
+            Err("operand can only be applied to numbers"),
+        ),
+    ];
+
+    #[test]
+    fn test_builtins_on_both_engines() {
+        for (input, expected) in CASES {
+            let prog = Parser::new(Lexer::new(input.as_bytes())).parse().unwrap();
+
+            let mut env = Env::new();
+            let evaluated = eval_program(&prog, &mut env)
+                .map(|obj| obj.to_string())
+                .map_err(|err| err.to_string());
+
+            let mut compiler = Compiler::new();
+            compiler.compile(&prog).unwrap();
+            let mut vm = Vm::new(compiler.bytecode());
+            let run = vm.run().map(|obj| obj.to_string());
+
+            let expected = expected.map(str::to_string).map_err(str::to_string);
+            assert_eq!(evaluated, expected, "evaluator: {}", input);
+            assert_eq!(run, expected, "vm: {}", input);
+        }
+    }
+}
@@ -0,0 +1,1188 @@
+use std::{
+    io::{self, Read},
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    ast::Program,
+    compiler::{Bytecode, Compiler},
+    coverage::Coverage,
+    debugger::SourceDebugger,
+    diagnostic::{json_diagnostic, Diagnostic},
+    engine::Engine,
+    env::Env,
+    evaluator::Evaluator,
+    fmt::{self, FormatConfig},
+    fold,
+    interpreter::Error,
+    lexer::Lexer,
+    lint::{self, LintConfig, Rule, Severity},
+    mkc,
+    object::Object,
+    optimizer,
+    parser::{ParseError, Parser},
+    profiler::Profiler,
+    sandbox::SandboxConfig,
+    testing,
+    token::Span,
+    vm::{Vm, VmConfig},
+};
+
+pub const USAGE: &str = "usage: interpreter-book-in-rust [options] [script]
+       interpreter-book-in-rust [options] -e <code>
+       interpreter-book-in-rust --dump-tokens|--dump-ast[=pretty] [script]
+       interpreter-book-in-rust compile <script> [-o <output.mkc>]
+       interpreter-book-in-rust lint <script> [--lint <rule>=<level>]...
+       interpreter-book-in-rust check <script>
+       interpreter-book-in-rust test [dir|script]
+       interpreter-book-in-rust debug <script>
+       interpreter-book-in-rust bench <script> [--iterations <n>] [--warmup <n>]
+
+without a script the repl starts, unless stdin isn't a terminal, then what's
+piped in is run as a script
+scripts ending in .mkc are loaded as compiled bytecode and run on the vm
+`test` runs the tests declared with `test(name, fn)` in every *_test.mk file
+under <dir>, the current directory by default
+`bench` runs <script> <n> times after the warmup runs and reports how long a
+run took and the steps it executed, puts and the like still print
+`debug` pauses before the first statement and at every `debugger;` statement
+`lint` and `check` report undefined variables, calls with the wrong number of
+arguments, unreachable statements and the like without running the script,
+`check` as errors with where they are and without unused bindings under
+--allow-unused
+
+options:
+    --max-steps <n>         abort after evaluating <n> expressions/statements
+    --max-call-depth <n>    abort when function calls nest deeper than <n>, 1024 by default
+    --timeout <ms>          abort after <ms> milliseconds of evaluation
+    --diagnostics <format>  report errors on stderr as `text` (default) or `json`
+    -e, --eval <code>       run <code> instead of a script and print its value
+    --engine <name>         run source on the tree-walking `eval`uator (default) or
+                            compile it for the `vm`, also applies to the repl
+    -o, --output <path>     where `compile` writes the bytecode, defaults to <script>.mkc
+    --iterations <n>        how many runs `bench` measures, 10 by default
+    --warmup <n>            how many runs `bench` does before measuring, 2 by default
+    --lint <rule>=<level>   set a rule of `lint` and `check` to `allow`, `warning` or `error`
+    --profile               report calls, nodes evaluated and time per function on stderr
+    --coverage              report the lines that ran on stderr, also applies to `test`
+    --allow-unused          don't warn about variables that are never used
+    --fold                  fold constant expressions like `2 * 3` and drop statements
+                            after a `return` before running or compiling source, also
+                            shown by --dump-ast
+    --check                 like `check`, for a script, -e code or stdin
+    --dump-tokens           print the tokens of the script or stdin instead of running it
+    --dump-ast[=<form>]     print the ast instead of running the script, as `debug` (default)
+                            or formatted back into source with `pretty`";
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum DiagnosticsFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub enum Command {
+    #[default]
+    Repl,
+    Run(String),
+    // source given on the command line
+    Eval(String),
+    Compile {
+        input: String,
+        output: String,
+    },
+    Lint(String),
+    Check(String),
+    Test(String),
+    Debug(String),
+    Bench {
+        script: String,
+        iterations: usize,
+        warmup: usize,
+    },
+}
+
+// what to print instead of running a script
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Dump {
+    Tokens,
+    // the `Debug` form of the statements
+    Ast,
+    // the ast formatted back into source
+    PrettyAst,
+}
+
+#[derive(Debug, Default)]
+pub struct Options {
+    pub sandbox: SandboxConfig,
+    pub diagnostics: DiagnosticsFormat,
+    pub lint: LintConfig,
+    pub profile: bool,
+    pub coverage: bool,
+    // unused bindings are warned about before running a script and by `check`
+    pub allow_unused: bool,
+    pub dump: Option<Dump>,
+    // `check` the script, -e code or stdin instead of running it
+    pub check: bool,
+    // fold constant expressions before running, compiling or dumping the ast
+    pub fold: bool,
+    pub engine: Engine,
+    pub command: Command,
+}
+
+pub fn parse_args<I: IntoIterator<Item = String>>(args: I) -> Result<Options, String> {
+    let mut opts = Options::default();
+    let mut positional = Vec::new();
+    let mut output = None;
+    let mut eval = None;
+    let mut iterations = None;
+    let mut warmup = None;
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        if !arg.starts_with('-') {
+            positional.push(arg);
+            continue;
+        }
+
+        // both `--flag value` and `--flag=value` are accepted
+        let (flag, inline_value) = match arg.split_once('=') {
+            Some((flag, value)) => (flag.to_string(), Some(value.to_string())),
+            None => (arg, None),
+        };
+        let mut value = || {
+            inline_value
+                .clone()
+                .or_else(|| args.next())
+                .ok_or_else(|| format!("missing value for '{}'", flag))
+        };
+
+        match flag.as_str() {
+            "--max-steps" => opts.sandbox.max_steps = Some(parse_num(&flag, &value()?)?),
+            "--max-call-depth" => opts.sandbox.max_call_depth = Some(parse_num(&flag, &value()?)?),
+            "--timeout" => {
+                opts.sandbox.timeout = Some(Duration::from_millis(parse_num(&flag, &value()?)?))
+            }
+            "--diagnostics" => {
+                opts.diagnostics = match value()?.as_str() {
+                    "text" => DiagnosticsFormat::Text,
+                    "json" => DiagnosticsFormat::Json,
+                    other => return Err(format!("invalid value '{}' for '{}'", other, flag)),
+                }
+            }
+            "-o" | "--output" => output = Some(value()?),
+            "-e" | "--eval" => eval = Some(value()?),
+            "--iterations" => iterations = Some(parse_num(&flag, &value()?)?),
+            "--warmup" => warmup = Some(parse_num(&flag, &value()?)?),
+            "--engine" => {
+                let value = value()?;
+                opts.engine = Engine::from_name(&value)
+                    .ok_or_else(|| format!("invalid value '{}' for '{}'", value, flag))?;
+            }
+            "--profile" if inline_value.is_none() => opts.profile = true,
+            "--coverage" if inline_value.is_none() => opts.coverage = true,
+            "--allow-unused" if inline_value.is_none() => opts.allow_unused = true,
+            "--check" if inline_value.is_none() => opts.check = true,
+            "--fold" if inline_value.is_none() => opts.fold = true,
+            "--dump-tokens" | "--dump-ast" => {
+                let dump = match inline_value.as_deref() {
+                    None if flag == "--dump-tokens" => Dump::Tokens,
+                    None | Some("debug") if flag == "--dump-ast" => Dump::Ast,
+                    Some("pretty") if flag == "--dump-ast" => Dump::PrettyAst,
+                    _ => return Err(format!("invalid value for '{}'", flag)),
+                };
+                if opts.dump.replace(dump).is_some() {
+                    return Err(
+                        "only one of '--dump-tokens' and '--dump-ast' can be given".to_string()
+                    );
+                }
+            }
+            "--lint" => {
+                let value = value()?;
+                let (rule, severity) = value
+                    .split_once('=')
+                    .and_then(|(rule, level)| {
+                        Some((Rule::from_id(rule)?, Severity::from_name(level)?))
+                    })
+                    .ok_or_else(|| format!("invalid value '{}' for '{}'", value, flag))?;
+                opts.lint.set(rule, severity);
+            }
+            _ => return Err(format!("unknown option '{}'", flag)),
+        }
+    }
+
+    opts.command = match positional.as_slice() {
+        [] => match eval.take() {
+            Some(source) => Command::Eval(source),
+            None => Command::Repl,
+        },
+        [cmd, input] if cmd == "compile" => Command::Compile {
+            output: output.take().unwrap_or_else(|| mkc::output_path(input)),
+            input: input.clone(),
+        },
+        [cmd] if cmd == "compile" => return Err("missing script for 'compile'".to_string()),
+        [cmd, input] if cmd == "lint" => Command::Lint(input.clone()),
+        [cmd] if cmd == "lint" => return Err("missing script for 'lint'".to_string()),
+        [cmd, input] if cmd == "check" => Command::Check(input.clone()),
+        [cmd] if cmd == "check" => return Err("missing script for 'check'".to_string()),
+        [cmd, path] if cmd == "test" => Command::Test(path.clone()),
+        [cmd] if cmd == "test" => Command::Test(".".to_string()),
+        [cmd, script] if cmd == "debug" => Command::Debug(script.clone()),
+        [cmd] if cmd == "debug" => return Err("missing script for 'debug'".to_string()),
+        [cmd, script] if cmd == "bench" => Command::Bench {
+            script: script.clone(),
+            iterations: iterations.take().unwrap_or(10),
+            warmup: warmup.take().unwrap_or(2),
+        },
+        [cmd] if cmd == "bench" => return Err("missing script for 'bench'".to_string()),
+        [script] => Command::Run(script.clone()),
+        [_, rest @ ..] => return Err(format!("unexpected argument '{}'", rest[0])),
+    };
+    if output.is_some() {
+        return Err("'--output' only applies to 'compile'".to_string());
+    }
+    if eval.is_some() {
+        return Err("'--eval' can't be combined with a script or command".to_string());
+    }
+    if iterations.is_some() || warmup.is_some() {
+        return Err("'--iterations' and '--warmup' only apply to 'bench'".to_string());
+    }
+    if matches!(opts.command, Command::Bench { iterations: 0, .. }) {
+        return Err("'bench' needs at least one iteration".to_string());
+    }
+    if opts.check && opts.dump.is_some() {
+        return Err("'--check' and dumping can't be combined".to_string());
+    }
+    if opts.check || opts.dump.is_some() {
+        let source = match &opts.command {
+            Command::Run(script) => !script.ends_with(mkc::EXTENSION),
+            Command::Eval(_) | Command::Repl => true,
+            _ => false,
+        };
+        if !source {
+            return Err(
+                "'--check' and dumping only apply to a source script, '--eval' or stdin"
+                    .to_string(),
+            );
+        }
+        if opts.profile || opts.coverage {
+            return Err(
+                "'--profile' and '--coverage' don't apply to '--check' or dumping".to_string(),
+            );
+        }
+    }
+    if opts.fold {
+        let source = match &opts.command {
+            Command::Run(script) => !script.ends_with(mkc::EXTENSION),
+            Command::Eval(_) | Command::Repl | Command::Compile { .. } => true,
+            _ => false,
+        };
+        if !source {
+            return Err(
+                "'--fold' only applies to running a source script, '--eval', stdin, the repl or 'compile'"
+                    .to_string(),
+            );
+        }
+    }
+    if opts.engine == Engine::Vm {
+        if !matches!(
+            opts.command,
+            Command::Run(_) | Command::Eval(_) | Command::Repl | Command::Bench { .. }
+        ) {
+            return Err(
+                "'--engine' only applies to running a script, '--eval', stdin, the repl or 'bench'"
+                    .to_string(),
+            );
+        }
+        if opts.profile || opts.coverage {
+            return Err("'--profile' and '--coverage' need '--engine=eval'".to_string());
+        }
+    }
+    // the profiler and coverage watch the evaluator, compiled scripts run on
+    // the vm, and the evaluator takes a single tracer
+    let evaluated = match &opts.command {
+        Command::Run(script) => !script.ends_with(mkc::EXTENSION),
+        Command::Eval(_) => true,
+        _ => false,
+    };
+    if opts.profile && !evaluated {
+        return Err("'--profile' only applies to running a source script".to_string());
+    }
+    if opts.coverage && !(evaluated || matches!(opts.command, Command::Test(_))) {
+        return Err("'--coverage' only applies to running a source script or 'test'".to_string());
+    }
+    if opts.profile && opts.coverage {
+        return Err("'--profile' and '--coverage' can't be combined".to_string());
+    }
+    Ok(opts)
+}
+
+fn parse_num<T: std::str::FromStr>(flag: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid value '{}' for '{}'", value, flag))
+}
+
+pub fn run_script(path: &str, opts: &Options) -> i32 {
+    let report = |code: &str, message: &str| report(opts.diagnostics, path, code, message);
+
+    if path.ends_with(mkc::EXTENSION) {
+        let bytecode = match std::fs::read(path) {
+            Ok(bytes) => mkc::decode(&bytes),
+            Err(err) => {
+                report("io-error", &format!("failed to read {}: {}", path, err));
+                return 1;
+            }
+        };
+        return match bytecode {
+            Ok(bytecode) => run_bytecode(path, "", bytecode, opts),
+            Err(err) => {
+                report("runtime-error", &err);
+                1
+            }
+        };
+    }
+    eval_script(path, opts, false)
+}
+
+// `source` is what the bytecode was compiled from, empty for a .mkc file
+fn run_bytecode(path: &str, source: &str, bytecode: Bytecode, opts: &Options) -> i32 {
+    let config = VmConfig {
+        sandbox: opts.sandbox,
+        ..Default::default()
+    };
+    let mut vm = Vm::with_config(bytecode, config);
+    match vm.run() {
+        Ok(Object::Null) => 0,
+        Ok(value) => {
+            println!("{}", value);
+            0
+        }
+        Err(err) => {
+            let diagnostic = Diagnostic::error(err, vm.span()).with_notes(vm.backtrace());
+            report_diagnostic(opts.diagnostics, path, source, "runtime-error", &diagnostic);
+            1
+        }
+    }
+}
+
+pub fn debug_script(path: &str, opts: &Options) -> i32 {
+    eval_script(path, opts, true)
+}
+
+// diagnostics about it are reported for the file `<eval>`
+pub fn eval_code(source: &str, opts: &Options) -> i32 {
+    eval_source("<eval>", source, opts, false)
+}
+
+fn dump_source(path: &str, source: &str, opts: &Options, dump: Dump) -> i32 {
+    if dump == Dump::Tokens {
+        print!("{}", dump_tokens(source));
+        return 0;
+    }
+    let mut prog = match Parser::new(Lexer::new(source.as_bytes())).parse() {
+        Ok(prog) => prog,
+        Err(errors) => {
+            for err in errors {
+                report_parse_error(opts.diagnostics, path, source, &err);
+            }
+            return 1;
+        }
+    };
+    if opts.fold {
+        fold::fold_program(&mut prog);
+    }
+    if dump == Dump::PrettyAst {
+        print!("{}", fmt::format_program(&prog, &FormatConfig::default()));
+    } else {
+        println!("{:#?}", prog.stmts);
+    }
+    0
+}
+
+// a line per token, with where it starts
+pub fn dump_tokens(source: &str) -> String {
+    Lexer::new(source.as_bytes())
+        .map(|token| {
+            format!(
+                "{} {:?} {}\n",
+                token.span,
+                token.token_type,
+                String::from_utf8_lossy(&token.literal)
+            )
+        })
+        .collect()
+}
+
+// all of stdin as one script, for input that's piped in
+pub fn eval_stdin(opts: &Options) -> i32 {
+    let mut source = String::new();
+    if let Err(err) = io::stdin().read_to_string(&mut source) {
+        let message = format!("failed to read stdin: {}", err);
+        report(opts.diagnostics, "<stdin>", "io-error", &message);
+        return 1;
+    }
+    eval_source("<stdin>", &source, opts, false)
+}
+
+fn eval_script(path: &str, opts: &Options, debug: bool) -> i32 {
+    let report = |code: &str, message: &str| report(opts.diagnostics, path, code, message);
+
+    let source = match std::fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(err) => {
+            report("io-error", &format!("failed to read {}: {}", path, err));
+            return 1;
+        }
+    };
+    eval_source(path, &source, opts, debug)
+}
+
+fn eval_source(path: &str, source: &str, opts: &Options, debug: bool) -> i32 {
+    if let Some(dump) = opts.dump {
+        return dump_source(path, source, opts, dump);
+    }
+    if opts.check {
+        return check_source(path, source, opts);
+    }
+    let mut p = Parser::new(Lexer::new(source.as_bytes()));
+    let mut prog = match p.parse() {
+        Ok(prog) => prog,
+        Err(errors) => {
+            for err in errors {
+                report_parse_error(opts.diagnostics, path, source, &err);
+            }
+            return 1;
+        }
+    };
+    if opts.fold {
+        fold_warning_dropped(path, source, &mut prog, opts);
+    }
+
+    if !opts.allow_unused {
+        for warning in lint::unused(&prog, &LintConfig::default()) {
+            let code = warning.rule.id();
+            report_diagnostic(
+                opts.diagnostics,
+                path,
+                source,
+                code,
+                &warning.to_diagnostic(),
+            );
+        }
+    }
+
+    // the same as compiling the script and running that
+    if opts.engine == Engine::Vm {
+        let mut compiler = Compiler::new();
+        if let Err(err) = compiler.compile(&prog) {
+            report(opts.diagnostics, path, "compile-error", &err);
+            return 1;
+        }
+        return run_bytecode(path, source, optimizer::optimize(compiler.bytecode()), opts);
+    }
+
+    let mut profiler = opts.profile.then(|| Profiler::new(&prog));
+    let coverage = opts.coverage.then(|| Coverage::new(source));
+    // the evaluator borrows the profiler until it's dropped
+    let code = {
+        let mut evaluator = Evaluator::new(opts.sandbox);
+        if debug {
+            evaluator =
+                evaluator.with_tracer(SourceDebugger::new(io::stdin().lock(), io::stdout()));
+        } else if let Some(profiler) = &mut profiler {
+            evaluator = evaluator.with_tracer(profiler);
+        } else if let Some(coverage) = &coverage {
+            evaluator = evaluator.with_tracer(coverage);
+        }
+        match evaluator.eval_program(&prog, &mut Env::new()) {
+            Ok(Object::Null) => 0,
+            Ok(value) => {
+                println!("{}", value);
+                0
+            }
+            Err(err) => {
+                let diagnostic = err.diagnostic();
+                report_diagnostic(opts.diagnostics, path, source, "runtime-error", &diagnostic);
+                1
+            }
+        }
+    };
+    if let Some(profiler) = profiler {
+        eprint!("{}", profiler.report());
+    }
+    if let Some(coverage) = coverage {
+        eprint!("{}", coverage.report(path));
+    }
+    code
+}
+
+pub fn lint_script(path: &str, opts: &Options) -> i32 {
+    let report = |severity: &str, code: &str, message: &str| {
+        report_with_severity(opts.diagnostics, path, None, severity, code, message)
+    };
+
+    let source = match std::fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(err) => {
+            report(
+                "error",
+                "io-error",
+                &format!("failed to read {}: {}", path, err),
+            );
+            return 1;
+        }
+    };
+
+    let mut p = Parser::new(Lexer::new(source.as_bytes()));
+    let prog = match p.parse() {
+        Ok(prog) => prog,
+        Err(errors) => {
+            for err in errors {
+                report_parse_error(opts.diagnostics, path, &source, &err);
+            }
+            return 1;
+        }
+    };
+
+    let lints = lint::lint(&prog, &opts.lint);
+    for lint in &lints {
+        let code = lint.rule.id();
+        report_diagnostic(opts.diagnostics, path, &source, code, &lint.to_diagnostic());
+    }
+    lints.iter().any(|lint| lint.severity == Severity::Error) as i32
+}
+
+pub fn check_script(path: &str, opts: &Options) -> i32 {
+    let source = match std::fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(err) => {
+            report(
+                opts.diagnostics,
+                path,
+                "io-error",
+                &format!("failed to read {}: {}", path, err),
+            );
+            return 1;
+        }
+    };
+    check_source(path, &source, opts)
+}
+
+fn check_source(path: &str, source: &str, opts: &Options) -> i32 {
+    let mut p = Parser::new(Lexer::new(source.as_bytes()));
+    let prog = match p.parse() {
+        Ok(prog) => prog,
+        Err(errors) => {
+            for err in errors {
+                report_parse_error(opts.diagnostics, path, source, &err);
+            }
+            return 1;
+        }
+    };
+
+    let mut config = opts.lint.clone();
+    if opts.allow_unused {
+        config.set(Rule::UnusedVariable, Severity::Allow);
+    }
+    let lints = lint::lint(&prog, &config);
+    for lint in &lints {
+        let code = lint.rule.id();
+        report_diagnostic(opts.diagnostics, path, source, code, &lint.to_diagnostic());
+    }
+    lints.iter().any(|lint| lint.severity == Severity::Error) as i32
+}
+
+// source scripts are parsed, and compiled for the vm, once, each run gets a
+// fresh env or fresh globals
+pub fn bench_script(path: &str, iterations: usize, warmup: usize, opts: &Options) -> i32 {
+    let report = |code: &str, message: &str| report(opts.diagnostics, path, code, message);
+
+    let bytes = match std::fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            report("io-error", &format!("failed to read {}: {}", path, err));
+            return 1;
+        }
+    };
+    // compiled scripts have no source to show errors in
+    let mut source = String::new();
+    let (engine, mut run): (_, Box<dyn FnMut() -> Result<u64, Diagnostic>>) =
+        if path.ends_with(mkc::EXTENSION) {
+            match mkc::decode(&bytes) {
+                Ok(bytecode) => (Engine::Vm, bench_vm(bytecode, opts)),
+                Err(err) => {
+                    report("runtime-error", &err);
+                    return 1;
+                }
+            }
+        } else {
+            source = match String::from_utf8(bytes) {
+                Ok(source) => source,
+                Err(err) => {
+                    report("io-error", &format!("failed to read {}: {}", path, err));
+                    return 1;
+                }
+            };
+            let prog = match Parser::new(Lexer::new(source.as_bytes())).parse() {
+                Ok(prog) => prog,
+                Err(errors) => {
+                    for err in errors {
+                        report_parse_error(opts.diagnostics, path, &source, &err);
+                    }
+                    return 1;
+                }
+            };
+            match opts.engine {
+                Engine::Eval => (Engine::Eval, bench_eval(prog, opts)),
+                Engine::Vm => {
+                    let mut compiler = Compiler::new();
+                    if let Err(err) = compiler.compile(&prog) {
+                        report("compile-error", &err);
+                        return 1;
+                    }
+                    let bytecode = optimizer::optimize(compiler.bytecode());
+                    (Engine::Vm, bench_vm(bytecode, opts))
+                }
+            }
+        };
+
+    let mut times = Vec::with_capacity(iterations);
+    let mut steps = 0;
+    for i in 0..warmup + iterations {
+        let start = Instant::now();
+        match run() {
+            Ok(run_steps) => steps = run_steps,
+            Err(err) => {
+                report_diagnostic(opts.diagnostics, path, &source, "runtime-error", &err);
+                return 1;
+            }
+        }
+        if i >= warmup {
+            times.push(start.elapsed());
+        }
+    }
+    println!("{}", bench_report(path, engine, warmup, &times, steps));
+    0
+}
+
+fn bench_eval<'a>(
+    prog: Program,
+    opts: &Options,
+) -> Box<dyn FnMut() -> Result<u64, Diagnostic> + 'a> {
+    let sandbox = opts.sandbox;
+    Box::new(move || {
+        let mut evaluator = Evaluator::new(sandbox);
+        let mut env = Env::new();
+        let result = evaluator.eval_program(&prog, &mut env);
+        // cycles the run made would otherwise pile up
+        let heap = env.heap();
+        drop(env);
+        heap.collect();
+        result.map_err(|err| err.diagnostic())?;
+        Ok(evaluator.steps())
+    })
+}
+
+fn bench_vm(bytecode: Bytecode, opts: &Options) -> Box<dyn FnMut() -> Result<u64, Diagnostic>> {
+    let config = VmConfig {
+        sandbox: opts.sandbox,
+        ..Default::default()
+    };
+    Box::new(move || {
+        let mut vm = Vm::with_config(bytecode.clone(), config);
+        vm.run()
+            .map_err(|err| Diagnostic::error(err, None).with_notes(vm.backtrace()))?;
+        Ok(vm.steps())
+    })
+}
+
+// `fib.mk on eval: 10 runs after 2 runs of warmup`, then the min, mean and max
+// time of a run and the steps of the last one
+fn bench_report(
+    path: &str,
+    engine: Engine,
+    warmup: usize,
+    times: &[Duration],
+    steps: u64,
+) -> String {
+    let ms = |time: Duration| time.as_secs_f64() * 1000.0;
+    let runs = |n: usize| format!("{} run{}", n, if n == 1 { "" } else { "s" });
+    let min = times.iter().min().copied().unwrap_or_default();
+    let max = times.iter().max().copied().unwrap_or_default();
+    let mean = times.iter().sum::<Duration>() / times.len().max(1) as u32;
+    format!(
+        "{} on {}: {} after {} of warmup\nmin {:.3}ms, mean {:.3}ms, max {:.3}ms, {} steps",
+        path,
+        engine.as_str(),
+        runs(times.len()),
+        runs(warmup),
+        ms(min),
+        ms(mean),
+        ms(max),
+        steps
+    )
+}
+
+pub fn test_scripts(path: &str, opts: &Options) -> i32 {
+    let scripts = match testing::discover(Path::new(path)) {
+        Ok(scripts) => scripts,
+        Err(err) => {
+            report(
+                opts.diagnostics,
+                path,
+                "io-error",
+                &format!("failed to read {}: {}", path, err),
+            );
+            return 1;
+        }
+    };
+
+    let (mut passed, mut failed) = (0, 0);
+    let mut coverage_reports = Vec::new();
+    for script in scripts {
+        let script = script.display().to_string();
+        let report = |code: &str, message: &str| report(opts.diagnostics, &script, code, message);
+        let source = match std::fs::read_to_string(&script) {
+            Ok(source) => source,
+            Err(err) => {
+                report("io-error", &format!("failed to read {}: {}", script, err));
+                failed += 1;
+                continue;
+            }
+        };
+        let coverage = opts.coverage.then(|| Coverage::new(&source));
+        let results = testing::run_tests(&source, opts.sandbox, coverage.as_ref());
+        if let Some(coverage) = coverage {
+            coverage_reports.push(coverage.report(&script));
+        }
+        match results {
+            Ok(results) => {
+                for result in results {
+                    match result.outcome {
+                        Ok(()) => {
+                            println!("ok   {}: {}", script, result.name);
+                            passed += 1;
+                        }
+                        Err(err) => {
+                            println!("FAIL {}: {}: {}", script, result.name, err);
+                            failed += 1;
+                        }
+                    }
+                }
+            }
+            Err(Error::Parse(errors)) => {
+                for err in errors {
+                    report_parse_error(opts.diagnostics, &script, &source, &err);
+                }
+                failed += 1;
+            }
+            Err(Error::Runtime(diagnostic)) => {
+                report_diagnostic(
+                    opts.diagnostics,
+                    &script,
+                    &source,
+                    "runtime-error",
+                    &diagnostic,
+                );
+                failed += 1;
+            }
+        }
+    }
+    println!("\n{} passed, {} failed", passed, failed);
+    for report in coverage_reports {
+        eprint!("\n{}", report);
+    }
+    (failed > 0) as i32
+}
+
+pub fn compile_script(input: &str, output: &str, opts: &Options) -> i32 {
+    let report = |code: &str, message: &str| report(opts.diagnostics, input, code, message);
+
+    let source = match std::fs::read_to_string(input) {
+        Ok(source) => source,
+        Err(err) => {
+            report("io-error", &format!("failed to read {}: {}", input, err));
+            return 1;
+        }
+    };
+
+    let mut p = Parser::new(Lexer::new(source.as_bytes()));
+    let mut prog = match p.parse() {
+        Ok(prog) => prog,
+        Err(errors) => {
+            for err in errors {
+                report_parse_error(opts.diagnostics, input, &source, &err);
+            }
+            return 1;
+        }
+    };
+    if opts.fold {
+        fold_warning_dropped(input, &source, &mut prog, opts);
+    }
+
+    let mut compiler = Compiler::new();
+    if let Err(err) = compiler.compile(&prog) {
+        report("compile-error", &err);
+        return 1;
+    }
+    let bytes = match mkc::encode(&optimizer::optimize(compiler.bytecode())) {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            report("compile-error", &err);
+            return 1;
+        }
+    };
+    if let Err(err) = std::fs::write(output, bytes) {
+        report("io-error", &format!("failed to write {}: {}", output, err));
+        return 1;
+    }
+    0
+}
+
+fn report(format: DiagnosticsFormat, file: &str, code: &str, message: &str) {
+    report_with_severity(format, file, None, "error", code, message)
+}
+
+// text diagnostics show the line the problem is on and the notes after the
+// message, json ones just the message
+fn report_diagnostic(
+    format: DiagnosticsFormat,
+    file: &str,
+    source: &str,
+    code: &str,
+    diagnostic: &Diagnostic,
+) {
+    let message = match format {
+        DiagnosticsFormat::Text => diagnostic.render(source),
+        DiagnosticsFormat::Json => diagnostic.message.clone(),
+    };
+    let severity = diagnostic.severity.as_str();
+    report_with_severity(format, file, diagnostic.span, severity, code, &message)
+}
+
+// folds `prog`, warning about the statements that were dropped
+fn fold_warning_dropped(path: &str, source: &str, prog: &mut Program, opts: &Options) {
+    for span in fold::fold_program(prog) {
+        let warning = Diagnostic::new(
+            Severity::Warning,
+            "unreachable statement dropped",
+            Some(span),
+        );
+        report_diagnostic(opts.diagnostics, path, source, "unreachable-code", &warning);
+    }
+}
+
+fn report_parse_error(format: DiagnosticsFormat, file: &str, source: &str, err: &ParseError) {
+    report_diagnostic(format, file, source, "parse-error", &err.diagnostic())
+}
+
+// text diagnostics put the line and column after the file, `a.mk:2:11`
+fn report_with_severity(
+    format: DiagnosticsFormat,
+    file: &str,
+    span: Option<Span>,
+    severity: &str,
+    code: &str,
+    message: &str,
+) {
+    // tokens that weren't read from the file have no position
+    let span = span.filter(|span| span.line > 0);
+    let location = match span {
+        Some(span) => format!("{}:{}", file, span),
+        None => file.to_string(),
+    };
+    match format {
+        DiagnosticsFormat::Text if severity == "error" => {
+            eprintln!("{}: {}: {}", location, code, message)
+        }
+        DiagnosticsFormat::Text => eprintln!("{}: {}: {}: {}", location, severity, code, message),
+        DiagnosticsFormat::Json => {
+            eprintln!("{}", json_diagnostic(file, span, severity, code, message))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{bench_report, parse_args, Command, DiagnosticsFormat, Dump};
+    use crate::{
+        engine::Engine,
+        lint::{Rule, Severity},
+    };
+
+    fn args(input: &[&str]) -> Vec<String> {
+        input.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse_limits() {
+        let opts = parse_args(args(&[
+            "--max-steps",
+            "100",
+            "--max-call-depth=10",
+            "--timeout",
+            "250",
+            "main.mk",
+        ]))
+        .unwrap();
+        assert_eq!(opts.sandbox.max_steps, Some(100));
+        assert_eq!(opts.sandbox.max_call_depth, Some(10));
+        assert_eq!(opts.sandbox.timeout, Some(Duration::from_millis(250)));
+        assert_eq!(opts.command, Command::Run("main.mk".to_string()));
+        assert_eq!(opts.diagnostics, DiagnosticsFormat::Text);
+    }
+
+    #[test]
+    fn test_parse_compile() {
+        let opts = parse_args(args(&["compile", "dir/fib.mk"])).unwrap();
+        assert_eq!(
+            opts.command,
+            Command::Compile {
+                input: "dir/fib.mk".to_string(),
+                output: "dir/fib.mkc".to_string()
+            }
+        );
+        let opts = parse_args(args(&["compile", "fib.mk", "-o", "out.mkc"])).unwrap();
+        assert_eq!(
+            opts.command,
+            Command::Compile {
+                input: "fib.mk".to_string(),
+                output: "out.mkc".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_lint() {
+        let opts = parse_args(args(&[
+            "lint",
+            "a.mk",
+            "--lint",
+            "empty-block=allow",
+            "--lint=unused-variable=error",
+        ]))
+        .unwrap();
+        assert_eq!(opts.command, Command::Lint("a.mk".to_string()));
+        assert_eq!(opts.lint.severity(Rule::EmptyBlock), Severity::Allow);
+        assert_eq!(opts.lint.severity(Rule::UnusedVariable), Severity::Error);
+        assert_eq!(
+            opts.lint.severity(Rule::ShadowedVariable),
+            Severity::Warning
+        );
+    }
+
+    #[test]
+    fn test_parse_check() {
+        let opts = parse_args(args(&["check", "a.mk"])).unwrap();
+        assert_eq!(opts.command, Command::Check("a.mk".to_string()));
+        assert!(!opts.allow_unused);
+        let opts = parse_args(args(&["--allow-unused", "a.mk"])).unwrap();
+        assert!(opts.allow_unused);
+    }
+
+    #[test]
+    fn test_parse_test() {
+        let opts = parse_args(args(&["test", "tests/"])).unwrap();
+        assert_eq!(opts.command, Command::Test("tests/".to_string()));
+        let opts = parse_args(args(&["--max-steps=10", "test"])).unwrap();
+        assert_eq!(opts.command, Command::Test(".".to_string()));
+        assert_eq!(opts.sandbox.max_steps, Some(10));
+    }
+
+    #[test]
+    fn test_parse_profile() {
+        let opts = parse_args(args(&["--profile", "fib.mk"])).unwrap();
+        assert!(opts.profile);
+        assert!(!parse_args(args(&["fib.mk"])).unwrap().profile);
+    }
+
+    #[test]
+    fn test_parse_coverage() {
+        let opts = parse_args(args(&["--coverage", "a.mk"])).unwrap();
+        assert!(opts.coverage);
+        let opts = parse_args(args(&["test", "--coverage"])).unwrap();
+        assert!(opts.coverage);
+        assert_eq!(opts.command, Command::Test(".".to_string()));
+    }
+
+    #[test]
+    fn test_parse_eval() {
+        let opts = parse_args(args(&["-e", "let x = 2; x * 21"])).unwrap();
+        assert_eq!(opts.command, Command::Eval("let x = 2; x * 21".to_string()));
+        let opts = parse_args(args(&["--profile", "--eval=1 + 2"])).unwrap();
+        assert_eq!(opts.command, Command::Eval("1 + 2".to_string()));
+        assert!(opts.profile);
+    }
+
+    #[test]
+    fn test_parse_check_flag() {
+        let opts = parse_args(args(&["--check", "a.mk"])).unwrap();
+        assert!(opts.check);
+        assert_eq!(opts.command, Command::Run("a.mk".to_string()));
+        assert!(parse_args(args(&["-e", "1", "--check"])).unwrap().check);
+        assert!(!parse_args(args(&["a.mk"])).unwrap().check);
+    }
+
+    #[test]
+    fn test_parse_fold() {
+        assert!(!parse_args(args(&["a.mk"])).unwrap().fold);
+        assert!(parse_args(args(&["--fold", "a.mk"])).unwrap().fold);
+        assert!(
+            parse_args(args(&["--fold", "--dump-ast", "-e", "1"]))
+                .unwrap()
+                .fold
+        );
+        let opts = parse_args(args(&["--fold", "compile", "a.mk"])).unwrap();
+        assert!(opts.fold);
+    }
+
+    #[test]
+    fn test_parse_engine() {
+        assert_eq!(parse_args(args(&["a.mk"])).unwrap().engine, Engine::Eval);
+        let opts = parse_args(args(&["--engine=vm", "a.mk"])).unwrap();
+        assert_eq!(opts.engine, Engine::Vm);
+        let opts = parse_args(args(&["--engine", "vm"])).unwrap();
+        assert_eq!((opts.engine, opts.command), (Engine::Vm, Command::Repl));
+        let opts = parse_args(args(&["--engine", "eval", "--profile", "a.mk"])).unwrap();
+        assert_eq!(opts.engine, Engine::Eval);
+    }
+
+    #[test]
+    fn test_parse_bench() {
+        let opts = parse_args(args(&["bench", "fib.mk"])).unwrap();
+        let expected = Command::Bench {
+            script: "fib.mk".to_string(),
+            iterations: 10,
+            warmup: 2,
+        };
+        assert_eq!(opts.command, expected);
+        let opts = parse_args(args(&[
+            "--engine=vm",
+            "bench",
+            "--iterations",
+            "3",
+            "fib.mk",
+            "--warmup=0",
+        ]))
+        .unwrap();
+        let expected = Command::Bench {
+            script: "fib.mk".to_string(),
+            iterations: 3,
+            warmup: 0,
+        };
+        assert_eq!((opts.engine, opts.command), (Engine::Vm, expected));
+    }
+
+    #[test]
+    fn test_bench_report() {
+        let times = [3, 1, 2].map(Duration::from_millis);
+        assert_eq!(
+            bench_report("fib.mk", Engine::Vm, 1, &times, 42),
+            "fib.mk on vm: 3 runs after 1 run of warmup\nmin 1.000ms, mean 2.000ms, max 3.000ms, 42 steps"
+        );
+    }
+
+    #[test]
+    fn test_parse_dump() {
+        let opts = parse_args(args(&["--dump-tokens", "a.mk"])).unwrap();
+        assert_eq!(opts.dump, Some(Dump::Tokens));
+        let opts = parse_args(args(&["--dump-ast"])).unwrap();
+        assert_eq!((opts.dump, opts.command), (Some(Dump::Ast), Command::Repl));
+        let opts = parse_args(args(&["--dump-ast=debug", "-e", "1"])).unwrap();
+        assert_eq!(opts.dump, Some(Dump::Ast));
+        let opts = parse_args(args(&["--dump-ast=pretty", "a.mk"])).unwrap();
+        assert_eq!(opts.dump, Some(Dump::PrettyAst));
+        assert_eq!(parse_args(args(&["a.mk"])).unwrap().dump, None);
+    }
+
+    #[test]
+    fn test_parse_debug() {
+        let opts = parse_args(args(&["debug", "a.mk"])).unwrap();
+        assert_eq!(opts.command, Command::Debug("a.mk".to_string()));
+    }
+
+    #[test]
+    fn test_parse_diagnostics() {
+        let opts = parse_args(args(&["--diagnostics=json", "main.mk"])).unwrap();
+        assert_eq!(opts.diagnostics, DiagnosticsFormat::Json);
+        let opts = parse_args(args(&["--diagnostics", "text"])).unwrap();
+        assert_eq!(opts.diagnostics, DiagnosticsFormat::Text);
+        assert!(parse_args(args(&["--diagnostics=xml"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_defaults() {
+        let opts = parse_args(args(&[])).unwrap();
+        assert_eq!(opts.command, Command::Repl);
+        assert!(opts.sandbox.max_steps.is_none());
+        assert_eq!(opts.sandbox.max_call_depth, Some(1024));
+        assert!(opts.sandbox.timeout.is_none());
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let inputs = [
+            vec!["--max-steps"],
+            vec!["--max-steps", "lots"],
+            vec!["--timeout=-1"],
+            vec!["--verbose"],
+            vec!["a.mk", "b.mk"],
+            vec!["compile"],
+            vec!["compile", "a.mk", "b.mk"],
+            vec!["-o", "out.mkc", "a.mk"],
+            vec!["lint"],
+            vec!["check"],
+            vec!["--profile", "check", "a.mk"],
+            vec!["test", "a", "b"],
+            vec!["debug"],
+            vec!["--profile"],
+            vec!["--profile", "fib.mkc"],
+            vec!["--profile", "test"],
+            vec!["--profile=yes", "fib.mk"],
+            vec!["--coverage", "lint", "a.mk"],
+            vec!["--coverage", "--profile", "a.mk"],
+            vec!["--allow-unused=yes", "a.mk"],
+            vec!["-e"],
+            vec!["--dump-tokens=yes", "a.mk"],
+            vec!["--dump-ast=json", "a.mk"],
+            vec!["--dump-tokens", "--dump-ast", "a.mk"],
+            vec!["--dump-ast", "check", "a.mk"],
+            vec!["--dump-ast", "a.mkc"],
+            vec!["--dump-tokens", "--profile", "a.mk"],
+            vec!["--check=yes", "a.mk"],
+            vec!["--check", "--dump-ast", "a.mk"],
+            vec!["--check", "--coverage", "a.mk"],
+            vec!["--check", "lint", "a.mk"],
+            vec!["--check", "a.mkc"],
+            vec!["--fold=yes", "a.mk"],
+            vec!["--fold", "a.mkc"],
+            vec!["--fold", "lint", "a.mk"],
+            vec!["--fold", "bench", "a.mk"],
+            vec!["--engine"],
+            vec!["--engine=jit", "a.mk"],
+            vec!["--engine=vm", "test"],
+            vec!["--engine=vm", "debug", "a.mk"],
+            vec!["--engine=vm", "--profile", "a.mk"],
+            vec!["--engine=vm", "--coverage", "a.mk"],
+            vec!["bench"],
+            vec!["bench", "a.mk", "b.mk"],
+            vec!["bench", "--iterations", "0", "a.mk"],
+            vec!["bench", "--iterations", "lots", "a.mk"],
+            vec!["--warmup", "1", "a.mk"],
+            vec!["--profile", "bench", "a.mk"],
+            vec!["-e", "1", "a.mk"],
+            vec!["--eval", "1", "check", "a.mk"],
+            vec!["--lint", "empty-block"],
+            vec!["--lint", "no-such-rule=error"],
+            vec!["--lint", "empty-block=fatal"],
+        ];
+        for input in inputs {
+            assert!(parse_args(args(&input)).is_err(), "{:?}", input);
+        }
+    }
+}
@@ -0,0 +1,291 @@
+
+use core::fmt;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Instructions(pub Vec<u8>);
+
+#[repr(u8)]
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Opcode {
+    Constant,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Pop,
+    True,
+    False,
+    Equal,
+    NotEqual,
+    GreaterThan,
+    Minus,
+    Bang,
+    GetGlobal,
+    SetGlobal,
+    Call,
+    ReturnValue,
+    Return,
+    GetLocal,
+    Closure,
+    GetFree,
+    JumpNotTruthy,
+    Jump,
+    Null,
+    SetLocal,
+    Array,
+    Index,
+    GetBuiltin,
+    LessThan,
+    Hash,
+    Iter,
+    IterNext,
+    Try,
+    EndTry,
+    Matches,
+    Destructure,
+    CurrentClosure,
+}
+
+pub struct Definition {
+    pub name: &'static str,
+    pub operand_widths: &'static [usize],
+}
+
+impl Opcode {
+    const ALL: &'static [Opcode] = &[
+        Opcode::Constant,
+        Opcode::Add,
+        Opcode::Sub,
+        Opcode::Mul,
+        Opcode::Div,
+        Opcode::Pop,
+        Opcode::True,
+        Opcode::False,
+        Opcode::Equal,
+        Opcode::NotEqual,
+        Opcode::GreaterThan,
+        Opcode::Minus,
+        Opcode::Bang,
+        Opcode::GetGlobal,
+        Opcode::SetGlobal,
+        Opcode::Call,
+        Opcode::ReturnValue,
+        Opcode::Return,
+        Opcode::GetLocal,
+        Opcode::Closure,
+        Opcode::GetFree,
+        Opcode::JumpNotTruthy,
+        Opcode::Jump,
+        Opcode::Null,
+        Opcode::SetLocal,
+        Opcode::Array,
+        Opcode::Index,
+        Opcode::GetBuiltin,
+        Opcode::LessThan,
+        Opcode::Hash,
+        Opcode::Iter,
+        Opcode::IterNext,
+        Opcode::Try,
+        Opcode::EndTry,
+        Opcode::Matches,
+        Opcode::Destructure,
+        Opcode::CurrentClosure,
+    ];
+
+    pub fn from_byte(byte: u8) -> Option<Opcode> {
+        Self::ALL.get(byte as usize).copied()
+    }
+
+    pub fn definition(&self) -> Definition {
+        let (name, operand_widths): (_, &'static [usize]) = match self {
+            Opcode::Constant => ("OpConstant", &[2]),
+            Opcode::Add => ("OpAdd", &[]),
+            Opcode::Sub => ("OpSub", &[]),
+            Opcode::Mul => ("OpMul", &[]),
+            Opcode::Div => ("OpDiv", &[]),
+            Opcode::Pop => ("OpPop", &[]),
+            Opcode::True => ("OpTrue", &[]),
+            Opcode::False => ("OpFalse", &[]),
+            Opcode::Equal => ("OpEqual", &[]),
+            Opcode::NotEqual => ("OpNotEqual", &[]),
+            Opcode::GreaterThan => ("OpGreaterThan", &[]),
+            Opcode::Minus => ("OpMinus", &[]),
+            Opcode::Bang => ("OpBang", &[]),
+            Opcode::GetGlobal => ("OpGetGlobal", &[2]),
+            Opcode::SetGlobal => ("OpSetGlobal", &[2]),
+            Opcode::Call => ("OpCall", &[1]),
+            Opcode::ReturnValue => ("OpReturnValue", &[]),
+            Opcode::Return => ("OpReturn", &[]),
+            Opcode::GetLocal => ("OpGetLocal", &[1]),
+            Opcode::Closure => ("OpClosure", &[2, 1]),
+            Opcode::GetFree => ("OpGetFree", &[1]),
+            Opcode::JumpNotTruthy => ("OpJumpNotTruthy", &[2]),
+            Opcode::Jump => ("OpJump", &[2]),
+            Opcode::Null => ("OpNull", &[]),
+            Opcode::SetLocal => ("OpSetLocal", &[1]),
+            Opcode::Array => ("OpArray", &[2]),
+            Opcode::Index => ("OpIndex", &[]),
+            Opcode::GetBuiltin => ("OpGetBuiltin", &[1]),
+            Opcode::LessThan => ("OpLessThan", &[]),
+            Opcode::Hash => ("OpHash", &[2]),
+            Opcode::Iter => ("OpIter", &[]),
+            Opcode::IterNext => ("OpIterNext", &[2]),
+            Opcode::Try => ("OpTry", &[2]),
+            Opcode::EndTry => ("OpEndTry", &[]),
+            Opcode::Matches => ("OpMatches", &[]),
+            Opcode::Destructure => ("OpDestructure", &[1]),
+            Opcode::CurrentClosure => ("OpCurrentClosure", &[]),
+        };
+        Definition {
+            name,
+            operand_widths,
+        }
+    }
+}
+
+pub fn make(op: Opcode, operands: &[usize]) -> Vec<u8> {
+    let def = op.definition();
+    let len = 1 + def.operand_widths.iter().sum::<usize>();
+    let mut instruction = Vec::with_capacity(len);
+    instruction.push(op as u8);
+    for (operand, width) in operands.iter().zip(def.operand_widths) {
+        match width {
+            2 => instruction.extend_from_slice(&(*operand as u16).to_be_bytes()),
+            1 => instruction.push(*operand as u8),
+            _ => unreachable!("unsupported operand width {}", width),
+        }
+    }
+    instruction
+}
+
+pub fn read_operands(def: &Definition, ins: &[u8]) -> (Vec<usize>, usize) {
+    let mut operands = Vec::with_capacity(def.operand_widths.len());
+    let mut offset = 0;
+    for width in def.operand_widths {
+        match width {
+            2 => operands.push(read_u16(&ins[offset..]) as usize),
+            1 => operands.push(ins[offset] as usize),
+            _ => unreachable!("unsupported operand width {}", width),
+        }
+        offset += width;
+    }
+    (operands, offset)
+}
+
+pub fn read_u16(ins: &[u8]) -> u16 {
+    u16::from_be_bytes([ins[0], ins[1]])
+}
+
+impl Instructions {
+    pub fn extend(&mut self, ins: &[u8]) {
+        self.0.extend_from_slice(ins);
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl From<Vec<Vec<u8>>> for Instructions {
+    fn from(value: Vec<Vec<u8>>) -> Self {
+        Instructions(value.concat())
+    }
+}
+
+impl fmt::Display for Instructions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut i = 0;
+        while i < self.0.len() {
+            let Some(op) = Opcode::from_byte(self.0[i]) else {
+                writeln!(f, "ERROR: undefined opcode {}", self.0[i])?;
+                i += 1;
+                continue;
+            };
+            let def = op.definition();
+            let (operands, read) = read_operands(&def, &self.0[i + 1..]);
+            write!(f, "{:04} {}", i, def.name)?;
+            for operand in operands {
+                write!(f, " {}", operand)?;
+            }
+            writeln!(f)?;
+            i += 1 + read;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{make, read_operands, Instructions, Opcode};
+
+    #[test]
+    fn test_make() {
+        let inputs = [
+            (
+                Opcode::Constant,
+                vec![65534],
+                vec![Opcode::Constant as u8, 255, 254],
+            ),
+            (Opcode::Add, vec![], vec![Opcode::Add as u8]),
+            (
+                Opcode::GetLocal,
+                vec![255],
+                vec![Opcode::GetLocal as u8, 255],
+            ),
+            (
+                Opcode::Closure,
+                vec![65534, 255],
+                vec![Opcode::Closure as u8, 255, 254, 255],
+            ),
+        ];
+        for (op, operands, expected) in inputs {
+            assert_eq!(make(op, &operands), expected);
+        }
+    }
+
+    #[test]
+    fn test_read_operands() {
+        let inputs = [
+            (Opcode::Constant, vec![65535], 2),
+            (Opcode::GetLocal, vec![255], 1),
+            (Opcode::Closure, vec![65535, 255], 3),
+        ];
+        for (op, operands, bytes_read) in inputs {
+            let ins = make(op, &operands);
+            let (read, n) = read_operands(&op.definition(), &ins[1..]);
+            assert_eq!(n, bytes_read);
+            assert_eq!(read, operands);
+        }
+    }
+
+    #[test]
+    fn test_opcode_roundtrip() {
+        for byte in 0..=u8::MAX {
+            if let Some(op) = Opcode::from_byte(byte) {
+                assert_eq!(op as u8, byte);
+            }
+        }
+    }
+
+    #[test]
+    fn test_instructions_display() {
+        let ins = Instructions::from(vec![
+            make(Opcode::Add, &[]),
+            make(Opcode::GetLocal, &[1]),
+            make(Opcode::Constant, &[2]),
+            make(Opcode::Constant, &[65535]),
+            make(Opcode::Closure, &[65535, 255]),
+        ]);
+        let expected = "0000 OpAdd
+0001 OpGetLocal 1
+0003 OpConstant 2
+0006 OpConstant 65535
+0009 OpClosure 65535 255
+";
+        assert_eq!(ins.to_string(), expected);
+    }
+}
@@ -0,0 +1,1284 @@
+
+// the vm runs what the evaluator does, except for:
+// - assigning to a name a function captured from the one around it, a
+//   closure gets a copy of what it captures when it's made, compiling the
+//   assignment fails
+// - a function using one its scope defines after it, like mutually
+//   recursive local functions, it's captured as nil before it's bound
+// - names nothing defines and `break` or `continue` outside of a loop fail
+//   to compile, the evaluator only fails when they're reached
+
+use std::collections::HashMap;
+
+use crate::{
+    ast::{Expr, Program, Stmt},
+    builtins::BUILTINS,
+    code::{make, Instructions, Opcode},
+    object::{CompiledFunction, Object},
+    rc::Rc,
+    symbol_table::{Symbol, SymbolScope, SymbolTable},
+    token::Span,
+};
+
+#[derive(Clone)]
+pub struct Bytecode {
+    pub instructions: Instructions,
+    pub constants: Vec<Object<'static>>,
+    // where the statements of `instructions` start, see
+    // `CompiledFunction::spans`
+    pub spans: Vec<(usize, Span)>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct EmittedInstruction {
+    opcode: Opcode,
+    position: usize,
+}
+
+#[derive(Default)]
+struct CompilationScope {
+    instructions: Instructions,
+    last_instruction: Option<EmittedInstruction>,
+    previous_instruction: Option<EmittedInstruction>,
+    // the loops around what's compiled, innermost last
+    loops: Vec<Loop>,
+    // how many `try` bodies what's compiled is in
+    tries: usize,
+    // the offset each statement starts at with its span
+    spans: Vec<(usize, Span)>,
+}
+
+struct Loop {
+    // where `continue` jumps to
+    start: usize,
+    // the `break` jumps, pointed at the end of the loop once it's known
+    breaks: Vec<usize>,
+    // the `try` bodies around the loop, the ones in it are left on a jump
+    // out of it
+    tries: usize,
+}
+
+// a constant that's reused rather than added again when it's repeated,
+// floats by their bits so `0.0` and `-0.0` stay apart
+#[derive(PartialEq, Eq, Hash)]
+enum Literal {
+    Integer(i64),
+    Float(u64),
+    Str(Rc<str>),
+}
+
+impl Literal {
+    fn of(obj: &Object) -> Option<Literal> {
+        match obj {
+            Object::Integer(value) => Some(Literal::Integer(*value)),
+            Object::Float(value) => Some(Literal::Float(value.to_bits())),
+            Object::Str(value) => Some(Literal::Str(value.clone())),
+            _ => None,
+        }
+    }
+}
+
+pub struct Compiler {
+    constants: Vec<Object<'static>>,
+    // where each literal in `constants` is
+    literals: HashMap<Literal, usize>,
+    symbol_table: SymbolTable,
+    scopes: Vec<CompilationScope>,
+    // the name the function literal being compiled is bound to
+    fn_name: Option<Rc<str>>,
+    // the name it refers to itself by, when that's not a global it can load
+    // once it's called
+    fn_self: Option<Vec<u8>>,
+}
+
+impl Default for Compiler {
+    fn default() -> Self {
+        let mut symbol_table = SymbolTable::new();
+        for (i, builtin) in BUILTINS.iter().enumerate() {
+            symbol_table.define_builtin(i, builtin.name.as_bytes());
+        }
+        Compiler {
+            constants: Vec::new(),
+            literals: HashMap::new(),
+            symbol_table,
+            scopes: vec![CompilationScope::default()],
+            fn_name: None,
+            fn_self: None,
+        }
+    }
+}
+
+impl Compiler {
+    pub fn new() -> Self {
+        Compiler::default()
+    }
+
+    // carries on from the globals and constants of earlier code, like the
+    // repl does from one line to the next, see `into_parts`
+    pub fn with_state(symbol_table: SymbolTable, constants: Vec<Object<'static>>) -> Self {
+        let mut literals = HashMap::new();
+        for (i, constant) in constants.iter().enumerate() {
+            if let Some(literal) = Literal::of(constant) {
+                literals.entry(literal).or_insert(i);
+            }
+        }
+        Compiler {
+            constants,
+            literals,
+            symbol_table,
+            scopes: vec![CompilationScope::default()],
+            fn_name: None,
+            fn_self: None,
+        }
+    }
+
+    pub fn compile(&mut self, prog: &Program) -> Result<(), String> {
+        for stmt in &prog.stmts {
+            self.compile_stmt(stmt)?;
+        }
+        Ok(())
+    }
+
+    pub fn bytecode(self) -> Bytecode {
+        self.into_parts().0
+    }
+
+    // the bytecode with the symbol table saying which globals it defined
+    pub fn into_parts(mut self) -> (Bytecode, SymbolTable) {
+        let scope = self.scopes.pop().unwrap_or_default();
+        let bytecode = Bytecode {
+            instructions: scope.instructions,
+            constants: self.constants,
+            spans: scope.spans,
+        };
+        (bytecode, self.symbol_table)
+    }
+
+    fn compile_stmt(&mut self, stmt: &Stmt) -> Result<(), String> {
+        // a block starts where its first statement does
+        if !matches!(stmt, Stmt::Block { .. }) {
+            let scope = self.scope_mut();
+            let offset = scope.instructions.len();
+            scope.spans.push((offset, stmt.first_token().span));
+        }
+        match stmt {
+            Stmt::Expr { expr } => {
+                self.compile_expr(expr)?;
+                self.emit(Opcode::Pop, &[]);
+            }
+            Stmt::Block { stmts, .. } => {
+                let block = self.symbol_table.enter_block();
+                for stmt in stmts {
+                    self.compile_stmt(stmt)?;
+                }
+                self.symbol_table.leave_block(block);
+            }
+            Stmt::Let { name, value, .. } => {
+                let name = match name {
+                    Expr::Identifier { value, .. } => value,
+                    Expr::ArrayLiteral { elements, .. } => {
+                        return self.compile_destructure(elements, value)
+                    }
+                    _ => return Err(format!("invalid binding target `{}`", name)),
+                };
+                // functions may refer to themselves, anything else sees the
+                // binding only after its value is computed
+                let symbol = if matches!(value, Expr::FnLiteral { .. }) {
+                    let symbol = self.symbol_table.define(name);
+                    self.fn_name = Some(String::from_utf8_lossy(name).into());
+                    if symbol.scope != SymbolScope::Global || self.symbol_table.is_scoped(&symbol) {
+                        self.fn_self = Some(name.to_vec());
+                    }
+                    self.compile_expr(value)?;
+                    symbol
+                } else {
+                    self.compile_expr(value)?;
+                    self.symbol_table.define(name)
+                };
+                self.store_symbol(&symbol);
+            }
+            Stmt::Return { value, .. } => {
+                self.compile_expr(value)?;
+                self.emit(Opcode::ReturnValue, &[]);
+            }
+            // only the evaluator pauses here, the vm debugger breaks on
+            // lines instead
+            Stmt::Debugger { .. } => {}
+            Stmt::For {
+                name,
+                iterable,
+                body,
+                ..
+            } => self.compile_for(name, iterable, body)?,
+            Stmt::Break { .. } | Stmt::Continue { .. } => {
+                let is_break = matches!(stmt, Stmt::Break { .. });
+                let tries = self.scope().tries;
+                let Some(start) = self.scope().loops.last().map(|l| l.start) else {
+                    let keyword = if is_break { "break" } else { "continue" };
+                    return Err(format!("{} outside of a loop", keyword));
+                };
+                let loop_tries = self.scope().loops.last().map_or(0, |l| l.tries);
+                for _ in loop_tries..tries {
+                    self.emit(Opcode::EndTry, &[]);
+                }
+                if is_break {
+                    let jump = self.emit(Opcode::Jump, &[9999]);
+                    if let Some(l) = self.scope_mut().loops.last_mut() {
+                        l.breaks.push(jump);
+                    }
+                } else {
+                    self.emit(Opcode::Jump, &[start]);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // the array and the index of the next element stay on the stack while
+    // the loop runs
+    fn compile_for(&mut self, name: &Expr, iterable: &Expr, body: &Stmt) -> Result<(), String> {
+        let Expr::Identifier { value: name, .. } = name else {
+            return Err("invalid loop variable".to_string());
+        };
+        self.compile_expr(iterable)?;
+        self.emit(Opcode::Iter, &[]);
+        let start = self.scope().instructions.len();
+        let next = self.emit(Opcode::IterNext, &[9999]);
+
+        // the variable is in a block of its own around the body
+        let block = self.symbol_table.enter_block();
+        let symbol = self.symbol_table.define(name);
+        self.store_symbol(&symbol);
+        let tries = self.scope().tries;
+        self.scope_mut().loops.push(Loop {
+            start,
+            breaks: vec![],
+            tries,
+        });
+        self.compile_stmt(body)?;
+        let finished = self.scope_mut().loops.pop().expect("loop was entered");
+        self.symbol_table.leave_block(block);
+        self.emit(Opcode::Jump, &[start]);
+
+        // OpIterNext drops them itself once the array is exhausted
+        let after_body = self.scope().instructions.len();
+        for jump in finished.breaks {
+            self.change_operand(jump, after_body);
+        }
+        self.emit(Opcode::Pop, &[]);
+        self.emit(Opcode::Pop, &[]);
+        let end = self.scope().instructions.len();
+        self.change_operand(next, end);
+        // like a `let`, a loop leaves the program without a value
+        self.emit(Opcode::Null, &[]);
+        self.emit(Opcode::Pop, &[]);
+        Ok(())
+    }
+
+    // OpDestructure pushes the elements last first, so the names are bound
+    // in order and a repeated one ends up with the last of its elements
+    fn compile_destructure(&mut self, names: &[Expr], value: &Expr) -> Result<(), String> {
+        if names.len() > u8::MAX as usize {
+            return Err("too many names to destructure".to_string());
+        }
+        self.compile_expr(value)?;
+        self.emit(Opcode::Destructure, &[names.len()]);
+        for name in names {
+            match name {
+                Expr::Identifier { value, .. } => {
+                    let symbol = self.symbol_table.define(value);
+                    self.store_symbol(&symbol);
+                }
+                _ => {
+                    self.emit(Opcode::Pop, &[]);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn compile_expr(&mut self, expr: &Expr) -> Result<(), String> {
+        match expr {
+            Expr::Identifier { value, .. } => {
+                let symbol = self.symbol_table.resolve(value).ok_or_else(|| {
+                    format!("variable not found: {}", String::from_utf8_lossy(value))
+                })?;
+                self.load_symbol(&symbol);
+            }
+            Expr::IntLiteral { value, .. } => {
+                let idx = self.add_constant(Object::Integer(*value));
+                self.emit(Opcode::Constant, &[idx]);
+            }
+            Expr::FloatLiteral { value, .. } => {
+                let idx = self.add_constant(Object::Float(*value));
+                self.emit(Opcode::Constant, &[idx]);
+            }
+            Expr::BoolLiteral { value, .. } => {
+                self.emit(if *value { Opcode::True } else { Opcode::False }, &[]);
+            }
+            Expr::StringLiteral { value, .. } => {
+                let idx = self.add_constant(Object::Str(value.clone()));
+                self.emit(Opcode::Constant, &[idx]);
+            }
+            Expr::ArrayLiteral { elements, .. } => {
+                for element in elements {
+                    self.compile_expr(element)?;
+                }
+                self.emit(Opcode::Array, &[elements.len()]);
+            }
+            Expr::Index { left, index, .. } => {
+                self.compile_expr(left)?;
+                self.compile_expr(index)?;
+                self.emit(Opcode::Index, &[]);
+            }
+            Expr::Prefix { op, expr, .. } => {
+                self.compile_expr(expr)?;
+                match op.as_bytes() {
+                    b"!" => self.emit(Opcode::Bang, &[]),
+                    b"-" => self.emit(Opcode::Minus, &[]),
+                    _ => return Err(format!("unknown operator {}", String::from_utf8_lossy(op))),
+                };
+            }
+            Expr::Infix {
+                left, op, right, ..
+            } => {
+                self.compile_expr(left)?;
+                self.compile_expr(right)?;
+                let opcode = match op.as_bytes() {
+                    b"+" => Opcode::Add,
+                    b"-" => Opcode::Sub,
+                    b"*" => Opcode::Mul,
+                    b"/" => Opcode::Div,
+                    b">" => Opcode::GreaterThan,
+                    b"<" => Opcode::LessThan,
+                    b"==" => Opcode::Equal,
+                    b"!=" => Opcode::NotEqual,
+                    _ => return Err(format!("unknown operator {}", String::from_utf8_lossy(op))),
+                };
+                self.emit(opcode, &[]);
+            }
+            Expr::FnLiteral {
+                parameters,
+                rest,
+                block,
+                ..
+            } => {
+                let name = self.fn_name.take();
+                let fn_self = self.fn_self.take();
+                self.enter_scope();
+                if let Some(fn_self) = fn_self {
+                    self.symbol_table.define_function_name(&fn_self);
+                }
+                for param in parameters.iter().chain(rest.as_deref()) {
+                    if let Expr::Identifier { value, .. } = param {
+                        self.symbol_table.define(value);
+                    }
+                }
+                self.compile_stmt(block)?;
+                if self.last_instruction_is(Opcode::Pop) {
+                    self.replace_last_pop_with_return();
+                }
+                if !self.last_instruction_is(Opcode::ReturnValue) {
+                    self.emit(Opcode::Return, &[]);
+                }
+
+                let free_symbols = self.symbol_table.free_symbols.clone();
+                let num_locals = self.symbol_table.num_definitions();
+                let (instructions, spans) = self.leave_scope();
+
+                for symbol in &free_symbols {
+                    self.load_symbol(symbol);
+                }
+                let function = CompiledFunction {
+                    instructions,
+                    num_locals,
+                    num_parameters: parameters.len(),
+                    rest: rest.is_some(),
+                    name,
+                    spans,
+                };
+                let idx = self.add_constant(Object::CompiledFunction(Rc::new(function)));
+                self.emit(Opcode::Closure, &[idx, free_symbols.len()]);
+            }
+            Expr::If {
+                condition,
+                consequence,
+                alternative,
+                ..
+            } => {
+                self.compile_expr(condition)?;
+                // operands are placeholders until the jump targets are known
+                let jump_not_truthy = self.emit(Opcode::JumpNotTruthy, &[9999]);
+                self.compile_branch(consequence)?;
+                let jump = self.emit(Opcode::Jump, &[9999]);
+
+                let after_consequence = self.scope().instructions.len();
+                self.change_operand(jump_not_truthy, after_consequence);
+
+                match alternative {
+                    Some(alternative) => self.compile_branch(alternative)?,
+                    None => {
+                        self.emit(Opcode::Null, &[]);
+                    }
+                }
+                let after_alternative = self.scope().instructions.len();
+                self.change_operand(jump, after_alternative);
+            }
+            Expr::Call {
+                function,
+                arguments,
+                ..
+            } => {
+                self.compile_expr(function)?;
+                for arg in arguments {
+                    self.compile_expr(arg)?;
+                }
+                self.emit(Opcode::Call, &[arguments.len()]);
+            }
+            Expr::HashLiteral { pairs, .. } => {
+                for (key, value) in pairs {
+                    self.compile_expr(key)?;
+                    self.compile_expr(value)?;
+                }
+                self.emit(Opcode::Hash, &[pairs.len()]);
+            }
+            Expr::Assign { name, value, .. } => {
+                let Expr::Identifier { value: name, .. } = &**name else {
+                    return Err("invalid assignment target".to_string());
+                };
+                let name_str = String::from_utf8_lossy(name);
+                let symbol = match self.symbol_table.resolve(name) {
+                    Some(symbol)
+                        if matches!(symbol.scope, SymbolScope::Global | SymbolScope::Local) =>
+                    {
+                        symbol
+                    }
+                    Some(Symbol {
+                        scope: SymbolScope::Free | SymbolScope::Function,
+                        ..
+                    }) => {
+                        return Err(format!(
+                            "compiling assignment to `{}` captured from an enclosing function is not supported",
+                            name_str
+                        ))
+                    }
+                    _ => return Err(format!("assignment to undefined variable: {}", name_str)),
+                };
+                self.compile_expr(value)?;
+                self.store_symbol(&symbol);
+                self.load_symbol(&symbol);
+            }
+            Expr::Match { subject, arms, .. } => {
+                // the subject stays on the stack until an arm matches
+                self.compile_expr(subject)?;
+                let mut ends = vec![];
+                let mut exhaustive = false;
+                for arm in arms {
+                    let next = match &arm.pattern {
+                        Some(pattern) => {
+                            self.compile_expr(pattern)?;
+                            self.emit(Opcode::Matches, &[]);
+                            Some(self.emit(Opcode::JumpNotTruthy, &[9999]))
+                        }
+                        None => None,
+                    };
+                    self.emit(Opcode::Pop, &[]);
+                    self.compile_expr(&arm.value)?;
+                    ends.push(self.emit(Opcode::Jump, &[9999]));
+                    let Some(next) = next else {
+                        exhaustive = true;
+                        break;
+                    };
+                    let after_arm = self.scope().instructions.len();
+                    self.change_operand(next, after_arm);
+                }
+                if !exhaustive {
+                    self.emit(Opcode::Pop, &[]);
+                    self.emit(Opcode::Null, &[]);
+                }
+                let end = self.scope().instructions.len();
+                for jump in ends {
+                    self.change_operand(jump, end);
+                }
+            }
+            Expr::Try {
+                body,
+                name,
+                handler,
+                ..
+            } => {
+                let Expr::Identifier { value: name, .. } = &**name else {
+                    return Err("invalid catch variable".to_string());
+                };
+                let start = self.emit(Opcode::Try, &[9999]);
+                self.scope_mut().tries += 1;
+                self.compile_branch(body)?;
+                self.scope_mut().tries -= 1;
+                self.emit(Opcode::EndTry, &[]);
+                let jump = self.emit(Opcode::Jump, &[9999]);
+
+                // the vm pushes the error it caught
+                let handler_start = self.scope().instructions.len();
+                self.change_operand(start, handler_start);
+                let block = self.symbol_table.enter_block();
+                let symbol = self.symbol_table.define(name);
+                self.store_symbol(&symbol);
+                self.compile_branch(handler)?;
+                self.symbol_table.leave_block(block);
+                let end = self.scope().instructions.len();
+                self.change_operand(jump, end);
+            }
+        }
+        Ok(())
+    }
+
+    // compiles a block so that it leaves its value on the stack, `if` is an
+    // expression and each branch has to produce something for the outer OpPop
+    fn compile_branch(&mut self, block: &Stmt) -> Result<(), String> {
+        let start = self.scope().instructions.len();
+        self.compile_stmt(block)?;
+        let emitted = self.scope().instructions.len() > start;
+        if emitted && self.last_instruction_is(Opcode::Pop) {
+            self.remove_last_pop();
+        } else if !(emitted && self.last_instruction_is(Opcode::ReturnValue)) {
+            self.emit(Opcode::Null, &[]);
+        }
+        Ok(())
+    }
+
+    fn load_symbol(&mut self, symbol: &Symbol) {
+        match symbol.scope {
+            SymbolScope::Global => self.emit(Opcode::GetGlobal, &[symbol.index]),
+            SymbolScope::Local => self.emit(Opcode::GetLocal, &[symbol.index]),
+            SymbolScope::Free => self.emit(Opcode::GetFree, &[symbol.index]),
+            SymbolScope::Builtin => self.emit(Opcode::GetBuiltin, &[symbol.index]),
+            SymbolScope::Function => self.emit(Opcode::CurrentClosure, &[]),
+        };
+    }
+
+    fn store_symbol(&mut self, symbol: &Symbol) {
+        match symbol.scope {
+            SymbolScope::Global => self.emit(Opcode::SetGlobal, &[symbol.index]),
+            SymbolScope::Local => self.emit(Opcode::SetLocal, &[symbol.index]),
+            SymbolScope::Free | SymbolScope::Builtin | SymbolScope::Function => {
+                unreachable!("let only defines globals and locals")
+            }
+        };
+    }
+
+    fn add_constant(&mut self, obj: Object<'static>) -> usize {
+        let literal = Literal::of(&obj);
+        if let Some(&idx) = literal
+            .as_ref()
+            .and_then(|literal| self.literals.get(literal))
+        {
+            return idx;
+        }
+        self.constants.push(obj);
+        let idx = self.constants.len() - 1;
+        if let Some(literal) = literal {
+            self.literals.insert(literal, idx);
+        }
+        idx
+    }
+
+    fn scope(&self) -> &CompilationScope {
+        self.scopes.last().expect("compiler has no scope")
+    }
+
+    fn scope_mut(&mut self) -> &mut CompilationScope {
+        self.scopes.last_mut().expect("compiler has no scope")
+    }
+
+    fn enter_scope(&mut self) {
+        self.scopes.push(CompilationScope::default());
+        let outer = std::mem::take(&mut self.symbol_table);
+        self.symbol_table = SymbolTable::new_enclosed(outer);
+    }
+
+    fn leave_scope(&mut self) -> (Instructions, Vec<(usize, Span)>) {
+        let scope = self.scopes.pop().expect("compiler has no scope");
+        if let Some(outer) = self.symbol_table.outer.take() {
+            self.symbol_table = *outer;
+        }
+        (scope.instructions, scope.spans)
+    }
+
+    fn emit(&mut self, op: Opcode, operands: &[usize]) -> usize {
+        let scope = self.scope_mut();
+        let position = scope.instructions.len();
+        scope.instructions.extend(&make(op, operands));
+        scope.previous_instruction = scope.last_instruction;
+        scope.last_instruction = Some(EmittedInstruction {
+            opcode: op,
+            position,
+        });
+        position
+    }
+
+    fn last_instruction_is(&self, op: Opcode) -> bool {
+        self.scope()
+            .last_instruction
+            .is_some_and(|last| last.opcode == op)
+    }
+
+    fn remove_last_pop(&mut self) {
+        let scope = self.scope_mut();
+        if let Some(last) = scope.last_instruction {
+            scope.instructions.0.truncate(last.position);
+            scope.last_instruction = scope.previous_instruction;
+        }
+    }
+
+    fn change_operand(&mut self, position: usize, operand: usize) {
+        let scope = self.scope_mut();
+        let op = Opcode::from_byte(scope.instructions.0[position]).expect("invalid opcode");
+        let ins = make(op, &[operand]);
+        scope.instructions.0[position..position + ins.len()].copy_from_slice(&ins);
+    }
+
+    fn replace_last_pop_with_return(&mut self) {
+        let scope = self.scope_mut();
+        if let Some(last) = scope.last_instruction.as_mut() {
+            let ins = make(Opcode::ReturnValue, &[]);
+            scope.instructions.0[last.position..last.position + ins.len()].copy_from_slice(&ins);
+            last.opcode = Opcode::ReturnValue;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        code::{make, Instructions, Opcode},
+        lexer::Lexer,
+        object::Object,
+        parser::Parser,
+        symbol_table::SymbolTable,
+        token::Span,
+    };
+
+    use super::{Bytecode, Compiler};
+
+    fn compile(input: &str) -> Result<Bytecode, String> {
+        let prog = Parser::new(Lexer::new(input.as_bytes()))
+            .parse()
+            .expect("failed to parse program");
+        let mut compiler = Compiler::new();
+        compiler.compile(&prog)?;
+        Ok(compiler.bytecode())
+    }
+
+    fn assert_bytecode(input: &str, constants: &[i64], instructions: Vec<Vec<u8>>) {
+        let bytecode = compile(input).unwrap();
+        let expected = Instructions::from(instructions);
+        assert_eq!(
+            bytecode.instructions, expected,
+            "wrong instructions for {}\nexpected:\n{}\ngot:\n{}",
+            input, expected, bytecode.instructions
+        );
+        assert_eq!(bytecode.constants.len(), constants.len());
+        for (obj, expected) in bytecode.constants.iter().zip(constants) {
+            match obj {
+                Object::Integer(value) => assert_eq!(value, expected),
+                _ => panic!("constant not an integer, got {:?}", obj),
+            }
+        }
+    }
+
+    #[test]
+    fn test_integer_arithmetic() {
+        let inputs = [
+            ("1 + 2", Opcode::Add),
+            ("1 - 2", Opcode::Sub),
+            ("1 * 2", Opcode::Mul),
+            ("2 / 1", Opcode::Div),
+        ];
+        for (input, op) in inputs {
+            let (a, b) = if op == Opcode::Div { (2, 1) } else { (1, 2) };
+            assert_bytecode(
+                input,
+                &[a, b],
+                vec![
+                    make(Opcode::Constant, &[0]),
+                    make(Opcode::Constant, &[1]),
+                    make(op, &[]),
+                    make(Opcode::Pop, &[]),
+                ],
+            );
+        }
+
+        assert_bytecode(
+            "1; 2",
+            &[1, 2],
+            vec![
+                make(Opcode::Constant, &[0]),
+                make(Opcode::Pop, &[]),
+                make(Opcode::Constant, &[1]),
+                make(Opcode::Pop, &[]),
+            ],
+        );
+        assert_bytecode(
+            "-1",
+            &[1],
+            vec![
+                make(Opcode::Constant, &[0]),
+                make(Opcode::Minus, &[]),
+                make(Opcode::Pop, &[]),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_boolean_expressions() {
+        assert_bytecode(
+            "true",
+            &[],
+            vec![make(Opcode::True, &[]), make(Opcode::Pop, &[])],
+        );
+        assert_bytecode(
+            "false",
+            &[],
+            vec![make(Opcode::False, &[]), make(Opcode::Pop, &[])],
+        );
+
+        let inputs = [
+            ("1 > 2", Opcode::GreaterThan),
+            ("1 == 2", Opcode::Equal),
+            ("1 != 2", Opcode::NotEqual),
+        ];
+        for (input, op) in inputs {
+            assert_bytecode(
+                input,
+                &[1, 2],
+                vec![
+                    make(Opcode::Constant, &[0]),
+                    make(Opcode::Constant, &[1]),
+                    make(op, &[]),
+                    make(Opcode::Pop, &[]),
+                ],
+            );
+        }
+
+        // operands are evaluated left to right for `<` as well
+        assert_bytecode(
+            "1 < 2",
+            &[1, 2],
+            vec![
+                make(Opcode::Constant, &[0]),
+                make(Opcode::Constant, &[1]),
+                make(Opcode::LessThan, &[]),
+                make(Opcode::Pop, &[]),
+            ],
+        );
+        assert_bytecode(
+            "true != false",
+            &[],
+            vec![
+                make(Opcode::True, &[]),
+                make(Opcode::False, &[]),
+                make(Opcode::NotEqual, &[]),
+                make(Opcode::Pop, &[]),
+            ],
+        );
+        assert_bytecode(
+            "!true",
+            &[],
+            vec![
+                make(Opcode::True, &[]),
+                make(Opcode::Bang, &[]),
+                make(Opcode::Pop, &[]),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_global_let_stmts() {
+        assert_bytecode(
+            "let one = 1; let two = 2;",
+            &[1, 2],
+            vec![
+                make(Opcode::Constant, &[0]),
+                make(Opcode::SetGlobal, &[0]),
+                make(Opcode::Constant, &[1]),
+                make(Opcode::SetGlobal, &[1]),
+            ],
+        );
+        assert_bytecode(
+            "let one = 1; one;",
+            &[1],
+            vec![
+                make(Opcode::Constant, &[0]),
+                make(Opcode::SetGlobal, &[0]),
+                make(Opcode::GetGlobal, &[0]),
+                make(Opcode::Pop, &[]),
+            ],
+        );
+        assert_bytecode(
+            "let one = 1; let two = one; two;",
+            &[1],
+            vec![
+                make(Opcode::Constant, &[0]),
+                make(Opcode::SetGlobal, &[0]),
+                make(Opcode::GetGlobal, &[0]),
+                make(Opcode::SetGlobal, &[1]),
+                make(Opcode::GetGlobal, &[1]),
+                make(Opcode::Pop, &[]),
+            ],
+        );
+    }
+
+    fn assert_disassembly(input: &str, expected: &str) {
+        let bytecode = compile(input).unwrap();
+        assert_eq!(
+            bytecode.instructions.to_string(),
+            expected,
+            "wrong instructions for {}",
+            input
+        );
+    }
+
+    #[test]
+    fn test_conditionals() {
+        assert_disassembly(
+            "if (true) { 10 }; 3333;",
+            "0000 OpTrue
+0001 OpJumpNotTruthy 10
+0004 OpConstant 0
+0007 OpJump 11
+0010 OpNull
+0011 OpPop
+0012 OpConstant 1
+0015 OpPop
+",
+        );
+        assert_disassembly(
+            "if (true) { 10 } else { 20 }; 3333;",
+            "0000 OpTrue
+0001 OpJumpNotTruthy 10
+0004 OpConstant 0
+0007 OpJump 13
+0010 OpConstant 1
+0013 OpPop
+0014 OpConstant 2
+0017 OpPop
+",
+        );
+        // empty branches and branches ending in a let still produce a value
+        assert_disassembly(
+            "if (1 > 2) { } else { let a = 1; }",
+            "0000 OpConstant 0
+0003 OpConstant 1
+0006 OpGreaterThan
+0007 OpJumpNotTruthy 14
+0010 OpNull
+0011 OpJump 21
+0014 OpConstant 0
+0017 OpSetGlobal 0
+0020 OpNull
+0021 OpPop
+",
+        );
+    }
+
+    fn assert_fn_constant(obj: &Object, instructions: Vec<Vec<u8>>, num_locals: usize) {
+        match obj {
+            Object::CompiledFunction(function) => {
+                let expected = Instructions::from(instructions);
+                assert_eq!(
+                    function.instructions, expected,
+                    "wrong function instructions\nexpected:\n{}\ngot:\n{}",
+                    expected, function.instructions
+                );
+                assert_eq!(function.num_locals, num_locals);
+            }
+            _ => panic!("constant not a compiled function, got {:?}", obj),
+        }
+    }
+
+    #[test]
+    fn test_functions() {
+        let inputs = ["fn() { return 5 + 10 }", "fn() { 5 + 10 }"];
+        for input in inputs {
+            let bytecode = compile(input).unwrap();
+            assert_eq!(
+                bytecode.instructions,
+                Instructions::from(vec![make(Opcode::Closure, &[2, 0]), make(Opcode::Pop, &[]),])
+            );
+            assert_fn_constant(
+                &bytecode.constants[2],
+                vec![
+                    make(Opcode::Constant, &[0]),
+                    make(Opcode::Constant, &[1]),
+                    make(Opcode::Add, &[]),
+                    make(Opcode::ReturnValue, &[]),
+                ],
+                0,
+            );
+        }
+
+        let bytecode = compile("fn() { }").unwrap();
+        assert_fn_constant(&bytecode.constants[0], vec![make(Opcode::Return, &[])], 0);
+    }
+
+    #[test]
+    fn test_function_calls() {
+        let bytecode = compile("let oneArg = fn(a) { a }; oneArg(24);").unwrap();
+        assert_eq!(
+            bytecode.instructions,
+            Instructions::from(vec![
+                make(Opcode::Closure, &[0, 0]),
+                make(Opcode::SetGlobal, &[0]),
+                make(Opcode::GetGlobal, &[0]),
+                make(Opcode::Constant, &[1]),
+                make(Opcode::Call, &[1]),
+                make(Opcode::Pop, &[]),
+            ])
+        );
+        assert_fn_constant(
+            &bytecode.constants[0],
+            vec![make(Opcode::GetLocal, &[0]), make(Opcode::ReturnValue, &[])],
+            1,
+        );
+
+        let bytecode =
+            compile("let manyArg = fn(a, b, c) { a; b; c }; manyArg(24, 25, 26);").unwrap();
+        assert_fn_constant(
+            &bytecode.constants[0],
+            vec![
+                make(Opcode::GetLocal, &[0]),
+                make(Opcode::Pop, &[]),
+                make(Opcode::GetLocal, &[1]),
+                make(Opcode::Pop, &[]),
+                make(Opcode::GetLocal, &[2]),
+                make(Opcode::ReturnValue, &[]),
+            ],
+            3,
+        );
+    }
+
+    #[test]
+    fn test_let_stmt_scopes() {
+        let bytecode = compile("let num = 55; fn() { num }").unwrap();
+        assert_fn_constant(
+            &bytecode.constants[1],
+            vec![
+                make(Opcode::GetGlobal, &[0]),
+                make(Opcode::ReturnValue, &[]),
+            ],
+            0,
+        );
+
+        let bytecode = compile("fn() { let num = 55; num }").unwrap();
+        assert_fn_constant(
+            &bytecode.constants[1],
+            vec![
+                make(Opcode::Constant, &[0]),
+                make(Opcode::SetLocal, &[0]),
+                make(Opcode::GetLocal, &[0]),
+                make(Opcode::ReturnValue, &[]),
+            ],
+            1,
+        );
+
+        let bytecode = compile("fn(a) { let b = 77; let c = a + b; c }").unwrap();
+        assert_fn_constant(
+            &bytecode.constants[1],
+            vec![
+                make(Opcode::Constant, &[0]),
+                make(Opcode::SetLocal, &[1]),
+                make(Opcode::GetLocal, &[0]),
+                make(Opcode::GetLocal, &[1]),
+                make(Opcode::Add, &[]),
+                make(Opcode::SetLocal, &[2]),
+                make(Opcode::GetLocal, &[2]),
+                make(Opcode::ReturnValue, &[]),
+            ],
+            3,
+        );
+    }
+
+    #[test]
+    fn test_closures() {
+        let bytecode = compile("fn(a) { fn(b) { a + b } }").unwrap();
+        assert_fn_constant(
+            &bytecode.constants[0],
+            vec![
+                make(Opcode::GetFree, &[0]),
+                make(Opcode::GetLocal, &[0]),
+                make(Opcode::Add, &[]),
+                make(Opcode::ReturnValue, &[]),
+            ],
+            1,
+        );
+        assert_fn_constant(
+            &bytecode.constants[1],
+            vec![
+                make(Opcode::GetLocal, &[0]),
+                make(Opcode::Closure, &[0, 1]),
+                make(Opcode::ReturnValue, &[]),
+            ],
+            1,
+        );
+        assert_eq!(
+            bytecode.instructions,
+            Instructions::from(vec![make(Opcode::Closure, &[1, 0]), make(Opcode::Pop, &[])])
+        );
+
+        let bytecode = compile("fn(a) { fn(b) { fn(c) { a + b + c } } };").unwrap();
+        assert_fn_constant(
+            &bytecode.constants[0],
+            vec![
+                make(Opcode::GetFree, &[0]),
+                make(Opcode::GetFree, &[1]),
+                make(Opcode::Add, &[]),
+                make(Opcode::GetLocal, &[0]),
+                make(Opcode::Add, &[]),
+                make(Opcode::ReturnValue, &[]),
+            ],
+            1,
+        );
+        assert_fn_constant(
+            &bytecode.constants[1],
+            vec![
+                make(Opcode::GetFree, &[0]),
+                make(Opcode::GetLocal, &[0]),
+                make(Opcode::Closure, &[0, 2]),
+                make(Opcode::ReturnValue, &[]),
+            ],
+            1,
+        );
+    }
+
+    #[test]
+    fn test_recursive_global_fn() {
+        let bytecode =
+            compile("let countDown = fn(x) { countDown(x - 1); }; countDown(1);").unwrap();
+        assert_fn_constant(
+            &bytecode.constants[1],
+            vec![
+                make(Opcode::GetGlobal, &[0]),
+                make(Opcode::GetLocal, &[0]),
+                make(Opcode::Constant, &[0]),
+                make(Opcode::Sub, &[]),
+                make(Opcode::Call, &[1]),
+                make(Opcode::ReturnValue, &[]),
+            ],
+            1,
+        );
+    }
+
+    #[test]
+    fn test_recursive_local_fn() {
+        let bytecode = compile("fn() { let f = fn(x) { f(x) }; f }").unwrap();
+        assert_fn_constant(
+            &bytecode.constants[0],
+            vec![
+                make(Opcode::CurrentClosure, &[]),
+                make(Opcode::GetLocal, &[0]),
+                make(Opcode::Call, &[1]),
+                make(Opcode::ReturnValue, &[]),
+            ],
+            1,
+        );
+        assert_fn_constant(
+            &bytecode.constants[1],
+            vec![
+                make(Opcode::Closure, &[0, 0]),
+                make(Opcode::SetLocal, &[0]),
+                make(Opcode::GetLocal, &[0]),
+                make(Opcode::ReturnValue, &[]),
+            ],
+            1,
+        );
+    }
+
+    #[test]
+    fn test_hash_literals() {
+        assert_bytecode(
+            "{1: 2, 3: 4 + 5}",
+            &[1, 2, 3, 4, 5],
+            vec![
+                make(Opcode::Constant, &[0]),
+                make(Opcode::Constant, &[1]),
+                make(Opcode::Constant, &[2]),
+                make(Opcode::Constant, &[3]),
+                make(Opcode::Constant, &[4]),
+                make(Opcode::Add, &[]),
+                make(Opcode::Hash, &[2]),
+                make(Opcode::Pop, &[]),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_loops() {
+        assert_bytecode(
+            "for (x in []) { if (x) { break; } continue; }",
+            &[],
+            vec![
+                make(Opcode::Array, &[0]),
+                make(Opcode::Iter, &[]),
+                // 0004
+                make(Opcode::IterNext, &[33]),
+                make(Opcode::SetGlobal, &[0]),
+                make(Opcode::GetGlobal, &[0]),
+                make(Opcode::JumpNotTruthy, &[23]),
+                make(Opcode::Jump, &[31]),
+                // 0019
+                make(Opcode::Null, &[]),
+                make(Opcode::Jump, &[24]),
+                // 0023
+                make(Opcode::Null, &[]),
+                make(Opcode::Pop, &[]),
+                make(Opcode::Jump, &[4]),
+                make(Opcode::Jump, &[4]),
+                // 0031
+                make(Opcode::Pop, &[]),
+                make(Opcode::Pop, &[]),
+                // 0033
+                make(Opcode::Null, &[]),
+                make(Opcode::Pop, &[]),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_try() {
+        assert_bytecode(
+            "try { 1 } catch (e) { e }",
+            &[1],
+            vec![
+                make(Opcode::Try, &[10]),
+                make(Opcode::Constant, &[0]),
+                make(Opcode::EndTry, &[]),
+                make(Opcode::Jump, &[16]),
+                // 0010
+                make(Opcode::SetGlobal, &[0]),
+                make(Opcode::GetGlobal, &[0]),
+                // 0016
+                make(Opcode::Pop, &[]),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_array_literals() {
+        assert_bytecode(
+            "[]",
+            &[],
+            vec![make(Opcode::Array, &[0]), make(Opcode::Pop, &[])],
+        );
+        assert_bytecode(
+            "[1 + 2, 3]",
+            &[1, 2, 3],
+            vec![
+                make(Opcode::Constant, &[0]),
+                make(Opcode::Constant, &[1]),
+                make(Opcode::Add, &[]),
+                make(Opcode::Constant, &[2]),
+                make(Opcode::Array, &[2]),
+                make(Opcode::Pop, &[]),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_index_expressions() {
+        assert_bytecode(
+            "[1, 2][1 - 1]",
+            &[1, 2],
+            vec![
+                make(Opcode::Constant, &[0]),
+                make(Opcode::Constant, &[1]),
+                make(Opcode::Array, &[2]),
+                make(Opcode::Constant, &[0]),
+                make(Opcode::Constant, &[0]),
+                make(Opcode::Sub, &[]),
+                make(Opcode::Index, &[]),
+                make(Opcode::Pop, &[]),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_builtins() {
+        assert_bytecode(
+            "len([]); push([], 1);",
+            &[1],
+            vec![
+                make(Opcode::GetBuiltin, &[0]),
+                make(Opcode::Array, &[0]),
+                make(Opcode::Call, &[1]),
+                make(Opcode::Pop, &[]),
+                make(Opcode::GetBuiltin, &[5]),
+                make(Opcode::Array, &[0]),
+                make(Opcode::Constant, &[0]),
+                make(Opcode::Call, &[2]),
+                make(Opcode::Pop, &[]),
+            ],
+        );
+
+        let bytecode = compile("fn() { len([]) }").unwrap();
+        assert_fn_constant(
+            &bytecode.constants[0],
+            vec![
+                make(Opcode::GetBuiltin, &[0]),
+                make(Opcode::Array, &[0]),
+                make(Opcode::Call, &[1]),
+                make(Opcode::ReturnValue, &[]),
+            ],
+            0,
+        );
+
+        // a global binding shadows the builtin of the same name
+        assert_bytecode(
+            "let len = 1; len",
+            &[1],
+            vec![
+                make(Opcode::Constant, &[0]),
+                make(Opcode::SetGlobal, &[0]),
+                make(Opcode::GetGlobal, &[0]),
+                make(Opcode::Pop, &[]),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_undefined_variable() {
+        let err = compile("let a = 1; a + b;").err().unwrap();
+        assert_eq!(err, "variable not found: b");
+        assert!(compile("let a = a;").is_err());
+    }
+
+    #[test]
+    fn test_constant_deduplication() {
+        let bytecode = compile(r#"[1, 2, 1, "a", "a", 1.5, 1.5, 0.0, 0.0, 2]"#).unwrap();
+        let constants: Vec<_> = bytecode.constants.iter().map(|c| c.to_string()).collect();
+        assert_eq!(constants, ["1", "2", "a", "1.5", "0.0"]);
+
+        // a compiler carrying on from another reuses its constants
+        let prog = Parser::new(Lexer::new("2; 3".as_bytes())).parse().unwrap();
+        let mut compiler = Compiler::with_state(SymbolTable::new(), bytecode.constants);
+        compiler.compile(&prog).unwrap();
+        let constants = compiler.bytecode().constants;
+        assert_eq!(constants.len(), 6);
+        assert_eq!(constants[5].to_string(), "3");
+    }
+
+    #[test]
+    fn test_spans() {
+        let bytecode = compile("let a = 1;\nlet f = fn() {\n  a;\n  a + 1\n};\nf()").unwrap();
+        let lines = |spans: &[(usize, Span)]| {
+            spans
+                .iter()
+                .map(|(offset, span)| (*offset, span.line, span.column))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(lines(&bytecode.spans), [(0, 1, 1), (6, 2, 1), (13, 6, 1)]);
+        let Object::CompiledFunction(function) = &bytecode.constants[1] else {
+            panic!("not a function: {}", bytecode.constants[1]);
+        };
+        assert_eq!(lines(&function.spans), [(0, 3, 3), (4, 4, 3)]);
+    }
+}
@@ -0,0 +1,191 @@
+use std::{cell::RefCell, collections::BTreeSet, fmt::Write};
+
+use crate::{
+    ast::{self, Stmt},
+    env::Env,
+    evaluator::Tracer,
+    lexer::Lexer,
+    parser::Parser,
+    source::SourceMap,
+    token::Token,
+};
+
+// which lines of a script ran, a line is executable when a statement other
+// than a block starts on it
+//
+// statements are placed by the span of their first token, tokens made up
+// rather than read from a source have no line and aren't recorded
+pub struct Coverage {
+    map: SourceMap,
+    executable: BTreeSet<usize>,
+    // a shared reference is the tracer, so one coverage can follow several
+    // evaluators, like the ones running each test
+    executed: RefCell<BTreeSet<usize>>,
+}
+
+impl Coverage {
+    // a script that doesn't parse has no executable lines
+    pub fn new(source: &str) -> Self {
+        let mut coverage = Coverage {
+            map: SourceMap::new(source),
+            executable: BTreeSet::new(),
+            executed: RefCell::default(),
+        };
+        if let Ok(prog) = Parser::new(Lexer::new(source.as_bytes())).parse() {
+            let mut executable = BTreeSet::new();
+            ast::visit_stmts(&prog.stmts, &mut |stmt| {
+                if !matches!(stmt, Stmt::Block { .. }) {
+                    executable.extend(coverage.line(stmt.first_token()));
+                }
+            });
+            coverage.executable = executable;
+        }
+        coverage
+    }
+
+    fn line(&self, token: &Token) -> Option<usize> {
+        let line = token.span.line;
+        (line > 0 && line <= self.map.line_count()).then_some(line)
+    }
+
+    pub fn executable(&self) -> &BTreeSet<usize> {
+        &self.executable
+    }
+
+    pub fn executed(&self) -> BTreeSet<usize> {
+        self.executed.borrow().clone()
+    }
+
+    pub fn missed(&self) -> BTreeSet<usize> {
+        &self.executable - &self.executed.borrow()
+    }
+
+    pub fn summary(&self, file: &str) -> String {
+        let total = self.executable.len();
+        let covered = total - self.missed().len();
+        match total {
+            0 => format!("{}: no executable lines", file),
+            _ => format!(
+                "{}: {} of {} lines covered ({:.1}%)",
+                file,
+                covered,
+                total,
+                covered as f64 * 100.0 / total as f64
+            ),
+        }
+    }
+
+    pub fn report(&self, file: &str) -> String {
+        format!("{}\n{}", self.summary(file), self.annotated())
+    }
+
+    // the source with `+` before the lines that ran and `!` before the ones
+    // that didn't
+    pub fn annotated(&self) -> String {
+        let missed = self.missed();
+        let mut out = String::new();
+        for line in 1..=self.map.line_count() {
+            let marker = if missed.contains(&line) {
+                '!'
+            } else if self.executable.contains(&line) {
+                '+'
+            } else {
+                ' '
+            };
+            let text = self.map.line(line).unwrap_or_default();
+            let annotated = format!("{:>5} {} {}", line, marker, text);
+            let _ = writeln!(out, "{}", annotated.trim_end());
+        }
+        out
+    }
+}
+
+impl<'a> Tracer<'a> for &Coverage {
+    fn statement(&mut self, stmt: &Stmt, _: &mut Env<'a>, _: usize) -> Result<(), &'a str> {
+        if !matches!(stmt, Stmt::Block { .. }) {
+            if let Some(line) = self.line(stmt.first_token()) {
+                self.executed.borrow_mut().insert(line);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use crate::{
+        env::Env, evaluator::Evaluator, lexer::Lexer, parser::Parser, sandbox::SandboxConfig,
+    };
+
+    use super::Coverage;
+
+    fn lines(lines: &[usize]) -> BTreeSet<usize> {
+        lines.iter().copied().collect()
+    }
+
+    #[test]
+    fn test_coverage() {
+        let source = "let abs = fn(n) {
+    if (n < 0) {
+        -n
+    } else {
+        n
+    }
+};
+let unused = fn() {
+    1
+};
+abs(5)
+";
+        let coverage = Coverage::new(source);
+        let prog = Parser::new(Lexer::new(source.as_bytes())).parse().unwrap();
+        let result = Evaluator::new(SandboxConfig::default())
+            .with_tracer(&coverage)
+            .eval_program(&prog, &mut Env::new());
+        assert_eq!(result.unwrap().to_string(), "5");
+
+        assert_eq!(coverage.executable(), &lines(&[1, 2, 3, 5, 8, 9, 11]));
+        assert_eq!(coverage.executed(), lines(&[1, 2, 5, 8, 11]));
+        assert_eq!(coverage.missed(), lines(&[3, 9]));
+        assert_eq!(
+            coverage.summary("abs.mk"),
+            "abs.mk: 5 of 7 lines covered (71.4%)"
+        );
+        let annotated = coverage.annotated();
+        assert!(
+            annotated.starts_with("    1 + let abs = fn(n) {\n"),
+            "{}",
+            annotated
+        );
+        assert!(annotated.contains("    3 !         -n\n"), "{}", annotated);
+        assert!(
+            annotated.contains("    4       } else {\n"),
+            "{}",
+            annotated
+        );
+        assert!(annotated.contains("    9 !     1\n"), "{}", annotated);
+        assert!(
+            annotated.ends_with("   11 + abs(5)\n   12\n"),
+            "{}",
+            annotated
+        );
+    }
+
+    #[test]
+    fn test_other_sources() {
+        let coverage = Coverage::new("let = 1;");
+        assert!(coverage.executable().is_empty());
+        assert_eq!(coverage.summary("bad.mk"), "bad.mk: no executable lines");
+
+        // lines past the end of the covered script aren't in it
+        let coverage = Coverage::new("1");
+        let prog = Parser::new(Lexer::new(b"\n\n2")).parse().unwrap();
+        Evaluator::new(SandboxConfig::default())
+            .with_tracer(&coverage)
+            .eval_program(&prog, &mut Env::new())
+            .unwrap();
+        assert!(coverage.executed().is_empty());
+    }
+}
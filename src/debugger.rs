@@ -0,0 +1,463 @@
+use std::{
+    collections::HashSet,
+    io::{self, BufRead, Write},
+};
+
+use crate::{
+    ast::{Expr, Program, Stmt},
+    env::Env,
+    evaluator::{Evaluator, Tracer},
+    lexer::Lexer,
+    object::Object,
+    parser::Parser,
+    sandbox::SandboxConfig,
+    vm::Vm,
+};
+
+const HELP: &str = "commands:
+    s, step [n]          execute the next n instructions, 1 by default
+    c, continue          run until a breakpoint or the end of the program
+    b, break <line>      stop before the statements on <line>
+    d, delete <line>     remove the breakpoint on <line>
+    l, list              disassembly around the next instruction
+    stack                the operand stack
+    locals               locals of the current frame
+    q, quit              stop debugging";
+
+const SOURCE_HELP: &str = "commands:
+    s, step              run to the next statement, entering calls
+    n, next              run to the next statement of the current call
+    c, continue          run until the next `debugger;` statement
+    p, print <name>      the value bound to <name>
+    env                  every binding in scope
+    set <name> = <expr>  rebind <name> to the value of <expr>
+    q, quit              stop the program";
+
+// breakpoints are lines, the vm stops where a statement on one starts in
+// whichever function it's in, from the compiler's spans
+fn statement_line(vm: &Vm, offset: usize) -> Option<usize> {
+    let spans = &vm.function().spans;
+    spans
+        .iter()
+        .find(|(start, _)| *start == offset)
+        .map(|(_, span)| span.line)
+}
+
+// drives `vm` with commands read from `input` until the program ends or the
+// user quits
+pub fn debug<R: BufRead, W: Write>(vm: &mut Vm, input: &mut R, out: &mut W) -> io::Result<()> {
+    let mut breakpoints = HashSet::new();
+    writeln!(out, "debugging, `help` lists the commands")?;
+    list(vm, &breakpoints, out)?;
+
+    loop {
+        write!(out, "(debug) ")?;
+        out.flush()?;
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(());
+        }
+        let mut words = line.split_whitespace();
+        let command = words.next().unwrap_or("");
+        let arg = words.next().map(str::parse::<usize>);
+
+        let outcome = match (command, arg) {
+            ("s" | "step", None) => advance(vm, Some(1), &breakpoints),
+            ("s" | "step", Some(Ok(steps))) => advance(vm, Some(steps), &breakpoints),
+            ("c" | "continue", None) => advance(vm, None, &breakpoints),
+            ("b" | "break", Some(Ok(line))) => {
+                breakpoints.insert(line);
+                list(vm, &breakpoints, out)?;
+                continue;
+            }
+            ("d" | "delete", Some(Ok(line))) => {
+                breakpoints.remove(&line);
+                list(vm, &breakpoints, out)?;
+                continue;
+            }
+            ("l" | "list", None) => {
+                list(vm, &breakpoints, out)?;
+                continue;
+            }
+            ("stack", None) => {
+                writeln!(out, "{}", objects(vm.stack()))?;
+                continue;
+            }
+            ("locals", None) => {
+                writeln!(out, "{}", objects(vm.locals()))?;
+                continue;
+            }
+            ("q" | "quit", None) => return Ok(()),
+            ("help", None) => {
+                writeln!(out, "{}", HELP)?;
+                continue;
+            }
+            ("", None) => continue,
+            _ => {
+                writeln!(out, "invalid command '{}', try `help`", line.trim())?;
+                continue;
+            }
+        };
+
+        match outcome {
+            Ok(Some(result)) => {
+                writeln!(out, "=> {}", result)?;
+                return Ok(());
+            }
+            Ok(None) => {
+                list(vm, &breakpoints, out)?;
+                writeln!(out, "stack: {}", objects(vm.stack()))?;
+            }
+            Err(err) => {
+                writeln!(out, "error: {}", err)?;
+                return Ok(());
+            }
+        }
+    }
+}
+
+// executes `steps` instructions, or up to the next breakpoint when None,
+// returns the result of the program if it ended on the way
+fn advance(
+    vm: &mut Vm,
+    steps: Option<usize>,
+    breakpoints: &HashSet<usize>,
+) -> Result<Option<Object<'static>>, String> {
+    let mut taken = 0;
+    loop {
+        if let Some(result) = vm.step()? {
+            return Ok(Some(result));
+        }
+        taken += 1;
+        match steps {
+            Some(steps) if taken >= steps => return Ok(None),
+            None if statement_line(vm, vm.ip()).is_some_and(|line| breakpoints.contains(&line)) => {
+                return Ok(None)
+            }
+            _ => {}
+        }
+    }
+}
+
+// a few instructions of the current function around the instruction
+// pointer, `->` marks the next one and `*` breakpoints
+fn list<W: Write>(vm: &Vm, breakpoints: &HashSet<usize>, out: &mut W) -> io::Result<()> {
+    let listing = vm.function().instructions.to_string();
+    let lines: Vec<(usize, &str)> = listing
+        .lines()
+        .filter_map(|line| Some((line.split_whitespace().next()?.parse().ok()?, line)))
+        .collect();
+    let current = lines
+        .iter()
+        .position(|(offset, _)| *offset >= vm.ip())
+        .unwrap_or(lines.len());
+
+    writeln!(out, "frame {}, ip {}", vm.depth(), vm.ip())?;
+    for (offset, line) in &lines[current.saturating_sub(2)..lines.len().min(current + 3)] {
+        let marker = if *offset == vm.ip() {
+            "->"
+        } else if statement_line(vm, *offset).is_some_and(|line| breakpoints.contains(&line)) {
+            " *"
+        } else {
+            "  "
+        };
+        writeln!(out, "{} {}", marker, line)?;
+    }
+    if current == lines.len() {
+        writeln!(out, "-> end of function")?;
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Mode {
+    Step,
+    // stop at the next statement at most this many calls deep
+    Next(usize),
+    Continue,
+}
+
+// pauses the evaluator before the first statement and at `debugger;`
+// statements, then reads commands from `input` until one resumes the program
+pub struct SourceDebugger<R, W> {
+    input: R,
+    out: W,
+    mode: Mode,
+}
+
+impl<R: BufRead, W: Write> SourceDebugger<R, W> {
+    pub fn new(input: R, out: W) -> Self {
+        SourceDebugger {
+            input,
+            out,
+            mode: Mode::Step,
+        }
+    }
+
+    fn should_pause(&self, stmt: &Stmt, depth: usize) -> bool {
+        match (stmt, self.mode) {
+            (Stmt::Debugger { .. }, _) => true,
+            // the statements of a block are shown one by one instead
+            (Stmt::Block { .. }, _) => false,
+            (_, Mode::Step) => true,
+            (_, Mode::Next(max)) => depth <= max,
+            (_, Mode::Continue) => false,
+        }
+    }
+
+    // false when the program should stop
+    fn pause<'a>(&mut self, stmt: &Stmt, env: &mut Env<'a>, depth: usize) -> io::Result<bool> {
+        writeln!(self.out, "-> {}", stmt)?;
+        loop {
+            write!(self.out, "(debug) ")?;
+            self.out.flush()?;
+            let mut line = String::new();
+            if self.input.read_line(&mut line)? == 0 {
+                return Ok(false);
+            }
+            let line = line.trim();
+            let (command, arg) = line
+                .split_once(char::is_whitespace)
+                .map_or((line, ""), |(command, arg)| (command, arg.trim()));
+
+            match (command, arg) {
+                ("s" | "step", "") => self.mode = Mode::Step,
+                ("n" | "next", "") => self.mode = Mode::Next(depth),
+                ("c" | "continue", "") => self.mode = Mode::Continue,
+                ("p" | "print", name) if !name.is_empty() => {
+                    match env.get(name.as_bytes()) {
+                        Some(value) => writeln!(self.out, "{}", value)?,
+                        None => writeln!(self.out, "`{}` is not bound", name)?,
+                    }
+                    continue;
+                }
+                ("env", "") => {
+                    for (name, value) in env.bindings() {
+                        writeln!(self.out, "{} = {}", String::from_utf8_lossy(&name), value)?;
+                    }
+                    continue;
+                }
+                ("set", assignment) if !assignment.is_empty() => {
+                    if let Err(err) = assign(assignment, env) {
+                        writeln!(self.out, "error: {}", err)?;
+                    }
+                    continue;
+                }
+                ("q" | "quit", "") => return Ok(false),
+                ("help", "") => {
+                    writeln!(self.out, "{}", SOURCE_HELP)?;
+                    continue;
+                }
+                ("", "") => continue,
+                _ => {
+                    writeln!(self.out, "invalid command '{}', try `help`", line)?;
+                    continue;
+                }
+            }
+            return Ok(true);
+        }
+    }
+}
+
+impl<'a, R: BufRead, W: Write> Tracer<'a> for SourceDebugger<R, W> {
+    fn statement(&mut self, stmt: &Stmt, env: &mut Env<'a>, depth: usize) -> Result<(), &'a str> {
+        if !self.should_pause(stmt, depth) {
+            return Ok(());
+        }
+        match self.pause(stmt, env, depth) {
+            Ok(true) => Ok(()),
+            Ok(false) | Err(_) => Err("program stopped by the debugger"),
+        }
+    }
+}
+
+// evaluates `<expr>` of `<name> = <expr>` in `env` and rebinds the binding
+// of `<name>` the program reads, whichever scope around `env` has it, a name
+// nothing binds yet is bound in `env`
+fn assign(assignment: &str, env: &mut Env) -> Result<(), String> {
+    // read as a `let` for its errors, which name what's missing
+    let source = format!("let {};", assignment);
+    let mut prog = Parser::new(Lexer::new(source.as_bytes()))
+        .parse()
+        .map_err(|errors| {
+            let messages: Vec<_> = errors.iter().map(|err| err.to_string()).collect();
+            messages.join(", ")
+        })?;
+    let (name, value) = match prog.stmts.pop() {
+        Some(Stmt::Let {
+            name: Expr::Identifier { symbol, .. },
+            value,
+            ..
+        }) if prog.stmts.is_empty() => (symbol, value),
+        _ => return Err("expected `set <name> = <expr>`".to_string()),
+    };
+    let prog = Program {
+        stmts: vec![Stmt::Expr { expr: value }],
+    };
+    let value = Evaluator::new(SandboxConfig::default())
+        .eval_program(&prog, env)
+        .map_err(|err| err.to_string())?;
+    if !env.assign(name.clone(), value.clone()) {
+        env.set(name, value);
+    }
+    Ok(())
+}
+
+fn objects(objects: &[Object]) -> String {
+    format!(
+        "[{}]",
+        objects
+            .iter()
+            .map(|obj| obj.to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        compiler::Compiler, env::Env, evaluator::Evaluator, lexer::Lexer, parser::Parser,
+        sandbox::SandboxConfig, vm::Vm,
+    };
+
+    use super::{debug, SourceDebugger};
+
+    fn session(source: &str, commands: &str) -> String {
+        let prog = Parser::new(Lexer::new(source.as_bytes())).parse().unwrap();
+        let mut compiler = Compiler::new();
+        compiler.compile(&prog).unwrap();
+        let mut vm = Vm::new(compiler.bytecode());
+        let mut out = Vec::new();
+        debug(&mut vm, &mut commands.as_bytes(), &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_step() {
+        let out = session("1 + 2", "s\ns\nstack\ns\nc\n");
+        assert!(out.contains("-> 0000 OpConstant 0"), "{}", out);
+        assert!(out.contains("-> 0003 OpConstant 1"), "{}", out);
+        assert!(out.contains("-> 0007 OpPop\n"), "{}", out);
+        assert!(out.contains("(debug) [1, 2]\n"), "{}", out);
+        assert!(out.contains("stack: [3]\n"), "{}", out);
+        assert!(out.ends_with("=> 3\n"), "{}", out);
+    }
+
+    #[test]
+    fn test_breakpoints_and_locals() {
+        let source = "let add = fn(a, b) {\n  let c = a;\n  c + b\n};\nadd(1, 2) + add(3, 4)";
+        // stop on both lines of the first call, then only on the last line
+        // of the second
+        let commands = "b 2\nb 3\nc\nc\nlocals\nd 2\nc\nlocals\nd 3\nc\n";
+        let out = session(source, commands);
+        assert!(out.contains("frame 2, ip 0"), "{}", out);
+        assert!(out.contains(" * 0004 OpGetLocal 2"), "{}", out);
+        assert!(out.contains("-> 0004 OpGetLocal 2"), "{}", out);
+        assert!(out.contains("[1, 2, 1]\n"), "{}", out);
+        assert!(out.contains("[3, 4, 3]\n"), "{}", out);
+        assert!(!out.contains("stack: [3, <closure/2>, 3, 4]\n"), "{}", out);
+        assert!(out.ends_with("=> 10\n"), "{}", out);
+    }
+
+    #[test]
+    fn test_errors_and_quit() {
+        let out = session("-true", "bogus\nc\n");
+        assert!(out.contains("invalid command 'bogus'"), "{}", out);
+        assert!(
+            out.ends_with("error: operator '-' only applies to numbers\n"),
+            "{}",
+            out
+        );
+
+        let out = session("1", "q\n");
+        assert!(!out.contains("=>"), "{}", out);
+    }
+
+    fn source_session(source: &str, commands: &str) -> (Result<String, String>, String) {
+        let mut out = Vec::new();
+        let result = {
+            let prog = Parser::new(Lexer::new(source.as_bytes())).parse().unwrap();
+            let debugger = SourceDebugger::new(commands.as_bytes(), &mut out);
+            Evaluator::new(SandboxConfig::default())
+                .with_tracer(debugger)
+                .eval_program(&prog, &mut Env::new())
+                .map(|obj| obj.to_string())
+                .map_err(|err| err.to_string())
+        };
+        (result, String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn test_source_step() {
+        let source = "let a = 1; let f = fn(x) { let y = x * 2; y }; f(a) + 1";
+        let (result, out) = source_session(source, "s\ns\ns\np x\nn\np y\nc\n");
+        assert_eq!(result.unwrap(), "3");
+        assert!(out.starts_with("-> let a = 1;\n"), "{}", out);
+        assert!(out.contains("-> (f(a) + 1)\n"), "{}", out);
+        assert!(out.contains("-> let y = (x * 2);\n(debug) 1\n"), "{}", out);
+        assert!(out.contains("-> y\n(debug) 2\n"), "{}", out);
+    }
+
+    #[test]
+    fn test_source_next() {
+        let source = "let f = fn(x) { x + 1 }; let a = f(1); a";
+        let (result, out) = source_session(source, "n\nn\nenv\nn\n");
+        assert_eq!(result.unwrap(), "2");
+        assert!(out.contains("-> a\n(debug) "), "{}", out);
+        assert!(!out.contains("-> (x + 1)"), "{}", out);
+        assert!(
+            out.contains("(debug) a = 2\nf = fn(x) { (x + 1); }\n"),
+            "{}",
+            out
+        );
+    }
+
+    #[test]
+    fn test_debugger_statement() {
+        let source = "let a = 1; debugger; let f = fn() { debugger; a }; f() + a";
+        let commands = "c\nset a = 41\np a\nc\nc\n";
+        let (result, out) = source_session(source, commands);
+        assert_eq!(result.unwrap(), "82");
+        assert!(
+            out.contains("-> debugger;\n(debug) (debug) 41\n"),
+            "{}",
+            out
+        );
+        assert_eq!(out.matches("-> debugger;").count(), 2, "{}", out);
+
+        // `set` in a call changes the bindings the function reads, its
+        // parameter and the global it closes over
+        let source = "let a = 1; let f = fn(x) { debugger; x + a }; f(2) + a";
+        let (result, _) = source_session(source, "c\nset x = 10\nset a = x - 5\nc\n");
+        assert_eq!(result.unwrap(), "20");
+
+        // without the debugger the statement does nothing
+        let prog = Parser::new(Lexer::new("debugger; 5".as_bytes()))
+            .parse()
+            .unwrap();
+        let result = Evaluator::new(SandboxConfig::default()).eval_program(&prog, &mut Env::new());
+        assert_eq!(result.unwrap().to_string(), "5");
+    }
+
+    #[test]
+    fn test_source_errors_and_quit() {
+        let (result, out) = source_session("1; 2", "bogus\np b\nset = 1\nset a\nq\n");
+        assert_eq!(result.unwrap_err(), "program stopped by the debugger");
+        assert!(out.contains("invalid command 'bogus'"), "{}", out);
+        assert!(out.contains("`b` is not bound"), "{}", out);
+        assert!(
+            out.contains("error: expected next token to be IDENT"),
+            "{}",
+            out
+        );
+        assert!(
+            out.contains("error: expected next token to be ASSIGN"),
+            "{}",
+            out
+        );
+
+        let (result, _) = source_session("1; 2", "s\n");
+        assert_eq!(result.unwrap_err(), "program stopped by the debugger");
+    }
+}
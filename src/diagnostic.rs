@@ -0,0 +1,261 @@
+use std::fmt;
+
+use crate::{lint::Severity, token::Span};
+
+// a problem with a program, whether the parser, the checker or the evaluator
+// found it, so they're all shown the same way
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    // None for problems that aren't about a place in the source
+    pub span: Option<Span>,
+    // other places the problem involves, each shown with what it is
+    pub labels: Vec<(Span, String)>,
+    // more about the problem, like the calls an error happened in
+    pub notes: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn new(severity: Severity, message: impl Into<String>, span: Option<Span>) -> Self {
+        Diagnostic {
+            severity,
+            message: message.into(),
+            span,
+            labels: vec![],
+            notes: vec![],
+        }
+    }
+
+    pub fn error(message: impl Into<String>, span: Option<Span>) -> Self {
+        Diagnostic::new(Severity::Error, message, span)
+    }
+
+    pub fn with_label(mut self, span: Span, label: impl Into<String>) -> Self {
+        self.labels.push((span, label.into()));
+        self
+    }
+
+    pub fn with_notes(mut self, notes: impl IntoIterator<Item = String>) -> Self {
+        self.notes.extend(notes);
+        self
+    }
+
+    // the message, the line the span is on with `^` under it, the line of
+    // each label with `-` and the label under it, then a line per note, tabs
+    // are kept so the markers line up with the source
+    pub fn render(&self, source: &str) -> String {
+        self.render_with(source, false)
+    }
+
+    // like `render`, with the marker in the color of the severity
+    pub fn render_colored(&self, source: &str) -> String {
+        self.render_with(source, true)
+    }
+
+    // the severity in its color, for terminals
+    pub fn colored_severity(&self) -> String {
+        paint(self.severity.as_str(), severity_color(self.severity))
+    }
+
+    fn render_with(&self, source: &str, color: bool) -> String {
+        let mut out = self.message.clone();
+        let marker = |marker: String| {
+            if color {
+                paint(&marker, severity_color(self.severity))
+            } else {
+                marker
+            }
+        };
+        let primary = |width| marker("^".repeat(width));
+        if let Some(snippet) = self.span.and_then(|span| snippet(source, span, primary)) {
+            out.push('\n');
+            out.push_str(&snippet);
+        }
+        for (span, label) in &self.labels {
+            let secondary = |width| format!("{} {}", "-".repeat(width), label);
+            if let Some(snippet) = snippet(source, *span, secondary) {
+                out.push('\n');
+                out.push_str(&snippet);
+            }
+        }
+        for note in &self.notes {
+            out.push_str("\n    ");
+            out.push_str(note);
+        }
+        out
+    }
+}
+
+pub const RED: &str = "1;31";
+pub const GREEN: &str = "32";
+pub const YELLOW: &str = "1;33";
+
+// `text` wrapped in the ansi escapes for `color`, one of the constants above
+pub fn paint(text: &str, color: &str) -> String {
+    format!("\x1b[{}m{}\x1b[0m", color, text)
+}
+
+fn severity_color(severity: Severity) -> &'static str {
+    match severity {
+        Severity::Error => RED,
+        _ => YELLOW,
+    }
+}
+
+// `underline` makes what goes under the span from how wide it is
+fn snippet(source: &str, span: Span, underline: impl Fn(usize) -> String) -> Option<String> {
+    let line = source.lines().nth(span.line.checked_sub(1)?)?;
+    let indent: String = line
+        .chars()
+        .take(span.column.saturating_sub(1))
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect();
+    let width = source
+        .get(span.start..span.end)
+        .and_then(|text| text.lines().next())
+        .map_or(0, |text| text.chars().count());
+    Some(format!(
+        "    {}\n    {}{}",
+        line,
+        indent,
+        underline(width.max(1))
+    ))
+}
+
+// `in f` per frame of a backtrace, innermost first, a frame repeated by
+// recursion is shown once with how many times it was
+pub fn calls(frames: impl Iterator<Item = String>) -> Vec<String> {
+    let mut calls = vec![];
+    let mut frames = frames.peekable();
+    while let Some(frame) = frames.next() {
+        let mut times = 1;
+        while frames.next_if_eq(&frame).is_some() {
+            times += 1;
+        }
+        calls.push(match times {
+            1 => format!("in {}", frame),
+            _ => format!("in {} ({} times)", frame, times),
+        });
+    }
+    calls
+}
+
+// `span` is null for diagnostics that aren't about a place in the file
+pub fn json_diagnostic(
+    file: &str,
+    span: Option<Span>,
+    severity: &str,
+    code: &str,
+    message: &str,
+) -> String {
+    let span = match span {
+        Some(span) => format!(
+            "{{\"line\":{},\"column\":{},\"start\":{},\"end\":{}}}",
+            span.line, span.column, span.start, span.end
+        ),
+        None => "null".to_string(),
+    };
+    format!(
+        "{{\"file\":{},\"span\":{},\"severity\":{},\"code\":{},\"message\":{}}}",
+        json_str(file),
+        span,
+        json_str(severity),
+        json_str(code),
+        json_str(message)
+    )
+}
+
+fn json_str(value: &str) -> String {
+    let mut out = String::with_capacity(value.len() + 2);
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+// `error: message`, without the source it's about
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.severity.as_str(), self.message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{lint::Severity, token::Span};
+
+    use super::{json_diagnostic, Diagnostic};
+
+    #[test]
+    fn test_render() {
+        let source = "let a = 1;\nlet b = a + true;\n";
+        let span = Span {
+            line: 2,
+            column: 11,
+            start: 21,
+            end: 22,
+        };
+        let diagnostic = Diagnostic::error("type mismatch", Some(span))
+            .with_notes(["in f, called at 3:1".to_string()]);
+        assert_eq!(diagnostic.to_string(), "error: type mismatch");
+        assert_eq!(
+            diagnostic.render(source),
+            "type mismatch\n    let b = a + true;\n              ^\n    in f, called at 3:1"
+        );
+
+        // spans outside the source, like the ones of made up tokens, have no
+        // line to show
+        let diagnostic = Diagnostic::new(Severity::Warning, "unused", Some(Span::default()));
+        assert_eq!(diagnostic.render(source), "unused");
+        assert_eq!(Diagnostic::error("stopped", None).render(source), "stopped");
+
+        let diagnostic = Diagnostic::error("type mismatch", Some(span));
+        assert_eq!(
+            diagnostic.render_colored(source),
+            "type mismatch\n    let b = a + true;\n              \x1b[1;31m^\x1b[0m"
+        );
+        assert_eq!(diagnostic.colored_severity(), "\x1b[1;31merror\x1b[0m");
+
+        let first = Span {
+            line: 1,
+            column: 5,
+            start: 4,
+            end: 5,
+        };
+        let diagnostic =
+            Diagnostic::error("type mismatch", Some(span)).with_label(first, "bound here");
+        assert_eq!(
+            diagnostic.render(source),
+            "type mismatch\n    let b = a + true;\n              ^\n    let a = 1;\n        - bound here"
+        );
+    }
+
+    #[test]
+    fn test_json_diagnostic() {
+        assert_eq!(
+            json_diagnostic("dir\\a.mk", None, "error", "runtime-error", "bad \"op\"\n"),
+            r#"{"file":"dir\\a.mk","span":null,"severity":"error","code":"runtime-error","message":"bad \"op\"\n"}"#
+        );
+        let span = Span {
+            line: 2,
+            column: 11,
+            start: 19,
+            end: 20,
+        };
+        assert_eq!(
+            json_diagnostic("a.mk", Some(span), "error", "runtime-error", "bad"),
+            r#"{"file":"a.mk","span":{"line":2,"column":11,"start":19,"end":20},"severity":"error","code":"runtime-error","message":"bad"}"#
+        );
+    }
+}
@@ -0,0 +1,24 @@
+// what runs source, compiled scripts always run on the vm
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum Engine {
+    #[default]
+    Eval,
+    Vm,
+}
+
+impl Engine {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Engine::Eval => "eval",
+            Engine::Vm => "vm",
+        }
+    }
+
+    pub fn from_name(value: &str) -> Option<Engine> {
+        match value {
+            "eval" => Some(Engine::Eval),
+            "vm" => Some(Engine::Vm),
+            _ => None,
+        }
+    }
+}
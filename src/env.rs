@@ -1,19 +1,503 @@
-use std::collections::HashMap;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt, mem,
+};
 
-use crate::object::Object;
+use crate::{
+    intern::{Name, Symbol},
+    object::{Function, HashKey, Object},
+    rc::{Rc, RefCell, Weak},
+    slots::Slot,
+};
 
-pub struct Env<'a>(HashMap<&'a [u8], Object>);
+// the bindings of a scope, looked up through the scopes enclosing it when a
+// name isn't bound in it
+//
+// an env is a handle, clones share the same bindings, so a function keeps
+// seeing the bindings made after it was defined in the env it captured
+#[derive(Clone)]
+pub struct Env<'a>(Rc<RefCell<EnvInner<'a>>>);
+
+#[derive(Default)]
+struct EnvInner<'a> {
+    // the names are interned, so looking one up doesn't compare its bytes
+    store: HashMap<Symbol, Object<'a>>,
+    // the bindings `resolve_slots` placed, with their names so they can be
+    // looked up by name too
+    slots: Vec<Option<(Symbol, Object<'a>)>>,
+    outer: Option<Env<'a>>,
+    heap: Heap<'a>,
+}
 
 impl<'a> Env<'a> {
     pub fn new() -> Self {
-        Env(HashMap::new())
+        let env = Env(Rc::default());
+        env.heap().register(&env);
+        env
+    }
+
+    // a scope of its own inside `outer`, like the one a function call runs in
+    pub fn new_enclosed(outer: &Env<'a>) -> Self {
+        let heap = outer.heap();
+        let env = Env(Rc::new(RefCell::new(EnvInner {
+            store: HashMap::new(),
+            slots: vec![],
+            outer: Some(outer.clone()),
+            heap: heap.clone(),
+        })));
+        heap.register(&env);
+        env
+    }
+
+    // the heap shared by this env, the one it was enclosed in and the ones
+    // enclosed in it
+    pub fn heap(&self) -> Heap<'a> {
+        self.0.borrow().heap.clone()
+    }
+
+    pub fn get(&self, key: impl Name) -> Option<Object<'a>> {
+        self.with(key, Object::clone)
+    }
+
+    pub fn contains(&self, key: impl Name) -> bool {
+        self.with(key, |_| ()).is_some()
+    }
+
+    // calls `f` with the value bound to `key` without cloning it, no other
+    // env sharing its scopes can be changed until `f` returns
+    pub fn with<R>(&self, key: impl Name, f: impl FnOnce(&Object<'a>) -> R) -> Option<R> {
+        self.with_symbol(&key.symbol(), f)
+    }
+
+    fn with_symbol<R>(&self, key: &Symbol, f: impl FnOnce(&Object<'a>) -> R) -> Option<R> {
+        let inner = self.0.borrow();
+        match inner.store.get(key).or_else(|| inner.slot_of(key)) {
+            Some(value) => Some(f(value)),
+            None => inner.outer.as_ref()?.with_symbol(key, f),
+        }
+    }
+
+    // binds `key` in this scope, shadowing any binding of an outer one
+    pub fn set(&mut self, key: impl Name, value: Object<'a>) {
+        let key = key.symbol();
+        let mut inner = self.0.borrow_mut();
+        match inner.slot_of_mut(&key) {
+            Some(binding) => *binding = value,
+            None => {
+                inner.store.insert(key, value);
+            }
+        }
+    }
+
+    // the value in `slot`, None when nothing is bound there yet
+    pub fn get_slot(&self, slot: Slot) -> Option<Object<'a>> {
+        let inner = self.0.borrow();
+        if slot.depth > 0 {
+            let depth = slot.depth - 1;
+            return inner.outer.as_ref()?.get_slot(Slot { depth, ..slot });
+        }
+        let (_, value) = inner.slots.get(slot.index)?.as_ref()?;
+        Some(value.clone())
+    }
+
+    // binds `key` in slot `index` of this scope
+    pub fn set_slot(&mut self, index: usize, key: Symbol, value: Object<'a>) {
+        let slots = &mut self.0.borrow_mut().slots;
+        if slots.len() <= index {
+            slots.resize_with(index + 1, || None);
+        }
+        slots[index] = Some((key, value));
+    }
+
+    // rebinds what's in `slot`, false when nothing is bound there yet
+    pub fn assign_slot(&mut self, slot: Slot, value: Object<'a>) -> bool {
+        if slot.depth > 0 {
+            let depth = slot.depth - 1;
+            let outer = self.0.borrow().outer.clone();
+            return outer.is_some_and(|mut outer| outer.assign_slot(Slot { depth, ..slot }, value));
+        }
+        let mut inner = self.0.borrow_mut();
+        match inner.slots.get_mut(slot.index) {
+            Some(Some((_, binding))) => {
+                *binding = value;
+                true
+            }
+            _ => false,
+        }
     }
 
-    pub fn get(&self, key: &[u8]) -> Option<Object> {
-        self.0.get(key).cloned()
+    // empties slot `index` of this scope
+    pub fn take_slot(&mut self, index: usize) -> Option<Object<'a>> {
+        let mut inner = self.0.borrow_mut();
+        let (_, value) = inner.slots.get_mut(index)?.take()?;
+        Some(value)
     }
 
-    pub fn set(&mut self, key: &'a [u8], value: Object) {
-        self.0.insert(key, value);
+    // rebinds `key` in the innermost scope that binds it, false when none does
+    pub fn assign(&mut self, key: impl Name, value: Object<'a>) -> bool {
+        self.assign_symbol(&key.symbol(), value)
+    }
+
+    fn assign_symbol(&mut self, key: &Symbol, value: Object<'a>) -> bool {
+        let mut outer = {
+            let mut inner = self.0.borrow_mut();
+            if let Some(binding) = inner.store.get_mut(key) {
+                *binding = value;
+                return true;
+            }
+            if let Some(binding) = inner.slot_of_mut(key) {
+                *binding = value;
+                return true;
+            }
+            match inner.outer.clone() {
+                Some(outer) => outer,
+                None => return false,
+            }
+        };
+        outer.assign_symbol(key, value)
+    }
+
+    // only removes a binding of this scope
+    pub fn remove(&mut self, key: impl Name) -> Option<Object<'a>> {
+        let key = key.symbol();
+        let mut inner = self.0.borrow_mut();
+        inner.store.remove(&key).or_else(|| {
+            let index = inner.slot_index(&key)?;
+            inner.slots[index].take().map(|(_, value)| value)
+        })
+    }
+
+    // every binding visible from this scope by name, the ones shadowed by an
+    // inner scope left out
+    pub fn bindings(&self) -> impl Iterator<Item = (Rc<[u8]>, Object<'a>)> {
+        let mut bindings = HashMap::new();
+        let mut env = Some(self.clone());
+        while let Some(current) = env {
+            let inner = current.0.borrow();
+            for (key, value) in inner.store.iter().chain(inner.slot_bindings()) {
+                bindings.entry(key.clone()).or_insert_with(|| value.clone());
+            }
+            env = inner.outer.clone();
+        }
+        let mut bindings: Vec<(Rc<[u8]>, _)> = bindings
+            .into_iter()
+            .map(|(key, value)| (Rc::from(key.as_bytes()), value))
+            .collect();
+        bindings.sort_by(|(a, _), (b, _)| a.cmp(b));
+        bindings.into_iter()
+    }
+}
+
+impl<'a> EnvInner<'a> {
+    fn slot_bindings(&self) -> impl Iterator<Item = (&Symbol, &Object<'a>)> {
+        self.slots.iter().flatten().map(|(key, value)| (key, value))
+    }
+
+    // the last slot bound to `key`, a later parameter of the same name hides
+    // an earlier one
+    fn slot_index(&self, key: &Symbol) -> Option<usize> {
+        self.slots
+            .iter()
+            .rposition(|slot| matches!(slot, Some((symbol, _)) if symbol == key))
+    }
+
+    fn slot_of(&self, key: &Symbol) -> Option<&Object<'a>> {
+        let (_, value) = self.slots[self.slot_index(key)?].as_ref()?;
+        Some(value)
+    }
+
+    fn slot_of_mut(&mut self, key: &Symbol) -> Option<&mut Object<'a>> {
+        let index = self.slot_index(key)?;
+        let (_, value) = self.slots[index].as_mut()?;
+        Some(value)
+    }
+}
+
+impl Default for Env<'_> {
+    fn default() -> Self {
+        Env::new()
+    }
+}
+
+// the envs enclosed in one made with `Env::new`, so the ones only kept alive
+// by each other can be found
+//
+// a function refers to the env it was defined in, which usually binds it or
+// is enclosed in the env binding it, so functions defined in a function call
+// keep its env alive after the call until the heap is collected
+#[derive(Clone, Default)]
+pub struct Heap<'a>(Rc<RefCell<Vec<Weak<RefCell<EnvInner<'a>>>>>>);
+
+impl<'a> Heap<'a> {
+    fn register(&self, env: &Env<'a>) {
+        let mut envs = self.0.borrow_mut();
+        // dropped envs are forgotten instead of growing the list
+        if envs.len() == envs.capacity() {
+            envs.retain(|env| env.strong_count() > 0);
+        }
+        envs.push(Rc::downgrade(&env.0));
+    }
+
+    // how many envs of the heap are alive
+    pub fn len(&self) -> usize {
+        let envs = self.0.borrow();
+        envs.iter().filter(|env| env.strong_count() > 0).count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // empties the envs that nothing outside of the heap can reach, which
+    // drops them, and returns how many there were
+    //
+    // the envs and the functions, arrays and hashes they refer to are
+    // counted like cpython counts its containers: whatever is referred to
+    // more often than they refer to it is reachable, and so is everything it
+    // refers to
+    //
+    // it must not run while the evaluator borrows an env, between programs
+    // is fine
+    pub fn collect(&self) -> usize {
+        let mut pending: Vec<_> = {
+            let mut envs = self.0.borrow_mut();
+            envs.retain(|env| env.strong_count() > 0);
+            envs.iter()
+                .filter_map(|env| Some(Node::Env(Env(env.upgrade()?))))
+                .collect()
+        };
+        let mut nodes = vec![];
+        let mut index = HashMap::new();
+        while let Some(node) = pending.pop() {
+            if index.contains_key(&node.address()) {
+                continue;
+            }
+            index.insert(node.address(), nodes.len());
+            node.references(&mut |reference| pending.push(reference));
+            nodes.push(node);
+        }
+
+        // the references from outside, the ones `nodes` holds left out
+        let mut outside: Vec<_> = nodes.iter().map(|node| node.strong_count() - 1).collect();
+        for node in &nodes {
+            node.references(&mut |reference| outside[index[&reference.address()]] -= 1);
+        }
+
+        let mut reachable = vec![false; nodes.len()];
+        let mut pending: Vec<_> = (0..nodes.len()).filter(|&i| outside[i] > 0).collect();
+        while let Some(i) = pending.pop() {
+            if mem::replace(&mut reachable[i], true) {
+                continue;
+            }
+            nodes[i].references(&mut |reference| pending.push(index[&reference.address()]));
+        }
+
+        let mut garbage = vec![];
+        for (node, reachable) in nodes.iter().zip(reachable) {
+            if let (Node::Env(env), false) = (node, reachable) {
+                let mut inner = env.0.borrow_mut();
+                let bindings = (mem::take(&mut inner.store), mem::take(&mut inner.slots));
+                garbage.push((bindings, inner.outer.take()));
+            }
+        }
+        let collected = garbage.len();
+        // the values are dropped once no env is borrowed
+        drop(garbage);
+        collected
+    }
+}
+
+// what the collector follows references through
+enum Node<'a> {
+    Env(Env<'a>),
+    Function(Rc<Function<'a>>),
+    Array(Rc<Vec<Object<'a>>>),
+    Hash(Rc<BTreeMap<HashKey, Object<'a>>>),
+}
+
+impl<'a> Node<'a> {
+    fn address(&self) -> *const () {
+        match self {
+            Node::Env(env) => Rc::as_ptr(&env.0) as *const (),
+            Node::Function(function) => Rc::as_ptr(function) as *const (),
+            Node::Array(elements) => Rc::as_ptr(elements) as *const (),
+            Node::Hash(pairs) => Rc::as_ptr(pairs) as *const (),
+        }
+    }
+
+    fn strong_count(&self) -> usize {
+        match self {
+            Node::Env(env) => Rc::strong_count(&env.0),
+            Node::Function(function) => Rc::strong_count(function),
+            Node::Array(elements) => Rc::strong_count(elements),
+            Node::Hash(pairs) => Rc::strong_count(pairs),
+        }
+    }
+
+    // once per reference, other objects referring to envs, like tasks, are
+    // left out so what they refer to is never collected
+    fn references(&self, f: &mut impl FnMut(Node<'a>)) {
+        match self {
+            Node::Env(env) => {
+                let inner = env.0.borrow();
+                if let Some(outer) = &inner.outer {
+                    f(Node::Env(outer.clone()));
+                }
+                inner
+                    .store
+                    .values()
+                    .chain(inner.slot_bindings().map(|(_, value)| value))
+                    .for_each(|value| object_nodes(value, f));
+            }
+            Node::Function(function) => f(Node::Env(function.env.clone())),
+            Node::Array(elements) => elements.iter().for_each(|e| object_nodes(e, f)),
+            Node::Hash(pairs) => pairs.values().for_each(|value| object_nodes(value, f)),
+        }
+    }
+}
+
+fn object_nodes<'a>(value: &Object<'a>, f: &mut impl FnMut(Node<'a>)) {
+    match value {
+        Object::Function(function) => f(Node::Function(Rc::clone(function))),
+        Object::Array(elements) => f(Node::Array(Rc::clone(elements))),
+        Object::Hash(pairs) => f(Node::Hash(Rc::clone(pairs))),
+        Object::ReturnValue(value) => object_nodes(value, f),
+        _ => {}
+    }
+}
+
+// functions refer to the env they were defined in, which usually binds them,
+// so only the names are shown
+impl fmt::Debug for Env<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let inner = self.0.borrow();
+        let mut names: Vec<_> = inner
+            .store
+            .iter()
+            .chain(inner.slot_bindings())
+            .map(|(key, _)| key.to_string())
+            .collect();
+        names.sort();
+        f.debug_struct("Env")
+            .field("names", &names)
+            .field("outer", &inner.outer)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        evaluator::eval_program, intern::Symbol, lexer::Lexer, object::Object, parser::Parser,
+        slots::Slot,
+    };
+
+    use super::Env;
+
+    #[test]
+    fn test_enclosed() {
+        let mut outer = Env::new();
+        outer.set(b"a", Object::Integer(1));
+        outer.set(b"b", Object::Integer(2));
+        let mut inner = Env::new_enclosed(&outer);
+        inner.set(b"b", Object::Integer(3));
+
+        assert_eq!(inner.get(b"a").unwrap().to_string(), "1");
+        assert_eq!(inner.get(b"b").unwrap().to_string(), "3");
+        assert_eq!(outer.get(b"b").unwrap().to_string(), "2");
+        assert!(inner.get(b"c").is_none());
+        assert!(inner.contains(b"a") && !inner.contains(b"c"));
+        assert_eq!(inner.with(b"b", |b| b.to_string()).unwrap(), "3");
+
+        assert!(inner.assign(b"a", Object::Integer(4)));
+        assert_eq!(outer.get(b"a").unwrap().to_string(), "4");
+        assert!(!inner.assign(b"c", Object::Integer(5)));
+        assert!(outer.get(b"c").is_none());
+
+        let bindings: Vec<_> = inner
+            .bindings()
+            .map(|(key, value)| (key.to_vec(), value.to_string()))
+            .collect();
+        assert_eq!(
+            bindings,
+            [
+                (b"a".to_vec(), "4".to_string()),
+                (b"b".to_vec(), "3".to_string())
+            ]
+        );
+
+        // a clone shares its bindings
+        let mut alias = outer.clone();
+        alias.set(b"c", Object::Integer(6));
+        assert_eq!(inner.get(b"c").unwrap().to_string(), "6");
+
+        // names are copied, so they can come from text the env outlives
+        let name = String::from("d");
+        outer.set(name.as_bytes(), Object::Integer(7));
+        drop(name);
+        assert_eq!(inner.get(b"d").unwrap().to_string(), "7");
+    }
+
+    #[test]
+    fn test_slots() {
+        let outer = Env::new();
+        let mut inner = Env::new_enclosed(&outer);
+        inner.set_slot(1, Symbol::intern(b"a"), Object::Integer(1));
+        let mut innermost = Env::new_enclosed(&inner);
+
+        let a = Slot { depth: 1, index: 1 };
+        assert_eq!(innermost.get_slot(a).unwrap().to_string(), "1");
+        assert!(innermost.get_slot(Slot { depth: 1, index: 0 }).is_none());
+        assert!(innermost.get_slot(Slot { depth: 3, index: 0 }).is_none());
+        assert!(innermost.assign_slot(a, Object::Integer(2)));
+        assert!(!innermost.assign_slot(Slot { depth: 0, index: 0 }, Object::Integer(3)));
+
+        // bindings in slots are there by name too
+        assert_eq!(innermost.get(b"a").unwrap().to_string(), "2");
+        assert!(innermost.assign(b"a", Object::Integer(4)));
+        inner.set(b"a", Object::Integer(5));
+        assert_eq!(innermost.get_slot(a).unwrap().to_string(), "5");
+        assert_eq!(innermost.bindings().count(), 1);
+
+        assert_eq!(inner.take_slot(1).unwrap().to_string(), "5");
+        assert!(innermost.get(b"a").is_none());
+        inner.set_slot(1, Symbol::intern(b"a"), Object::Integer(6));
+        assert_eq!(inner.remove(b"a").unwrap().to_string(), "6");
+        assert!(inner.take_slot(1).is_none());
+    }
+
+    #[test]
+    fn test_collect() {
+        // every call leaves an env behind that the closure made in it keeps
+        // alive, and the closure is bound in it
+        let source = "
+            let make = fn(n) { let count = fn(i) { if (i > 0) { count(i - 1) } else { n } }; count };
+            let kept = make(1);
+            make(2)(2); make(3)(3);
+            let shared = make(4); let alias = shared;
+            kept(0)
+        ";
+        let prog = Parser::new(Lexer::new(source.as_bytes())).parse().unwrap();
+        let mut env = Env::new();
+        let result = eval_program(&prog, &mut env).unwrap();
+        assert_eq!(result.to_string(), "1");
+        let heap = env.heap();
+        let before = heap.len();
+
+        // the env of each of the two other calls and of its body, the calls
+        // `kept` and `shared` were made in are still reachable, `shared`
+        // through `alias` too
+        assert_eq!(heap.collect(), 4);
+        assert_eq!(heap.len(), before - 4);
+        assert_eq!(heap.collect(), 0);
+        let prog = Parser::new(Lexer::new("kept(2) + shared(1)".as_bytes()))
+            .parse()
+            .unwrap();
+        let result = eval_program(&prog, &mut env).unwrap();
+        assert_eq!(result.to_string(), "5");
+
+        drop((result, env));
+        assert_eq!(heap.collect(), 5);
+        assert!(heap.is_empty());
     }
 }
@@ -1,158 +1,1139 @@
+use std::{collections::BTreeMap, iter};
+
+use core::fmt;
+
 use crate::{
-    ast::{Expr, Program, Stmt},
+    ast::{Expr, MatchArm, Program, Stmt},
+    builtins,
+    diagnostic::{self, Diagnostic},
     env::Env,
-    object::Object,
+    intern::Symbol,
+    object::{Function, HashKey, Object},
+    rc::Rc,
+    sandbox::{CancellationToken, Meter, SandboxConfig},
+    slots::Slot,
+    tasks::{self, Scheduler, Task, TaskOp},
+    token::{Text, Token},
 };
 
-pub fn eval_program<'a>(prog: Program<'a>, env: &mut Env<'a>) -> Result<Object, &'a str> {
-    let mut result = Ok(Object::Null);
-    for stmt in prog.stmts {
-        result = eval_stmt(stmt, env);
-        match result {
-            Ok(Object::ReturnValue(value)) => return Ok(*value),
-            err @ Err(_) => return err,
-            _ => {}
+pub struct Evaluator<'a> {
+    meter: Meter,
+    depth: usize,
+    tasks: Scheduler<'a>,
+    tracer: Option<Box<dyn Tracer<'a> + 'a>>,
+    truthiness: Truthiness,
+}
+
+// how conditions and `!` treat values that aren't booleans
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Truthiness {
+    // only booleans can be tested, anything else is an error
+    #[default]
+    Strict,
+    // like the Monkey book, nil and false are falsy and everything else is
+    // truthy
+    Lenient,
+}
+
+impl Truthiness {
+    // None when the value can't be tested
+    pub fn test(self, obj: &Object) -> Option<bool> {
+        match (self, obj) {
+            (_, Object::Bool(value)) => Some(*value),
+            (Truthiness::Strict, _) => None,
+            (Truthiness::Lenient, Object::Null) => Some(false),
+            (Truthiness::Lenient, _) => Some(true),
         }
     }
-    result
 }
 
-fn eval_block<'a>(stmts: Vec<Stmt<'a>>, env: &mut Env<'a>) -> Result<Object, &'a str> {
-    let mut result = Ok(Object::Null);
-    for stmt in stmts {
-        result = eval_stmt(stmt, env);
-        match result {
-            Ok(Object::ReturnValue(_)) => return result,
-            err @ Err(_) => return err,
-            _ => {}
+// what stopped an evaluation, with the token of the innermost expression or
+// statement it happened in, None when it happened outside of any, like a
+// break that reached the top of a function
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuntimeError<'a> {
+    pub kind: ErrorKind<'a>,
+    pub token: Option<Token>,
+    // the calls to Monkey functions it happened in, innermost first
+    pub trace: Vec<Frame>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ErrorKind<'a> {
+    // a name with no binding and no builtin
+    UnknownIdentifier(Text),
+    // assigning to a name that was never bound
+    UndefinedAssignment(Text),
+    // an operator that doesn't apply to its operands
+    BadOperator(&'static str),
+    // a value of the wrong type where one kind of value is needed, like a
+    // condition that isn't a boolean
+    TypeMismatch(&'static str),
+    NotAFunction,
+    WrongArgumentCount,
+    // integer division by zero, floats give an infinity or NaN instead
+    DivisionByZero,
+    // an integer result outside of i64, like `MIN / -1`
+    IntegerOverflow,
+    // a `break` or `continue` no loop caught
+    OutsideLoop(&'static str),
+    // from builtins and host functions
+    Other(&'a str),
+    // from sandbox limits and tracers, a `try` doesn't catch these
+    Aborted(&'a str),
+}
+
+impl<'a> RuntimeError<'a> {
+    // keeps the token of an error that already has one
+    fn at(mut self, token: &Token) -> Self {
+        self.token.get_or_insert_with(|| token.clone());
+        self
+    }
+
+    // one line per frame of the trace, empty when there's none
+    pub fn backtrace(&self) -> String {
+        self.calls()
+            .iter()
+            .map(|call| format!("    {}", call))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    // the frames of the trace as `in f, called at 1:2`
+    fn calls(&self) -> Vec<String> {
+        // tokens compare by their text, so frames are told apart by how
+        // they're shown, which includes where they are
+        diagnostic::calls(self.trace.iter().map(|frame| frame.to_string()))
+    }
+
+    pub fn diagnostic(&self) -> Diagnostic {
+        let span = self.token.as_ref().map(|token| token.span);
+        Diagnostic::error(self.to_string(), span).with_notes(self.calls())
+    }
+}
+
+impl<'a> From<ErrorKind<'a>> for RuntimeError<'a> {
+    fn from(kind: ErrorKind<'a>) -> Self {
+        RuntimeError {
+            kind,
+            token: None,
+            trace: vec![],
         }
     }
-    result
 }
 
-fn eval_stmt<'a>(stmt: Stmt<'a>, env: &mut Env<'a>) -> Result<Object, &'a str> {
-    match stmt {
-        Stmt::Expr { expr } => eval(expr, env),
-        Stmt::Block { stmts, .. } => eval_block(stmts, env),
-        Stmt::Return { value, .. } => Ok(Object::ReturnValue(Box::new(eval(value, env)?))),
-        Stmt::Let { name, value, .. } => {
-            let result = eval(value, env);
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    // the name the function was called by, if it was called by one
+    pub name: Option<Text>,
+    // the start of the function's body, to tell functions without a name
+    // apart
+    pub body: Token,
+    // None for calls the runtime made, like running a spawned task
+    pub call: Option<Token>,
+}
+
+impl Frame {
+    fn new(function: &Function, call: Option<&Expr>) -> Self {
+        let name = match call {
+            Some(Expr::Call { function, .. }) => match **function {
+                Expr::Identifier { ref value, .. } => Some(value.clone()),
+                _ => None,
+            },
+            _ => None,
+        };
+        Frame {
+            name,
+            body: function.body.first_token().clone(),
+            call: call.map(|call| call.first_token().clone()),
+        }
+    }
+
+    // the same function called from the same place, like each call of a
+    // recursion
+    fn same_call(&self, other: &Frame) -> bool {
+        self.name == other.name
+            && self.body.span == other.body.span
+            && self.call.as_ref().map(|call| call.span) == other.call.as_ref().map(|call| call.span)
+    }
+}
+
+// `f, called at 3:5` or `fn at 1:9` for a function without a name
+impl fmt::Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.name {
+            Some(name) => write!(f, "{}", String::from_utf8_lossy(name))?,
+            None => write!(f, "fn at {}", self.body.span)?,
+        }
+        match &self.call {
+            Some(call) => write!(f, ", called at {}", call.span),
+            None => Ok(()),
+        }
+    }
+}
+
+// what a function's body evaluated to, a value or the call it ends with
+enum Tail<'a> {
+    Value(Object<'a>),
+    Call(Rc<Function<'a>>, Vec<Object<'a>>, Frame),
+}
+
+impl<'a, 'b: 'a> From<&'b str> for RuntimeError<'a> {
+    fn from(message: &'b str) -> Self {
+        ErrorKind::Other(message).into()
+    }
+}
+
+// the same messages as the vm for the errors both engines can hit
+impl fmt::Display for RuntimeError<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            ErrorKind::UnknownIdentifier(name) => {
+                write!(f, "variable not found: {}", String::from_utf8_lossy(name))
+            }
+            ErrorKind::UndefinedAssignment(name) => write!(
+                f,
+                "assignment to undefined variable: {}",
+                String::from_utf8_lossy(name)
+            ),
+            ErrorKind::BadOperator(message)
+            | ErrorKind::TypeMismatch(message)
+            | ErrorKind::Other(message)
+            | ErrorKind::Aborted(message) => write!(f, "{}", message),
+            ErrorKind::NotAFunction => write!(f, "not a function"),
+            ErrorKind::WrongArgumentCount => write!(f, "wrong number of arguments"),
+            ErrorKind::DivisionByZero => write!(f, "division by zero"),
+            ErrorKind::IntegerOverflow => write!(f, "integer overflow"),
+            ErrorKind::OutsideLoop(keyword) => write!(f, "{} outside of a loop", keyword),
+        }
+    }
+}
+
+impl std::error::Error for RuntimeError<'_> {}
+
+// lets tools like the debugger and the profiler watch a program run, an
+// error from a hook stops the program with it
+pub trait Tracer<'a> {
+    // called before each statement with the env it runs in and the number of
+    // calls it's nested in
+    fn statement(
+        &mut self,
+        _stmt: &Stmt,
+        _env: &mut Env<'a>,
+        _depth: usize,
+    ) -> Result<(), &'a str> {
+        Ok(())
+    }
+
+    fn expr(&mut self, _expr: &Expr) {}
+
+    // around the body of every call to a Monkey function
+    fn enter(&mut self, _function: &Function<'a>) {}
+    fn exit(&mut self, _function: &Function<'a>) {}
+}
+
+// so a tracer can be lent to an evaluator and inspected once it's done
+impl<'a, T: Tracer<'a> + ?Sized> Tracer<'a> for &mut T {
+    fn statement(&mut self, stmt: &Stmt, env: &mut Env<'a>, depth: usize) -> Result<(), &'a str> {
+        (**self).statement(stmt, env, depth)
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        (**self).expr(expr)
+    }
+
+    fn enter(&mut self, function: &Function<'a>) {
+        (**self).enter(function)
+    }
+
+    fn exit(&mut self, function: &Function<'a>) {
+        (**self).exit(function)
+    }
+}
+
+pub fn eval_program<'a>(prog: &Program, env: &mut Env<'a>) -> Result<Object<'a>, RuntimeError<'a>> {
+    Evaluator::new(SandboxConfig::default()).eval_program(prog, env)
+}
+
+// like `eval_program`, but stops with an error soon after `token` is
+// cancelled, from another thread for example, bindings made before that are
+// kept in `env`
+pub fn eval_with_cancel<'a>(
+    prog: &Program,
+    env: &mut Env<'a>,
+    token: CancellationToken,
+) -> Result<Object<'a>, RuntimeError<'a>> {
+    Evaluator::new(SandboxConfig::default())
+        .with_cancellation(token)
+        .eval_program(prog, env)
+}
+
+impl<'a> Evaluator<'a> {
+    pub fn new(config: SandboxConfig) -> Self {
+        Evaluator {
+            meter: Meter::new(config),
+            depth: 0,
+            tasks: Scheduler::default(),
+            tracer: None,
+            truthiness: Truthiness::default(),
+        }
+    }
+
+    pub fn with_tracer(mut self, tracer: impl Tracer<'a> + 'a) -> Self {
+        self.tracer = Some(Box::new(tracer));
+        self
+    }
+
+    pub fn with_truthiness(mut self, truthiness: Truthiness) -> Self {
+        self.truthiness = truthiness;
+        self
+    }
+
+    pub fn with_cancellation(mut self, token: CancellationToken) -> Self {
+        self.meter.cancel = Some(token);
+        self
+    }
+
+    // the statements and expressions the last program evaluated
+    pub fn steps(&self) -> u64 {
+        self.meter.steps()
+    }
+
+    pub fn eval_program(
+        &mut self,
+        prog: &Program,
+        env: &mut Env<'a>,
+    ) -> Result<Object<'a>, RuntimeError<'a>> {
+        self.depth = 0;
+        self.meter.start().map_err(ErrorKind::Aborted)?;
+
+        let mut result = Object::Null;
+        for stmt in &prog.stmts {
+            result = match self.eval_stmt(stmt, env)? {
+                Object::ReturnValue(value) => {
+                    result = *value;
+                    break;
+                }
+                value => outside_loop(value)?,
+            };
+        }
+        self.run_pending_tasks(env)?;
+        Ok(result)
+    }
+
+    // calls `function` as if from code running in `env`
+    pub fn call(
+        &mut self,
+        function: Object<'a>,
+        args: Vec<Object<'a>>,
+        env: &Env<'a>,
+    ) -> Result<Object<'a>, RuntimeError<'a>> {
+        self.depth = 0;
+        self.meter.start().map_err(ErrorKind::Aborted)?;
+        let result = self.apply_fn(function, args, env, None)?;
+        self.run_pending_tasks(env)?;
+        Ok(result)
+    }
+
+    fn eval_for(
+        &mut self,
+        name: &Expr,
+        iterable: &Expr,
+        body: &Stmt,
+        env: &mut Env<'a>,
+    ) -> Result<Object<'a>, RuntimeError<'a>> {
+        let Object::Array(elements) = self.eval(iterable, env)? else {
+            return Err(ErrorKind::TypeMismatch("for loops can only iterate over arrays").into());
+        };
+        let Expr::Identifier {
+            symbol: ref name,
+            slot,
+            ..
+        } = *name
+        else {
+            return Err("invalid loop variable".into());
+        };
+        // each iteration binds the variable in a scope of its own, so a
+        // closure made in the body keeps that iteration's value, assignments
+        // still reach the bindings around the loop
+        let mut result = Ok(Object::Null);
+        for element in elements.iter() {
+            if let Err(err) = self.meter.check_cancelled() {
+                result = Err(ErrorKind::Aborted(err).into());
+                break;
+            }
+            let mut iteration = Env::new_enclosed(env);
+            bind(&mut iteration, name, slot, element.clone());
+            result = self.eval_stmt(body, &mut iteration);
             match result {
-                Ok(value) => {
-                    if let Expr::Identifier { value: name, .. } = name {
-                        env.set(name, value);
+                Ok(Object::ReturnValue(_)) | Err(_) => break,
+                Ok(Object::Break) => {
+                    result = Ok(Object::Null);
+                    break;
+                }
+                _ => result = Ok(Object::Null),
+            }
+        }
+        result
+    }
+
+    fn eval_block(
+        &mut self,
+        stmts: &[Stmt],
+        env: &mut Env<'a>,
+    ) -> Result<Object<'a>, RuntimeError<'a>> {
+        let mut result = Ok(Object::Null);
+        for stmt in stmts {
+            result = self.eval_stmt(stmt, env);
+            match result {
+                Ok(Object::ReturnValue(_) | Object::Break | Object::Continue) => return result,
+                err @ Err(_) => return err,
+                _ => {}
+            }
+        }
+        result
+    }
+
+    fn eval_stmt(
+        &mut self,
+        stmt: &Stmt,
+        env: &mut Env<'a>,
+    ) -> Result<Object<'a>, RuntimeError<'a>> {
+        self.eval_stmt_inner(stmt, env)
+            .map_err(|err| err.at(stmt.first_token()))
+    }
+
+    fn eval_stmt_inner(
+        &mut self,
+        stmt: &Stmt,
+        env: &mut Env<'a>,
+    ) -> Result<Object<'a>, RuntimeError<'a>> {
+        self.enter_stmt(stmt, env)?;
+        match stmt {
+            Stmt::Expr { expr } => self.eval(expr, env),
+            Stmt::Debugger { .. } => Ok(Object::Null),
+            Stmt::For {
+                name,
+                iterable,
+                body,
+                ..
+            } => self.eval_for(name, iterable, body, env),
+            // a block's bindings end with it, assignments still reach the
+            // bindings around it
+            Stmt::Block { stmts, .. } => self.eval_block(stmts, &mut Env::new_enclosed(env)),
+            Stmt::Break { .. } => Ok(Object::Break),
+            Stmt::Continue { .. } => Ok(Object::Continue),
+            Stmt::Return { value, .. } => Ok(Object::ReturnValue(Box::new(self.eval(value, env)?))),
+            Stmt::Let { name, value, .. } => {
+                let result = self.eval(value, env);
+                match result {
+                    Ok(value) => {
+                        match name {
+                            Expr::Identifier { symbol, slot, .. } => {
+                                bind(env, symbol, *slot, value)
+                            }
+                            // names past the end of the array are bound to nil
+                            Expr::ArrayLiteral {
+                                elements: names, ..
+                            } => {
+                                let Object::Array(elements) = value else {
+                                    return Err(ErrorKind::TypeMismatch(
+                                        "only arrays can be destructured",
+                                    )
+                                    .into());
+                                };
+                                for (i, name) in names.iter().enumerate() {
+                                    if let Expr::Identifier { symbol, slot, .. } = name {
+                                        let element = elements.get(i).cloned();
+                                        bind(env, symbol, *slot, element.unwrap_or(Object::Null));
+                                    }
+                                }
+                            }
+                            _ => {}
+                        }
+                        Ok(Object::Null)
                     }
-                    Ok(Object::Null)
+                    _ => result,
                 }
-                _ => result,
             }
         }
     }
+
+    // what every statement does before it runs
+    fn enter_stmt(&mut self, stmt: &Stmt, env: &mut Env<'a>) -> Result<(), RuntimeError<'a>> {
+        self.meter.tick().map_err(ErrorKind::Aborted)?;
+        if let Some(tracer) = &mut self.tracer {
+            tracer
+                .statement(stmt, env, self.depth)
+                .map_err(ErrorKind::Aborted)?;
+        }
+        Ok(())
+    }
+
+    // what every expression does before it's evaluated
+    fn enter_expr(&mut self, expr: &Expr) -> Result<(), RuntimeError<'a>> {
+        self.meter.tick().map_err(ErrorKind::Aborted)?;
+        if let Some(tracer) = &mut self.tracer {
+            tracer.expr(expr);
+        }
+        Ok(())
+    }
+
+    fn eval(&mut self, expr: &Expr, env: &mut Env<'a>) -> Result<Object<'a>, RuntimeError<'a>> {
+        self.eval_expr(expr, env)
+            .map_err(|err| err.at(error_token(expr)))
+    }
+
+    fn eval_expr(
+        &mut self,
+        expr: &Expr,
+        env: &mut Env<'a>,
+    ) -> Result<Object<'a>, RuntimeError<'a>> {
+        self.enter_expr(expr)?;
+        match expr {
+            Expr::Identifier {
+                value,
+                symbol,
+                slot,
+                ..
+            } => eval_identifier(value, symbol, *slot, env),
+            Expr::IntLiteral { value, .. } => Ok(Object::Integer(*value)),
+            Expr::FloatLiteral { value, .. } => Ok(Object::Float(*value)),
+            Expr::BoolLiteral { value, .. } => Ok(Object::Bool(*value)),
+            Expr::StringLiteral { value, .. } => {
+                let string = Object::Str(value.clone());
+                self.meter.allocate(&string).map_err(ErrorKind::Aborted)?;
+                Ok(string)
+            }
+            Expr::FnLiteral {
+                parameters,
+                rest,
+                block,
+                ..
+            } => {
+                let name = |p: &Expr| match p {
+                    Expr::Identifier { symbol, .. } => Some(symbol.clone()),
+                    _ => None,
+                };
+                let function = Object::Function(Rc::new(Function {
+                    parameters: parameters.iter().filter_map(name).collect(),
+                    rest: rest.as_deref().and_then(name),
+                    body: Rc::clone(block),
+                    env: env.clone(),
+                }));
+                self.meter.allocate(&function).map_err(ErrorKind::Aborted)?;
+                Ok(function)
+            }
+            Expr::Call {
+                function,
+                arguments,
+                ..
+            } => {
+                let function = self.eval(function, env)?;
+                let args = arguments
+                    .iter()
+                    .map(|arg| self.eval(arg, env))
+                    .collect::<Result<Vec<_>, _>>()?;
+                self.apply_fn(function, args, env, Some(expr))
+            }
+            Expr::Prefix { op, expr, .. } => {
+                let right = self.eval(expr, env)?;
+                match (op.as_bytes(), self.truthiness.test(&right)) {
+                    (b"!", Some(value)) => Ok(Object::Bool(!value)),
+                    _ => eval_prefix_expr(op, right),
+                }
+            }
+            Expr::Infix {
+                left, op, right, ..
+            } => {
+                let left = self.eval(left, env)?;
+                let right = self.eval(right, env)?;
+                let value = eval_infix_expr(op, left, right)?;
+                self.meter.allocate(&value).map_err(ErrorKind::Aborted)?;
+                Ok(value)
+            }
+            Expr::If {
+                condition,
+                consequence,
+                alternative,
+                ..
+            } => self.eval_conditional_expr(condition, consequence, alternative.as_deref(), env),
+            Expr::Try {
+                body,
+                name,
+                handler,
+                ..
+            } => {
+                let err = match self.eval_stmt(body, env) {
+                    Err(err) if !matches!(err.kind, ErrorKind::Aborted(_)) => err,
+                    result => return result,
+                };
+                let Expr::Identifier {
+                    symbol: ref name,
+                    slot,
+                    ..
+                } = **name
+                else {
+                    return Err("invalid catch variable".into());
+                };
+                let error = Object::Error(err.to_string().into());
+                self.meter.allocate(&error).map_err(ErrorKind::Aborted)?;
+                // bound like a loop variable, in the env itself
+                let shadowed = unbind(env, name, slot);
+                bind(env, name, slot, error);
+                let result = self.eval_stmt(handler, env);
+                match shadowed {
+                    Some(value) => bind(env, name, slot, value),
+                    None => {
+                        unbind(env, name, slot);
+                    }
+                }
+                result
+            }
+            Expr::ArrayLiteral { elements, .. } => {
+                let elements = elements
+                    .iter()
+                    .map(|e| self.eval(e, env))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let array = Object::Array(Rc::new(elements));
+                self.meter.allocate(&array).map_err(ErrorKind::Aborted)?;
+                Ok(array)
+            }
+            Expr::Index { left, index, .. } => {
+                let left = self.eval(left, env)?;
+                let index = self.eval(index, env)?;
+                eval_index_expr(left, index)
+            }
+            Expr::Assign { name, value, .. } => {
+                let Expr::Identifier {
+                    value: ref name,
+                    ref symbol,
+                    slot,
+                    ..
+                } = **name
+                else {
+                    return Err("invalid assignment target".into());
+                };
+                let bound = slot.is_some_and(|slot| env.get_slot(slot).is_some());
+                if !bound && !env.contains(symbol.clone()) {
+                    return Err(ErrorKind::UndefinedAssignment(name.clone()).into());
+                }
+                let value = self.eval(value, env)?;
+                if !slot.is_some_and(|slot| env.assign_slot(slot, value.clone())) {
+                    env.assign(symbol.clone(), value.clone());
+                }
+                Ok(value)
+            }
+            Expr::Match { subject, arms, .. } => match self.match_arm(subject, arms, env)? {
+                Some(value) => self.eval(value, env),
+                None => Ok(Object::Null),
+            },
+            Expr::HashLiteral { pairs, .. } => {
+                // every pair is evaluated before the keys are checked, the
+                // order the vm builds a hash in
+                let mut evaluated = Vec::with_capacity(pairs.len());
+                for (key, value) in pairs {
+                    evaluated.push((self.eval(key, env)?, self.eval(value, env)?));
+                }
+                let mut hash = BTreeMap::new();
+                for (key, value) in evaluated {
+                    let key = HashKey::from_object(&key)
+                        .ok_or(ErrorKind::TypeMismatch("unusable as hash key"))?;
+                    hash.insert(key, value);
+                }
+                let hash = Object::Hash(Rc::new(hash));
+                self.meter.allocate(&hash).map_err(ErrorKind::Aborted)?;
+                Ok(hash)
+            }
+        }
+    }
+
+    fn apply_fn(
+        &mut self,
+        function: Object<'a>,
+        args: Vec<Object<'a>>,
+        env: &Env<'a>,
+        call: Option<&Expr>,
+    ) -> Result<Object<'a>, RuntimeError<'a>> {
+        let result = match function {
+            Object::Function(function) => return self.apply_function(function, args, call),
+            Object::Builtin(builtin) => {
+                self.meter
+                    .check_capability(builtin.capability)
+                    .map_err(ErrorKind::Aborted)?;
+                match TaskOp::of(builtin) {
+                    Some(op) => self.apply_task_op(op, &args, env)?,
+                    None => (builtin.func)(&args)?,
+                }
+            }
+            Object::Host(host) => (host.func)(&args)?,
+            _ => return Err(ErrorKind::NotAFunction.into()),
+        };
+        self.meter.allocate(&result).map_err(ErrorKind::Aborted)?;
+        Ok(result)
+    }
+
+    fn apply_task_op(
+        &mut self,
+        op: TaskOp,
+        args: &[Object<'a>],
+        env: &Env<'a>,
+    ) -> Result<Object<'a>, RuntimeError<'a>> {
+        match op {
+            TaskOp::Spawn => Ok(self.tasks.spawn(args)?),
+            TaskOp::Join => {
+                let task = tasks::join_arg(args)?;
+                if task.start() {
+                    self.run_task(&task, env)?;
+                }
+                Ok(task.result().ok_or("task is still running")?)
+            }
+            TaskOp::Recv => {
+                let channel = tasks::recv_arg(args)?;
+                loop {
+                    if let Some(value) = channel.try_recv() {
+                        return Ok(value);
+                    }
+                    let Some(task) = self.tasks.start_next() else {
+                        return Err("recv on an empty channel with no tasks left".into());
+                    };
+                    self.run_task(&task, env)?;
+                }
+            }
+        }
+    }
+
+    fn run_pending_tasks(&mut self, env: &Env<'a>) -> Result<(), RuntimeError<'a>> {
+        while let Some(task) = self.tasks.start_next() {
+            self.run_task(&task, env)?;
+        }
+        Ok(())
+    }
+
+    fn run_task(&mut self, task: &Task<'a>, env: &Env<'a>) -> Result<(), RuntimeError<'a>> {
+        let result = self.apply_fn(task.function.clone(), vec![], env, None)?;
+        task.finish(result);
+        Ok(())
+    }
+
+    // a call in tail position hands the function back here instead of
+    // making the call, so the loop runs it in place of the caller and
+    // recursion like `fn(n) { if (n > 0) { f(n - 1) } }` doesn't grow the
+    // stack or the env chain
+    //
+    // the calls it replaced take no stack so they don't count toward the
+    // depth limit, but are in the trace of an error, a recursion is kept as
+    // its frame and how many times it was
+    fn apply_function(
+        &mut self,
+        mut function: Rc<Function<'a>>,
+        mut args: Vec<Object<'a>>,
+        call: Option<&Expr>,
+    ) -> Result<Object<'a>, RuntimeError<'a>> {
+        let mut frames = vec![(Frame::new(&function, call), 1)];
+        loop {
+            let result = match self.run_function(&function, args) {
+                Ok(Tail::Call(next, next_args, frame)) => {
+                    match frames.last_mut() {
+                        Some((last, times)) if last.same_call(&frame) => *times += 1,
+                        _ => frames.push((frame, 1)),
+                    }
+                    (function, args) = (next, next_args);
+                    continue;
+                }
+                Ok(Tail::Value(Object::ReturnValue(value))) => Ok(*value),
+                Ok(Tail::Value(value)) => outside_loop(value),
+                Err(err) => Err(err),
+            };
+            return result.map_err(|mut err| {
+                for (frame, times) in frames.into_iter().rev() {
+                    err.trace.extend(iter::repeat_n(frame, times));
+                }
+                err
+            });
+        }
+    }
+
+    fn run_function(
+        &mut self,
+        function: &Function<'a>,
+        args: Vec<Object<'a>>,
+    ) -> Result<Tail<'a>, RuntimeError<'a>> {
+        let arity = function.parameters.len();
+        if args.len() < arity || args.len() > arity && function.rest.is_none() {
+            return Err(ErrorKind::WrongArgumentCount.into());
+        }
+        self.meter
+            .check_depth(self.depth)
+            .map_err(ErrorKind::Aborted)?;
+
+        // the parameters are bound in a scope of the call's own, inside the
+        // env the function was defined in, a slot each in order
+        let mut fn_env = Env::new_enclosed(&function.env);
+        let mut args = args.into_iter();
+        for (i, (param, arg)) in function.parameters.iter().zip(args.by_ref()).enumerate() {
+            fn_env.set_slot(i, param.clone(), arg);
+        }
+        if let Some(rest) = &function.rest {
+            let rest_args = Object::Array(Rc::new(args.collect()));
+            self.meter
+                .allocate(&rest_args)
+                .map_err(ErrorKind::Aborted)?;
+            fn_env.set_slot(arity, rest.clone(), rest_args);
+        }
+
+        if let Some(tracer) = &mut self.tracer {
+            tracer.enter(function);
+        }
+        self.depth += 1;
+        let result = grow_stack(|| self.eval_tail_stmt(&function.body, &mut fn_env));
+        self.depth -= 1;
+        if let Some(tracer) = &mut self.tracer {
+            tracer.exit(function);
+        }
+        result
+    }
+
+    // like `eval_stmt` for a statement whose value the function returns
+    fn eval_tail_stmt(
+        &mut self,
+        stmt: &Stmt,
+        env: &mut Env<'a>,
+    ) -> Result<Tail<'a>, RuntimeError<'a>> {
+        self.eval_tail_stmt_inner(stmt, env)
+            .map_err(|err| err.at(stmt.first_token()))
+    }
+
+    fn eval_tail_stmt_inner(
+        &mut self,
+        stmt: &Stmt,
+        env: &mut Env<'a>,
+    ) -> Result<Tail<'a>, RuntimeError<'a>> {
+        match stmt {
+            Stmt::Expr { expr } | Stmt::Return { value: expr, .. } => {
+                self.enter_stmt(stmt, env)?;
+                self.eval_tail_expr(expr, env)
+            }
+            Stmt::Block { stmts, .. } => {
+                self.enter_stmt(stmt, env)?;
+                let Some((last, stmts)) = stmts.split_last() else {
+                    return Ok(Tail::Value(Object::Null));
+                };
+                let mut env = Env::new_enclosed(env);
+                match self.eval_block(stmts, &mut env)? {
+                    value @ (Object::ReturnValue(_) | Object::Break | Object::Continue) => {
+                        Ok(Tail::Value(value))
+                    }
+                    _ => self.eval_tail_stmt(last, &mut env),
+                }
+            }
+            _ => self.eval_stmt(stmt, env).map(Tail::Value),
+        }
+    }
+
+    // like `eval` for an expression whose value the function returns
+    fn eval_tail_expr(
+        &mut self,
+        expr: &Expr,
+        env: &mut Env<'a>,
+    ) -> Result<Tail<'a>, RuntimeError<'a>> {
+        self.eval_tail_expr_inner(expr, env)
+            .map_err(|err| err.at(error_token(expr)))
+    }
+
+    fn eval_tail_expr_inner(
+        &mut self,
+        expr: &Expr,
+        env: &mut Env<'a>,
+    ) -> Result<Tail<'a>, RuntimeError<'a>> {
+        match expr {
+            Expr::Call {
+                function,
+                arguments,
+                ..
+            } => {
+                self.enter_expr(expr)?;
+                let function = self.eval(function, env)?;
+                let args = arguments
+                    .iter()
+                    .map(|arg| self.eval(arg, env))
+                    .collect::<Result<Vec<_>, _>>()?;
+                match function {
+                    Object::Function(function) => {
+                        let frame = Frame::new(&function, Some(expr));
+                        Ok(Tail::Call(function, args, frame))
+                    }
+                    function => self
+                        .apply_fn(function, args, env, Some(expr))
+                        .map(Tail::Value),
+                }
+            }
+            Expr::If {
+                condition,
+                consequence,
+                alternative,
+                ..
+            } => {
+                self.enter_expr(expr)?;
+                let cond = self.eval(condition, env)?;
+                match (self.truthiness.test(&cond), alternative) {
+                    (Some(true), _) => self.eval_tail_stmt(consequence, env),
+                    (Some(false), Some(alternative)) => self.eval_tail_stmt(alternative, env),
+                    (Some(false), None) => Ok(Tail::Value(Object::Null)),
+                    (None, _) => Err(ErrorKind::TypeMismatch(
+                        "conditional expression isn't a boolean",
+                    )
+                    .into()),
+                }
+            }
+            Expr::Match { subject, arms, .. } => {
+                self.enter_expr(expr)?;
+                match self.match_arm(subject, arms, env)? {
+                    Some(value) => self.eval_tail_expr(value, env),
+                    None => Ok(Tail::Value(Object::Null)),
+                }
+            }
+            _ => self.eval(expr, env).map(Tail::Value),
+        }
+    }
+
+    // the value of the first arm whose pattern equals the subject
+    fn match_arm<'e>(
+        &mut self,
+        subject: &Expr,
+        arms: &'e [MatchArm],
+        env: &mut Env<'a>,
+    ) -> Result<Option<&'e Expr>, RuntimeError<'a>> {
+        let subject = self.eval(subject, env)?;
+        for arm in arms {
+            let matched = match &arm.pattern {
+                Some(pattern) => {
+                    let pattern = self.eval(pattern, env)?;
+                    // values that can't be compared just don't match
+                    let equal = eval_infix_expr(b"==", subject.clone(), pattern);
+                    matches!(equal, Ok(Object::Bool(true)))
+                }
+                None => true,
+            };
+            if matched {
+                return Ok(Some(&arm.value));
+            }
+        }
+        Ok(None)
+    }
+
+    fn eval_conditional_expr(
+        &mut self,
+        condition: &Expr,
+        consequence: &Stmt,
+        alternative: Option<&Stmt>,
+        env: &mut Env<'a>,
+    ) -> Result<Object<'a>, RuntimeError<'a>> {
+        let cond = self.eval(condition, env)?;
+        match self.truthiness.test(&cond) {
+            Some(true) => self.eval_stmt(consequence, env),
+            Some(false) => match alternative {
+                Some(alternative) => self.eval_stmt(alternative, env),
+                None => Ok(Object::Null),
+            },
+            None => Err(ErrorKind::TypeMismatch("conditional expression isn't a boolean").into()),
+        }
+    }
 }
 
-fn eval<'a>(expr: Expr<'a>, env: &mut Env<'a>) -> Result<Object, &'a str> {
-    match expr {
-        Expr::Identifier { value, .. } => eval_identifier(value, env),
-        Expr::IntLiteral { value, .. } => Ok(Object::Integer(value)),
-        Expr::BoolLiteral { value, .. } => Ok(Object::Bool(value)),
-        //Expr::FnLiteral { token, parameters, block } => todo!(),
-        //Expr::Call { token, function, arguments } => todo!(),
-        Expr::Prefix { op, expr, .. } => {
-            let right = eval(*expr, env)?;
-            eval_prefix_expr(op, right)
-        }
-        Expr::Infix {
-            left, op, right, ..
-        } => eval_infix_expr(op, eval(*left, env)?, eval(*right, env)?),
-        Expr::If {
-            condition,
-            consequence,
-            alternative,
-            ..
-        } => eval_conditional_expr(*condition, *consequence, alternative.map(|s| *s), env),
-        _ => Err("not supported expr type"),
+// a call nests several of the evaluator's frames, which are large in debug
+// builds, so the stack is grown as it runs out and `max_call_depth` is what
+// stops deep recursion
+#[cfg(not(target_arch = "wasm32"))]
+fn grow_stack<R>(f: impl FnOnce() -> R) -> R {
+    stacker::maybe_grow(256 * 1024, 4 * 1024 * 1024, f)
+}
+
+// wasm's stack can't be switched, running out of it traps instead
+#[cfg(target_arch = "wasm32")]
+fn grow_stack<R>(f: impl FnOnce() -> R) -> R {
+    f()
+}
+
+// a break or continue that no loop caught
+fn outside_loop<'a>(value: Object<'a>) -> Result<Object<'a>, RuntimeError<'a>> {
+    match value {
+        Object::Break => Err(ErrorKind::OutsideLoop("break").into()),
+        Object::Continue => Err(ErrorKind::OutsideLoop("continue").into()),
+        value => Ok(value),
     }
 }
 
-fn eval_identifier<'a>(ident: &[u8], env: &mut Env<'a>) -> Result<Object, &'a str> {
-    match env.get(ident) {
+// an identifier whose slot is still empty is looked up by name, its scope
+// binds it later but one around it may have already
+fn eval_identifier<'a>(
+    ident: &Text,
+    symbol: &Symbol,
+    slot: Option<Slot>,
+    env: &Env<'a>,
+) -> Result<Object<'a>, RuntimeError<'a>> {
+    match slot
+        .and_then(|slot| env.get_slot(slot))
+        .or_else(|| env.get(symbol.clone()))
+    {
         Some(value) => Ok(value),
-        None => Err("variable not found"),
+        None => builtins::lookup(ident)
+            .map(Object::Builtin)
+            .ok_or_else(|| ErrorKind::UnknownIdentifier(ident.clone()).into()),
     }
 }
 
-fn eval_conditional_expr<'a>(
-    condition: Expr<'a>,
-    consequence: Stmt<'a>,
-    alternative: Option<Stmt<'a>>,
-    env: &mut Env<'a>,
-) -> Result<Object, &'a str> {
-    let cond = eval(condition, env)?;
-    match cond {
-        Object::Bool(value) => {
-            if value {
-                eval_stmt(consequence, env)
-            } else if alternative.is_some() {
-                eval_stmt(alternative.unwrap(), env)
-            } else {
-                Ok(Object::Null)
-            }
-        }
-        _ => return Err("conditional expression isn't a boolean"),
+// binds `name` in `env` itself, in its slot if it has one
+fn bind<'a>(env: &mut Env<'a>, name: &Symbol, slot: Option<Slot>, value: Object<'a>) {
+    match slot {
+        Some(slot) => env.set_slot(slot.index, name.clone(), value),
+        None => env.set(name.clone(), value),
+    }
+}
+
+fn unbind<'a>(env: &mut Env<'a>, name: &Symbol, slot: Option<Slot>) -> Option<Object<'a>> {
+    match slot {
+        Some(slot) => env.take_slot(slot.index),
+        None => env.remove(name.clone()),
+    }
+}
+
+// operators point at themselves, everything else at where it starts
+fn error_token(expr: &Expr) -> &Token {
+    match expr {
+        Expr::Infix { token, .. } | Expr::Index { token, .. } | Expr::Assign { token, .. } => token,
+        expr => expr.first_token(),
     }
 }
 
-fn eval_prefix_expr(op: &[u8], right: Object) -> Result<Object, &str> {
+fn eval_index_expr<'a>(
+    left: Object<'a>,
+    index: Object<'a>,
+) -> Result<Object<'a>, RuntimeError<'a>> {
+    match (left, index) {
+        (Object::Array(elements), Object::Integer(idx)) => Ok(usize::try_from(idx)
+            .ok()
+            .and_then(|idx| elements.get(idx).cloned())
+            .unwrap_or(Object::Null)),
+        (Object::Hash(pairs), index) => match HashKey::from_object(&index) {
+            Some(key) => Ok(pairs.get(&key).cloned().unwrap_or(Object::Null)),
+            None => Err(ErrorKind::TypeMismatch("unusable as hash key").into()),
+        },
+        _ => Err(ErrorKind::BadOperator("index operator not supported").into()),
+    }
+}
+
+fn eval_prefix_expr<'a>(op: &[u8], right: Object<'a>) -> Result<Object<'a>, RuntimeError<'a>> {
     match op {
         b"!" => match right {
             Object::Bool(value) => Ok(Object::Bool(!value)),
-            _ => Err("operator '!' only applies to boolean types"),
+            _ => Err(ErrorKind::TypeMismatch("operator '!' only applies to boolean types").into()),
         },
         b"-" => match right {
-            Object::Integer(value) => Ok(Object::Integer(-value)),
-            _ => Err("operator '-' only applies to numbers"),
+            Object::Integer(value) => value
+                .checked_neg()
+                .map(Object::Integer)
+                .ok_or_else(|| ErrorKind::IntegerOverflow.into()),
+            Object::Float(value) => Ok(Object::Float(-value)),
+            _ => Err(ErrorKind::TypeMismatch("operator '-' only applies to numbers").into()),
         },
-        _ => Err("operator not supported"),
+        _ => Err(ErrorKind::BadOperator("operator not supported").into()),
     }
 }
 
-fn eval_infix_expr(op: &[u8], left: Object, right: Object) -> Result<Object, &str> {
+const BAD_OPERANDS: ErrorKind = ErrorKind::BadOperator("operator not supported for given types");
+
+fn eval_infix_expr<'a>(
+    op: &[u8],
+    left: Object<'a>,
+    right: Object<'a>,
+) -> Result<Object<'a>, RuntimeError<'a>> {
     match (left, right) {
         (Object::Integer(left), Object::Integer(right)) => Ok(match op {
-            b"*" => Object::Integer(left * right),
-            b"-" => Object::Integer(left - right),
-            b"+" => Object::Integer(left + right),
-            b"/" => Object::Integer(left / right),
+            b"*" => Object::Integer(left.checked_mul(right).ok_or(ErrorKind::IntegerOverflow)?),
+            b"-" => Object::Integer(left.checked_sub(right).ok_or(ErrorKind::IntegerOverflow)?),
+            b"+" => Object::Integer(left.checked_add(right).ok_or(ErrorKind::IntegerOverflow)?),
+            b"/" if right == 0 => return Err(ErrorKind::DivisionByZero.into()),
+            b"/" => Object::Integer(left.checked_div(right).ok_or(ErrorKind::IntegerOverflow)?),
             b"<" => Object::Bool(left < right),
             b">" => Object::Bool(left > right),
             b"==" => Object::Bool(left == right),
             b"!=" => Object::Bool(left != right),
-            _ => return Err("operator not supported for given types"),
+            _ => return Err(BAD_OPERANDS.into()),
         }),
+        // an integer meeting a float is promoted to one
+        (Object::Float(left), Object::Float(right)) => eval_float_infix_expr(op, left, right),
+        (Object::Integer(left), Object::Float(right)) => {
+            eval_float_infix_expr(op, left as f64, right)
+        }
+        (Object::Float(left), Object::Integer(right)) => {
+            eval_float_infix_expr(op, left, right as f64)
+        }
         (Object::Bool(left), Object::Bool(right)) => Ok(match op {
             b"==" => Object::Bool(left == right),
             b"!=" => Object::Bool(left != right),
-            _ => return Err("operator not supported for given types"),
+            _ => return Err(BAD_OPERANDS.into()),
+        }),
+        (Object::Str(left), Object::Str(right)) => Ok(match op {
+            b"+" => Object::Str([&*left, &*right].concat().into()),
+            b"==" => Object::Bool(left == right),
+            b"!=" => Object::Bool(left != right),
+            _ => return Err(BAD_OPERANDS.into()),
         }),
 
-        _ => Err("operand can only be applied to numbers"),
+        _ => Err(ErrorKind::TypeMismatch("operand can only be applied to numbers").into()),
     }
 }
 
+fn eval_float_infix_expr<'a>(
+    op: &[u8],
+    left: f64,
+    right: f64,
+) -> Result<Object<'a>, RuntimeError<'a>> {
+    Ok(match op {
+        b"*" => Object::Float(left * right),
+        b"-" => Object::Float(left - right),
+        b"+" => Object::Float(left + right),
+        b"/" => Object::Float(left / right),
+        b"<" => Object::Bool(left < right),
+        b">" => Object::Bool(left > right),
+        b"==" => Object::Bool(left == right),
+        b"!=" => Object::Bool(left != right),
+        _ => return Err(BAD_OPERANDS.into()),
+    })
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::{env::Env, lexer::Lexer, object::Object, parser::Parser};
+    use std::time::Duration;
 
-    use super::eval_program;
+    use crate::{
+        env::Env,
+        host::HostFunction,
+        lexer::Lexer,
+        object::Object,
+        parser::Parser,
+        rc::Rc,
+        sandbox::{CancellationToken, SandboxConfig},
+        token::Text,
+    };
 
-    fn eval_prog<'a>(input: &'a str) -> Result<Object, &str> {
+    use super::{eval_program, eval_with_cancel, ErrorKind, Evaluator, Truthiness, BAD_OPERANDS};
+
+    fn eval_prog(input: &str) -> Result<Object<'_>, String> {
+        eval_prog_with_config(input, SandboxConfig::default())
+    }
+
+    fn eval_prog_with_config(input: &str, config: SandboxConfig) -> Result<Object<'_>, String> {
         let l = Lexer::new(input.as_bytes());
         let mut parser = Parser::new(l);
         let prog = parser.parse();
         let mut env = Env::new();
 
         match prog {
-            Ok(p) => eval_program(p, &mut env),
+            Ok(p) => Evaluator::new(config)
+                .eval_program(&p, &mut env)
+                .map_err(|err| err.to_string()),
             Err(_) => panic!("failed to evaluate program"),
         }
     }
@@ -245,8 +1226,90 @@ mod tests {
     fn test_bang_with_non_boolean_types() {
         let neg_input = ["!5", "!!5"];
         for i in neg_input {
-            assert!(eval_prog(&i).is_err());
+            assert!(eval_prog(i).is_err());
+        }
+    }
+
+    #[test]
+    fn test_match() {
+        let name = "let name = fn(x) { match (x) { 1 => \"one\", 2.5 => \"two and a half\", \"a\" => \"letter\", true => \"yes\", _ => \"other\" } };";
+        let inputs = [
+            ("name(1)", "one"),
+            ("name(2.5)", "two and a half"),
+            ("name(\"a\")", "letter"),
+            ("name(true)", "yes"),
+            ("name([1])", "other"),
+            ("name(2)", "other"),
+        ];
+        for (input, expected) in inputs {
+            let input = format!("{} {}", name, input);
+            assert_eq!(
+                eval_prog(&input).unwrap().to_string(),
+                expected,
+                "{}",
+                input
+            );
         }
+        assert_eq!(
+            eval_prog("match (3) { 1 => 1, 2 => 2 }")
+                .unwrap()
+                .to_string(),
+            "nil"
+        );
+        assert_eq!(
+            eval_prog("match (1.0) { -1 => 0, 1 => 2 }")
+                .unwrap()
+                .to_string(),
+            "2"
+        );
+        assert_eq!(
+            eval_prog("match (1) { 1 => x }").unwrap_err(),
+            "variable not found: x"
+        );
+    }
+
+    #[test]
+    fn test_ternary() {
+        let inputs = [
+            ("1 < 2 ? 10 : 20", "10"),
+            ("1 > 2 ? 10 : 20", "20"),
+            (
+                "let sign = fn(x) { x < 0 ? -1 : x == 0 ? 0 : 1 }; [sign(-5), sign(0), sign(3)]",
+                "[-1, 0, 1]",
+            ),
+            ("(true ? 1 : 2) + 1", "2"),
+        ];
+        for (input, expected) in inputs {
+            assert_eq!(eval_prog(input).unwrap().to_string(), expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_truthiness() {
+        let inputs = [
+            ("!5", "false"),
+            ("!!5", "true"),
+            ("!0", "false"),
+            ("!\"\"", "false"),
+            ("![]", "false"),
+            ("!if (false) { 1 }", "true"),
+            ("if (5) { 1 } else { 2 }", "1"),
+            ("if (0) { 1 } else { 2 }", "1"),
+            ("if (if (false) { 1 }) { 1 } else { 2 }", "2"),
+            ("if (fn() { }) { 1 }", "1"),
+            ("if (false) { 1 } else { 2 }", "2"),
+        ];
+        for (input, expected) in inputs {
+            let prog = Parser::new(Lexer::new(input.as_bytes())).parse().unwrap();
+            let result = Evaluator::new(SandboxConfig::default())
+                .with_truthiness(Truthiness::Lenient)
+                .eval_program(&prog, &mut Env::new());
+            assert_eq!(result.unwrap().to_string(), expected, "{}", input);
+        }
+        assert_eq!(
+            eval_prog("if (5) { 1 }").unwrap_err(),
+            "conditional expression isn't a boolean"
+        );
     }
 
     #[test]
@@ -327,4 +1390,625 @@ return 1;
             assert_int_obj(&obj, evalue);
         }
     }
+
+    #[test]
+    fn test_fn_application() {
+        let inputs = [
+            ("let identity = fn(x) { x; }; identity(5);", 5),
+            ("let identity = fn(x) { return x; }; identity(5);", 5),
+            ("let double = fn(x) { x * 2; }; double(5);", 10),
+            ("let add = fn(x, y) { x + y; }; add(5, 5);", 10),
+            ("let add = fn(x, y) { x + y; }; add(5 + 5, add(5, 5));", 20),
+            ("fn(x) { x; }(5)", 5),
+            (
+                "let fact = fn(n) { if (n < 2) { 1 } else { n * fact(n - 1) } }; fact(5);",
+                120,
+            ),
+        ];
+        for (i, expected) in inputs {
+            let obj = eval_prog(i).unwrap();
+            assert_int_obj(&obj, expected);
+        }
+    }
+
+    #[test]
+    fn test_closures() {
+        let inputs = [
+            (
+                "let newAdder = fn(x) { fn(y) { x + y } }; let addTwo = newAdder(2); addTwo(3);",
+                5,
+            ),
+            ("fn(a) { fn(b) { fn(c) { a + b + c } } }(1)(2)(3)", 6),
+            // the captured env is shared, not copied
+            (
+                "let counter = fn() { let n = 0; fn() { n = n + 1 } }; let c = counter(); c(); c(); c()",
+                3,
+            ),
+            ("let n = 1; let bump = fn() { n = n + 1 }; bump(); bump(); n", 3),
+            ("let f = fn() { g() }; let g = fn() { 4 }; f()", 4),
+            ("let x = 1; let f = fn(x) { x }; f(2) + x", 3),
+        ];
+        for (i, expected) in inputs {
+            let obj = eval_prog(i).unwrap();
+            assert_int_obj(&obj, expected);
+        }
+        // names are looked up where the function was defined, not called
+        assert_eq!(
+            eval_prog("let f = fn() { y }; let g = fn() { let y = 1; f() }; g()").unwrap_err(),
+            "variable not found: y"
+        );
+    }
+
+    #[test]
+    fn test_block_scope() {
+        let inputs = [
+            ("let x = 1; if (true) { let x = 2; x + 1 }", 3),
+            ("let x = 1; if (true) { let x = 2; } x", 1),
+            // assignments still reach the binding around the block
+            ("let x = 1; if (true) { x = 2; } x", 2),
+            (
+                "let f = fn() { let n = 1; if (true) { let n = 5; n = 6; } n }; f()",
+                1,
+            ),
+        ];
+        for (i, expected) in inputs {
+            let obj = eval_prog(i).unwrap();
+            assert_int_obj(&obj, expected);
+        }
+        assert_eq!(
+            eval_prog("if (true) { let x = 1; } x").unwrap_err(),
+            "variable not found: x"
+        );
+    }
+
+    #[test]
+    fn test_fn_call_errors() {
+        let inputs = ["let f = fn(x) { x; }; f(1, 2);", "let a = 5; a(1);"];
+        for i in inputs {
+            assert!(eval_prog(i).is_err());
+        }
+    }
+
+    #[test]
+    fn test_arrays() {
+        let inputs = [
+            ("[1, 2 * 2, 3 + 3]", "[1, 4, 6]"),
+            ("[1, 2, 3][0]", "1"),
+            ("[1, 2, 3][1 + 1]", "3"),
+            ("let i = 0; [1][i]", "1"),
+            (
+                "let myArray = [1, 2, 3]; myArray[0] + myArray[1] + myArray[2];",
+                "6",
+            ),
+            ("[1, 2, 3][3]", "nil"),
+            ("[1, 2, 3][-1]", "nil"),
+            ("[[1], fn(x) { x }][1](2)", "2"),
+        ];
+        for (input, expected) in inputs {
+            assert_eq!(eval_prog(input).unwrap().to_string(), expected);
+        }
+        assert_eq!(
+            eval_prog("1[0]").unwrap_err(),
+            "index operator not supported"
+        );
+    }
+
+    #[test]
+    fn test_strings() {
+        let inputs = [
+            ("\"Hello World!\"", "Hello World!"),
+            ("\"Hello\" + \" \" + \"World!\"", "Hello World!"),
+            ("\"a\" == \"a\"", "true"),
+            ("\"a\" != \"a\"", "false"),
+            ("let s = \"abc\"; len(s + \"é\")", "4"),
+        ];
+        for (input, expected) in inputs {
+            assert_eq!(eval_prog(input).unwrap().to_string(), expected);
+        }
+        for input in ["\"a\" - \"b\"", "\"a\" + 1"] {
+            assert!(eval_prog(input).is_err(), "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_floats() {
+        let inputs = [
+            ("3.14", "3.14"),
+            ("-2.5", "-2.5"),
+            ("0.5 + 0.25", "0.75"),
+            ("1 + 0.5", "1.5"),
+            ("3.0 * 2", "6.0"),
+            ("1 / 2.0", "0.5"),
+            ("1.5 - 2", "-0.5"),
+            ("2 > 1.5", "true"),
+            ("0.5 < 0.25", "false"),
+            ("1 == 1.0", "true"),
+            ("0.1 + 0.2 != 0.3", "true"),
+        ];
+        for (input, expected) in inputs {
+            assert_eq!(eval_prog(input).unwrap().to_string(), expected, "{}", input);
+        }
+        assert!(eval_prog("1.5 + true").is_err());
+    }
+
+    #[test]
+    fn test_for_loops() {
+        let inputs = [
+            ("for (x in [1, 2, 3]) { x }", "nil"),
+            (
+                "let sum = fn(xs) { for (x in xs) { if (x > 2) { return x * 10; } } 0 }; sum([1, 2, 3])",
+                "30",
+            ),
+            ("let f = fn(xs) { for (x in xs) { return x; } -1 }; f([])", "-1"),
+            ("let x = 5; for (x in [1]) { } x", "5"),
+            ("let f = fn() { for (x in [[1, 2]]) { for (y in x) { return y + 1; } } }; f()", "2"),
+            // closures keep the variable of the iteration they were made in
+            ("let g = fn() { for (i in [7]) { return fn() { i }; } }; g()()", "7"),
+            (
+                "let fs = []; for (i in [1, 2, 3]) { fs = push(fs, fn() { i }); }; fs[0]()",
+                "1",
+            ),
+            ("let n = 0; for (i in [1, 2, 3]) { n = n + i; } n", "6"),
+        ];
+        for (input, expected) in inputs {
+            assert_eq!(eval_prog(input).unwrap().to_string(), expected, "{}", input);
+        }
+        assert_eq!(
+            eval_prog("for (x in 5) { }").unwrap_err(),
+            "for loops can only iterate over arrays"
+        );
+        assert_eq!(
+            eval_prog("for (x in [1]) { } x").unwrap_err(),
+            "variable not found: x"
+        );
+    }
+
+    #[test]
+    fn test_break_and_continue() {
+        let inputs = [
+            (
+                "let n = 0; for (x in [1, 2, 3, 4]) { if (x == 3) { break; } n = n + x; } n",
+                "3",
+            ),
+            (
+                "let n = 0; for (x in [1, 2, 3, 4]) { if (x == 2) { continue; } n = n + x; } n",
+                "8",
+            ),
+            (
+                "let n = 0; for (x in [1, 2]) { for (y in [1, 2]) { break; } n = n + 1; } n",
+                "2",
+            ),
+            (
+                "let f = fn() { for (x in [1, 2]) { if (x == 2) { return x; } continue; } }; f()",
+                "2",
+            ),
+        ];
+        for (input, expected) in inputs {
+            assert_eq!(eval_prog(input).unwrap().to_string(), expected, "{}", input);
+        }
+        let errors = [
+            ("break;", "break outside of a loop"),
+            ("if (true) { continue }", "continue outside of a loop"),
+            (
+                "for (x in [1]) { fn() { break; }() }",
+                "break outside of a loop",
+            ),
+        ];
+        for (input, expected) in errors {
+            assert_eq!(eval_prog(input).unwrap_err(), expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_destructuring() {
+        let inputs = [
+            ("let [a, b, c] = [1, 2, 3]; a + b * c", "7"),
+            ("let [a, b] = [1]; [a, b]", "[1, nil]"),
+            ("let [a] = [1, 2]; a", "1"),
+            ("let [] = [1]; 1", "1"),
+            (
+                "let pair = fn(x) { [x, x * 2] }; let [x, y] = pair(3); y - x",
+                "3",
+            ),
+        ];
+        for (input, expected) in inputs {
+            assert_eq!(eval_prog(input).unwrap().to_string(), expected, "{}", input);
+        }
+        assert_eq!(
+            eval_prog("let [a] = 1;").unwrap_err(),
+            "only arrays can be destructured"
+        );
+    }
+
+    #[test]
+    fn test_rest_parameter() {
+        let inputs = [
+            ("let f = fn(a, ...rest) { [a, rest] }; f(1, 2, 3)", "[1, [2, 3]]"),
+            ("let f = fn(a, ...rest) { rest }; f(1)", "[]"),
+            ("let sum = fn(...xs) { let total = 0; for (x in xs) { total = total + x; }; total }; sum(1, 2, 3)", "6"),
+            ("fn(a, ...rest) { a }", "fn(a, ...rest) { a; }"),
+        ];
+        for (input, expected) in inputs {
+            assert_eq!(eval_prog(input).unwrap().to_string(), expected, "{}", input);
+        }
+        assert_eq!(
+            eval_prog("fn(a, b, ...rest) { a }(1)").unwrap_err(),
+            "wrong number of arguments"
+        );
+    }
+
+    #[test]
+    fn test_assignment() {
+        let inputs = [
+            ("let x = 1; x = x + 1; x", "2"),
+            ("let x = 1; x = 5", "5"),
+            ("let a = 1; let b = 2; a = b = 3; a + b", "6"),
+            (
+                "let sum = 0; for (x in [1, 2, 3]) { sum = sum + x; } sum",
+                "6",
+            ),
+            ("let f = fn() { let n = 1; n = n * 10; n }; f()", "10"),
+        ];
+        for (input, expected) in inputs {
+            assert_eq!(eval_prog(input).unwrap().to_string(), expected, "{}", input);
+        }
+        let undefined = [
+            ("x = 1", "x"),
+            ("len = 1", "len"),
+            ("let f = fn() { y = 1 }; f()", "y"),
+        ];
+        for (input, name) in undefined {
+            assert_eq!(
+                eval_prog(input).unwrap_err(),
+                format!("assignment to undefined variable: {}", name),
+                "{}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_hashes() {
+        let inputs = [
+            (
+                "let two = \"two\"; {\"one\": 10 - 9, two: 1 + 1, 4: 4, true: 5}",
+                "{4: 4, true: 5, one: 1, two: 2}",
+            ),
+            ("{\"foo\": 5}[\"foo\"]", "5"),
+            ("{\"foo\": 5}[\"bar\"]", "nil"),
+            ("let key = \"foo\"; {\"foo\": 5}[key]", "5"),
+            ("{}[\"foo\"]", "nil"),
+            ("{5: 5}[5]", "5"),
+            ("{true: 5}[true]", "5"),
+            ("{false: 5}[false]", "5"),
+            ("{1: 1, 1: 2}[1]", "2"),
+        ];
+        for (input, expected) in inputs {
+            assert_eq!(eval_prog(input).unwrap().to_string(), expected);
+        }
+        for input in ["{fn(x) { x }: 1}", "{1: 1}[[1]]"] {
+            assert_eq!(eval_prog(input).unwrap_err(), "unusable as hash key");
+        }
+    }
+
+    #[test]
+    fn test_limits() {
+        let looping = "let f = fn(n) { 1 + f(n + 1) }; f(0);";
+        // the default stops it before the stack runs out
+        assert_eq!(eval_prog(looping).unwrap_err(), "call depth limit exceeded");
+        let nested = "let f = fn(n) { if (n == 0) { 0 } else { 1 + f(n - 1) } }; f(1000)";
+        assert_eq!(eval_prog(nested).unwrap().to_string(), "1000");
+        let res = eval_prog_with_config(
+            looping,
+            SandboxConfig {
+                max_call_depth: Some(50),
+                ..Default::default()
+            },
+        );
+        assert_eq!(res.unwrap_err(), "call depth limit exceeded");
+
+        let res = eval_prog_with_config(
+            looping,
+            SandboxConfig {
+                max_steps: Some(100),
+                ..Default::default()
+            },
+        );
+        assert_eq!(res.unwrap_err(), "step limit exceeded");
+
+        let fib = "let fib = fn(n) { if (n < 2) { n } else { fib(n - 1) + fib(n - 2) } }; fib(15);";
+        let res = eval_prog_with_config(
+            fib,
+            SandboxConfig {
+                timeout: Some(Duration::ZERO),
+                ..Default::default()
+            },
+        );
+        assert_eq!(res.unwrap_err(), "timeout exceeded");
+
+        let res = eval_prog_with_config(
+            fib,
+            SandboxConfig {
+                max_steps: Some(1_000_000),
+                max_call_depth: Some(100),
+                timeout: Some(Duration::from_secs(60)),
+                ..Default::default()
+            },
+        );
+        assert_int_obj(&res.unwrap(), 610);
+
+        // a statement, the infix and its two operands
+        let prog = Parser::new(Lexer::new("1 + 2".as_bytes())).parse().unwrap();
+        let mut evaluator = Evaluator::new(SandboxConfig::default());
+        evaluator.eval_program(&prog, &mut Env::new()).unwrap();
+        assert_eq!(evaluator.steps(), 4);
+    }
+
+    #[test]
+    fn test_runtime_errors() {
+        let inputs = [
+            (
+                "1 + foo",
+                ErrorKind::UnknownIdentifier(Text::from_static(b"foo")),
+                "foo",
+            ),
+            (
+                "1 + true",
+                ErrorKind::TypeMismatch("operand can only be applied to numbers"),
+                "+",
+            ),
+            ("true < false", BAD_OPERANDS, "<"),
+            (
+                "let x = 1; x[0]",
+                ErrorKind::BadOperator("index operator not supported"),
+                "[",
+            ),
+            (
+                "if (1) { 2 }",
+                ErrorKind::TypeMismatch("conditional expression isn't a boolean"),
+                "if",
+            ),
+            (
+                "let f = fn(x) {\n  -true\n}; f(1)",
+                ErrorKind::TypeMismatch("operator '-' only applies to numbers"),
+                "-",
+            ),
+            ("5(1)", ErrorKind::NotAFunction, "5"),
+            (
+                "len(1)",
+                ErrorKind::Other("argument to `len` not supported"),
+                "len",
+            ),
+            (
+                "for (x in 1) { }",
+                ErrorKind::TypeMismatch("for loops can only iterate over arrays"),
+                "for",
+            ),
+        ];
+        for (input, kind, at) in inputs {
+            let prog = Parser::new(Lexer::new(input.as_bytes())).parse().unwrap();
+            let err = Evaluator::new(SandboxConfig::default())
+                .eval_program(&prog, &mut Env::new())
+                .unwrap_err();
+            assert_eq!(err.kind, kind, "{}", input);
+            let token = err.token.unwrap();
+            assert_eq!(token.literal, at.as_bytes(), "{}", input);
+            // the token is the one read from the source, span and all
+            assert_eq!(
+                &input.as_bytes()[token.span.start..token.span.end],
+                at.as_bytes()
+            );
+        }
+
+        let prog = Parser::new(Lexer::new("break;".as_bytes()))
+            .parse()
+            .unwrap();
+        let err = eval_program(&prog, &mut Env::new()).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::OutsideLoop("break"));
+        assert_eq!(err.token, None);
+        assert_eq!(err.to_string(), "break outside of a loop");
+    }
+
+    #[test]
+    fn test_division_by_zero() {
+        for input in [
+            "5 / 0",
+            "let zero = 1 - 1; 10 / zero",
+            "let f = fn(n) { 1 / n }; f(0)",
+        ] {
+            assert_eq!(
+                eval_prog(input).unwrap_err(),
+                "division by zero",
+                "{}",
+                input
+            );
+        }
+        assert_eq!(eval_prog("1.0 / 0").unwrap().to_string(), "inf");
+        assert_eq!(eval_prog("0 / 5").unwrap().to_string(), "0");
+    }
+
+    #[test]
+    fn test_integer_overflow() {
+        for input in [
+            "9223372036854775807 + 1",
+            "-9223372036854775807 - 2",
+            "4611686018427387904 * 2",
+            "(-9223372036854775807 - 1) / -1",
+            "-(-9223372036854775807 - 1)",
+        ] {
+            assert_eq!(
+                eval_prog(input).unwrap_err(),
+                "integer overflow",
+                "{}",
+                input
+            );
+        }
+
+        let input = "let min = -9223372036854775807 - 1;\nmin / -1";
+        let prog = Parser::new(Lexer::new(input.as_bytes())).parse().unwrap();
+        let err = eval_program(&prog, &mut Env::new()).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::IntegerOverflow);
+        assert_eq!(err.token.unwrap().span.to_string(), "2:5");
+
+        let caught = "let min = -9223372036854775807 - 1; try { min / -1 } catch (e) { e }";
+        assert_eq!(eval_prog(caught).unwrap().to_string(), "integer overflow");
+    }
+
+    #[test]
+    fn test_try_catch() {
+        let tests = [
+            ("try { 1 / 0 } catch (e) { e }", "division by zero"),
+            ("try { x } catch (e) { e }", "variable not found: x"),
+            ("let f = fn() { len(1) }; try { f() } catch (e) { 0 }", "0"),
+            ("try { 1 + 1 } catch (e) { 0 }", "2"),
+            ("let e = 1; try { 1 / 0 } catch (e) { e }; e", "1"),
+            (
+                "let f = fn() { try { return 1; } catch (e) { 2 }; 3 }; f()",
+                "1",
+            ),
+            (
+                "let r = 0; for (x in [1, 0, 2]) { r = r + try { 4 / x } catch (e) { 10 } }; r",
+                "16",
+            ),
+        ];
+        for (input, expected) in tests {
+            assert_eq!(eval_prog(input).unwrap().to_string(), expected, "{}", input);
+        }
+        assert_eq!(
+            eval_prog("try { 1 } catch (e) { e }; e").unwrap_err(),
+            "variable not found: e"
+        );
+
+        // running out of a sandbox limit isn't something a program can catch
+        let res = eval_prog_with_config(
+            "let f = fn(n) { f(n + 1) }; try { f(0) } catch (e) { 0 }",
+            SandboxConfig {
+                max_steps: Some(100),
+                ..Default::default()
+            },
+        );
+        assert_eq!(res.unwrap_err(), "step limit exceeded");
+    }
+
+    #[test]
+    fn test_backtrace() {
+        let input = "let inner = fn(x) { x + true };
+let outer = fn(x) { [inner][0](x) * 2 };
+let twice = fn(f) { f(1) };
+twice(outer)";
+        let prog = Parser::new(Lexer::new(input.as_bytes())).parse().unwrap();
+        let err = eval_program(&prog, &mut Env::new()).unwrap_err();
+        assert_eq!(err.token.as_ref().unwrap().span.to_string(), "1:23");
+        let names: Vec<_> = err
+            .trace
+            .iter()
+            .map(|frame| frame.name.as_deref())
+            .collect();
+        assert_eq!(names, [None, Some(&b"f"[..]), Some(b"twice")]);
+        assert_eq!(
+            err.backtrace(),
+            "    in fn at 1:19, called at 2:21
+    in f, called at 3:21
+    in twice, called at 4:1"
+        );
+
+        // calls that finished before the error aren't in the trace
+        let prog = Parser::new(Lexer::new("let f = fn() { 1 }; f() + true".as_bytes()))
+            .parse()
+            .unwrap();
+        let err = eval_program(&prog, &mut Env::new()).unwrap_err();
+        assert!(err.trace.is_empty());
+        assert_eq!(err.backtrace(), "");
+    }
+
+    #[test]
+    fn test_tail_calls() {
+        let inputs = [
+            (
+                "let countdown = fn(n) { if (n == 0) { 0 } else { countdown(n - 1) } }; countdown(1000000)",
+                "0",
+            ),
+            (
+                "let sum = fn(n, acc) { if (n == 0) { return acc; } let m = n - 1; return sum(m, acc + n); }; sum(100000, 0)",
+                "5000050000",
+            ),
+            (
+                "let even = fn(n) { if (n == 0) { true } else { odd(n - 1) } }; let odd = fn(n) { if (n == 0) { false } else { even(n - 1) } }; odd(100001)",
+                "true",
+            ),
+            (
+                "let c = fn(n) { match (n) { 0 => 0, _ => c(n - 1) } }; c(100000)",
+                "0",
+            ),
+            // a call that isn't the last thing the function does still nests
+            ("let f = fn(n) { if (n == 0) { 0 } else { 1 + f(n - 1) } }; f(20)", "20"),
+            ("let f = fn(n) { for (x in [1]) { return n; }; 0 }; f(3)", "3"),
+        ];
+        for (input, expected) in inputs {
+            assert_eq!(eval_prog(input).unwrap().to_string(), expected, "{}", input);
+        }
+
+        // the calls a tail call replaced are still in the trace
+        let input = "let f = fn(n) { if (n == 0) { n + true } else { f(n - 1) } };
+let g = fn() { f(10) };
+g()";
+        let prog = Parser::new(Lexer::new(input.as_bytes())).parse().unwrap();
+        let err = eval_program(&prog, &mut Env::new()).unwrap_err();
+        assert_eq!(
+            err.backtrace(),
+            "    in f, called at 1:49 (10 times)
+    in f, called at 2:16
+    in g, called at 3:1"
+        );
+    }
+
+    #[test]
+    fn test_cancellation() {
+        let fib = "let fib = fn(n) { if (n < 2) { n } else { fib(n - 1) + fib(n - 2) } }; fib(40);";
+        let prog = Parser::new(Lexer::new(fib.as_bytes())).parse().unwrap();
+        let token = CancellationToken::new();
+        let canceller = token.clone();
+        let handle = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(20));
+            canceller.cancel();
+        });
+        let res = Evaluator::new(SandboxConfig::default())
+            .with_cancellation(token.clone())
+            .eval_program(&prog, &mut Env::new());
+        handle.join().unwrap();
+        assert_eq!(res.unwrap_err().to_string(), "evaluation cancelled");
+
+        // a cancelled token stops even programs too short to reach a check
+        let prog = Parser::new(Lexer::new("1 + 1".as_bytes())).parse().unwrap();
+        let res = Evaluator::new(SandboxConfig::default())
+            .with_cancellation(token.clone())
+            .eval_program(&prog, &mut Env::new());
+        assert_eq!(res.unwrap_err().to_string(), "evaluation cancelled");
+        let res = eval_with_cancel(&prog, &mut Env::new(), token);
+        assert_eq!(res.unwrap_err().to_string(), "evaluation cancelled");
+    }
+
+    #[test]
+    fn test_cancel_loop() {
+        // the loop checks the token on every iteration, well before the
+        // statements in it reach a periodic check, what ran before is kept
+        let input = "let n = 0; for (x in [1, 2, 3]) { n = n + cancel() }; n";
+        let prog = Parser::new(Lexer::new(input.as_bytes())).parse().unwrap();
+        let token = CancellationToken::new();
+        let canceller = token.clone();
+        let mut env = Env::new();
+        env.set(
+            b"cancel",
+            Object::Host(Rc::new(HostFunction {
+                name: "cancel",
+                func: Box::new(move |_| {
+                    canceller.cancel();
+                    Ok(Object::Integer(1))
+                }),
+            })),
+        );
+        let res = eval_with_cancel(&prog, &mut env, token);
+        assert_eq!(res.unwrap_err().to_string(), "evaluation cancelled");
+        assert_eq!(env.get(b"n").unwrap().to_string(), "1");
+    }
 }
@@ -0,0 +1,227 @@
+// a c interface to the interpreter, for embedding it in c, c++ or python
+// through ctypes, `include/monkey.h` declares these functions
+use std::{
+    any::Any,
+    ffi::{c_char, CStr, CString},
+    panic::{self, AssertUnwindSafe},
+    ptr,
+};
+
+use crate::Interpreter;
+
+// an interpreter with the strings it last handed out
+pub struct Monkey {
+    interp: Interpreter<'static>,
+    result: Option<CString>,
+    error: Option<CString>,
+    // the line and column of the error, 0 when it isn't about a place in
+    // the source
+    error_at: (usize, usize),
+}
+
+/// Makes an interpreter with no bindings, free it with `monkey_free`. Calls
+/// nested deeper than 1024 fail instead of overflowing the stack.
+#[no_mangle]
+pub extern "C" fn monkey_new() -> *mut Monkey {
+    Box::into_raw(Box::new(Monkey {
+        interp: Interpreter::new(),
+        result: None,
+        error: None,
+        error_at: (0, 0),
+    }))
+}
+
+/// Evaluates `source` and returns its value printed, or null if it failed,
+/// `monkey_last_error` says why then. The string is owned by `monkey` and
+/// valid until the next call.
+///
+/// # Safety
+///
+/// `monkey` has to come from `monkey_new` and not be freed, `source` has to
+/// be a nul terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn monkey_eval(monkey: *mut Monkey, source: *const c_char) -> *const c_char {
+    let Some(monkey) = monkey.as_mut() else {
+        return ptr::null();
+    };
+    monkey.result = None;
+    monkey.error = None;
+    monkey.error_at = (0, 0);
+    if source.is_null() {
+        monkey.error = Some(c_string("the source is null".to_string()));
+        return ptr::null();
+    }
+    let source = match CStr::from_ptr(source).to_str() {
+        Ok(source) => source,
+        Err(_) => {
+            monkey.error = Some(c_string("the source isn't valid utf-8".to_string()));
+            return ptr::null();
+        }
+    };
+    // unwinding into c is undefined behavior, a panic is reported as an error
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        monkey.interp.eval(source).map(|value| value.to_string())
+    }));
+    match result {
+        Ok(Ok(value)) => monkey.result.insert(c_string(value)).as_ptr(),
+        Ok(Err(err)) => {
+            if let Some(span) = err.span() {
+                monkey.error_at = (span.line, span.column);
+            }
+            monkey.error = Some(c_string(err.to_string()));
+            ptr::null()
+        }
+        Err(payload) => {
+            monkey.error = Some(c_string(panic_message(payload)));
+            ptr::null()
+        }
+    }
+}
+
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    let message = match payload.downcast::<String>() {
+        Ok(message) => *message,
+        Err(payload) => match payload.downcast::<&str>() {
+            Ok(message) => message.to_string(),
+            Err(_) => return "the interpreter panicked".to_string(),
+        },
+    };
+    format!("the interpreter panicked: {}", message)
+}
+
+/// The error of the last `monkey_eval`, or null if it succeeded. The string
+/// is owned by `monkey` and valid until the next call to `monkey_eval`.
+///
+/// # Safety
+///
+/// `monkey` has to come from `monkey_new` and not be freed.
+#[no_mangle]
+pub unsafe extern "C" fn monkey_last_error(monkey: *const Monkey) -> *const c_char {
+    match monkey.as_ref().and_then(|monkey| monkey.error.as_ref()) {
+        Some(error) => error.as_ptr(),
+        None => ptr::null(),
+    }
+}
+
+/// The line of the last `monkey_eval`'s error, counting from 1, or 0 if it
+/// succeeded or the error isn't about a place in the source.
+///
+/// # Safety
+///
+/// `monkey` has to come from `monkey_new` and not be freed.
+#[no_mangle]
+pub unsafe extern "C" fn monkey_last_error_line(monkey: *const Monkey) -> usize {
+    monkey.as_ref().map_or(0, |monkey| monkey.error_at.0)
+}
+
+/// The column of the last `monkey_eval`'s error, in characters counting
+/// from 1, or 0 like `monkey_last_error_line`.
+///
+/// # Safety
+///
+/// `monkey` has to come from `monkey_new` and not be freed.
+#[no_mangle]
+pub unsafe extern "C" fn monkey_last_error_column(monkey: *const Monkey) -> usize {
+    monkey.as_ref().map_or(0, |monkey| monkey.error_at.1)
+}
+
+/// Frees an interpreter and the strings it returned, null is ignored.
+///
+/// # Safety
+///
+/// `monkey` has to come from `monkey_new` and not be freed already.
+#[no_mangle]
+pub unsafe extern "C" fn monkey_free(monkey: *mut Monkey) {
+    if !monkey.is_null() {
+        drop(Box::from_raw(monkey));
+    }
+}
+
+// c strings end at the first nul, so ones inside are dropped
+fn c_string(text: String) -> CString {
+    CString::new(text.replace('\0', "")).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        ffi::{c_char, CStr},
+        ptr,
+    };
+
+    use super::{
+        monkey_eval, monkey_free, monkey_last_error, monkey_last_error_column,
+        monkey_last_error_line, monkey_new, panic_message,
+    };
+
+    unsafe fn text(ptr: *const c_char) -> Option<String> {
+        (!ptr.is_null()).then(|| CStr::from_ptr(ptr).to_string_lossy().into_owned())
+    }
+
+    #[test]
+    fn test_ffi() {
+        unsafe {
+            let monkey = monkey_new();
+            let result = monkey_eval(monkey, c"let double = fn(x) { x * 2 };".as_ptr());
+            assert_eq!(text(result).as_deref(), Some("nil"));
+            // bindings and the functions' bodies outlive the source they came from
+            let result = monkey_eval(monkey, c"double(21)".as_ptr());
+            assert_eq!(text(result).as_deref(), Some("42"));
+            assert_eq!(text(monkey_last_error(monkey)), None);
+
+            assert!(monkey_eval(monkey, c"1 + true".as_ptr()).is_null());
+            assert_eq!(
+                text(monkey_last_error(monkey)).as_deref(),
+                Some("runtime error: operand can only be applied to numbers")
+            );
+            assert_eq!(monkey_last_error_line(monkey), 1);
+            assert_eq!(monkey_last_error_column(monkey), 3);
+            assert!(monkey_eval(monkey, c"let = 1;".as_ptr()).is_null());
+            assert!(text(monkey_last_error(monkey))
+                .unwrap()
+                .starts_with("parse error: "));
+            assert!(monkey_eval(monkey, c"\xff".as_ptr()).is_null());
+            assert_eq!(
+                text(monkey_last_error(monkey)).as_deref(),
+                Some("the source isn't valid utf-8")
+            );
+            assert!(monkey_eval(monkey, ptr::null()).is_null());
+
+            // a later success clears the error
+            let result = monkey_eval(monkey, c"double(2)".as_ptr());
+            assert_eq!(text(result).as_deref(), Some("4"));
+            assert_eq!(text(monkey_last_error(monkey)), None);
+            assert_eq!(monkey_last_error_line(monkey), 0);
+            monkey_free(monkey);
+
+            assert!(monkey_eval(ptr::null_mut(), c"1".as_ptr()).is_null());
+            assert!(monkey_last_error(ptr::null()).is_null());
+            assert_eq!(monkey_last_error_column(ptr::null()), 0);
+            monkey_free(ptr::null_mut());
+        }
+    }
+
+    #[test]
+    fn test_deep_recursion() {
+        unsafe {
+            let monkey = monkey_new();
+            let source = c"let f = fn(n) { if (n == 0) { 0 } else { 1 + f(n - 1) } }; f(100000)";
+            assert!(monkey_eval(monkey, source.as_ptr()).is_null());
+            assert_eq!(
+                text(monkey_last_error(monkey)).as_deref(),
+                Some("runtime error: call depth limit exceeded")
+            );
+            let result = monkey_eval(monkey, c"f(1000)".as_ptr());
+            assert_eq!(text(result).as_deref(), Some("1000"));
+            monkey_free(monkey);
+        }
+    }
+
+    #[test]
+    fn test_panic_message() {
+        let payload = std::panic::catch_unwind(|| panic!("boom {}", 1)).unwrap_err();
+        assert_eq!(panic_message(payload), "the interpreter panicked: boom 1");
+        let payload = std::panic::catch_unwind(|| std::panic::panic_any(1)).unwrap_err();
+        assert_eq!(panic_message(payload), "the interpreter panicked");
+    }
+}
@@ -0,0 +1,678 @@
+use std::ops::{Index, IndexMut};
+
+use crate::{
+    ast::{Expr, MatchArm, Program, Stmt},
+    intern::Symbol,
+    rc::Rc,
+    slots::Slot,
+    token::{Text, Token},
+};
+
+// a program with its nodes in two vectors, children are indices into them
+// instead of boxes, so passes that look at every node go through the vectors
+// in order rather than chasing pointers
+//
+// children always come before their parents, one pass from the first node
+// to the last sees every operand before the expression using it
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FlatProgram {
+    exprs: Vec<FlatExpr>,
+    stmts: Vec<FlatStmt>,
+    // the statements of the program itself, in order
+    pub roots: Vec<StmtId>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ExprId(u32);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct StmtId(u32);
+
+impl ExprId {
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+impl StmtId {
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+// the same nodes as `ast::Expr`
+#[derive(Debug, Clone, PartialEq)]
+pub enum FlatExpr {
+    Identifier {
+        token: Token,
+        value: Text,
+        symbol: Symbol,
+        slot: Option<Slot>,
+    },
+    IntLiteral {
+        token: Token,
+        value: i64,
+    },
+    FloatLiteral {
+        token: Token,
+        value: f64,
+    },
+    BoolLiteral {
+        token: Token,
+        value: bool,
+    },
+    StringLiteral {
+        token: Token,
+        value: Rc<str>,
+    },
+    FnLiteral {
+        token: Token,
+        parameters: Vec<ExprId>,
+        rest: Option<ExprId>,
+        block: StmtId,
+    },
+    Call {
+        token: Token,
+        function: ExprId,
+        arguments: Vec<ExprId>,
+    },
+    Prefix {
+        token: Token,
+        op: Text,
+        expr: ExprId,
+    },
+    Infix {
+        token: Token,
+        left: ExprId,
+        op: Text,
+        right: ExprId,
+    },
+    If {
+        token: Token,
+        condition: ExprId,
+        consequence: StmtId,
+        alternative: Option<StmtId>,
+    },
+    ArrayLiteral {
+        token: Token,
+        elements: Vec<ExprId>,
+    },
+    Index {
+        token: Token,
+        left: ExprId,
+        index: ExprId,
+    },
+    HashLiteral {
+        token: Token,
+        pairs: Vec<(ExprId, ExprId)>,
+    },
+    // the arms as `(pattern, value)`, `_` has no pattern
+    Match {
+        token: Token,
+        subject: ExprId,
+        arms: Vec<(Option<ExprId>, ExprId)>,
+    },
+    Try {
+        token: Token,
+        body: StmtId,
+        name: ExprId,
+        handler: StmtId,
+    },
+    Assign {
+        token: Token,
+        name: ExprId,
+        value: ExprId,
+    },
+}
+
+// the same nodes as `ast::Stmt`
+#[derive(Debug, Clone, PartialEq)]
+pub enum FlatStmt {
+    Let {
+        token: Token,
+        name: ExprId,
+        value: ExprId,
+    },
+    Return {
+        token: Token,
+        value: ExprId,
+    },
+    Expr {
+        expr: ExprId,
+    },
+    Block {
+        token: Token,
+        stmts: Vec<StmtId>,
+    },
+    Debugger {
+        token: Token,
+    },
+    For {
+        token: Token,
+        name: ExprId,
+        iterable: ExprId,
+        body: StmtId,
+    },
+    Break {
+        token: Token,
+    },
+    Continue {
+        token: Token,
+    },
+}
+
+impl FlatProgram {
+    pub fn new(prog: &Program) -> Self {
+        let mut flat = FlatProgram::default();
+        flat.roots = prog
+            .stmts
+            .iter()
+            .map(|stmt| flat.lower_stmt(stmt))
+            .collect();
+        flat
+    }
+
+    // the tree the evaluator and compiler take, equal to the one this was
+    // made from unless nodes were changed since
+    pub fn to_program(&self) -> Program {
+        Program {
+            stmts: self.roots.iter().map(|&id| self.lift_stmt(id)).collect(),
+        }
+    }
+
+    // every expression with its id, children first
+    pub fn exprs(&self) -> impl Iterator<Item = (ExprId, &FlatExpr)> {
+        self.exprs
+            .iter()
+            .enumerate()
+            .map(|(i, expr)| (ExprId(i as u32), expr))
+    }
+
+    // every statement with its id, children first
+    pub fn stmts(&self) -> impl Iterator<Item = (StmtId, &FlatStmt)> {
+        self.stmts
+            .iter()
+            .enumerate()
+            .map(|(i, stmt)| (StmtId(i as u32), stmt))
+    }
+
+    pub fn expr_count(&self) -> usize {
+        self.exprs.len()
+    }
+
+    pub fn stmt_count(&self) -> usize {
+        self.stmts.len()
+    }
+
+    fn push_expr(&mut self, expr: FlatExpr) -> ExprId {
+        self.exprs.push(expr);
+        ExprId((self.exprs.len() - 1) as u32)
+    }
+
+    fn push_stmt(&mut self, stmt: FlatStmt) -> StmtId {
+        self.stmts.push(stmt);
+        StmtId((self.stmts.len() - 1) as u32)
+    }
+
+    fn lower_exprs(&mut self, exprs: &[Expr]) -> Vec<ExprId> {
+        exprs.iter().map(|expr| self.lower_expr(expr)).collect()
+    }
+
+    fn lower_expr(&mut self, expr: &Expr) -> ExprId {
+        let flat = match expr {
+            Expr::Identifier {
+                token,
+                value,
+                symbol,
+                slot,
+            } => FlatExpr::Identifier {
+                token: token.clone(),
+                value: value.clone(),
+                symbol: symbol.clone(),
+                slot: *slot,
+            },
+            Expr::IntLiteral { token, value } => FlatExpr::IntLiteral {
+                token: token.clone(),
+                value: *value,
+            },
+            Expr::FloatLiteral { token, value } => FlatExpr::FloatLiteral {
+                token: token.clone(),
+                value: *value,
+            },
+            Expr::BoolLiteral { token, value } => FlatExpr::BoolLiteral {
+                token: token.clone(),
+                value: *value,
+            },
+            Expr::StringLiteral { token, value } => FlatExpr::StringLiteral {
+                token: token.clone(),
+                value: value.clone(),
+            },
+            Expr::FnLiteral {
+                token,
+                parameters,
+                rest,
+                block,
+            } => FlatExpr::FnLiteral {
+                token: token.clone(),
+                parameters: self.lower_exprs(parameters),
+                rest: rest.as_ref().map(|rest| self.lower_expr(rest)),
+                block: self.lower_stmt(block),
+            },
+            Expr::Call {
+                token,
+                function,
+                arguments,
+            } => FlatExpr::Call {
+                token: token.clone(),
+                function: self.lower_expr(function),
+                arguments: self.lower_exprs(arguments),
+            },
+            Expr::Prefix { token, op, expr } => FlatExpr::Prefix {
+                token: token.clone(),
+                op: op.clone(),
+                expr: self.lower_expr(expr),
+            },
+            Expr::Infix {
+                token,
+                left,
+                op,
+                right,
+            } => FlatExpr::Infix {
+                token: token.clone(),
+                left: self.lower_expr(left),
+                op: op.clone(),
+                right: self.lower_expr(right),
+            },
+            Expr::If {
+                token,
+                condition,
+                consequence,
+                alternative,
+            } => FlatExpr::If {
+                token: token.clone(),
+                condition: self.lower_expr(condition),
+                consequence: self.lower_stmt(consequence),
+                alternative: alternative.as_ref().map(|stmt| self.lower_stmt(stmt)),
+            },
+            Expr::ArrayLiteral { token, elements } => FlatExpr::ArrayLiteral {
+                token: token.clone(),
+                elements: self.lower_exprs(elements),
+            },
+            Expr::Index { token, left, index } => FlatExpr::Index {
+                token: token.clone(),
+                left: self.lower_expr(left),
+                index: self.lower_expr(index),
+            },
+            Expr::HashLiteral { token, pairs } => FlatExpr::HashLiteral {
+                token: token.clone(),
+                pairs: pairs
+                    .iter()
+                    .map(|(key, value)| (self.lower_expr(key), self.lower_expr(value)))
+                    .collect(),
+            },
+            Expr::Match {
+                token,
+                subject,
+                arms,
+            } => FlatExpr::Match {
+                token: token.clone(),
+                subject: self.lower_expr(subject),
+                arms: arms
+                    .iter()
+                    .map(|arm| {
+                        let pattern = arm.pattern.as_ref().map(|p| self.lower_expr(p));
+                        (pattern, self.lower_expr(&arm.value))
+                    })
+                    .collect(),
+            },
+            Expr::Try {
+                token,
+                body,
+                name,
+                handler,
+            } => FlatExpr::Try {
+                token: token.clone(),
+                body: self.lower_stmt(body),
+                name: self.lower_expr(name),
+                handler: self.lower_stmt(handler),
+            },
+            Expr::Assign { token, name, value } => FlatExpr::Assign {
+                token: token.clone(),
+                name: self.lower_expr(name),
+                value: self.lower_expr(value),
+            },
+        };
+        self.push_expr(flat)
+    }
+
+    fn lower_stmt(&mut self, stmt: &Stmt) -> StmtId {
+        let flat = match stmt {
+            Stmt::Let { token, name, value } => FlatStmt::Let {
+                token: token.clone(),
+                name: self.lower_expr(name),
+                value: self.lower_expr(value),
+            },
+            Stmt::Return { token, value } => FlatStmt::Return {
+                token: token.clone(),
+                value: self.lower_expr(value),
+            },
+            Stmt::Expr { expr } => FlatStmt::Expr {
+                expr: self.lower_expr(expr),
+            },
+            Stmt::Block { token, stmts } => FlatStmt::Block {
+                token: token.clone(),
+                stmts: stmts.iter().map(|stmt| self.lower_stmt(stmt)).collect(),
+            },
+            Stmt::Debugger { token } => FlatStmt::Debugger {
+                token: token.clone(),
+            },
+            Stmt::For {
+                token,
+                name,
+                iterable,
+                body,
+            } => FlatStmt::For {
+                token: token.clone(),
+                name: self.lower_expr(name),
+                iterable: self.lower_expr(iterable),
+                body: self.lower_stmt(body),
+            },
+            Stmt::Break { token } => FlatStmt::Break {
+                token: token.clone(),
+            },
+            Stmt::Continue { token } => FlatStmt::Continue {
+                token: token.clone(),
+            },
+        };
+        self.push_stmt(flat)
+    }
+
+    fn lift_exprs(&self, ids: &[ExprId]) -> Vec<Expr> {
+        ids.iter().map(|&id| self.lift_expr(id)).collect()
+    }
+
+    fn lift_box(&self, id: ExprId) -> Box<Expr> {
+        Box::new(self.lift_expr(id))
+    }
+
+    fn lift_expr(&self, id: ExprId) -> Expr {
+        match &self[id] {
+            FlatExpr::Identifier {
+                token,
+                value,
+                symbol,
+                slot,
+            } => Expr::Identifier {
+                token: token.clone(),
+                value: value.clone(),
+                symbol: symbol.clone(),
+                slot: *slot,
+            },
+            FlatExpr::IntLiteral { token, value } => Expr::IntLiteral {
+                token: token.clone(),
+                value: *value,
+            },
+            FlatExpr::FloatLiteral { token, value } => Expr::FloatLiteral {
+                token: token.clone(),
+                value: *value,
+            },
+            FlatExpr::BoolLiteral { token, value } => Expr::BoolLiteral {
+                token: token.clone(),
+                value: *value,
+            },
+            FlatExpr::StringLiteral { token, value } => Expr::StringLiteral {
+                token: token.clone(),
+                value: value.clone(),
+            },
+            FlatExpr::FnLiteral {
+                token,
+                parameters,
+                rest,
+                block,
+            } => Expr::FnLiteral {
+                token: token.clone(),
+                parameters: self.lift_exprs(parameters),
+                rest: rest.map(|rest| self.lift_box(rest)),
+                block: Rc::new(self.lift_stmt(*block)),
+            },
+            FlatExpr::Call {
+                token,
+                function,
+                arguments,
+            } => Expr::Call {
+                token: token.clone(),
+                function: self.lift_box(*function),
+                arguments: self.lift_exprs(arguments),
+            },
+            FlatExpr::Prefix { token, op, expr } => Expr::Prefix {
+                token: token.clone(),
+                op: op.clone(),
+                expr: self.lift_box(*expr),
+            },
+            FlatExpr::Infix {
+                token,
+                left,
+                op,
+                right,
+            } => Expr::Infix {
+                token: token.clone(),
+                left: self.lift_box(*left),
+                op: op.clone(),
+                right: self.lift_box(*right),
+            },
+            FlatExpr::If {
+                token,
+                condition,
+                consequence,
+                alternative,
+            } => Expr::If {
+                token: token.clone(),
+                condition: self.lift_box(*condition),
+                consequence: Box::new(self.lift_stmt(*consequence)),
+                alternative: alternative.map(|stmt| Box::new(self.lift_stmt(stmt))),
+            },
+            FlatExpr::ArrayLiteral { token, elements } => Expr::ArrayLiteral {
+                token: token.clone(),
+                elements: self.lift_exprs(elements),
+            },
+            FlatExpr::Index { token, left, index } => Expr::Index {
+                token: token.clone(),
+                left: self.lift_box(*left),
+                index: self.lift_box(*index),
+            },
+            FlatExpr::HashLiteral { token, pairs } => Expr::HashLiteral {
+                token: token.clone(),
+                pairs: pairs
+                    .iter()
+                    .map(|&(key, value)| (self.lift_expr(key), self.lift_expr(value)))
+                    .collect(),
+            },
+            FlatExpr::Match {
+                token,
+                subject,
+                arms,
+            } => Expr::Match {
+                token: token.clone(),
+                subject: self.lift_box(*subject),
+                arms: arms
+                    .iter()
+                    .map(|&(pattern, value)| MatchArm {
+                        pattern: pattern.map(|pattern| self.lift_expr(pattern)),
+                        value: self.lift_expr(value),
+                    })
+                    .collect(),
+            },
+            FlatExpr::Try {
+                token,
+                body,
+                name,
+                handler,
+            } => Expr::Try {
+                token: token.clone(),
+                body: Box::new(self.lift_stmt(*body)),
+                name: self.lift_box(*name),
+                handler: Box::new(self.lift_stmt(*handler)),
+            },
+            FlatExpr::Assign { token, name, value } => Expr::Assign {
+                token: token.clone(),
+                name: self.lift_box(*name),
+                value: self.lift_box(*value),
+            },
+        }
+    }
+
+    fn lift_stmt(&self, id: StmtId) -> Stmt {
+        match &self[id] {
+            FlatStmt::Let { token, name, value } => Stmt::Let {
+                token: token.clone(),
+                name: self.lift_expr(*name),
+                value: self.lift_expr(*value),
+            },
+            FlatStmt::Return { token, value } => Stmt::Return {
+                token: token.clone(),
+                value: self.lift_expr(*value),
+            },
+            FlatStmt::Expr { expr } => Stmt::Expr {
+                expr: self.lift_expr(*expr),
+            },
+            FlatStmt::Block { token, stmts } => Stmt::Block {
+                token: token.clone(),
+                stmts: stmts.iter().map(|&id| self.lift_stmt(id)).collect(),
+            },
+            FlatStmt::Debugger { token } => Stmt::Debugger {
+                token: token.clone(),
+            },
+            FlatStmt::For {
+                token,
+                name,
+                iterable,
+                body,
+            } => Stmt::For {
+                token: token.clone(),
+                name: self.lift_expr(*name),
+                iterable: self.lift_expr(*iterable),
+                body: Box::new(self.lift_stmt(*body)),
+            },
+            FlatStmt::Break { token } => Stmt::Break {
+                token: token.clone(),
+            },
+            FlatStmt::Continue { token } => Stmt::Continue {
+                token: token.clone(),
+            },
+        }
+    }
+}
+
+impl Index<ExprId> for FlatProgram {
+    type Output = FlatExpr;
+
+    fn index(&self, id: ExprId) -> &FlatExpr {
+        &self.exprs[id.index()]
+    }
+}
+
+impl IndexMut<ExprId> for FlatProgram {
+    fn index_mut(&mut self, id: ExprId) -> &mut FlatExpr {
+        &mut self.exprs[id.index()]
+    }
+}
+
+impl Index<StmtId> for FlatProgram {
+    type Output = FlatStmt;
+
+    fn index(&self, id: StmtId) -> &FlatStmt {
+        &self.stmts[id.index()]
+    }
+}
+
+impl IndexMut<StmtId> for FlatProgram {
+    fn index_mut(&mut self, id: StmtId) -> &mut FlatStmt {
+        &mut self.stmts[id.index()]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        lexer::Lexer,
+        parser::Parser,
+        token::{Text, Token, TokenType},
+    };
+
+    use super::{FlatExpr, FlatProgram, FlatStmt};
+
+    #[test]
+    fn test_round_trip() {
+        let inputs = [
+            "let f = fn(a, ...rest) { return a * len(rest); }; f(1, 2, 3)",
+            "if (x < y) { x } else { y }; [1, 2.5, \"s\"][0]; {true: -a}",
+            "for (x in xs) { if (x) { break; } else { continue } }; debugger",
+            "match (x + 1) { 1 => a, _ => c }; try { f(1) } catch (e) { x = e }",
+            "let [a, b] = [1, 2]; !b",
+        ];
+        for input in inputs {
+            let prog = Parser::new(Lexer::new(input.as_bytes())).parse().unwrap();
+            let flat = FlatProgram::new(&prog);
+            assert_eq!(flat.to_program(), prog, "{}", input);
+            assert_eq!(flat.roots.len(), prog.stmts.len());
+        }
+    }
+
+    #[test]
+    fn test_children_first() {
+        let input = "let x = 1 + 2 * 3; fn(y) { y - x }";
+        let prog = Parser::new(Lexer::new(input.as_bytes())).parse().unwrap();
+        let mut flat = FlatProgram::new(&prog);
+        assert_eq!((flat.expr_count(), flat.stmt_count()), (11, 4));
+        for (id, expr) in flat.exprs() {
+            let children = match expr {
+                FlatExpr::Infix { left, right, .. } => vec![*left, *right],
+                FlatExpr::FnLiteral { parameters, .. } => parameters.clone(),
+                _ => vec![],
+            };
+            assert!(children.iter().all(|child| *child < id), "{:?}", expr);
+        }
+        for (id, stmt) in flat.stmts() {
+            if let FlatStmt::Block { stmts, .. } = stmt {
+                assert!(stmts.iter().all(|child| *child < id));
+            }
+        }
+
+        // so a single pass in order can fold constants, each operand is
+        // already folded when its parent is reached
+        let ids: Vec<_> = flat.exprs().map(|(id, _)| id).collect();
+        for id in ids {
+            let FlatExpr::Infix {
+                token,
+                left,
+                op,
+                right,
+            } = flat[id].clone()
+            else {
+                continue;
+            };
+            let (FlatExpr::IntLiteral { value: l, .. }, FlatExpr::IntLiteral { value: r, .. }) =
+                (&flat[left], &flat[right])
+            else {
+                continue;
+            };
+            let value = match op.as_bytes() {
+                b"+" => l + r,
+                b"*" => l * r,
+                _ => continue,
+            };
+            flat[id] = FlatExpr::IntLiteral {
+                token: Token {
+                    token_type: TokenType::INT,
+                    literal: Text::from_static(b""),
+                    span: token.span,
+                },
+                value,
+            };
+        }
+        assert_eq!(
+            flat.to_program().to_string(),
+            "let x = 7; fn(y) { (y - x); };"
+        );
+    }
+}
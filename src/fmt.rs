@@ -0,0 +1,476 @@
+use crate::{
+    ast::{literal_text, Expr, Program, Stmt},
+    token::Prec,
+};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum BraceStyle {
+    // `if (x) {`
+    #[default]
+    SameLine,
+    // `if (x)` with the `{` on a line of its own
+    NextLine,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct FormatConfig {
+    pub indent_width: usize,
+    pub brace_style: BraceStyle,
+    // argument, parameter and array lists longer than this are put one
+    // element per line
+    pub max_width: usize,
+}
+
+impl Default for FormatConfig {
+    fn default() -> Self {
+        FormatConfig {
+            indent_width: 4,
+            brace_style: BraceStyle::SameLine,
+            max_width: 80,
+        }
+    }
+}
+
+pub fn format_program(prog: &Program, config: &FormatConfig) -> String {
+    let mut printer = Printer::new(config, 0, 0);
+    for stmt in &prog.stmts {
+        printer.stmt(stmt, false);
+        printer.out.push('\n');
+    }
+    printer.out
+}
+
+// formats a single statement as if it were nested `level` blocks deep, so
+// tools can replace a range of statements without touching the rest
+pub fn format_stmt(stmt: &Stmt, config: &FormatConfig, level: usize) -> String {
+    let mut printer = Printer::new(config, level, level * config.indent_width);
+    printer.stmt(stmt, false);
+    printer.out
+}
+
+struct Printer<'c> {
+    config: &'c FormatConfig,
+    out: String,
+    level: usize,
+    // column the output starts at, for measuring lines before the first newline
+    start: usize,
+}
+
+impl<'c> Printer<'c> {
+    fn new(config: &'c FormatConfig, level: usize, start: usize) -> Self {
+        Printer {
+            config,
+            out: String::new(),
+            level,
+            start,
+        }
+    }
+
+    fn column(&self) -> usize {
+        match self.out.rfind('\n') {
+            Some(i) => self.out[i + 1..].chars().count(),
+            None => self.start + self.out.chars().count(),
+        }
+    }
+
+    fn newline(&mut self) {
+        self.out.push('\n');
+        let width = self.level * self.config.indent_width;
+        self.out.extend(std::iter::repeat_n(' ', width));
+    }
+
+    fn stmt(&mut self, stmt: &Stmt, last_in_block: bool) {
+        match stmt {
+            Stmt::Let { name, value, .. } => {
+                self.out.push_str("let ");
+                self.expr(name);
+                self.out.push_str(" = ");
+                self.expr(value);
+                self.out.push(';');
+            }
+            Stmt::Return { value, .. } => {
+                self.out.push_str("return ");
+                self.expr(value);
+                self.out.push(';');
+            }
+            Stmt::Expr { expr } => {
+                self.expr(expr);
+                // the last expression of a block is its value, leave it bare
+                if !last_in_block {
+                    self.out.push(';');
+                }
+            }
+            Stmt::Block { stmts, .. } => self.block_stmts(stmts),
+            Stmt::Debugger { .. } => self.out.push_str("debugger;"),
+            Stmt::Break { .. } => self.out.push_str("break;"),
+            Stmt::Continue { .. } => self.out.push_str("continue;"),
+            Stmt::For {
+                name,
+                iterable,
+                body,
+                ..
+            } => {
+                self.out.push_str("for (");
+                self.expr(name);
+                self.out.push_str(" in ");
+                self.expr(iterable);
+                self.out.push(')');
+                self.block(body);
+            }
+        }
+    }
+
+    // a block following a header like `fn(x)` or `if (x)`
+    fn block(&mut self, block: &Stmt) {
+        match self.config.brace_style {
+            BraceStyle::SameLine => self.out.push(' '),
+            BraceStyle::NextLine => self.newline(),
+        }
+        match block {
+            Stmt::Block { stmts, .. } => self.block_stmts(stmts),
+            stmt => self.block_stmts(std::slice::from_ref(stmt)),
+        }
+    }
+
+    fn block_stmts(&mut self, stmts: &[Stmt]) {
+        if stmts.is_empty() {
+            self.out.push_str("{}");
+            return;
+        }
+        self.out.push('{');
+        self.level += 1;
+        for (i, stmt) in stmts.iter().enumerate() {
+            self.newline();
+            self.stmt(stmt, i + 1 == stmts.len());
+        }
+        self.level -= 1;
+        self.newline();
+        self.out.push('}');
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Identifier { value, .. } => self.out.push_str(&String::from_utf8_lossy(value)),
+            Expr::IntLiteral { token, value } => self.out.push_str(&literal_text(token, value)),
+            Expr::FloatLiteral { token, value } => self.out.push_str(&literal_text(token, value)),
+            Expr::BoolLiteral { token, value } => self.out.push_str(&literal_text(token, value)),
+            Expr::StringLiteral { value, .. } => {
+                self.out.push('"');
+                self.out.push_str(value);
+                self.out.push('"');
+            }
+            Expr::FnLiteral {
+                parameters,
+                rest,
+                block,
+                ..
+            } => {
+                self.out.push_str("fn");
+                match rest {
+                    None => self.list('(', parameters, ')'),
+                    // a rest parameter isn't an entry, so these stay on one line
+                    Some(rest) => {
+                        let names: Vec<_> = parameters
+                            .iter()
+                            .map(|p| p.to_string())
+                            .chain([format!("...{}", rest)])
+                            .collect();
+                        self.out.push_str(&format!("({})", names.join(", ")));
+                    }
+                }
+                self.block(block);
+            }
+            Expr::Call {
+                function,
+                arguments,
+                ..
+            } => {
+                self.operand(function, Prec::FnCall as u8);
+                self.list('(', arguments, ')');
+            }
+            Expr::Index { left, index, .. } => {
+                self.operand(left, Prec::FnCall as u8);
+                self.out.push('[');
+                self.expr(index);
+                self.out.push(']');
+            }
+            Expr::Prefix { op, expr, .. } => {
+                self.out.push_str(&String::from_utf8_lossy(op));
+                self.operand(expr, Prec::Prefix as u8);
+            }
+            Expr::Infix {
+                left, op, right, ..
+            } => {
+                // operators are left associative, so a right operand of the
+                // same precedence needs parentheses
+                let prec = precedence(expr) as u8;
+                self.operand(left, prec);
+                self.out.push(' ');
+                self.out.push_str(&String::from_utf8_lossy(op));
+                self.out.push(' ');
+                self.operand(right, prec + 1);
+            }
+            Expr::If {
+                condition,
+                consequence,
+                alternative,
+                ..
+            } => {
+                self.out.push_str("if (");
+                self.expr(condition);
+                self.out.push(')');
+                self.block(consequence);
+                if let Some(alternative) = alternative {
+                    match self.config.brace_style {
+                        BraceStyle::SameLine => self.out.push(' '),
+                        BraceStyle::NextLine => self.newline(),
+                    }
+                    self.out.push_str("else");
+                    self.block(alternative);
+                }
+            }
+            Expr::Try {
+                body,
+                name,
+                handler,
+                ..
+            } => {
+                self.out.push_str("try");
+                self.block(body);
+                match self.config.brace_style {
+                    BraceStyle::SameLine => self.out.push(' '),
+                    BraceStyle::NextLine => self.newline(),
+                }
+                self.out.push_str("catch (");
+                self.expr(name);
+                self.out.push(')');
+                self.block(handler);
+            }
+            Expr::ArrayLiteral { elements, .. } => self.list('[', elements, ']'),
+            Expr::Match { subject, arms, .. } => {
+                self.out.push_str("match (");
+                self.expr(subject);
+                self.out.push_str(") {");
+                if !arms.is_empty() {
+                    self.level += 1;
+                    for arm in arms {
+                        self.newline();
+                        match &arm.pattern {
+                            Some(pattern) => self.expr(pattern),
+                            None => self.out.push('_'),
+                        }
+                        self.out.push_str(" => ");
+                        self.expr(&arm.value);
+                        self.out.push(',');
+                    }
+                    self.level -= 1;
+                    self.newline();
+                }
+                self.out.push('}');
+            }
+            Expr::Assign { name, value, .. } => {
+                self.expr(name);
+                self.out.push_str(" = ");
+                self.expr(value);
+            }
+            Expr::HashLiteral { pairs, .. } => {
+                let entries: Vec<_> = pairs
+                    .iter()
+                    .map(|(key, value)| (Some(key), value))
+                    .collect();
+                self.entries('{', &entries, '}');
+            }
+        }
+    }
+
+    // an operand that has to bind at least as tight as `min`
+    fn operand(&mut self, expr: &Expr, min: u8) {
+        let compound = matches!(
+            expr,
+            Expr::If { .. } | Expr::FnLiteral { .. } | Expr::Match { .. } | Expr::Try { .. }
+        );
+        if compound || (precedence(expr) as u8) < min {
+            self.out.push('(');
+            self.expr(expr);
+            self.out.push(')');
+        } else {
+            self.expr(expr);
+        }
+    }
+
+    fn list(&mut self, open: char, items: &[Expr], close: char) {
+        let entries: Vec<_> = items.iter().map(|item| (None, item)).collect();
+        self.entries(open, &entries, close);
+    }
+
+    // list elements, or `key: value` pairs when there's a key
+    fn entries(&mut self, open: char, items: &[(Option<&Expr>, &Expr)], close: char) {
+        let rendered = items
+            .iter()
+            .map(|&(key, value)| {
+                let mut printer = Printer::new(self.config, self.level, self.column());
+                printer.entry(key, value);
+                printer.out
+            })
+            .collect::<Vec<_>>();
+        let flat = format!("{}{}{}", open, rendered.join(", "), close);
+        // only the last element may span lines, like a trailing fn literal
+        let multiline = rendered.iter().rev().skip(1).any(|r| r.contains('\n'));
+        let first_line = flat.lines().next().unwrap_or_default();
+        if !multiline && self.column() + first_line.chars().count() <= self.config.max_width {
+            self.out.push_str(&flat);
+            return;
+        }
+
+        self.out.push(open);
+        self.level += 1;
+        for (i, &(key, value)) in items.iter().enumerate() {
+            self.newline();
+            self.entry(key, value);
+            if i + 1 < items.len() {
+                self.out.push(',');
+            }
+        }
+        self.level -= 1;
+        self.newline();
+        self.out.push(close);
+    }
+
+    fn entry(&mut self, key: Option<&Expr>, value: &Expr) {
+        if let Some(key) = key {
+            self.expr(key);
+            self.out.push_str(": ");
+        }
+        self.expr(value);
+    }
+}
+
+fn precedence(expr: &Expr) -> Prec {
+    match expr {
+        Expr::Prefix { .. } => Prec::Prefix,
+        Expr::Infix { token, .. } => token.token_type.precedence(),
+        Expr::Call { .. } => Prec::FnCall,
+        Expr::Assign { .. } => Prec::Lowest,
+        // literals, identifiers and everything wrapped in delimiters
+        _ => Prec::Index,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_program, format_stmt, BraceStyle, FormatConfig};
+    use crate::{lexer::Lexer, parser::Parser};
+
+    fn format(input: &str, config: &FormatConfig) -> String {
+        let prog = Parser::new(Lexer::new(input.as_bytes())).parse().unwrap();
+        format_program(&prog, config)
+    }
+
+    #[test]
+    fn test_format_program() {
+        let input = "let add=fn(a,b){a+b};let x=if(add(1,2)>2){-(1+2)*3}else{ };
+            add(x)[0]; return (a - (b - c)) - d / (e * f);";
+        let expected = "let add = fn(a, b) {
+    a + b
+};
+let x = if (add(1, 2) > 2) {
+    -(1 + 2) * 3
+} else {};
+add(x)[0];
+return a - (b - c) - d / (e * f);
+";
+        assert_eq!(format(input, &FormatConfig::default()), expected);
+    }
+
+    #[test]
+    fn test_brace_style() {
+        let config = FormatConfig {
+            indent_width: 2,
+            brace_style: BraceStyle::NextLine,
+            ..Default::default()
+        };
+        let expected = "let f = fn(x)
+{
+  if (x)
+  {
+    1
+  }
+  else
+  {
+    2
+  }
+};
+";
+        assert_eq!(
+            format("let f = fn(x) { if (x) { 1 } else { 2 } }", &config),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_wrapping() {
+        let config = FormatConfig {
+            max_width: 24,
+            ..Default::default()
+        };
+        let input = "let xs = [first, second, third]; let y = f(g(1), fn(x) { x });
+            {\"first\":1,\"second\":{}}; for(x in xs){puts(x)}";
+        let expected = "let xs = [
+    first,
+    second,
+    third
+];
+let y = f(g(1), fn(x) {
+    x
+});
+{
+    \"first\": 1,
+    \"second\": {}
+};
+for (x in xs) {
+    puts(x)
+}
+";
+        assert_eq!(format(input, &config), expected);
+    }
+
+    #[test]
+    fn test_reparses() {
+        let inputs = [
+            "let a = fn(x, y) { let z = x * (y + 1); return z; }; a(1, 2);",
+            "if (!(a == b)) { -a } else { [1, 2 * 3][0] }; (fn(x) { x })(1);",
+            "(if (a) { b } else { c }) + 1; a - (b + c); --a; f(x)(y)[z]",
+            "a = b = 1; (a = 2) * 3; -(a = 1); f(a = 1)",
+            "match (x) { 1 => fn(y) { y }, _ => 2 }(3) + match (y) { }",
+            "let f = fn(a, b, ...rest) { rest }; fn(...xs) { xs }",
+            "try { 1 / x } catch (e) { puts(e); 0 } + 1; let y = try { f() } catch (err) { }",
+        ];
+        for input in inputs {
+            for max_width in [80, 10] {
+                let config = FormatConfig {
+                    max_width,
+                    ..Default::default()
+                };
+                let formatted = format(input, &config);
+                let prog = Parser::new(Lexer::new(input.as_bytes())).parse().unwrap();
+                let reparsed = Parser::new(Lexer::new(formatted.as_bytes()))
+                    .parse()
+                    .unwrap();
+                assert_eq!(reparsed.to_string(), prog.to_string(), "{}", formatted);
+                assert_eq!(format(&formatted, &config), formatted);
+            }
+        }
+    }
+
+    #[test]
+    fn test_format_stmt() {
+        let prog = Parser::new(Lexer::new("if (a) { b }".as_bytes()))
+            .parse()
+            .unwrap();
+        let config = FormatConfig::default();
+        assert_eq!(
+            format_stmt(&prog.stmts[0], &config, 1),
+            "if (a) {\n        b\n    };"
+        );
+    }
+}
@@ -0,0 +1,363 @@
+use crate::{
+    ast::{Expr, Program, Stmt},
+    rc::Rc,
+    token::{Span, Text, Token, TokenType},
+};
+
+// replaces the expressions whose operands are all literals with the literal
+// the evaluator would get for them, `2 * 3 + x` becomes `6 + x`, drops the
+// branch of an `if` that a literal condition never takes and the statements
+// after a `return`, `break` or `continue` in the same block
+//
+// expressions that fail when run, like dividing by zero or a sum that
+// overflows, are left for the engine to report
+//
+// returns where the first of each run of dropped statements was, for warning
+// about them
+pub fn fold_program(prog: &mut Program) -> Vec<Span> {
+    let mut folder = Folder::default();
+    folder.stmts(&mut prog.stmts);
+    folder.dropped
+}
+
+#[derive(Default)]
+struct Folder {
+    dropped: Vec<Span>,
+}
+
+impl Folder {
+    fn stmts(&mut self, stmts: &mut Vec<Stmt>) {
+        let exits = stmts.iter().position(|stmt| {
+            matches!(
+                stmt,
+                Stmt::Return { .. } | Stmt::Break { .. } | Stmt::Continue { .. }
+            )
+        });
+        if let Some(exit) = exits.filter(|exit| exit + 1 < stmts.len()) {
+            self.dropped.push(stmts[exit + 1].first_token().span);
+            stmts.truncate(exit + 1);
+        }
+        for stmt in stmts {
+            self.stmt(stmt);
+        }
+    }
+
+    fn stmt(&mut self, stmt: &mut Stmt) {
+        match stmt {
+            Stmt::Let { value: expr, .. }
+            | Stmt::Return { value: expr, .. }
+            | Stmt::Expr { expr } => self.expr(expr),
+            Stmt::Block { stmts, .. } => self.stmts(stmts),
+            Stmt::For { iterable, body, .. } => {
+                self.expr(iterable);
+                self.stmt(body);
+            }
+            Stmt::Debugger { .. } | Stmt::Break { .. } | Stmt::Continue { .. } => {}
+        }
+    }
+
+    fn expr(&mut self, expr: &mut Expr) {
+        match expr {
+            Expr::Identifier { .. }
+            | Expr::IntLiteral { .. }
+            | Expr::FloatLiteral { .. }
+            | Expr::BoolLiteral { .. }
+            | Expr::StringLiteral { .. } => {}
+            Expr::FnLiteral { block, .. } => {
+                // a body already shared with closures is left as it is
+                if let Some(block) = Rc::get_mut(block) {
+                    self.stmt(block);
+                }
+            }
+            Expr::Call {
+                function,
+                arguments,
+                ..
+            } => {
+                self.expr(function);
+                for argument in arguments {
+                    self.expr(argument);
+                }
+            }
+            Expr::Prefix {
+                token,
+                op,
+                expr: operand,
+            } => {
+                self.expr(operand);
+                let value = match (&op[..], constant(operand)) {
+                    (b"-", Some(Constant::Int(value))) => value.checked_neg().map(Constant::Int),
+                    (b"-", Some(Constant::Float(value))) => Some(Constant::Float(-value)),
+                    (b"!", Some(Constant::Bool(value))) => Some(Constant::Bool(!value)),
+                    _ => None,
+                };
+                if let Some(folded) = value.and_then(|value| literal(value, token)) {
+                    *expr = folded;
+                }
+            }
+            Expr::Infix {
+                left, op, right, ..
+            } => {
+                self.expr(left);
+                self.expr(right);
+                let value = match (constant(left), constant(right)) {
+                    (Some(l), Some(r)) => fold_infix(op, l, r),
+                    _ => None,
+                };
+                if let Some(folded) = value.and_then(|value| literal(value, left.first_token())) {
+                    *expr = folded;
+                }
+            }
+            Expr::If {
+                condition,
+                consequence,
+                alternative,
+                ..
+            } => {
+                self.expr(condition);
+                self.stmt(consequence);
+                if let Some(alternative) = alternative {
+                    self.stmt(alternative);
+                }
+                if let Expr::BoolLiteral { token, value } = &mut **condition {
+                    if *value {
+                        *alternative = None;
+                    } else if let Some(taken) = alternative.take() {
+                        // `if (false) { a } else { b }` runs as `if (true) { b }`
+                        *consequence = taken;
+                        *value = true;
+                        token.literal = Text::from_static(b"");
+                    }
+                }
+            }
+            Expr::ArrayLiteral { elements, .. } => {
+                for element in elements {
+                    self.expr(element);
+                }
+            }
+            Expr::Index { left, index, .. } => {
+                self.expr(left);
+                self.expr(index);
+            }
+            Expr::HashLiteral { pairs, .. } => {
+                for (key, value) in pairs {
+                    self.expr(key);
+                    self.expr(value);
+                }
+            }
+            Expr::Match { subject, arms, .. } => {
+                self.expr(subject);
+                for arm in arms {
+                    if let Some(pattern) = &mut arm.pattern {
+                        self.expr(pattern);
+                    }
+                    self.expr(&mut arm.value);
+                }
+            }
+            Expr::Try { body, handler, .. } => {
+                self.stmt(body);
+                self.stmt(handler);
+            }
+            Expr::Assign { value, .. } => self.expr(value),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Constant {
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+}
+
+impl Constant {
+    // an integer meeting a float is promoted to one
+    fn as_float(self) -> Option<f64> {
+        match self {
+            Constant::Int(value) => Some(value as f64),
+            Constant::Float(value) => Some(value),
+            Constant::Bool(_) => None,
+        }
+    }
+}
+
+fn constant(expr: &Expr) -> Option<Constant> {
+    match expr {
+        Expr::IntLiteral { value, .. } => Some(Constant::Int(*value)),
+        Expr::FloatLiteral { value, .. } => Some(Constant::Float(*value)),
+        Expr::BoolLiteral { value, .. } => Some(Constant::Bool(*value)),
+        _ => None,
+    }
+}
+
+// the same operators on the same types as the evaluator
+fn fold_infix(op: &[u8], left: Constant, right: Constant) -> Option<Constant> {
+    Some(match (left, right) {
+        (Constant::Int(l), Constant::Int(r)) => match op {
+            b"*" => Constant::Int(l.checked_mul(r)?),
+            b"-" => Constant::Int(l.checked_sub(r)?),
+            b"+" => Constant::Int(l.checked_add(r)?),
+            b"/" => Constant::Int(l.checked_div(r)?),
+            b"<" => Constant::Bool(l < r),
+            b">" => Constant::Bool(l > r),
+            b"==" => Constant::Bool(l == r),
+            b"!=" => Constant::Bool(l != r),
+            _ => return None,
+        },
+        (Constant::Bool(l), Constant::Bool(r)) => match op {
+            b"==" => Constant::Bool(l == r),
+            b"!=" => Constant::Bool(l != r),
+            _ => return None,
+        },
+        (l, r) => {
+            let (l, r) = (l.as_float()?, r.as_float()?);
+            match op {
+                b"*" => Constant::Float(l * r),
+                b"-" => Constant::Float(l - r),
+                b"+" => Constant::Float(l + r),
+                b"/" => Constant::Float(l / r),
+                b"<" => Constant::Bool(l < r),
+                b">" => Constant::Bool(l > r),
+                b"==" => Constant::Bool(l == r),
+                b"!=" => Constant::Bool(l != r),
+                _ => return None,
+            }
+        }
+    })
+}
+
+// a literal placed where the folded expression started, its token has no
+// text so it's shown by its value, floats that can't be written in source
+// aren't folded
+fn literal(value: Constant, at: &Token) -> Option<Expr> {
+    let token = |token_type| Token {
+        token_type,
+        literal: Text::from_static(b""),
+        span: at.span,
+    };
+    Some(match value {
+        Constant::Int(value) => Expr::IntLiteral {
+            token: token(TokenType::INT),
+            value,
+        },
+        Constant::Float(value) if value.is_finite() => Expr::FloatLiteral {
+            token: token(TokenType::FLOAT),
+            value,
+        },
+        Constant::Float(_) => return None,
+        Constant::Bool(value) => Expr::BoolLiteral {
+            token: token(if value {
+                TokenType::TRUE
+            } else {
+                TokenType::FALSE
+            }),
+            value,
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        ast::Program, compiler::Compiler, env::Env, evaluator::Evaluator, lexer::Lexer,
+        parser::Parser, sandbox::SandboxConfig, vm::Vm,
+    };
+
+    use super::fold_program;
+
+    fn folded(input: &str) -> Program {
+        let mut prog = Parser::new(Lexer::new(input.as_bytes())).parse().unwrap();
+        assert!(fold_program(&mut prog).is_empty(), "{}", input);
+        prog
+    }
+
+    #[test]
+    fn test_fold() {
+        let tests = [
+            ("2 * 3 + x", "(6 + x);"),
+            ("x + 2 * 3", "(x + 6);"),
+            ("1 + 2 + x", "(3 + x);"),
+            // `x + 1 + 2` is `(x + 1) + 2`, which has no constant operands
+            ("x + 1 + 2", "((x + 1) + 2);"),
+            ("-(1 + 2)", "-3;"),
+            ("0xFF + 1", "256;"),
+            ("1 + 0.5", "1.5;"),
+            ("10 / 4", "2;"),
+            ("2.0 * 3", "6.0;"),
+            ("1 < 2", "true;"),
+            ("1 == 1.0", "true;"),
+            ("!(true == false)", "true;"),
+            (
+                "let f = fn(x) { x * (60 * 60) };",
+                "let f = fn(x) { (x * 3600); };",
+            ),
+            ("[1 + 1, {2 * 2: 3 - 3}][0]", "([2, {4: 0}][0]);"),
+            ("if (1 < 2) { a } else { b }", "if (true) { a; };"),
+            ("if (1 > 2) { a } else { b }", "if (true) { b; };"),
+            ("if (false) { a }", "if (false) { a; };"),
+            (
+                "if (x) { 1 + 1 } else { 2 + 2 }",
+                "if (x) { 2; } else { 4; };",
+            ),
+            // failing operations are left for the engine to report
+            ("1 / 0", "(1 / 0);"),
+            ("1.0 / 0", "(1.0 / 0);"),
+            ("9223372036854775807 + 1", "(9223372036854775807 + 1);"),
+            ("1 + true", "(1 + true);"),
+            ("-true", "(-true);"),
+            ("\"a\" + \"b\"", "(\"a\" + \"b\");"),
+        ];
+        for (input, expected) in tests {
+            assert_eq!(folded(input).to_string(), expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_dead_code() {
+        let input = "let f = fn(x) {\n  return x;\n  puts(x);\n  x\n};\nfor (x in [1]) { if (x) { break; 1 } }";
+        let mut prog = Parser::new(Lexer::new(input.as_bytes())).parse().unwrap();
+        let dropped = fold_program(&mut prog);
+        assert_eq!(
+            prog.to_string(),
+            "let f = fn(x) { return x; }; for (x in [1]) { if (x) { break; }; }"
+        );
+        let dropped: Vec<_> = dropped.iter().map(|span| span.to_string()).collect();
+        assert_eq!(dropped, ["3:3", "6:34"]);
+
+        // a return as the last statement drops nothing
+        assert_eq!(
+            folded("fn() { return 1; }").to_string(),
+            "fn() { return 1; };"
+        );
+    }
+
+    #[test]
+    fn test_same_results() {
+        let tests = [
+            "let x = 4; 2 * 3 + x",
+            "let f = fn(n) { if (1 > 2) { 0 } else { n * (2 + 3) } }; f(2)",
+            "if (!true) { 1 }",
+            "-(2.5 * 2) + 0xF",
+            "match (1 + 1) { 2 => 1 < 2, _ => false }",
+        ];
+        for input in tests {
+            let prog = Parser::new(Lexer::new(input.as_bytes())).parse().unwrap();
+            let expected = Evaluator::new(SandboxConfig::default())
+                .eval_program(&prog, &mut Env::new())
+                .unwrap()
+                .to_string();
+
+            let prog = folded(input);
+            let evaluated = Evaluator::new(SandboxConfig::default())
+                .eval_program(&prog, &mut Env::new())
+                .unwrap();
+            assert_eq!(evaluated.to_string(), expected, "{}", input);
+
+            let mut compiler = Compiler::new();
+            if compiler.compile(&prog).is_ok() {
+                let result = Vm::new(compiler.bytecode()).run().unwrap();
+                assert_eq!(result.to_string(), expected, "{}", input);
+            }
+        }
+    }
+}
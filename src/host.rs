@@ -0,0 +1,303 @@
+use core::fmt;
+use std::{
+    collections::{BTreeMap, HashMap},
+    hash::Hash,
+};
+
+use crate::{
+    object::{HashKey, Object},
+    rc::Rc,
+};
+
+#[cfg(not(feature = "sync"))]
+pub type HostFn<'a> = Box<dyn Fn(&[Object<'a>]) -> Result<Object<'a>, &'static str> + 'a>;
+#[cfg(feature = "sync")]
+pub type HostFn<'a> =
+    Box<dyn Fn(&[Object<'a>]) -> Result<Object<'a>, &'static str> + Send + Sync + 'a>;
+
+// closures bound with the `sync` feature have to be shareable across threads
+#[cfg(not(feature = "sync"))]
+pub trait MaybeSync {}
+#[cfg(not(feature = "sync"))]
+impl<T> MaybeSync for T {}
+#[cfg(feature = "sync")]
+pub trait MaybeSync: Send + Sync {}
+#[cfg(feature = "sync")]
+impl<T: Send + Sync> MaybeSync for T {}
+
+// a rust function callable from monkey, see `Interpreter::bind`
+pub struct HostFunction<'a> {
+    pub name: &'a str,
+    pub func: HostFn<'a>,
+}
+
+impl fmt::Debug for HostFunction<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "HostFunction({})", self.name)
+    }
+}
+
+/// Conversion of a Monkey value into a Rust argument of a host function.
+pub trait FromMonkey<'a>: Sized {
+    fn from_monkey(obj: Object<'a>) -> Result<Self, &'static str>;
+}
+
+/// Conversion of the value a host function returns back into Monkey.
+pub trait IntoMonkey<'a> {
+    fn into_monkey(self) -> Object<'a>;
+}
+
+impl<'a> FromMonkey<'a> for Object<'a> {
+    fn from_monkey(obj: Object<'a>) -> Result<Self, &'static str> {
+        Ok(obj)
+    }
+}
+
+impl<'a> FromMonkey<'a> for i64 {
+    fn from_monkey(obj: Object<'a>) -> Result<Self, &'static str> {
+        match obj {
+            Object::Integer(value) => Ok(value),
+            _ => Err("expected an integer argument"),
+        }
+    }
+}
+
+// integers are promoted, like when they meet a float in monkey
+impl<'a> FromMonkey<'a> for f64 {
+    fn from_monkey(obj: Object<'a>) -> Result<Self, &'static str> {
+        match obj {
+            Object::Float(value) => Ok(value),
+            Object::Integer(value) => Ok(value as f64),
+            _ => Err("expected a number argument"),
+        }
+    }
+}
+
+impl<'a> FromMonkey<'a> for bool {
+    fn from_monkey(obj: Object<'a>) -> Result<Self, &'static str> {
+        match obj {
+            Object::Bool(value) => Ok(value),
+            _ => Err("expected a boolean argument"),
+        }
+    }
+}
+
+impl<'a> FromMonkey<'a> for String {
+    fn from_monkey(obj: Object<'a>) -> Result<Self, &'static str> {
+        match obj {
+            Object::Str(value) => Ok(value.to_string()),
+            _ => Err("expected a string argument"),
+        }
+    }
+}
+
+impl<'a, T: FromMonkey<'a>> FromMonkey<'a> for Vec<T> {
+    fn from_monkey(obj: Object<'a>) -> Result<Self, &'static str> {
+        match obj {
+            Object::Array(elements) => elements.iter().cloned().map(T::from_monkey).collect(),
+            _ => Err("expected an array argument"),
+        }
+    }
+}
+
+impl<'a, K, V> FromMonkey<'a> for HashMap<K, V>
+where
+    K: FromMonkey<'a> + Eq + Hash,
+    V: FromMonkey<'a>,
+{
+    fn from_monkey(obj: Object<'a>) -> Result<Self, &'static str> {
+        match obj {
+            Object::Hash(pairs) => pairs
+                .iter()
+                .map(|(key, value)| {
+                    let key = K::from_monkey(key.clone().into())?;
+                    Ok((key, V::from_monkey(value.clone())?))
+                })
+                .collect(),
+            _ => Err("expected a hash argument"),
+        }
+    }
+}
+
+impl<'a> IntoMonkey<'a> for Object<'a> {
+    fn into_monkey(self) -> Object<'a> {
+        self
+    }
+}
+
+impl<'a> IntoMonkey<'a> for i64 {
+    fn into_monkey(self) -> Object<'a> {
+        Object::Integer(self)
+    }
+}
+
+impl<'a> IntoMonkey<'a> for f64 {
+    fn into_monkey(self) -> Object<'a> {
+        Object::Float(self)
+    }
+}
+
+impl<'a> IntoMonkey<'a> for bool {
+    fn into_monkey(self) -> Object<'a> {
+        Object::Bool(self)
+    }
+}
+
+impl<'a> IntoMonkey<'a> for String {
+    fn into_monkey(self) -> Object<'a> {
+        Object::Str(self.into())
+    }
+}
+
+impl<'a> IntoMonkey<'a> for &str {
+    fn into_monkey(self) -> Object<'a> {
+        Object::Str(self.into())
+    }
+}
+
+impl<'a> IntoMonkey<'a> for () {
+    fn into_monkey(self) -> Object<'a> {
+        Object::Null
+    }
+}
+
+impl<'a, T: IntoMonkey<'a>> IntoMonkey<'a> for Vec<T> {
+    fn into_monkey(self) -> Object<'a> {
+        Object::Array(Rc::new(self.into_iter().map(T::into_monkey).collect()))
+    }
+}
+
+// keys are limited to the types monkey can hash
+impl<'a, K: Into<HashKey>, V: IntoMonkey<'a>> IntoMonkey<'a> for HashMap<K, V> {
+    fn into_monkey(self) -> Object<'a> {
+        let pairs: BTreeMap<_, _> = self
+            .into_iter()
+            .map(|(key, value)| (key.into(), value.into_monkey()))
+            .collect();
+        Object::Hash(Rc::new(pairs))
+    }
+}
+
+impl<'a, T: IntoMonkey<'a>> IntoMonkey<'a> for Option<T> {
+    fn into_monkey(self) -> Object<'a> {
+        self.map_or(Object::Null, T::into_monkey)
+    }
+}
+
+// the same conversions as `From` and `TryFrom`, `Object::from(2)` or
+// `i64::try_from(obj)`
+macro_rules! impl_from {
+    ($($ty:ty),*) => {$(
+        impl<'a> From<$ty> for Object<'a> {
+            fn from(value: $ty) -> Self {
+                value.into_monkey()
+            }
+        }
+
+        impl<'a> TryFrom<Object<'a>> for $ty {
+            type Error = &'static str;
+
+            fn try_from(obj: Object<'a>) -> Result<Self, Self::Error> {
+                <$ty>::from_monkey(obj)
+            }
+        }
+    )*};
+}
+
+impl_from!(i64, f64, bool, String);
+
+impl<'a> From<&str> for Object<'a> {
+    fn from(value: &str) -> Self {
+        value.into_monkey()
+    }
+}
+
+impl<'a, T: IntoMonkey<'a>> From<Vec<T>> for Object<'a> {
+    fn from(value: Vec<T>) -> Self {
+        value.into_monkey()
+    }
+}
+
+impl<'a, T: FromMonkey<'a>> TryFrom<Object<'a>> for Vec<T> {
+    type Error = &'static str;
+
+    fn try_from(obj: Object<'a>) -> Result<Self, Self::Error> {
+        Vec::from_monkey(obj)
+    }
+}
+
+impl<'a, K: Into<HashKey>, V: IntoMonkey<'a>> From<HashMap<K, V>> for Object<'a> {
+    fn from(value: HashMap<K, V>) -> Self {
+        value.into_monkey()
+    }
+}
+
+impl<'a, K, V> TryFrom<Object<'a>> for HashMap<K, V>
+where
+    K: FromMonkey<'a> + Eq + Hash,
+    V: FromMonkey<'a>,
+{
+    type Error = &'static str;
+
+    fn try_from(obj: Object<'a>) -> Result<Self, Self::Error> {
+        HashMap::from_monkey(obj)
+    }
+}
+
+/// Arguments of a Monkey function called from Rust, implemented for tuples
+/// of up to four [`IntoMonkey`] values.
+pub trait IntoArgs<'a> {
+    fn into_args(self) -> Vec<Object<'a>>;
+}
+
+macro_rules! impl_into_args {
+    ($($arg:ident),*) => {
+        impl<'a, $($arg: IntoMonkey<'a>),*> IntoArgs<'a> for ($($arg,)*) {
+            #[allow(non_snake_case)]
+            fn into_args(self) -> Vec<Object<'a>> {
+                let ($($arg,)*) = self;
+                vec![$($arg.into_monkey()),*]
+            }
+        }
+    };
+}
+
+impl_into_args!();
+impl_into_args!(A);
+impl_into_args!(A, B);
+impl_into_args!(A, B, C);
+impl_into_args!(A, B, C, D);
+
+/// Rust closures that can be bound as Monkey functions, implemented for
+/// closures of up to four [`FromMonkey`] arguments returning an
+/// [`IntoMonkey`] value. `Args` only tells the implementations apart.
+pub trait IntoHostFn<'a, Args> {
+    fn into_host_fn(self) -> HostFn<'a>;
+}
+
+macro_rules! impl_into_host_fn {
+    ($($arg:ident),*) => {
+        impl<'a, F, R, $($arg),*> IntoHostFn<'a, ($($arg,)*)> for F
+        where
+            F: Fn($($arg),*) -> R + MaybeSync + 'a,
+            R: IntoMonkey<'a>,
+            $($arg: FromMonkey<'a>,)*
+        {
+            #[allow(non_snake_case)]
+            fn into_host_fn(self) -> HostFn<'a> {
+                Box::new(move |args| {
+                    let [$($arg),*] = args else {
+                        return Err("wrong number of arguments");
+                    };
+                    Ok(self($($arg::from_monkey($arg.clone())?),*).into_monkey())
+                })
+            }
+        }
+    };
+}
+
+impl_into_host_fn!();
+impl_into_host_fn!(A);
+impl_into_host_fn!(A, B);
+impl_into_host_fn!(A, B, C);
+impl_into_host_fn!(A, B, C, D);
@@ -0,0 +1,169 @@
+use std::{
+    cmp::Ordering,
+    collections::HashSet,
+    fmt,
+    hash::{Hash, Hasher},
+    sync::{Arc, Mutex, OnceLock},
+};
+
+// a name interned for the whole process, the parser gives every identifier
+// one so bindings are looked up by a pointer instead of hashing and
+// comparing the bytes of the name
+//
+// symbols count references to their name, a name is freed once no symbol
+// refers to it, so programs that come and go don't pile up names. it's an
+// `Arc` even without `sync`, programs on different threads share names
+#[derive(Clone)]
+pub struct Symbol(Arc<[u8]>);
+
+#[derive(Default)]
+struct Interner {
+    names: HashSet<Arc<[u8]>>,
+    // how many names were in use the last time unused ones were dropped
+    live: usize,
+}
+
+fn interner() -> &'static Mutex<Interner> {
+    static INTERNER: OnceLock<Mutex<Interner>> = OnceLock::new();
+    INTERNER.get_or_init(Mutex::default)
+}
+
+impl Interner {
+    // drops the names only the interner refers to once there are twice as
+    // many as were in use last time, so each name is looked at a bounded
+    // number of times
+    fn sweep(&mut self) {
+        if self.names.len() > 2 * self.live.max(64) {
+            self.names.retain(|name| Arc::strong_count(name) > 1);
+            self.live = self.names.len();
+        }
+    }
+}
+
+impl Symbol {
+    // the same symbol for the same name, wherever it comes from
+    pub fn intern(name: &[u8]) -> Symbol {
+        let mut interner = interner().lock().unwrap_or_else(|err| err.into_inner());
+        if let Some(name) = interner.names.get(name) {
+            return Symbol(Arc::clone(name));
+        }
+        interner.sweep();
+        let name: Arc<[u8]> = name.into();
+        interner.names.insert(Arc::clone(&name));
+        Symbol(name)
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+// names are interned, so the same name is the same pointer
+impl PartialEq for Symbol {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for Symbol {}
+
+impl Hash for Symbol {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        Arc::as_ptr(&self.0).cast::<u8>().hash(state)
+    }
+}
+
+// by name, which doesn't depend on where the names were allocated
+impl Ord for Symbol {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.cmp(&other.0)
+    }
+}
+
+impl PartialOrd for Symbol {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", String::from_utf8_lossy(self.as_bytes()))
+    }
+}
+
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Symbol({})", self)
+    }
+}
+
+// what bindings can be looked up by, a symbol or the bytes of a name, which
+// are interned first
+pub trait Name {
+    fn symbol(&self) -> Symbol;
+}
+
+impl Name for Symbol {
+    fn symbol(&self) -> Symbol {
+        self.clone()
+    }
+}
+
+impl<T: AsRef<[u8]> + ?Sized> Name for T {
+    fn symbol(&self) -> Symbol {
+        Symbol::intern(self.as_ref())
+    }
+}
+
+// written as the name, so serialized programs stay readable
+#[cfg(feature = "serde")]
+impl ::serde::Serialize for Symbol {
+    fn serialize<S: ::serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> ::serde::Deserialize<'de> for Symbol {
+    fn deserialize<D: ::serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        Ok(Symbol::intern(name.as_bytes()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::{Name, Symbol};
+
+    #[test]
+    fn test_intern() {
+        let a = Symbol::intern(b"interned_a");
+        assert_eq!(Symbol::intern(b"interned_a"), a);
+        assert_ne!(Symbol::intern(b"interned_b"), a);
+        assert_eq!(a.as_bytes(), b"interned_a");
+        assert_eq!(a.to_string(), "interned_a");
+        assert_eq!(format!("{:?}", a), "Symbol(interned_a)");
+
+        assert_eq!(b"interned_a".symbol(), a);
+        assert_eq!("interned_a".symbol(), a);
+        assert_eq!(a.symbol(), a);
+        assert!(a < Symbol::intern(b"interned_b"));
+    }
+
+    #[test]
+    fn test_freed() {
+        let name = Arc::downgrade(&Symbol::intern(b"freed_name").0);
+        // other tests intern names at the same time, so there's no telling
+        // which new name makes the interner drop the unused ones
+        let freed = (0..100_000).any(|i| {
+            Symbol::intern(format!("freed_{}", i).as_bytes());
+            name.upgrade().is_none()
+        });
+        assert!(freed);
+        let again = Symbol::intern(b"freed_name");
+        assert_eq!(again.as_bytes(), b"freed_name");
+    }
+}
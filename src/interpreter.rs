@@ -0,0 +1,776 @@
+use core::fmt;
+use std::{io::Write, sync::Mutex};
+
+use crate::{
+    ast::Program,
+    diagnostic::Diagnostic,
+    engine::Engine,
+    env::Env,
+    evaluator::{Evaluator, Truthiness},
+    host::{FromMonkey, HostFunction, IntoArgs, IntoHostFn, MaybeSync},
+    lexer::Lexer,
+    object::Object,
+    parser::{ParseError, Parser},
+    rc::Rc,
+    sandbox::{CancellationToken, SandboxConfig},
+    token::Span,
+    vm::{VmConfig, VmState},
+};
+
+/// Error returned by [`Interpreter::eval`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    /// The source didn't parse, one error per problem found.
+    Parse(Vec<ParseError>),
+    /// Evaluation failed, with where in the source when it's known.
+    Runtime(Diagnostic),
+}
+
+impl Error {
+    /// Where the error is, the first one for parse errors, None when it
+    /// isn't about a place in the source.
+    ///
+    /// ```
+    /// use interpreter_book_in_rust::eval;
+    ///
+    /// let span = eval("let a = 1;\na + true").unwrap_err().span().unwrap();
+    /// assert_eq!((span.line, span.column), (2, 3));
+    /// ```
+    pub fn span(&self) -> Option<Span> {
+        let span = match self {
+            Error::Parse(errors) => errors.first().map(|err| err.span),
+            Error::Runtime(diagnostic) => diagnostic.span,
+        };
+        // tokens that weren't read from the source have no position
+        span.filter(|span| span.line > 0)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Parse(errors) => {
+                let messages: Vec<_> = errors.iter().map(|err| err.to_string()).collect();
+                write!(f, "parse error: {}", messages.join("; "))
+            }
+            Error::Runtime(diagnostic) => write!(f, "runtime error: {}", diagnostic.message),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Evaluates `source` on its own, with no bindings but the builtins, and
+/// returns the value of its last statement.
+///
+/// ```
+/// use interpreter_book_in_rust::eval;
+///
+/// assert_eq!(eval("len([1, 2, 3]) * 2").unwrap().to_string(), "6");
+/// assert_eq!(
+///     eval("1 +").unwrap_err().to_string(),
+///     "parse error: expected an operand for +, got the end of the input"
+/// );
+/// ```
+pub fn eval(source: &str) -> Result<Object<'static>, Error> {
+    Interpreter::new().eval(source)
+}
+
+/// Evaluates Monkey source and keeps its global bindings between calls.
+///
+/// Functions keep their body along with its text, so a source can be
+/// dropped once it's evaluated, `'a` is how long the values and functions
+/// the host binds live.
+///
+/// ```
+/// use interpreter_book_in_rust::{Interpreter, Object};
+///
+/// let mut interp = Interpreter::new();
+/// interp.set_global("x", Object::Integer(2));
+/// let source = String::from("let double = fn(n) { n * 2 };");
+/// interp.eval(&source).unwrap();
+/// drop(source);
+/// assert_eq!(interp.eval("double(x)").unwrap().to_string(), "4");
+/// ```
+///
+/// It's configured with the `with_` methods, before evaluating anything:
+///
+/// ```
+/// use interpreter_book_in_rust::{
+///     evaluator::Truthiness, sandbox::SandboxConfig, Interpreter, Object,
+/// };
+///
+/// let mut out = Vec::new();
+/// let mut interp = Interpreter::new()
+///     .with_truthiness(Truthiness::Lenient)
+///     .with_sandbox(SandboxConfig {
+///         max_steps: Some(10_000),
+///         ..Default::default()
+///     })
+///     .with_output(&mut out)
+///     .with_global("limit", Object::Integer(3));
+/// interp.eval("if (limit) { puts(limit * 2) }").unwrap();
+/// drop(interp);
+/// assert_eq!(out, b"6\n");
+/// ```
+#[derive(Default)]
+pub struct Interpreter<'a> {
+    env: Env<'a>,
+    // set by `with_engine` to run source on the vm, which keeps bindings of
+    // its own instead of `env`
+    vm: Option<Box<dyn Compiled<'a> + 'a>>,
+    sandbox: SandboxConfig,
+    truthiness: Truthiness,
+    // what `with_output` and `with_global` bound, `reset` binds it again
+    preloaded: Vec<(&'a str, Object<'a>)>,
+}
+
+// what an interpreter needs from the vm, only interpreters of `'static`
+// source can have one since everything the vm makes is `'static`
+trait Compiled<'a>: MaybeSync {
+    fn run(
+        &mut self,
+        prog: &Program,
+        config: VmConfig,
+        token: &CancellationToken,
+    ) -> Result<Object<'a>, Error>;
+    fn call(
+        &mut self,
+        function: Object<'a>,
+        args: Vec<Object<'a>>,
+        config: VmConfig,
+    ) -> Result<Object<'a>, Error>;
+    fn set(&mut self, name: &[u8], value: Object<'a>);
+    fn get(&self, name: &[u8]) -> Option<Object<'a>>;
+    fn bindings(&self) -> Vec<(Vec<u8>, Object<'a>)>;
+    fn reset(&mut self);
+}
+
+impl Compiled<'static> for VmState {
+    fn run(
+        &mut self,
+        prog: &Program,
+        config: VmConfig,
+        token: &CancellationToken,
+    ) -> Result<Object<'static>, Error> {
+        let (result, _) = VmState::run(self, prog, config, token);
+        result.map_err(Error::Runtime)
+    }
+
+    fn call(
+        &mut self,
+        function: Object<'static>,
+        args: Vec<Object<'static>>,
+        config: VmConfig,
+    ) -> Result<Object<'static>, Error> {
+        VmState::call(self, function, args, config, &CancellationToken::new())
+            .map_err(Error::Runtime)
+    }
+
+    fn set(&mut self, name: &[u8], value: Object<'static>) {
+        VmState::set(self, name, value);
+    }
+
+    fn get(&self, name: &[u8]) -> Option<Object<'static>> {
+        VmState::get(self, name)
+    }
+
+    fn bindings(&self) -> Vec<(Vec<u8>, Object<'static>)> {
+        VmState::bindings(self).collect()
+    }
+
+    fn reset(&mut self) {
+        *self = VmState::default();
+    }
+}
+
+impl Interpreter<'static> {
+    /// Runs every following evaluation on `engine`, the globals bound so far
+    /// are carried over. Only an interpreter whose bindings are `'static` can
+    /// run on the [`Vm`](crate::vm::Vm), functions bound with [`bind`](Self::bind) can
+    /// be called from it but the vm doesn't compile every expression the
+    /// evaluator runs.
+    ///
+    /// ```
+    /// use interpreter_book_in_rust::{engine::Engine, Interpreter};
+    ///
+    /// let mut interp = Interpreter::new().with_engine(Engine::Vm);
+    /// interp.bind("double", |n: i64| n * 2);
+    /// interp.eval("let f = fn(n) { double(n) + 1 };").unwrap();
+    /// assert_eq!(interp.eval("f(20)").unwrap().to_string(), "41");
+    /// assert_eq!(interp.call_function::<_, i64>("f", (1,)).unwrap(), 3);
+    /// ```
+    pub fn with_engine(mut self, engine: Engine) -> Self {
+        let bindings = match &self.vm {
+            Some(vm) => vm.bindings(),
+            None => self
+                .env
+                .bindings()
+                .map(|(name, value)| (name.to_vec(), value))
+                .collect(),
+        };
+        self.reset();
+        if engine == Engine::Vm {
+            self.vm = Some(Box::new(VmState::default()));
+        }
+        for (name, value) in bindings {
+            self.set(&name, value);
+        }
+        self
+    }
+}
+
+impl<'a> Interpreter<'a> {
+    pub fn new() -> Self {
+        Interpreter::default()
+    }
+
+    /// Runs every following evaluation under `config`.
+    ///
+    /// ```
+    /// use interpreter_book_in_rust::{sandbox::SandboxConfig, Interpreter};
+    ///
+    /// let mut interp = Interpreter::new().with_sandbox(SandboxConfig {
+    ///     max_call_depth: Some(10),
+    ///     ..Default::default()
+    /// });
+    /// interp.eval("let f = fn(n) { 1 + f(n + 1) };").unwrap();
+    /// assert_eq!(
+    ///     interp.eval("f(0)").unwrap_err().to_string(),
+    ///     "runtime error: call depth limit exceeded"
+    /// );
+    /// ```
+    pub fn with_sandbox(mut self, config: SandboxConfig) -> Self {
+        self.sandbox = config;
+        self
+    }
+
+    /// Tests conditions and `!` operands that aren't booleans with
+    /// `truthiness` instead of failing, see [`Truthiness`].
+    pub fn with_truthiness(mut self, truthiness: Truthiness) -> Self {
+        self.truthiness = truthiness;
+        self
+    }
+
+    /// Makes `puts` write its lines to `out` instead of stdout, by binding
+    /// a function of that name that [`reset`](Self::reset) keeps.
+    pub fn with_output(self, out: impl Write + MaybeSync + 'a) -> Self {
+        let out = Mutex::new(out);
+        let host = HostFunction {
+            name: "puts",
+            func: Box::new(move |args| {
+                let mut out = out.lock().map_err(|_| "output poisoned")?;
+                for arg in args {
+                    writeln!(out, "{}", arg).map_err(|_| "failed to write output")?;
+                }
+                Ok(Object::Null)
+            }),
+        };
+        self.with_global("puts", Object::Host(Rc::new(host)))
+    }
+
+    /// Like [`set_global`](Self::set_global), for preloading globals, which
+    /// [`reset`](Self::reset) binds again.
+    pub fn with_global(mut self, name: &'a str, value: Object<'a>) -> Self {
+        self.set_global(name, value.clone());
+        self.preloaded.retain(|(preloaded, _)| *preloaded != name);
+        self.preloaded.push((name, value));
+        self
+    }
+
+    /// Runs `source` against the current globals and returns the value of
+    /// its last statement.
+    pub fn eval(&mut self, source: &str) -> Result<Object<'a>, Error> {
+        self.run(source, None)
+    }
+
+    /// Like [`eval`](Self::eval), but stops with a runtime error soon after
+    /// `token` is cancelled, from another thread for example. Bindings made
+    /// before that are kept.
+    ///
+    /// ```
+    /// use interpreter_book_in_rust::{sandbox::CancellationToken, Interpreter};
+    ///
+    /// let token = CancellationToken::new();
+    /// token.cancel();
+    /// let err = Interpreter::new().eval_cancellable("1 + 1", token).unwrap_err();
+    /// assert_eq!(err.to_string(), "runtime error: evaluation cancelled");
+    /// ```
+    pub fn eval_cancellable(
+        &mut self,
+        source: &str,
+        token: CancellationToken,
+    ) -> Result<Object<'a>, Error> {
+        self.run(source, Some(token))
+    }
+
+    fn run(&mut self, source: &str, token: Option<CancellationToken>) -> Result<Object<'a>, Error> {
+        let prog = Parser::new(Lexer::new(source.as_bytes()))
+            .parse()
+            .map_err(Error::Parse)?;
+        let config = self.vm_config();
+        if let Some(vm) = &mut self.vm {
+            return vm.run(&prog, config, &token.unwrap_or_default());
+        }
+        let mut evaluator = Evaluator::new(self.sandbox).with_truthiness(self.truthiness);
+        if let Some(token) = token {
+            evaluator = evaluator.with_cancellation(token);
+        }
+        let result = evaluator
+            .eval_program(&prog, &mut self.env)
+            .map_err(|err| Error::Runtime(err.diagnostic()));
+        // frees the envs of calls that only the closures made in them refer to
+        self.env.heap().collect();
+        result
+    }
+
+    /// Binds `name` for every following [`eval`](Self::eval), replacing a
+    /// previous binding.
+    pub fn set_global(&mut self, name: &'a str, value: Object<'a>) {
+        self.set(name.as_bytes(), value);
+    }
+
+    fn set(&mut self, name: &[u8], value: Object<'a>) {
+        match &mut self.vm {
+            Some(vm) => vm.set(name, value),
+            None => self.env.set(name, value),
+        }
+    }
+
+    fn vm_config(&self) -> VmConfig {
+        VmConfig {
+            sandbox: self.sandbox,
+            truthiness: self.truthiness,
+            ..Default::default()
+        }
+    }
+
+    /// Binds a Rust closure as the function `name`, arguments and the return
+    /// value are converted with [`FromMonkey`](crate::host::FromMonkey) and
+    /// [`IntoMonkey`](crate::host::IntoMonkey).
+    ///
+    /// ```
+    /// use interpreter_book_in_rust::Interpreter;
+    ///
+    /// let mut interp = Interpreter::new();
+    /// interp.bind("add", |a: i64, b: i64| a + b);
+    /// assert_eq!(interp.eval("add(1, 2)").unwrap().to_string(), "3");
+    /// assert_eq!(
+    ///     interp.eval("add(1, true)").unwrap_err().to_string(),
+    ///     "runtime error: expected an integer argument"
+    /// );
+    /// ```
+    pub fn bind<Args, F: IntoHostFn<'a, Args>>(&mut self, name: &'a str, f: F) {
+        let host = HostFunction {
+            name,
+            func: f.into_host_fn(),
+        };
+        self.set_global(name, Object::Host(Rc::new(host)));
+    }
+
+    pub fn get_global(&self, name: &str) -> Option<Object<'a>> {
+        match &self.vm {
+            Some(vm) => vm.get(name.as_bytes()),
+            None => self.env.get(name.as_bytes()),
+        }
+    }
+
+    /// Calls the global function `name` with a tuple of arguments, each
+    /// converted with [`IntoMonkey`](crate::host::IntoMonkey), and converts
+    /// what it returns with [`FromMonkey`].
+    ///
+    /// ```
+    /// use interpreter_book_in_rust::Interpreter;
+    ///
+    /// let mut interp = Interpreter::new();
+    /// interp.eval("let scale = fn(x, by) { x * by };").unwrap();
+    /// let scaled: f64 = interp.call_function("scale", (1.5, 2)).unwrap();
+    /// assert_eq!(scaled, 3.0);
+    /// ```
+    pub fn call_function<Args, R>(&mut self, name: &str, args: Args) -> Result<R, Error>
+    where
+        Args: IntoArgs<'a>,
+        R: FromMonkey<'a>,
+    {
+        let function = self.get_global(name).ok_or_else(|| {
+            let message = format!("variable not found: {}", name);
+            Error::Runtime(Diagnostic::error(message, None))
+        })?;
+        let config = self.vm_config();
+        let result = match &mut self.vm {
+            Some(vm) => vm.call(function, args.into_args(), config),
+            None => Evaluator::new(self.sandbox)
+                .with_truthiness(self.truthiness)
+                .call(function, args.into_args(), &self.env)
+                .map_err(|err| Error::Runtime(err.diagnostic())),
+        };
+        self.env.heap().collect();
+        R::from_monkey(result?).map_err(|err| {
+            let message = format!("cannot convert what {} returned: {}", name, err);
+            Error::Runtime(Diagnostic::error(message, None))
+        })
+    }
+
+    /// Drops every global binding but the ones the `with_` methods made,
+    /// those go back to what they were given.
+    pub fn reset(&mut self) {
+        self.clear();
+        for (name, value) in self.preloaded.clone() {
+            self.set_global(name, value);
+        }
+    }
+
+    fn clear(&mut self) {
+        let heap = self.env.heap();
+        self.env = Env::new();
+        // functions bound as globals refer back to the globals
+        heap.collect();
+        if let Some(vm) = &mut self.vm {
+            vm.reset();
+        }
+    }
+}
+
+impl Drop for Interpreter<'_> {
+    fn drop(&mut self) {
+        self.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        io::Write,
+        sync::{Arc, Mutex},
+    };
+
+    use crate::{
+        engine::Engine, evaluator::Truthiness, object::Object, sandbox::CancellationToken,
+    };
+
+    use super::{Error, Interpreter};
+
+    // the message of a runtime error, where it is aside
+    fn runtime<T: std::fmt::Debug>(result: Result<T, Error>) -> String {
+        match result {
+            Err(Error::Runtime(diagnostic)) => diagnostic.message,
+            result => panic!("expected a runtime error, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_globals_persist() {
+        let mut interp = Interpreter::new();
+        assert!(matches!(interp.eval("let a = 5;"), Ok(Object::Null)));
+        assert!(matches!(interp.eval("a * 2"), Ok(Object::Integer(10))));
+
+        interp.eval("let add = fn(x) { a + x };").unwrap();
+        assert!(matches!(interp.eval("add(1)"), Ok(Object::Integer(6))));
+        assert!(matches!(interp.get_global("a"), Some(Object::Integer(5))));
+        assert!(interp.get_global("b").is_none());
+    }
+
+    #[test]
+    fn test_set_global() {
+        let mut interp = Interpreter::new();
+        interp.set_global("x", Object::Integer(3));
+        assert!(matches!(interp.eval("x + 1"), Ok(Object::Integer(4))));
+        interp.set_global("x", Object::Bool(true));
+        assert!(matches!(interp.eval("!x"), Ok(Object::Bool(false))));
+    }
+
+    #[test]
+    fn test_reset() {
+        let mut interp = Interpreter::new();
+        interp.eval("let a = 1;").unwrap();
+        interp.reset();
+        assert!(interp.get_global("a").is_none());
+        assert_eq!(runtime(interp.eval("a")), "variable not found: a");
+    }
+
+    #[test]
+    fn test_call_function() {
+        let mut interp = Interpreter::new();
+        interp
+            .eval(
+                "let greet = fn(name) { \"hi \" + name };
+                 let lookup = fn(table, key) { table[key] };
+                 let evens = fn(n) { if (n < 1) { [] } else { push(evens(n - 1), n * 2) } };
+                 let both = fn(a, b) { {\"a\": a, \"b\": b} };",
+            )
+            .unwrap();
+
+        let greeting: String = interp.call_function("greet", ("ada",)).unwrap();
+        assert_eq!(greeting, "hi ada");
+        let table = HashMap::from([("one", 1), ("two", 2)]);
+        let value: i64 = interp.call_function("lookup", (table, "two")).unwrap();
+        assert_eq!(value, 2);
+        let evens: Vec<i64> = interp.call_function("evens", (3,)).unwrap();
+        assert_eq!(evens, [2, 4, 6]);
+        let both: HashMap<String, f64> = interp.call_function("both", (1.5, 2)).unwrap();
+        assert_eq!(both, HashMap::from([("a".into(), 1.5), ("b".into(), 2.0)]));
+
+        let errors = [
+            (
+                interp.call_function::<_, i64>("nope", ()),
+                "variable not found: nope",
+            ),
+            (
+                interp.call_function::<_, i64>("greet", ()),
+                "wrong number of arguments",
+            ),
+            (
+                interp.call_function::<_, i64>("greet", (1,)),
+                "operand can only be applied to numbers",
+            ),
+            (
+                interp.call_function::<_, i64>("greet", ("ada",)),
+                "cannot convert what greet returned: expected an integer argument",
+            ),
+        ];
+        for (result, expected) in errors {
+            assert_eq!(runtime(result), expected);
+        }
+    }
+
+    #[test]
+    fn test_conversions() {
+        assert_eq!(Object::from(2).to_string(), "2");
+        assert_eq!(Object::from(0.5).to_string(), "0.5");
+        assert_eq!(Object::from("a").to_string(), "a");
+        assert_eq!(Object::from(vec![true, false]).to_string(), "[true, false]");
+        let hash = Object::from(HashMap::from([(1, "one")]));
+        assert_eq!(hash.to_string(), "{1: one}");
+
+        assert_eq!(i64::try_from(Object::Integer(3)), Ok(3));
+        assert_eq!(f64::try_from(Object::Integer(3)), Ok(3.0));
+        assert_eq!(
+            bool::try_from(Object::Null),
+            Err("expected a boolean argument")
+        );
+        assert_eq!(String::try_from(Object::from("s")), Ok("s".to_string()));
+        assert_eq!(
+            Vec::<i64>::try_from(Object::from(vec![1, 2])),
+            Ok(vec![1, 2])
+        );
+        assert_eq!(
+            HashMap::<i64, String>::try_from(hash),
+            Ok(HashMap::from([(1, "one".to_string())]))
+        );
+        assert_eq!(
+            HashMap::<i64, i64>::try_from(Object::from(vec![1])),
+            Err("expected a hash argument")
+        );
+    }
+
+    #[test]
+    fn test_engine() {
+        let mut interp = Interpreter::new()
+            .with_global("a", Object::Integer(1))
+            .with_engine(Engine::Vm);
+        interp.bind("add", |a: i64, b: i64| a + b);
+        interp
+            .eval("let f = fn(n) { add(n, a) }; let b = f(1);")
+            .unwrap();
+        assert!(matches!(interp.get_global("b"), Some(Object::Integer(2))));
+        assert_eq!(interp.call_function::<_, i64>("f", (2,)), Ok(3));
+        assert_eq!(
+            runtime(interp.eval("if (1) { 2 }")),
+            "conditional expression isn't a boolean"
+        );
+
+        // the vm's globals go back to the evaluator
+        let mut interp = interp.with_engine(Engine::Eval);
+        assert_eq!(interp.eval("b + add(a, 1)").unwrap().to_string(), "4");
+
+        let mut interp = interp.with_engine(Engine::Vm);
+        interp.reset();
+        assert_eq!(runtime(interp.eval("b")), "variable not found: b");
+    }
+
+    #[test]
+    fn test_truthiness_and_output() {
+        for engine in [Engine::Eval, Engine::Vm] {
+            let out = Arc::new(Mutex::new(Vec::new()));
+            let mut interp = Interpreter::new()
+                .with_engine(engine)
+                .with_truthiness(Truthiness::Lenient)
+                .with_output(SharedOutput(Arc::clone(&out)));
+            let result = interp.eval("puts(1, \"two\"); if (0) { puts(!puts()) }; !3");
+            assert!(matches!(result, Ok(Object::Bool(false))), "{:?}", engine);
+            assert_eq!(*out.lock().unwrap(), b"1\ntwo\ntrue\n", "{:?}", engine);
+        }
+    }
+
+    #[test]
+    fn test_reset_keeps_preloaded() {
+        for engine in [Engine::Eval, Engine::Vm] {
+            let out = Arc::new(Mutex::new(Vec::new()));
+            let mut interp = Interpreter::new()
+                .with_output(SharedOutput(Arc::clone(&out)))
+                .with_global("a", Object::Integer(1))
+                .with_engine(engine);
+            interp
+                .eval("let puts = fn(x) { x }; let a = 2; puts(a);")
+                .unwrap();
+            interp.reset();
+            interp.eval("puts(a)").unwrap();
+            assert_eq!(*out.lock().unwrap(), b"1\n", "{:?}", engine);
+        }
+    }
+
+    // a writer the test can still read once the interpreter has it
+    struct SharedOutput(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedOutput {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn first_or<'a>(values: Vec<Object<'a>>, default: Object<'a>) -> Object<'a> {
+        values.into_iter().next().unwrap_or(default)
+    }
+
+    #[test]
+    fn test_bind() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let calls = AtomicUsize::new(0);
+        let mut interp = Interpreter::new();
+        interp.bind("add", |a: i64, b: i64| a + b);
+        interp.bind("not", |a: bool| !a);
+        interp.bind("sum", |values: Vec<i64>| values.iter().sum::<i64>());
+        interp.bind("range", |n: i64| (0..n).collect::<Vec<_>>());
+        interp.bind("count", || {
+            calls.fetch_add(1, Ordering::Relaxed);
+        });
+        interp.bind("first_or", first_or);
+
+        let inputs = [
+            ("add(1, 2)", "3"),
+            ("let twice = fn(f, x) { f(f(x, x), x) }; twice(add, 2)", "6"),
+            ("not(1 > 2)", "true"),
+            ("sum(range(5))", "10"),
+            ("count(); count()", "nil"),
+            ("first_or([], 7)", "7"),
+            ("first_or([true], 7)", "true"),
+            ("add", "<host fn add>"),
+        ];
+        for (input, expected) in inputs {
+            assert_eq!(
+                interp.eval(input).unwrap().to_string(),
+                expected,
+                "{}",
+                input
+            );
+        }
+        assert_eq!(calls.load(Ordering::Relaxed), 2);
+
+        let errors = [
+            ("add(1)", "wrong number of arguments"),
+            ("add(1, 2, 3)", "wrong number of arguments"),
+            ("add(1, true)", "expected an integer argument"),
+            ("not(0)", "expected a boolean argument"),
+            ("sum(1)", "expected an array argument"),
+            ("sum([1, false])", "expected an integer argument"),
+        ];
+        for (input, expected) in errors {
+            assert_eq!(runtime(interp.eval(input)), expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut interp = Interpreter::new();
+        let err = interp.eval("let = 1;").unwrap_err();
+        assert!(matches!(err, Error::Parse(_)));
+        assert!(err.to_string().starts_with("parse error: "));
+
+        let err = interp.eval("1 + true").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "runtime error: operand can only be applied to numbers"
+        );
+        assert_eq!(
+            err.span().map(|span| span.to_string()).as_deref(),
+            Some("1:3")
+        );
+
+        // the vm places its errors too
+        let mut interp = Interpreter::new().with_engine(Engine::Vm);
+        let err = interp.eval("let a = 1;\na + true").unwrap_err();
+        assert_eq!(
+            err.span().map(|span| span.to_string()).as_deref(),
+            Some("2:1")
+        );
+        let err = interp.call_function::<_, i64>("missing", ()).unwrap_err();
+        assert_eq!(err.span(), None);
+    }
+
+    #[test]
+    fn test_eval_cancellable() {
+        let mut interp = Interpreter::new();
+        interp
+            .eval("let fib = fn(n) { if (n < 2) { n } else { fib(n - 1) + fib(n - 2) } };")
+            .unwrap();
+
+        let token = CancellationToken::new();
+        let canceller = token.clone();
+        let handle = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(20));
+            canceller.cancel();
+        });
+        assert_eq!(
+            runtime(interp.eval_cancellable("fib(40)", token)),
+            "evaluation cancelled"
+        );
+        handle.join().unwrap();
+
+        // the interpreter keeps working, a fresh token isn't cancelled
+        assert!(matches!(
+            interp.eval_cancellable("fib(10)", CancellationToken::new()),
+            Ok(Object::Integer(55))
+        ));
+    }
+
+    #[cfg(feature = "sync")]
+    #[test]
+    fn test_send_to_threads() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Interpreter<'static>>();
+        assert_send_sync::<Object<'static>>();
+
+        let mut interp = Interpreter::new();
+        interp.bind("double", |x: i64| x * 2);
+        interp.eval("let f = fn(x) { double(x) + 1 };").unwrap();
+        let f = interp.get_global("f").unwrap();
+
+        // values can be shared by a pool of interpreters on other threads
+        let mut handles = (0..4)
+            .map(|i| {
+                let f = f.clone();
+                std::thread::spawn(move || {
+                    let mut worker = Interpreter::new();
+                    worker.set_global("f", f);
+                    worker.set_global("i", Object::Integer(i));
+                    worker.eval("f(i)").unwrap().to_string()
+                })
+            })
+            .collect::<Vec<_>>();
+        // and an interpreter can move to another thread itself
+        handles.push(std::thread::spawn(move || {
+            interp.eval("f(20)").unwrap().to_string()
+        }));
+        let results = handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(results, ["1", "3", "5", "7", "41"]);
+    }
+}
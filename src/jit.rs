@@ -0,0 +1,615 @@
+use std::collections::HashMap;
+
+use cranelift_codegen::{
+    entity::EntityRef,
+    ir::{
+        condcodes::IntCC, types, AbiParam, Block, InstBuilder, MemFlags, StackSlotData,
+        StackSlotKind, Value,
+    },
+    settings::{self, Configurable},
+};
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext, Variable};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{Linkage, Module};
+
+use crate::{
+    code::{read_operands, Opcode},
+    object::{CompiledFunction, Object},
+    rc::Rc,
+};
+
+// calls through the vm before a function is handed to cranelift
+pub const HOT_THRESHOLD: u32 = 100;
+
+// (arguments, frames left for nested calls, flag set to the `Exit` that
+// stopped the code early)
+type NativeFn = unsafe extern "C" fn(*const i64, i64, *mut u8) -> i64;
+
+// why native code stopped before returning a value
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exit {
+    OutOfFrames = 1,
+    Overflow = 2,
+}
+
+#[derive(Clone, Copy)]
+pub struct NativeFunction {
+    code: NativeFn,
+    // the global the function calls itself through, native code recurses
+    // directly so the vm has to check that it still holds the function
+    pub self_global: Option<usize>,
+    // deepest the operand stack gets in the function
+    pub max_height: usize,
+}
+
+impl NativeFunction {
+    // fails when the nested calls needed more than `frames` or the
+    // arithmetic overflowed
+    pub fn call(&self, args: &[i64], frames: usize) -> Result<i64, Exit> {
+        let mut exit = 0u8;
+        // args holds one integer per parameter, the only thing `check` lets
+        // the compiled code read
+        let result = unsafe { (self.code)(args.as_ptr(), frames as i64, &mut exit) };
+        match exit {
+            0 => Ok(result),
+            1 => Err(Exit::OutOfFrames),
+            _ => Err(Exit::Overflow),
+        }
+    }
+}
+
+struct Profile {
+    // keeps the function alive so its address isn't reused as a key
+    _function: Rc<CompiledFunction>,
+    calls: u32,
+    native: Option<NativeFunction>,
+}
+
+pub struct Jit {
+    // None when cranelift can't target the host
+    module: Option<JITModule>,
+    profiles: HashMap<*const CompiledFunction, Profile>,
+    compiled: usize,
+}
+
+impl Default for Jit {
+    fn default() -> Self {
+        Jit::new()
+    }
+}
+
+impl Jit {
+    pub fn new() -> Self {
+        let module = cranelift_native::builder().ok().and_then(|isa_builder| {
+            let mut flags = settings::builder();
+            flags.set("opt_level", "speed").ok()?;
+            let isa = isa_builder.finish(settings::Flags::new(flags)).ok()?;
+            let builder = JITBuilder::with_isa(isa, cranelift_module::default_libcall_names());
+            Some(JITModule::new(builder))
+        });
+        Jit {
+            module,
+            profiles: HashMap::new(),
+            compiled: 0,
+        }
+    }
+
+    // number of functions running as native code
+    pub fn compiled(&self) -> usize {
+        self.compiled
+    }
+
+    // counts a call of `function` and returns its native code once it got hot
+    // and could be compiled
+    pub fn lookup(
+        &mut self,
+        function: &Rc<CompiledFunction>,
+        constants: &[Object<'static>],
+        globals: &[Object<'static>],
+    ) -> Option<NativeFunction> {
+        let module = self.module.as_mut()?;
+        let profile = self
+            .profiles
+            .entry(Rc::as_ptr(function))
+            .or_insert_with(|| Profile {
+                _function: Rc::clone(function),
+                calls: 0,
+                native: None,
+            });
+        if profile.calls < HOT_THRESHOLD {
+            profile.calls += 1;
+            if profile.calls == HOT_THRESHOLD {
+                profile.native = compile(module, function, constants, globals, self.compiled);
+                self.compiled += profile.native.is_some() as usize;
+            }
+        }
+        profile.native
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Ty {
+    Int,
+    Bool,
+    // the function itself, loaded to be called
+    Callee,
+}
+
+struct Shape {
+    // stack height at every jump target
+    targets: HashMap<usize, usize>,
+    self_global: Option<usize>,
+    max_height: usize,
+}
+
+fn expect(stack: &mut Vec<Ty>, ty: Ty) -> Option<()> {
+    (stack.pop()? == ty).then_some(())
+}
+
+// accepts functions that only compute with integers and booleans, return an
+// integer and call nothing but themselves
+fn check(
+    function: &Rc<CompiledFunction>,
+    constants: &[Object<'static>],
+    globals: &[Object<'static>],
+) -> Option<Shape> {
+    // rest arguments come as an array
+    if function.rest {
+        return None;
+    }
+    let ins = &function.instructions.0;
+    let mut stack = Vec::new();
+    let mut targets: HashMap<usize, Vec<Ty>> = HashMap::new();
+    let mut self_global = None;
+    let mut max_height = 0;
+    let mut reachable = true;
+    let mut ip = 0;
+
+    while ip < ins.len() {
+        max_height = max_height.max(stack.len());
+        if let Some(state) = targets.get(&ip) {
+            if reachable && *state != stack {
+                return None;
+            }
+            stack = state.clone();
+            reachable = true;
+        }
+        let op = Opcode::from_byte(ins[ip])?;
+        let (operands, read) = read_operands(&op.definition(), &ins[ip + 1..]);
+        let branch = |targets: &mut HashMap<usize, Vec<Ty>>, stack: &Vec<Ty>| {
+            let target = operands[0];
+            if target <= ip || stack.contains(&Ty::Callee) {
+                return None;
+            }
+            match targets.get(&target) {
+                Some(state) if state != stack => None,
+                _ => {
+                    targets.insert(target, stack.clone());
+                    Some(())
+                }
+            }
+        };
+        if reachable {
+            match op {
+                Opcode::Constant => match constants.get(operands[0])? {
+                    Object::Integer(_) => stack.push(Ty::Int),
+                    _ => return None,
+                },
+                Opcode::True | Opcode::False => stack.push(Ty::Bool),
+                Opcode::Add | Opcode::Sub | Opcode::Mul => {
+                    expect(&mut stack, Ty::Int)?;
+                    expect(&mut stack, Ty::Int)?;
+                    stack.push(Ty::Int);
+                }
+                Opcode::GreaterThan | Opcode::LessThan => {
+                    expect(&mut stack, Ty::Int)?;
+                    expect(&mut stack, Ty::Int)?;
+                    stack.push(Ty::Bool);
+                }
+                Opcode::Equal | Opcode::NotEqual => {
+                    let right = stack.pop()?;
+                    if right == Ty::Callee {
+                        return None;
+                    }
+                    expect(&mut stack, right)?;
+                    stack.push(Ty::Bool);
+                }
+                Opcode::Minus => {
+                    expect(&mut stack, Ty::Int)?;
+                    stack.push(Ty::Int);
+                }
+                Opcode::Bang => {
+                    expect(&mut stack, Ty::Bool)?;
+                    stack.push(Ty::Bool);
+                }
+                Opcode::Pop => {
+                    if stack.pop()? == Ty::Callee {
+                        return None;
+                    }
+                }
+                Opcode::GetLocal if operands[0] < function.num_locals => stack.push(Ty::Int),
+                Opcode::SetLocal if operands[0] < function.num_locals => {
+                    expect(&mut stack, Ty::Int)?;
+                }
+                Opcode::GetGlobal => match globals.get(operands[0])? {
+                    Object::Closure(closure) if Rc::ptr_eq(&closure.function, function) => {
+                        self_global = Some(operands[0]);
+                        stack.push(Ty::Callee);
+                    }
+                    _ => return None,
+                },
+                Opcode::Call if operands[0] == function.num_parameters => {
+                    for _ in 0..operands[0] {
+                        expect(&mut stack, Ty::Int)?;
+                    }
+                    expect(&mut stack, Ty::Callee)?;
+                    stack.push(Ty::Int);
+                }
+                Opcode::JumpNotTruthy => {
+                    expect(&mut stack, Ty::Bool)?;
+                    branch(&mut targets, &stack)?;
+                }
+                Opcode::Jump => {
+                    branch(&mut targets, &stack)?;
+                    reachable = false;
+                }
+                Opcode::ReturnValue => {
+                    expect(&mut stack, Ty::Int)?;
+                    reachable = false;
+                }
+                _ => return None,
+            }
+        }
+        ip += 1 + read;
+    }
+
+    // falling off the end or jumping there returns null
+    if reachable || targets.keys().any(|&target| target >= ins.len()) {
+        return None;
+    }
+    Some(Shape {
+        targets: targets
+            .into_iter()
+            .map(|(target, state)| (target, state.len()))
+            .collect(),
+        self_global,
+        max_height,
+    })
+}
+
+fn compile(
+    module: &mut JITModule,
+    function: &Rc<CompiledFunction>,
+    constants: &[Object<'static>],
+    globals: &[Object<'static>],
+    id: usize,
+) -> Option<NativeFunction> {
+    let shape = check(function, constants, globals)?;
+    let ptr = module.target_config().pointer_type();
+
+    let mut ctx = module.make_context();
+    ctx.func.signature.params.extend([
+        AbiParam::new(ptr),
+        AbiParam::new(types::I64),
+        AbiParam::new(ptr),
+    ]);
+    ctx.func.signature.returns.push(AbiParam::new(types::I64));
+    let func_id = module
+        .declare_function(
+            &format!("monkey_fn_{}", id),
+            Linkage::Local,
+            &ctx.func.signature,
+        )
+        .ok()?;
+    let self_ref = module.declare_func_in_func(func_id, &mut ctx.func);
+
+    let mut builder_ctx = FunctionBuilderContext::new();
+    let mut b = FunctionBuilder::new(&mut ctx.func, &mut builder_ctx);
+    translate(&mut b, function, constants, &shape, self_ref, ptr);
+    b.seal_all_blocks();
+    b.finalize();
+
+    module.define_function(func_id, &mut ctx).ok()?;
+    module.clear_context(&mut ctx);
+    module.finalize_definitions().ok()?;
+    let code = module.get_finalized_function(func_id);
+    Some(NativeFunction {
+        // the signature matches the one declared above
+        code: unsafe { std::mem::transmute::<*const u8, NativeFn>(code) },
+        self_global: shape.self_global,
+        max_height: shape.max_height,
+    })
+}
+
+// the callee slot of a call has no value, everything else is an i64 with
+// booleans as 0 or 1
+fn values(stack: &[Option<Value>]) -> Vec<Value> {
+    stack
+        .iter()
+        .map(|v| v.expect("callee on a merged stack"))
+        .collect()
+}
+
+fn pop(stack: &mut Vec<Option<Value>>) -> Value {
+    stack.pop().flatten().expect("value on the stack")
+}
+
+fn translate(
+    b: &mut FunctionBuilder,
+    function: &CompiledFunction,
+    constants: &[Object<'static>],
+    shape: &Shape,
+    self_ref: cranelift_codegen::ir::FuncRef,
+    ptr: types::Type,
+) {
+    let entry = b.create_block();
+    b.append_block_params_for_function_params(entry);
+    b.switch_to_block(entry);
+    let params = b.block_params(entry).to_vec();
+    let (args, frames, exit_flag) = (params[0], params[1], params[2]);
+
+    for i in 0..function.num_locals {
+        let var = Variable::new(i);
+        b.declare_var(var, types::I64);
+        let value = if i < function.num_parameters {
+            b.ins()
+                .load(types::I64, MemFlags::trusted(), args, (i * 8) as i32)
+        } else {
+            b.ins().iconst(types::I64, 0)
+        };
+        b.def_var(var, value);
+    }
+
+    let blocks: HashMap<usize, Block> = shape
+        .targets
+        .iter()
+        .map(|(&target, &height)| {
+            let block = b.create_block();
+            for _ in 0..height {
+                b.append_block_param(block, types::I64);
+            }
+            (target, block)
+        })
+        .collect();
+    let out_of_frames = b.create_block();
+    let overflow = b.create_block();
+    let unwind = b.create_block();
+
+    let ins = &function.instructions.0;
+    let mut stack: Vec<Option<Value>> = Vec::new();
+    let mut reachable = true;
+    let mut ip = 0;
+    while ip < ins.len() {
+        if let Some(&block) = blocks.get(&ip) {
+            if reachable {
+                b.ins().jump(block, &values(&stack));
+            }
+            b.switch_to_block(block);
+            stack = b.block_params(block).iter().map(|v| Some(*v)).collect();
+            reachable = true;
+        }
+        let op = Opcode::from_byte(ins[ip]).expect("checked opcode");
+        let (operands, read) = read_operands(&op.definition(), &ins[ip + 1..]);
+        ip += 1 + read;
+        if !reachable {
+            continue;
+        }
+
+        match op {
+            Opcode::Constant => {
+                let Object::Integer(value) = constants[operands[0]] else {
+                    unreachable!("checked integer constant");
+                };
+                stack.push(Some(b.ins().iconst(types::I64, value)));
+            }
+            Opcode::True => stack.push(Some(b.ins().iconst(types::I64, 1))),
+            Opcode::False => stack.push(Some(b.ins().iconst(types::I64, 0))),
+            Opcode::Add | Opcode::Sub | Opcode::Mul => {
+                let right = pop(&mut stack);
+                let left = pop(&mut stack);
+                let (value, overflowed) = match op {
+                    Opcode::Add => b.ins().sadd_overflow(left, right),
+                    Opcode::Sub => b.ins().ssub_overflow(left, right),
+                    _ => b.ins().smul_overflow(left, right),
+                };
+                let next = b.create_block();
+                b.ins().brif(overflowed, overflow, &[], next, &[]);
+                b.switch_to_block(next);
+                stack.push(Some(value));
+            }
+            Opcode::GreaterThan | Opcode::LessThan | Opcode::Equal | Opcode::NotEqual => {
+                let right = pop(&mut stack);
+                let left = pop(&mut stack);
+                let cc = match op {
+                    Opcode::GreaterThan => IntCC::SignedGreaterThan,
+                    Opcode::LessThan => IntCC::SignedLessThan,
+                    Opcode::Equal => IntCC::Equal,
+                    _ => IntCC::NotEqual,
+                };
+                let flag = b.ins().icmp(cc, left, right);
+                stack.push(Some(b.ins().uextend(types::I64, flag)));
+            }
+            Opcode::Minus => {
+                let value = pop(&mut stack);
+                let zero = b.ins().iconst(types::I64, 0);
+                let (value, overflowed) = b.ins().ssub_overflow(zero, value);
+                let next = b.create_block();
+                b.ins().brif(overflowed, overflow, &[], next, &[]);
+                b.switch_to_block(next);
+                stack.push(Some(value));
+            }
+            Opcode::Bang => {
+                let value = pop(&mut stack);
+                stack.push(Some(b.ins().bxor_imm(value, 1)));
+            }
+            Opcode::Pop => {
+                stack.pop();
+            }
+            Opcode::GetLocal => stack.push(Some(b.use_var(Variable::new(operands[0])))),
+            Opcode::SetLocal => {
+                let value = pop(&mut stack);
+                b.def_var(Variable::new(operands[0]), value);
+            }
+            Opcode::GetGlobal => stack.push(None),
+            Opcode::Call => {
+                let call_args = values(&stack[stack.len() - operands[0]..]);
+                stack.truncate(stack.len() - operands[0] - 1);
+
+                let call = b.create_block();
+                let no_frames = b.ins().icmp_imm(IntCC::SignedLessThanOrEqual, frames, 0);
+                b.ins().brif(no_frames, out_of_frames, &[], call, &[]);
+                b.switch_to_block(call);
+
+                let slot = b.create_sized_stack_slot(StackSlotData::new(
+                    StackSlotKind::ExplicitSlot,
+                    (8 * call_args.len().max(1)) as u32,
+                    3,
+                ));
+                for (i, arg) in call_args.iter().enumerate() {
+                    b.ins().stack_store(*arg, slot, (i * 8) as i32);
+                }
+                let addr = b.ins().stack_addr(ptr, slot, 0);
+                let frames_left = b.ins().iadd_imm(frames, -1);
+                let inst = b.ins().call(self_ref, &[addr, frames_left, exit_flag]);
+                let result = b.inst_results(inst)[0];
+
+                let after = b.create_block();
+                let flag = b.ins().load(types::I8, MemFlags::trusted(), exit_flag, 0);
+                b.ins().brif(flag, unwind, &[], after, &[]);
+                b.switch_to_block(after);
+                stack.push(Some(result));
+            }
+            Opcode::JumpNotTruthy => {
+                let cond = pop(&mut stack);
+                let next = b.create_block();
+                b.ins()
+                    .brif(cond, next, &[], blocks[&operands[0]], &values(&stack));
+                b.switch_to_block(next);
+            }
+            Opcode::Jump => {
+                b.ins().jump(blocks[&operands[0]], &values(&stack));
+                reachable = false;
+            }
+            Opcode::ReturnValue => {
+                let value = pop(&mut stack);
+                b.ins().return_(&[value]);
+                reachable = false;
+            }
+            _ => unreachable!("{:?} rejected by check", op),
+        }
+    }
+
+    for (block, exit) in [
+        (out_of_frames, Exit::OutOfFrames),
+        (overflow, Exit::Overflow),
+    ] {
+        b.switch_to_block(block);
+        let exit = b.ins().iconst(types::I8, exit as i64);
+        b.ins().store(MemFlags::trusted(), exit, exit_flag, 0);
+        let zero = b.ins().iconst(types::I64, 0);
+        b.ins().return_(&[zero]);
+    }
+
+    b.switch_to_block(unwind);
+    let zero = b.ins().iconst(types::I64, 0);
+    b.ins().return_(&[zero]);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{compiler::Compiler, lexer::Lexer, parser::Parser, vm::Vm};
+
+    use super::HOT_THRESHOLD;
+
+    // runs `input` with `f` called often enough to get hot first, returns the
+    // result and how many functions were compiled
+    fn run(input: &str) -> (Result<String, String>, usize) {
+        let warmup = format!(
+            "let warm = fn(n) {{ if (n == 0) {{ 0 }} else {{ warm(n - 1) }} }}; {}",
+            input
+        );
+        let prog = Parser::new(Lexer::new(warmup.as_bytes())).parse().unwrap();
+        let mut compiler = Compiler::new();
+        compiler.compile(&prog).unwrap();
+        let mut vm = Vm::new(compiler.bytecode());
+        let result = vm.run().map(|obj| obj.to_string());
+        (result, vm.jit().compiled())
+    }
+
+    #[test]
+    fn test_compiles_hot_integer_functions() {
+        let fib = "let fib = fn(n) { if (n < 2) { n } else { fib(n - 1) + fib(n - 2) } }; fib(25)";
+        assert_eq!(run(fib), (Ok("75025".to_string()), 1));
+
+        let (result, compiled) = run("warm(200)");
+        assert_eq!(result, Ok("0".to_string()));
+        assert_eq!(compiled, 1);
+
+        let locals = "let f = fn(a, b) { let c = a * b; let d = -c; if (!(d > 0) == true) { d } else { 0 } };
+            let loop = fn(n, acc) { if (n == 0) { acc } else { loop(n - 1, acc + f(n, 2)) } };
+            loop(300, 0)";
+        assert_eq!(run(locals), (Ok("-90300".to_string()), 1));
+    }
+
+    #[test]
+    fn test_falls_back_to_vm() {
+        let inputs = [
+            // returns a boolean
+            (
+                "let f = fn(n) { n > 1 }; let loop = fn(n) { if (n == 0) { f(0) } else { f(n); loop(n - 1) } }; loop(150)",
+                0,
+            ),
+            // calls another function, only `g` is compiled
+            (
+                "let g = fn(n) { n }; let f = fn(n) { g(n) + 1 }; let loop = fn(n) { if (n == 0) { f(0) } else { f(n); loop(n - 1) } }; loop(150)",
+                1,
+            ),
+            // divides
+            (
+                "let f = fn(n) { n / 2 }; let loop = fn(n) { if (n == 0) { f(0) } else { f(n); loop(n - 1) } }; loop(150)",
+                0,
+            ),
+            // works on arrays
+            (
+                "let f = fn(a) { len(a) }; let loop = fn(n) { if (n == 0) { f([]) } else { f([n]); loop(n - 1) } }; loop(150)",
+                0,
+            ),
+        ];
+        for (input, expected) in inputs {
+            let (result, compiled) = run(input);
+            assert!(result.is_ok(), "{}: {:?}", input, result);
+            assert_eq!(compiled, expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_guards() {
+        // a hot function called with something other than integers
+        let input = format!(
+            "let id = fn(x) {{ x }}; let loop = fn(n) {{ if (n == 0) {{ 0 }} else {{ id(n); loop(n - 1) }} }}; loop({}); id(true)",
+            HOT_THRESHOLD + 10
+        );
+        assert_eq!(run(&input).0, Ok("true".to_string()));
+
+        // the global the compiled code recursed through was reassigned
+        let input = "let f = fn(n) { if (n == 0) { 0 } else { f(n - 1) } }; f(150);
+            let g = f; let f = fn(n) { 42 }; g(5)";
+        assert_eq!(run(input).0, Ok("42".to_string()));
+
+        let err = run("let f = fn(n) { f(n + 1) }; f(0)").0.unwrap_err();
+        assert!(
+            err == "stack overflow" || err == "frame overflow",
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_integer_overflow() {
+        let input = "let f = fn(n) { n * 2 }; let loop = fn(n) { if (n == 0) { f(4611686018427387904) } else { f(n); loop(n - 1) } }; loop(150)";
+        assert_eq!(run(input), (Err("integer overflow".to_string()), 1));
+
+        let input = "let f = fn(n) { -n }; let loop = fn(n) { if (n == 0) { f(-9223372036854775807 - 1) } else { f(n); loop(n - 1) } }; loop(150)";
+        assert_eq!(run(input), (Err("integer overflow".to_string()), 1));
+    }
+}
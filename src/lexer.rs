@@ -1,77 +1,136 @@
 #![allow(unused, dead_code)]
 
-use crate::token::{self, Token, TokenType as tt};
+use std::ops::Range;
+
+use crate::{
+    rc::Rc,
+    token::{self, Span, Text, Token, TokenType as tt},
+};
 
 #[derive(Default)]
-pub(crate) struct Lexer<'a> {
+pub struct Lexer {
     // todo: use &str instead to support utf-8
-    // todo: impl Iterator for lexer since Lexer is techinally an iterator 
-    // yielding char/byte tokens
-    input: &'a [u8],
+    //
+    // a copy of the source the tokens share their text with, see `Text`
+    input: Rc<[u8]>,
     pos: usize,
     read_pos: usize,
-    ch: &'a [u8],
+    ch: u8,
+    // the line `ch` is on and the offset that line starts at
+    line: usize,
+    line_start: usize,
+    // set once iterating has reached the end of the input
+    done: bool,
+}
+
+// the tokens of `input` up to the end, without the EOF token
+pub fn tokenize(input: &[u8]) -> Vec<Token> {
+    Lexer::new(input).collect()
 }
 
-impl<'a> Lexer<'a> {
-    pub fn new<I: Into<&'a [u8]>>(input: I) -> Self {
+impl Lexer {
+    pub fn new(input: &[u8]) -> Self {
         let mut l = Lexer {
-            input: input.into(),
+            input: Rc::from(input),
+            line: 1,
             ..Default::default()
         };
         l.read_char();
         l
     }
 
-    pub fn next_token(&mut self) -> Token<'a> {
+    pub fn next_token(&mut self) -> Token {
         self.skip_ws();
+        let start = self.pos.min(self.input.len());
+        let line = self.line;
+        let column = self.column(start);
+        let mut tok = self.read_token();
+        tok.span = Span {
+            line,
+            column,
+            start,
+            end: self.pos.min(self.input.len()),
+        };
+        tok
+    }
+
+    // counts the characters before `offset` on the current line, bytes that
+    // continue a utf-8 character don't start one
+    fn column(&self, offset: usize) -> usize {
+        let before = &self.input[self.line_start.min(offset)..offset];
+        before.iter().filter(|&&b| b & 0xC0 != 0x80).count() + 1
+    }
+
+    // a token of the input from `start` up to the current character,
+    // including it
+    fn token(&self, token_type: tt, start: usize) -> Token {
+        let end = (self.pos + 1).min(self.input.len());
+        Token::with_text(token_type, Text::shared(&self.input, start..end))
+    }
+
+    fn read_token(&mut self) -> Token {
         let ch = self.ch;
-        debug_assert_ne!(ch, b" ");
+        let start = self.pos;
+        debug_assert_ne!(ch, b' ');
         let mut consume_next = true;
-        let tok = match ch[0] {
+        let tok = match ch {
             b'=' => {
-                let next = self.peek_char()[0];
+                let next = self.peek_char();
                 if next == b'=' {
                     self.read_char();
-                    Token::new(tt::EQ, b"==")
+                    self.token(tt::EQ, start)
+                } else if next == b'>' {
+                    self.read_char();
+                    self.token(tt::FATARROW, start)
                 } else {
-                    Token::new(tt::ASSIGN, ch)
+                    self.token(tt::ASSIGN, start)
                 }
             }
             b'!' => {
-                let next = self.peek_char()[0];
+                let next = self.peek_char();
                 if next == b'=' {
                     self.read_char();
-                    Token::new(tt::NOTEQ, b"!=")
+                    self.token(tt::NOTEQ, start)
                 } else {
-                    Token::new(tt::BANG, ch)
+                    self.token(tt::BANG, start)
                 }
             }
-            b'+' => Token::new(tt::PLUS, ch),
-            b';' => Token::new(tt::SEMICOLON, ch),
-            b'(' => Token::new(tt::LPAREN, ch),
-            b')' => Token::new(tt::RPAREN, ch),
-            b'{' => Token::new(tt::LBRACE, ch),
-            b'}' => Token::new(tt::RBRACE, ch),
-            b',' => Token::new(tt::COMMA, ch),
-            b'-' => Token::new(tt::MINUS, ch),
-            b'/' => Token::new(tt::FSLASH, ch),
-            b'*' => Token::new(tt::MUL, ch),
-            b'<' => Token::new(tt::LT, ch),
-            b'>' => Token::new(tt::GT, ch),
-            b'\0' => Token::new(tt::EOF, ch),
+            b'+' => self.token(tt::PLUS, start),
+            b';' => self.token(tt::SEMICOLON, start),
+            b':' => self.token(tt::COLON, start),
+            b'?' => self.token(tt::QUESTION, start),
+            b'.' if self.input.get(self.read_pos..self.read_pos + 2) == Some(b"..") => {
+                self.read_char();
+                self.read_char();
+                self.token(tt::ELLIPSIS, start)
+            }
+            b'(' => self.token(tt::LPAREN, start),
+            b')' => self.token(tt::RPAREN, start),
+            b'{' => self.token(tt::LBRACE, start),
+            b'}' => self.token(tt::RBRACE, start),
+            b'[' => self.token(tt::LBRACKET, start),
+            b']' => self.token(tt::RBRACKET, start),
+            b',' => self.token(tt::COMMA, start),
+            b'-' => self.token(tt::MINUS, start),
+            b'/' => self.token(tt::FSLASH, start),
+            b'*' => self.token(tt::MUL, start),
+            b'<' => self.token(tt::LT, start),
+            b'>' => self.token(tt::GT, start),
+            // past the end of the input there's no text to share
+            b'\0' if self.pos >= self.input.len() => Token::new(tt::EOF, b"\0"),
+            b'\0' => self.token(tt::EOF, start),
+            b'"' => self.read_string(),
             c if Self::is_letter(c) => {
                 consume_next = false;
                 let ident = self.read_ident();
-                let ttype = token::lookup_ident(ident);
-                Token::new(ttype, ident)
+                let ttype = token::lookup_ident(&self.input[ident.clone()]);
+                Token::with_text(ttype, Text::shared(&self.input, ident))
             }
             c if Self::is_digit(c) => {
                 consume_next = false;
-                let ident = self.read_num();
-                Token::new(tt::INT, ident)
+                self.read_num()
             }
-            _ => Token::new(tt::ILLEGAL, ch),
+            _ => self.token(tt::ILLEGAL, start),
         };
         if consume_next {
             self.read_char();
@@ -79,41 +138,76 @@ impl<'a> Lexer<'a> {
         tok
     }
 
-    fn read_ident(&mut self) -> &'a [u8] {
+    fn read_ident(&mut self) -> Range<usize> {
         let start = self.pos;
-        while Self::is_letter(self.ch[0]) {
+        while Self::is_letter(self.ch) {
             self.read_char()
         }
-        &self.input[start..self.pos]
+        start..self.pos
     }
 
-    fn read_num(&mut self) -> &'a [u8] {
+    // a dot only continues a number when a digit follows it
+    //
+    // `0x`, `0o` and `0b` start an integer in another radix, every letter and
+    // digit after the prefix is taken so the parser can reject bad ones
+    fn read_num(&mut self) -> Token {
         let start = self.pos;
-        while Self::is_digit(self.ch[0]) {
+        let text = |l: &Self| Text::shared(&l.input, start..l.pos);
+        if self.ch == b'0' && b"xob".contains(&self.peek_char()) {
+            self.read_char();
+            self.read_char();
+            while Self::is_letter(self.ch) || Self::is_digit(self.ch) {
+                self.read_char()
+            }
+            return Token::with_text(tt::INT, text(self));
+        }
+        while Self::is_digit(self.ch) {
+            self.read_char()
+        }
+        if self.ch != b'.' || !Self::is_digit(self.peek_char()) {
+            return Token::with_text(tt::INT, text(self));
+        }
+        self.read_char();
+        while Self::is_digit(self.ch) {
             self.read_char()
         }
-        &self.input[start..self.pos]
+        Token::with_text(tt::FLOAT, text(self))
+    }
+
+    // the literal of a string token is the text between the quotes, there
+    // are no escapes so it can't contain a quote
+    fn read_string(&mut self) -> Token {
+        let start = self.pos;
+        loop {
+            self.read_char();
+            if self.pos >= self.input.len() {
+                let text = Text::shared(&self.input, start..self.input.len());
+                return Token::with_text(tt::ILLEGAL, text);
+            }
+            if self.ch == b'"' {
+                let text = Text::shared(&self.input, start + 1..self.pos);
+                return Token::with_text(tt::STRING, text);
+            }
+        }
     }
 
     fn read_char(&mut self) {
-        let ch = self
-            .input
-            .get(self.read_pos..(self.read_pos + 1))
-            .unwrap_or(b"\0");
+        if self.ch == b'\n' {
+            self.line += 1;
+            self.line_start = self.read_pos;
+        }
         self.pos = self.read_pos;
         self.read_pos += 1;
-        self.ch = ch;
+        self.ch = self.input.get(self.pos).copied().unwrap_or(b'\0');
     }
 
-    fn peek_char(&mut self) -> &[u8] {
-        self.input
-            .get(self.read_pos..(self.read_pos + 1))
-            .unwrap_or(b"\0")
+    fn peek_char(&mut self) -> u8 {
+        self.input.get(self.read_pos).copied().unwrap_or(b'\0')
     }
 
     fn skip_ws(&mut self) {
         const WS_CHARS: &[u8] = b" \t\n\r";
-        while WS_CHARS.contains(&self.ch[0]) {
+        while WS_CHARS.contains(&self.ch) {
             self.read_char();
         }
     }
@@ -127,13 +221,92 @@ impl<'a> Lexer<'a> {
     }
 }
 
+// yields tokens until the end of the input, the EOF token isn't yielded,
+// `next_token` keeps returning it instead
+impl Iterator for Lexer {
+    type Item = Token;
+
+    fn next(&mut self) -> Option<Token> {
+        if self.done {
+            return None;
+        }
+        let tok = self.next_token();
+        if tok.token_type == tt::EOF {
+            self.done = true;
+            return None;
+        }
+        Some(tok)
+    }
+}
+
+impl std::iter::FusedIterator for Lexer {}
+
 #[cfg(test)]
 mod tests {
     use crate::{
-        lexer::Lexer,
+        lexer::{tokenize, Lexer},
         token::{Token, TokenType as tt},
     };
 
+    #[test]
+    fn test_spans() {
+        let input = "let x = \"é\";\n  x ==\r\n\tfoo(é1)";
+        let expected = [
+            (tt::LET, 1, 1, 0..3),
+            (tt::IDENT, 1, 5, 4..5),
+            (tt::ASSIGN, 1, 7, 6..7),
+            (tt::STRING, 1, 9, 8..12),
+            (tt::SEMICOLON, 1, 12, 12..13),
+            (tt::IDENT, 2, 3, 16..17),
+            (tt::EQ, 2, 5, 18..20),
+            (tt::IDENT, 3, 2, 23..26),
+            (tt::LPAREN, 3, 5, 26..27),
+            (tt::ILLEGAL, 3, 6, 27..28),
+        ];
+        let mut l = Lexer::new(input.as_bytes());
+        for (token_type, line, column, range) in expected {
+            let tok = l.next_token();
+            assert_eq!(tok.token_type, token_type, "{}", tok);
+            assert_eq!(
+                (tok.span.line, tok.span.column, tok.span.start..tok.span.end),
+                (line, column, range),
+                "{}",
+                tok
+            );
+        }
+        let eof = std::iter::repeat_with(|| l.next_token())
+            .find(|tok| tok.token_type == tt::EOF)
+            .unwrap();
+        assert_eq!((eof.span.start, eof.span.end), (input.len(), input.len()));
+        assert_eq!(eof.span.to_string(), "3:9");
+    }
+
+    #[test]
+    fn test_iterator() {
+        let types: Vec<_> = Lexer::new("let x = 1;".as_bytes())
+            .map(|tok| tok.token_type)
+            .collect();
+        assert_eq!(
+            types,
+            [tt::LET, tt::IDENT, tt::ASSIGN, tt::INT, tt::SEMICOLON]
+        );
+
+        let mut l = Lexer::new("a".as_bytes());
+        assert_eq!(
+            l.next().map(|tok| tok.literal.to_vec()),
+            Some(b"a".to_vec())
+        );
+        assert!(l.next().is_none());
+        assert!(l.next().is_none());
+
+        let idents = tokenize(b"fn(a, b) { a + b }")
+            .into_iter()
+            .filter(|tok| tok.token_type == tt::IDENT)
+            .count();
+        assert_eq!(idents, 4);
+        assert!(tokenize(b"  \n").is_empty());
+    }
+
     #[test]
     fn test_new_token() {
         let input = "let five = 5;
@@ -151,7 +324,16 @@ if (5 < 10) {
 }
 10 == 10;
 10 != 9;
-";
+\"foobar\"
+\"foo bar\"
+\"\";
+{\"foo\": \"bar\"}
+3.14 1.x
+a ? b : c
+match (x) { _ => 1 }
+fn(...xs) ..
+0x1F 0o17 0b1010 0xg1 0b
+\"open";
 
         let tests = [
             Token::new(tt::LET, b"let"),
@@ -227,6 +409,46 @@ if (5 < 10) {
             Token::new(tt::NOTEQ, b"!="),
             Token::new(tt::INT, b"9"),
             Token::new(tt::SEMICOLON, b";"),
+            Token::new(tt::STRING, b"foobar"),
+            Token::new(tt::STRING, b"foo bar"),
+            Token::new(tt::STRING, b""),
+            Token::new(tt::SEMICOLON, b";"),
+            Token::new(tt::LBRACE, b"{"),
+            Token::new(tt::STRING, b"foo"),
+            Token::new(tt::COLON, b":"),
+            Token::new(tt::STRING, b"bar"),
+            Token::new(tt::RBRACE, b"}"),
+            Token::new(tt::FLOAT, b"3.14"),
+            Token::new(tt::INT, b"1"),
+            Token::new(tt::ILLEGAL, b"."),
+            Token::new(tt::IDENT, b"x"),
+            Token::new(tt::IDENT, b"a"),
+            Token::new(tt::QUESTION, b"?"),
+            Token::new(tt::IDENT, b"b"),
+            Token::new(tt::COLON, b":"),
+            Token::new(tt::IDENT, b"c"),
+            Token::new(tt::MATCH, b"match"),
+            Token::new(tt::LPAREN, b"("),
+            Token::new(tt::IDENT, b"x"),
+            Token::new(tt::RPAREN, b")"),
+            Token::new(tt::LBRACE, b"{"),
+            Token::new(tt::IDENT, b"_"),
+            Token::new(tt::FATARROW, b"=>"),
+            Token::new(tt::INT, b"1"),
+            Token::new(tt::RBRACE, b"}"),
+            Token::new(tt::FUNCTION, b"fn"),
+            Token::new(tt::LPAREN, b"("),
+            Token::new(tt::ELLIPSIS, b"..."),
+            Token::new(tt::IDENT, b"xs"),
+            Token::new(tt::RPAREN, b")"),
+            Token::new(tt::ILLEGAL, b"."),
+            Token::new(tt::ILLEGAL, b"."),
+            Token::new(tt::INT, b"0x1F"),
+            Token::new(tt::INT, b"0o17"),
+            Token::new(tt::INT, b"0b1010"),
+            Token::new(tt::INT, b"0xg1"),
+            Token::new(tt::INT, b"0b"),
+            Token::new(tt::ILLEGAL, b"\"open"),
             Token::new(tt::EOF, b"\0"),
         ];
 
@@ -247,7 +469,7 @@ if (5 < 10) {
             println!(
                 "case {:?} success, value = {:?}",
                 case.token_type,
-                std::str::from_utf8(tok.literal)
+                std::str::from_utf8(&tok.literal)
             );
         }
     }
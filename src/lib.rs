@@ -0,0 +1,72 @@
+//! A Monkey interpreter, the language of "Writing An Interpreter In Go",
+//! usable as a library as well as through the `interpreter-book-in-rust`
+//! binary.
+//!
+//! [`eval`] runs a piece of source on its own, an [`Interpreter`] keeps the
+//! bindings of one evaluation for the next and lets Rust values and
+//! functions in. The stages are there to use separately too: [`Lexer`]
+//! turns source into tokens, [`Parser`] turns those into a
+//! [`Program`](ast::Program), and [`Evaluator`](evaluator::Evaluator) runs
+//! that in an [`Env`], or [`Compiler`](compiler::Compiler) compiles it for
+//! the [`Vm`](vm::Vm).
+//!
+//! ```
+//! use interpreter_book_in_rust::{
+//!     evaluator::Evaluator, sandbox::SandboxConfig, Env, Lexer, Parser,
+//! };
+//!
+//! let source = "let a = 2; a * 21";
+//! let prog = Parser::new(Lexer::new(source.as_bytes())).parse().unwrap();
+//! let value = Evaluator::new(SandboxConfig::default()).eval_program(&prog, &mut Env::new());
+//! assert_eq!(value.unwrap().to_string(), "42");
+//! ```
+
+pub mod lexer;
+pub mod token;
+#[cfg(all(feature = "repl", not(target_arch = "wasm32")))]
+pub mod repl;
+pub mod parser;
+pub mod ast;
+pub mod object;
+pub mod evaluator;
+pub mod env;
+pub mod cli;
+pub mod code;
+pub mod compiler;
+pub mod symbol_table;
+pub mod vm;
+pub mod mkc;
+pub mod builtins;
+pub mod optimizer;
+pub mod debugger;
+#[cfg(feature = "jit")]
+pub mod jit;
+pub mod interpreter;
+pub mod engine;
+pub mod intern;
+pub mod host;
+pub mod fmt;
+pub mod fold;
+pub mod flat;
+pub mod lint;
+pub mod rc;
+pub mod slots;
+pub mod sandbox;
+pub mod source;
+pub mod tasks;
+pub mod testing;
+pub mod profiler;
+pub mod coverage;
+pub mod diagnostic;
+#[cfg(feature = "serde")]
+pub mod serde;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+
+pub use env::Env;
+pub use interpreter::{eval, Error, Interpreter};
+pub use lexer::Lexer;
+pub use object::Object;
+pub use parser::Parser;
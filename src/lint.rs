@@ -0,0 +1,778 @@
+use std::{collections::HashMap, fmt};
+
+use crate::{
+    ast::{Expr, Program, Stmt},
+    builtins,
+    diagnostic::Diagnostic,
+    token::Span,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Allow,
+    Warning,
+    Error,
+}
+
+impl Severity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Severity::Allow => "allow",
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        }
+    }
+
+    pub fn from_name(value: &str) -> Option<Severity> {
+        match value {
+            "allow" => Some(Severity::Allow),
+            "warning" => Some(Severity::Warning),
+            "error" => Some(Severity::Error),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Rule {
+    UndefinedVariable,
+    // a call to a function literal bound by a `let` that's never reassigned
+    WrongArgumentCount,
+    // a `let`, loop variable or caught error nothing refers to
+    UnusedVariable,
+    UnusedParameter,
+    // a binding hiding one of an enclosing block or function
+    ShadowedVariable,
+    ShadowedBuiltin,
+    UnreachableCode,
+    ConstantCondition,
+    EmptyBlock,
+}
+
+impl Rule {
+    pub const ALL: &'static [Rule] = &[
+        Rule::UndefinedVariable,
+        Rule::WrongArgumentCount,
+        Rule::UnusedVariable,
+        Rule::UnusedParameter,
+        Rule::ShadowedVariable,
+        Rule::ShadowedBuiltin,
+        Rule::UnreachableCode,
+        Rule::ConstantCondition,
+        Rule::EmptyBlock,
+    ];
+
+    pub fn id(&self) -> &'static str {
+        match self {
+            Rule::UndefinedVariable => "undefined-variable",
+            Rule::WrongArgumentCount => "wrong-argument-count",
+            Rule::UnusedVariable => "unused-variable",
+            Rule::UnusedParameter => "unused-parameter",
+            Rule::ShadowedVariable => "shadowed-variable",
+            Rule::ShadowedBuiltin => "shadowed-builtin",
+            Rule::UnreachableCode => "unreachable-code",
+            Rule::ConstantCondition => "constant-condition",
+            Rule::EmptyBlock => "empty-block",
+        }
+    }
+
+    pub fn from_id(id: &str) -> Option<Rule> {
+        Self::ALL.iter().copied().find(|rule| rule.id() == id)
+    }
+
+    // what would fail when the program runs is an error, the rest doesn't
+    // stop it, code that never runs can't fail either
+    pub fn default_severity(&self) -> Severity {
+        match self {
+            Rule::UndefinedVariable | Rule::WrongArgumentCount => Severity::Error,
+            _ => Severity::Warning,
+        }
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.id())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LintConfig {
+    pub(crate) severities: HashMap<Rule, Severity>,
+    // whether unused bindings outside of functions are reported, the next
+    // line can still use them in the repl
+    pub unused_globals: bool,
+}
+
+impl Default for LintConfig {
+    fn default() -> Self {
+        LintConfig {
+            severities: HashMap::new(),
+            unused_globals: true,
+        }
+    }
+}
+
+impl LintConfig {
+    pub fn set(&mut self, rule: Rule, severity: Severity) -> &mut Self {
+        self.severities.insert(rule, severity);
+        self
+    }
+
+    pub fn severity(&self, rule: Rule) -> Severity {
+        self.severities
+            .get(&rule)
+            .copied()
+            .unwrap_or(rule.default_severity())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lint {
+    pub rule: Rule,
+    pub severity: Severity,
+    pub message: String,
+    pub span: Span,
+    // other places the lint is about, like the binding a `let` shadows
+    pub labels: Vec<(Span, String)>,
+}
+
+impl Lint {
+    pub fn to_diagnostic(&self) -> Diagnostic {
+        let diagnostic = Diagnostic::new(self.severity, self.message.clone(), Some(self.span));
+        self.labels
+            .iter()
+            .fold(diagnostic, |diagnostic, (span, label)| {
+                diagnostic.with_label(*span, label.clone())
+            })
+    }
+}
+
+// the problems that can be found without running the program, all of them
+// rather than the first, in the order they appear in the source
+pub fn lint(prog: &Program, config: &LintConfig) -> Vec<Lint> {
+    let mut linter = Linter {
+        config,
+        scopes: vec![Scope::default()],
+        functions: HashMap::new(),
+        calls: vec![],
+        lints: vec![],
+    };
+    linter.stmts(&prog.stmts);
+    let scope = linter.pop_scope();
+    for ident in scope.pending {
+        if let Expr::Identifier { value, token, .. } = ident {
+            let message = format!("undefined variable {}", name(value));
+            linter.report(Rule::UndefinedVariable, message, token.span);
+        }
+    }
+    for (declaration, call, found) in std::mem::take(&mut linter.calls) {
+        let Some(&(arity, variadic)) = linter.functions.get(&declaration) else {
+            continue;
+        };
+        if found < arity || found > arity && !variadic {
+            let Expr::Call { function, .. } = call else {
+                continue;
+            };
+            let message = format!(
+                "`{}` takes {}{} argument{}, got {}",
+                function,
+                if variadic { "at least " } else { "" },
+                arity,
+                if arity == 1 { "" } else { "s" },
+                found
+            );
+            linter.report(Rule::WrongArgumentCount, message, call.first_token().span);
+        }
+    }
+
+    let mut lints = linter.lints;
+    lints.sort_by_key(|lint| lint.span.start);
+    lints
+}
+
+// just the warnings about unused variables, what's reported before running
+// a script
+pub fn unused(prog: &Program, config: &LintConfig) -> Vec<Lint> {
+    let mut lints = lint(prog, config);
+    lints.retain(|lint| lint.rule == Rule::UnusedVariable);
+    lints
+}
+
+fn name(value: &[u8]) -> String {
+    String::from_utf8_lossy(value).into_owned()
+}
+
+struct Declaration<'p> {
+    name: &'p [u8],
+    ident: &'p Expr,
+    used: bool,
+    // what it's reported as when it isn't used
+    unused: Rule,
+    // bound outside of every function
+    global: bool,
+}
+
+// one scope per function, the declarations of a block are dropped from it
+// when the block ends
+#[derive(Default)]
+struct Scope<'p> {
+    declarations: Vec<Declaration<'p>>,
+    // the first declaration of the innermost block
+    block_start: usize,
+    // identifiers used here or in nested functions that nothing declared
+    // yet, a function can use a name its enclosing one defines later
+    pending: Vec<&'p Expr>,
+}
+
+struct Linter<'c, 'p> {
+    config: &'c LintConfig,
+    scopes: Vec<Scope<'p>>,
+    // the arity of the function literals bound by a `let`, by the name they're
+    // bound to, until the name is reassigned
+    functions: HashMap<*const Expr, (usize, bool)>,
+    // calls by name with the declaration of the name and how many arguments
+    // they pass, checked once every assignment has been seen
+    calls: Vec<(*const Expr, &'p Expr, usize)>,
+    lints: Vec<Lint>,
+}
+
+impl<'p> Linter<'_, 'p> {
+    fn report(&mut self, rule: Rule, message: String, span: Span) {
+        self.report_labeled(rule, message, span, vec![]);
+    }
+
+    fn report_labeled(
+        &mut self,
+        rule: Rule,
+        message: String,
+        span: Span,
+        labels: Vec<(Span, String)>,
+    ) {
+        let severity = self.config.severity(rule);
+        if severity != Severity::Allow {
+            self.lints.push(Lint {
+                rule,
+                severity,
+                message,
+                span,
+                labels,
+            });
+        }
+    }
+
+    fn scope(&mut self) -> &mut Scope<'p> {
+        self.scopes.last_mut().expect("linter has no scope")
+    }
+
+    fn pop_scope(&mut self) -> Scope<'p> {
+        let scope = self.scopes.pop().expect("linter has no scope");
+        self.report_unused(&scope.declarations);
+        scope
+    }
+
+    fn report_unused(&mut self, declarations: &[Declaration<'p>]) {
+        for decl in declarations {
+            // a leading underscore marks a binding as intentionally unused
+            if decl.used || decl.name.starts_with(b"_") {
+                continue;
+            }
+            if decl.global && !self.config.unused_globals {
+                continue;
+            }
+            let message = format!("`{}` is never used", name(decl.name));
+            self.report(decl.unused, message, decl.ident.first_token().span);
+        }
+    }
+
+    fn block(&mut self, f: impl FnOnce(&mut Self)) {
+        let start = self.scope().declarations.len();
+        let outer_start = std::mem::replace(&mut self.scope().block_start, start);
+        f(self);
+        self.scope().block_start = outer_start;
+        let declarations = self.scope().declarations.split_off(start);
+        self.report_unused(&declarations);
+    }
+
+    // a `let` redefining a name of its own block only rebinds it
+    fn shadowing(&mut self, name: &[u8], span: Span) {
+        let innermost = self.scopes.len() - 1;
+        let shadowed = self.scopes.iter().enumerate().rev().find_map(|(i, scope)| {
+            let enclosing = match i {
+                i if i == innermost => &scope.declarations[..scope.block_start],
+                _ => &scope.declarations[..],
+            };
+            enclosing.iter().rev().find(|d| d.name == name)
+        });
+        match shadowed {
+            Some(declaration) => {
+                let original = declaration.ident.first_token().span;
+                let message = format!("`{}` shadows the binding at {}", self::name(name), original);
+                let label = (original, "shadowed binding".to_string());
+                self.report_labeled(Rule::ShadowedVariable, message, span, vec![label]);
+            }
+            None if builtins::lookup(name).is_some() => {
+                let message = format!("`{}` shadows a builtin", self::name(name));
+                self.report(Rule::ShadowedBuiltin, message, span);
+            }
+            None => {}
+        }
+    }
+
+    fn define(&mut self, ident: &'p Expr, unused: Rule) {
+        let Expr::Identifier {
+            value: name, token, ..
+        } = ident
+        else {
+            // the names a `let` destructures an array into
+            if let Expr::ArrayLiteral { elements, .. } = ident {
+                elements
+                    .iter()
+                    .for_each(|element| self.define(element, unused));
+            }
+            return;
+        };
+        self.shadowing(name, token.span);
+
+        // uses in functions defined before the name are fine once they're
+        // called, and they don't make it unused either
+        let pending = std::mem::take(&mut self.scope().pending);
+        let (early, pending): (Vec<_>, Vec<_>) = pending
+            .into_iter()
+            .partition(|p| matches!(p, Expr::Identifier { value, .. } if value == name));
+        self.scope().pending = pending;
+
+        let global = self.scopes.len() == 1;
+        self.scope().declarations.push(Declaration {
+            name,
+            ident,
+            used: !early.is_empty(),
+            unused,
+            global,
+        });
+    }
+
+    // the declaration an identifier refers to, None for builtins and names
+    // nothing declared yet
+    fn lookup(&mut self, ident: &'p Expr) -> Option<&'p Expr> {
+        let Expr::Identifier { value: name, .. } = ident else {
+            return None;
+        };
+        let declaration = self.scopes.iter_mut().rev().find_map(|scope| {
+            scope
+                .declarations
+                .iter_mut()
+                .rev()
+                .find(|d| d.name == &**name)
+        });
+        match declaration {
+            Some(declaration) => {
+                declaration.used = true;
+                Some(declaration.ident)
+            }
+            None => {
+                if builtins::lookup(name).is_none() {
+                    self.scope().pending.push(ident);
+                }
+                None
+            }
+        }
+    }
+
+    fn stmts(&mut self, stmts: &'p [Stmt]) {
+        // only the first of the statements that can't run is reported
+        let exits = stmts.iter().position(|stmt| {
+            matches!(
+                stmt,
+                Stmt::Return { .. } | Stmt::Break { .. } | Stmt::Continue { .. }
+            )
+        });
+        if let Some(unreachable) = exits.and_then(|i| stmts.get(i + 1)) {
+            let span = unreachable.first_token().span;
+            self.report(
+                Rule::UnreachableCode,
+                "unreachable statement".to_string(),
+                span,
+            );
+        }
+        for stmt in stmts {
+            self.stmt(stmt);
+        }
+    }
+
+    fn stmt(&mut self, stmt: &'p Stmt) {
+        match stmt {
+            Stmt::Let { name, value, .. } => {
+                // functions can call themselves, so they're bound first
+                if let Expr::FnLiteral {
+                    parameters, rest, ..
+                } = value
+                {
+                    let arity = (parameters.len(), rest.is_some());
+                    self.functions.insert(name as *const _, arity);
+                    self.define(name, Rule::UnusedVariable);
+                    self.expr(value);
+                } else {
+                    self.expr(value);
+                    self.define(name, Rule::UnusedVariable);
+                }
+            }
+            Stmt::Return { value: expr, .. } | Stmt::Expr { expr } => self.expr(expr),
+            Stmt::Block { stmts, .. } => self.block(|linter| linter.stmts(stmts)),
+            Stmt::Debugger { .. } | Stmt::Break { .. } | Stmt::Continue { .. } => {}
+            Stmt::For {
+                name,
+                iterable,
+                body,
+                ..
+            } => {
+                self.expr(iterable);
+                self.block(|linter| {
+                    linter.define(name, Rule::UnusedVariable);
+                    linter.body(body);
+                });
+            }
+        }
+    }
+
+    fn body(&mut self, block: &'p Stmt) {
+        if let Stmt::Block { stmts, .. } = block {
+            if stmts.is_empty() {
+                let span = block.first_token().span;
+                self.report(Rule::EmptyBlock, "empty block".to_string(), span);
+            }
+        }
+        self.stmt(block);
+    }
+
+    fn expr(&mut self, expr: &'p Expr) {
+        match expr {
+            Expr::Identifier { .. } => {
+                self.lookup(expr);
+            }
+            Expr::IntLiteral { .. }
+            | Expr::FloatLiteral { .. }
+            | Expr::BoolLiteral { .. }
+            | Expr::StringLiteral { .. } => {}
+            Expr::FnLiteral {
+                parameters,
+                rest,
+                block,
+                ..
+            } => {
+                self.scopes.push(Scope::default());
+                for param in parameters.iter().chain(rest.as_deref()) {
+                    self.define(param, Rule::UnusedParameter);
+                }
+                self.body(block);
+                // names still unknown may be declared later in the
+                // enclosing function
+                let scope = self.pop_scope();
+                self.scope().pending.extend(scope.pending);
+            }
+            Expr::Call {
+                function,
+                arguments,
+                ..
+            } => {
+                if let Some(declaration) = self.lookup_or_expr(function) {
+                    self.calls
+                        .push((declaration as *const _, expr, arguments.len()));
+                }
+                arguments.iter().for_each(|arg| self.expr(arg));
+            }
+            Expr::Prefix { expr, .. } => self.expr(expr),
+            Expr::Infix { left, right, .. }
+            | Expr::Index {
+                left, index: right, ..
+            } => {
+                self.expr(left);
+                self.expr(right);
+            }
+            Expr::If {
+                condition,
+                consequence,
+                alternative,
+                ..
+            } => {
+                if is_constant(condition) {
+                    let message = format!("condition `{}` is constant", condition);
+                    self.report(
+                        Rule::ConstantCondition,
+                        message,
+                        condition.first_token().span,
+                    );
+                }
+                self.expr(condition);
+                self.body(consequence);
+                if let Some(alternative) = alternative {
+                    self.body(alternative);
+                }
+            }
+            Expr::Try {
+                body,
+                name,
+                handler,
+                ..
+            } => {
+                self.body(body);
+                self.block(|linter| {
+                    linter.define(name, Rule::UnusedVariable);
+                    linter.body(handler);
+                });
+            }
+            Expr::ArrayLiteral { elements, .. } => elements.iter().for_each(|e| self.expr(e)),
+            Expr::Assign { name, value, .. } => {
+                self.expr(value);
+                // the name may be bound to anything after this
+                if let Some(declaration) = self.lookup(name) {
+                    self.functions.remove(&(declaration as *const _));
+                }
+            }
+            Expr::Match { subject, arms, .. } => {
+                self.expr(subject);
+                for arm in arms {
+                    if let Some(pattern) = &arm.pattern {
+                        self.expr(pattern);
+                    }
+                    self.expr(&arm.value);
+                }
+            }
+            Expr::HashLiteral { pairs, .. } => pairs.iter().for_each(|(key, value)| {
+                self.expr(key);
+                self.expr(value);
+            }),
+        }
+    }
+
+    // the declaration of a called name, anything else is just walked
+    fn lookup_or_expr(&mut self, expr: &'p Expr) -> Option<&'p Expr> {
+        match expr {
+            Expr::Identifier { .. } => self.lookup(expr),
+            _ => {
+                self.expr(expr);
+                None
+            }
+        }
+    }
+}
+
+fn is_constant(expr: &Expr) -> bool {
+    match expr {
+        Expr::IntLiteral { .. } | Expr::FloatLiteral { .. } | Expr::BoolLiteral { .. } => true,
+        Expr::Prefix { expr, .. } => is_constant(expr),
+        Expr::Infix { left, right, .. } => is_constant(left) && is_constant(right),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{lint, unused, LintConfig, Rule, Severity};
+    use crate::{lexer::Lexer, parser::Parser};
+
+    fn lints(input: &str, config: &LintConfig) -> Vec<String> {
+        let prog = Parser::new(Lexer::new(input.as_bytes())).parse().unwrap();
+        lint(&prog, config)
+            .iter()
+            .map(|lint| format!("{}: {}: {}", lint.span, lint.rule, lint.message))
+            .collect()
+    }
+
+    #[test]
+    fn test_rules() {
+        let inputs: [(&str, &[&str]); 13] = [
+            ("let x = 1; puts(x);", &[]),
+            ("for (x in [1]) { puts(x) }", &[]),
+            (
+                "let [a, b] = [1, 2]; puts(a);",
+                &["1:9: unused-variable: `b` is never used"],
+            ),
+            (
+                "for (x in [1]) { }",
+                &[
+                    "1:6: unused-variable: `x` is never used",
+                    "1:16: empty-block: empty block",
+                ],
+            ),
+            ("let x = 1;", &["1:5: unused-variable: `x` is never used"]),
+            ("let _x = 1; let f = fn(_a) { 1 }; f(2);", &[]),
+            (
+                "let f = fn(x, y) { x }; f(1, 2);",
+                &["1:15: unused-parameter: `y` is never used"],
+            ),
+            (
+                "let x = 1; let f = fn(x) { x }; f(x);",
+                &["1:23: shadowed-variable: `x` shadows the binding at 1:5"],
+            ),
+            (
+                "let len = fn(x) { x }; len(1)",
+                &["1:5: shadowed-builtin: `len` shadows a builtin"],
+            ),
+            (
+                "if (1 < 2) { 1 }; if (!true) { 2 } else { 3 }; if (len([])) { 4 }",
+                &[
+                    "1:5: constant-condition: condition `(1 < 2)` is constant",
+                    "1:23: constant-condition: condition `(!true)` is constant",
+                ],
+            ),
+            (
+                "let f = fn() { }; if (f()) { } else { 1 }",
+                &[
+                    "1:14: empty-block: empty block",
+                    "1:28: empty-block: empty block",
+                ],
+            ),
+            (
+                "try { 1 } catch (e) { 2 }",
+                &["1:18: unused-variable: `e` is never used"],
+            ),
+            ("let a = 1; let f = fn(x) { a = x }; f(1)", &[]),
+        ];
+        for (input, expected) in inputs {
+            assert_eq!(lints(input, &LintConfig::default()), expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_check() {
+        let inputs: [(&str, &[&str]); 8] = [
+            ("let f = fn(x) { x }; f(1) + len([])", &[]),
+            (
+                "x + y",
+                &[
+                    "1:1: undefined-variable: undefined variable x",
+                    "1:5: undefined-variable: undefined variable y",
+                ],
+            ),
+            (
+                "let f = fn(a, b) { a + b }; f(1); f(1, 2, 3)",
+                &[
+                    "1:29: wrong-argument-count: `f` takes 2 arguments, got 1",
+                    "1:35: wrong-argument-count: `f` takes 2 arguments, got 3",
+                ],
+            ),
+            (
+                "let f = fn(a, ...more) { more }; f(); f(1, 2, 3)",
+                &[
+                    "1:12: unused-parameter: `a` is never used",
+                    "1:34: wrong-argument-count: `f` takes at least 1 argument, got 0",
+                ],
+            ),
+            // a reassigned name may be bound to any function
+            ("let f = fn() { 1 }; f(1); f = fn(x) { x }", &[]),
+            (
+                "let f = fn() { return 1; f(); }; f()",
+                &["1:26: unreachable-code: unreachable statement"],
+            ),
+            (
+                "for (x in [1]) { break; puts(x); continue }",
+                &["1:25: unreachable-code: unreachable statement"],
+            ),
+            // names used before their definition are fine in functions called
+            // after it
+            (
+                "let f = fn() { g(1) }; let g = fn() { z };\nf()",
+                &["1:39: undefined-variable: undefined variable z"],
+            ),
+        ];
+        for (input, expected) in inputs {
+            assert_eq!(lints(input, &LintConfig::default()), expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_shadowing() {
+        let input = "let x = 1; let f = fn(a) { let x = a; if (a) { let x = 2; let a = x; a } else { x } };\nlet x = f(1); let [len, y] = [x, 2]; len + y";
+        assert_eq!(
+            lints(input, &LintConfig::default()),
+            [
+                "1:5: unused-variable: `x` is never used",
+                "1:32: shadowed-variable: `x` shadows the binding at 1:5",
+                "1:52: shadowed-variable: `x` shadows the binding at 1:32",
+                "1:63: shadowed-variable: `a` shadows the binding at 1:23",
+                "2:20: shadowed-builtin: `len` shadows a builtin",
+            ]
+        );
+        let source = "let len = 1; len";
+        let prog = Parser::new(Lexer::new(source.as_bytes())).parse().unwrap();
+        let diagnostic = lint(&prog, &LintConfig::default())[0].to_diagnostic();
+        assert_eq!(diagnostic.to_string(), "warning: `len` shadows a builtin");
+        assert_eq!(
+            diagnostic.render(source),
+            "`len` shadows a builtin\n    let len = 1; len\n        ^^^"
+        );
+        let source = "let x = 1;\nlet f = fn() { let x = 2; x };\nf() + x";
+        let prog = Parser::new(Lexer::new(source.as_bytes())).parse().unwrap();
+        let diagnostic = lint(&prog, &LintConfig::default())[0].to_diagnostic();
+        assert_eq!(
+            diagnostic.render(source),
+            "`x` shadows the binding at 1:5\n    let f = fn() { let x = 2; x };\n                       ^\n    let x = 1;\n        - shadowed binding"
+        );
+    }
+
+    #[test]
+    fn test_unused() {
+        let input = "let a = 1; let [b, _c] = [2]; let f = fn(x, y) { let d = x; a };\nlet _ = f";
+        let prog = Parser::new(Lexer::new(input.as_bytes())).parse().unwrap();
+        let warnings: Vec<_> = unused(&prog, &LintConfig::default())
+            .iter()
+            .map(|l| format!("{}: {} {}", l.span, l.severity.as_str(), l.message))
+            .collect();
+        assert_eq!(
+            warnings,
+            [
+                "1:17: warning `b` is never used",
+                "1:54: warning `d` is never used",
+            ]
+        );
+        let config = LintConfig {
+            unused_globals: false,
+            ..Default::default()
+        };
+        let warnings: Vec<_> = unused(&prog, &config)
+            .iter()
+            .map(|l| l.message.clone())
+            .collect();
+        assert_eq!(warnings, ["`d` is never used"]);
+    }
+
+    #[test]
+    fn test_config() {
+        let input = "let x = 1; if (true) { }";
+        let mut config = LintConfig::default();
+        config
+            .set(Rule::UnusedVariable, Severity::Error)
+            .set(Rule::EmptyBlock, Severity::Allow);
+        let prog = Parser::new(Lexer::new(input.as_bytes())).parse().unwrap();
+        let found = lint(&prog, &config)
+            .into_iter()
+            .map(|lint| (lint.rule, lint.severity))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            found,
+            [
+                (Rule::UnusedVariable, Severity::Error),
+                (Rule::ConstantCondition, Severity::Warning),
+            ]
+        );
+        // by default only what would fail at run time is an error
+        let prog = Parser::new(Lexer::new("fn() { return 1; x }".as_bytes()))
+            .parse()
+            .unwrap();
+        let found = lint(&prog, &LintConfig::default())
+            .into_iter()
+            .map(|lint| (lint.rule, lint.severity))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            found,
+            [
+                (Rule::UnreachableCode, Severity::Warning),
+                (Rule::UndefinedVariable, Severity::Error),
+            ]
+        );
+        for rule in Rule::ALL {
+            assert_eq!(Rule::from_id(rule.id()), Some(*rule));
+        }
+    }
+}
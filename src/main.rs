@@ -1,12 +1,47 @@
-mod lexer;
-mod token;
-mod repl;
-mod parser;
-mod ast;
-mod object;
-mod evaluator;
-mod env;
+use std::io::IsTerminal;
+
+use interpreter_book_in_rust::cli;
+#[cfg(all(feature = "repl", not(target_arch = "wasm32")))]
+use interpreter_book_in_rust::repl;
 
 fn main() {
-    repl::start();
+    let opts = match cli::parse_args(std::env::args().skip(1)) {
+        Ok(opts) => opts,
+        Err(err) => {
+            eprintln!("{}\n\n{}", err, cli::USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    match &opts.command {
+        cli::Command::Repl
+            if opts.check || opts.dump.is_some() || !std::io::stdin().is_terminal() =>
+        {
+            std::process::exit(cli::eval_stdin(&opts))
+        }
+        #[cfg(all(feature = "repl", not(target_arch = "wasm32")))]
+        cli::Command::Repl => repl::start(&opts),
+        #[cfg(not(all(feature = "repl", not(target_arch = "wasm32"))))]
+        cli::Command::Repl => {
+            eprintln!(
+                "this build has no repl, pipe a script to stdin instead\n\n{}",
+                cli::USAGE
+            );
+            std::process::exit(2);
+        }
+        cli::Command::Run(path) => std::process::exit(cli::run_script(path, &opts)),
+        cli::Command::Eval(source) => std::process::exit(cli::eval_code(source, &opts)),
+        cli::Command::Lint(path) => std::process::exit(cli::lint_script(path, &opts)),
+        cli::Command::Check(path) => std::process::exit(cli::check_script(path, &opts)),
+        cli::Command::Test(path) => std::process::exit(cli::test_scripts(path, &opts)),
+        cli::Command::Debug(path) => std::process::exit(cli::debug_script(path, &opts)),
+        cli::Command::Bench {
+            script,
+            iterations,
+            warmup,
+        } => std::process::exit(cli::bench_script(script, *iterations, *warmup, &opts)),
+        cli::Command::Compile { input, output } => {
+            std::process::exit(cli::compile_script(input, output, &opts))
+        }
+    }
 }
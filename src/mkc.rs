@@ -0,0 +1,601 @@
+use crate::{
+    builtins::BUILTINS,
+    code::{read_operands, Instructions, Opcode},
+    compiler::Bytecode,
+    object::{CompiledFunction, Object},
+    rc::Rc,
+    vm::VmConfig,
+};
+
+// layout, all integers big endian:
+//   magic "MKC\0", version u8
+//   instructions: u32 length, bytes
+//   constants: u32 count, then per constant a tag byte and its payload
+//     TAG_INTEGER  i64
+//     TAG_FUNCTION u32 num_locals, u32 num_parameters, u32 length, bytes,
+//                  then since version 2 u32 length, utf-8 name, empty when
+//                  it has none, then since version 3 a u8, 1 when it takes
+//                  rest arguments
+//     TAG_STRING   u32 length, utf-8 bytes
+//     TAG_FLOAT    f64 bits
+// the spans of statements aren't kept, only the debugger uses them
+const MAGIC: &[u8; 4] = b"MKC\0";
+const VERSION: u8 = 3;
+
+const TAG_INTEGER: u8 = 0;
+const TAG_FUNCTION: u8 = 1;
+const TAG_STRING: u8 = 2;
+const TAG_FLOAT: u8 = 3;
+
+pub const EXTENSION: &str = ".mkc";
+
+pub fn output_path(input: &str) -> String {
+    let stem = input.strip_suffix(".mk").unwrap_or(input);
+    format!("{}{}", stem, EXTENSION)
+}
+
+pub fn encode(bytecode: &Bytecode) -> Result<Vec<u8>, String> {
+    let mut out = Vec::new();
+    out.extend_from_slice(MAGIC);
+    out.push(VERSION);
+    write_bytes(&mut out, &bytecode.instructions.0);
+    write_u32(&mut out, bytecode.constants.len());
+    for constant in &bytecode.constants {
+        match constant {
+            Object::Integer(value) => {
+                out.push(TAG_INTEGER);
+                out.extend_from_slice(&value.to_be_bytes());
+            }
+            Object::CompiledFunction(function) => {
+                out.push(TAG_FUNCTION);
+                write_u32(&mut out, function.num_locals);
+                write_u32(&mut out, function.num_parameters);
+                write_bytes(&mut out, &function.instructions.0);
+                let name = function.name.as_deref().unwrap_or_default();
+                write_bytes(&mut out, name.as_bytes());
+                out.push(function.rest as u8);
+            }
+            Object::Float(value) => {
+                out.push(TAG_FLOAT);
+                out.extend_from_slice(&value.to_bits().to_be_bytes());
+            }
+            Object::Str(value) => {
+                out.push(TAG_STRING);
+                write_bytes(&mut out, value.as_bytes());
+            }
+            other => return Err(format!("constant {} can't be serialized", other)),
+        }
+    }
+    Ok(out)
+}
+
+pub fn decode(bytes: &[u8]) -> Result<Bytecode, String> {
+    let mut r = Reader { bytes, pos: 0 };
+    if r.take(MAGIC.len())? != MAGIC {
+        return Err("not a compiled monkey file".to_string());
+    }
+    // version 1 is version 2 without function names, version 2 is version
+    // 3 without rest parameters
+    let version = r.take(1)?[0];
+    if !(1..=VERSION).contains(&version) {
+        return Err(format!("unsupported bytecode version {}", version));
+    }
+
+    let instructions = Instructions(r.read_bytes()?.to_vec());
+    let count = r.read_u32()?;
+    let mut constants = Vec::with_capacity(count.min(bytes.len()));
+    for _ in 0..count {
+        let constant = match r.take(1)?[0] {
+            TAG_INTEGER => Object::Integer(i64::from_be_bytes(r.take(8)?.try_into().unwrap())),
+            TAG_FUNCTION => {
+                let num_locals = r.read_u32()?;
+                let num_parameters = r.read_u32()?;
+                let instructions = Instructions(r.read_bytes()?.to_vec());
+                let name = if version > 1 {
+                    match std::str::from_utf8(r.read_bytes()?) {
+                        Ok("") => None,
+                        Ok(name) => Some(name.into()),
+                        Err(_) => return Err("function name isn't valid UTF-8".to_string()),
+                    }
+                } else {
+                    None
+                };
+                let rest = match version {
+                    1 | 2 => false,
+                    _ => match r.take(1)?[0] {
+                        0 => false,
+                        1 => true,
+                        _ => return Err("invalid rest parameter flag".to_string()),
+                    },
+                };
+                Object::CompiledFunction(Rc::new(CompiledFunction {
+                    instructions,
+                    num_locals,
+                    num_parameters,
+                    rest,
+                    name,
+                    spans: vec![],
+                }))
+            }
+            TAG_FLOAT => Object::Float(f64::from_bits(u64::from_be_bytes(
+                r.take(8)?.try_into().unwrap(),
+            ))),
+            TAG_STRING => match std::str::from_utf8(r.read_bytes()?) {
+                Ok(value) => Object::Str(value.into()),
+                Err(_) => return Err("string constant isn't valid UTF-8".to_string()),
+            },
+            tag => return Err(format!("unknown constant tag {}", tag)),
+        };
+        constants.push(constant);
+    }
+    if r.pos != bytes.len() {
+        return Err("trailing data after constants".to_string());
+    }
+
+    let bytecode = Bytecode {
+        instructions,
+        constants,
+        spans: vec![],
+    };
+    verify(&bytecode)?;
+    Ok(bytecode)
+}
+
+// the vm trusts its bytecode to be what the compiler makes, a file is checked
+// for anything it would otherwise panic on: unknown opcodes, operands cut
+// off, indexes out of range, jumps into an instruction and popping more than
+// was pushed
+fn verify(bytecode: &Bytecode) -> Result<(), String> {
+    let constants = &bytecode.constants;
+    let mut closures = verify_code(&bytecode.instructions.0, None, constants)
+        .map_err(|err| format!("invalid instructions: {}", err))?
+        .closures;
+    // how many free variables each function reads
+    let mut free = vec![0; constants.len()];
+    for (idx, constant) in constants.iter().enumerate() {
+        if let Object::CompiledFunction(function) = constant {
+            let code = verify_code(&function.instructions.0, Some(function), constants)
+                .map_err(|err| format!("invalid function constant {}: {}", idx, err))?;
+            closures.extend(code.closures);
+            free[idx] = code.free;
+        }
+    }
+    for (idx, num_free) in closures {
+        if num_free < free[idx] {
+            return Err(format!(
+                "function constant {} needs {} free variables, its closure has {}",
+                idx, free[idx], num_free
+            ));
+        }
+    }
+    Ok(())
+}
+
+#[derive(Default)]
+struct Code {
+    // the function constant and the number of free variables of every
+    // OpClosure
+    closures: Vec<(usize, usize)>,
+    // one past the highest free variable read
+    free: usize,
+}
+
+fn verify_code(
+    ins: &[u8],
+    function: Option<&CompiledFunction>,
+    constants: &[Object],
+) -> Result<Code, String> {
+    let locals = function.map_or(0, |function| function.num_locals);
+    if function.is_some_and(|function| function.num_parameters > locals) {
+        return Err("more parameters than locals".to_string());
+    }
+
+    // where each instruction starts
+    let mut starts = vec![false; ins.len() + 1];
+    let mut code = Code::default();
+    let mut i = 0;
+    while i < ins.len() {
+        let op = Opcode::from_byte(ins[i])
+            .ok_or_else(|| format!("unknown opcode {} at {}", ins[i], i))?;
+        let width: usize = op.definition().operand_widths.iter().sum();
+        if i + 1 + width > ins.len() {
+            return Err(format!("truncated operands at {}", i));
+        }
+        starts[i] = true;
+        i += 1 + width;
+    }
+    starts[ins.len()] = true;
+
+    // the fewest values on the stack each instruction can start with, only
+    // a lower one is walked again so the walk ends
+    let mut heights: Vec<Option<usize>> = vec![None; ins.len() + 1];
+    let mut work = vec![(0, 0)];
+    while let Some((ip, height)) = work.pop() {
+        if ip == ins.len() {
+            // the main program ends there, a function has to return first
+            if function.is_some() {
+                return Err("runs past its end".to_string());
+            }
+            continue;
+        }
+        if heights[ip].is_some_and(|seen| seen <= height) {
+            continue;
+        }
+        heights[ip] = Some(height);
+
+        let op = Opcode::from_byte(ins[ip]).unwrap();
+        let def = op.definition();
+        let (operands, read) = read_operands(&def, &ins[ip + 1..]);
+        let operand = operands.first().copied().unwrap_or_default();
+        let next = ip + 1 + read;
+        let mut jump = |target: usize, height: usize| {
+            if !starts.get(target).is_some_and(|start| *start) {
+                return Err(format!("jump to {} isn't an instruction", target));
+            }
+            work.push((target, height));
+            Ok(())
+        };
+        // what the instruction pops and pushes
+        let (pops, pushes) = match op {
+            Opcode::Constant => {
+                if operand >= constants.len() {
+                    return Err(format!("constant {} out of range at {}", operand, ip));
+                }
+                (0, 1)
+            }
+            Opcode::GetGlobal | Opcode::SetGlobal
+                if operand >= VmConfig::default().globals_size =>
+            {
+                return Err(format!("global {} out of range at {}", operand, ip));
+            }
+            Opcode::GetLocal | Opcode::SetLocal if operand >= locals => {
+                return Err(format!("local {} out of range at {}", operand, ip));
+            }
+            Opcode::GetBuiltin if operand >= BUILTINS.len() => {
+                return Err(format!("builtin {} out of range at {}", operand, ip));
+            }
+            Opcode::Closure => {
+                if !matches!(constants.get(operand), Some(Object::CompiledFunction(_))) {
+                    return Err(format!("constant {} isn't a function at {}", operand, ip));
+                }
+                code.closures.push((operand, operands[1]));
+                (operands[1], 1)
+            }
+            Opcode::GetFree => {
+                code.free = code.free.max(operand + 1);
+                (0, 1)
+            }
+            Opcode::True
+            | Opcode::False
+            | Opcode::Null
+            | Opcode::GetGlobal
+            | Opcode::GetLocal
+            | Opcode::GetBuiltin
+            | Opcode::CurrentClosure => (0, 1),
+            Opcode::Pop | Opcode::SetGlobal | Opcode::SetLocal => (1, 0),
+            Opcode::Add
+            | Opcode::Sub
+            | Opcode::Mul
+            | Opcode::Div
+            | Opcode::Equal
+            | Opcode::NotEqual
+            | Opcode::GreaterThan
+            | Opcode::LessThan
+            | Opcode::Index => (2, 1),
+            Opcode::Minus | Opcode::Bang => (1, 1),
+            // the subject stays for the next arm
+            Opcode::Matches => (2, 2),
+            Opcode::Call => (operand + 1, 1),
+            Opcode::Array => (operand, 1),
+            Opcode::Hash => (2 * operand, 1),
+            Opcode::Iter => (1, 2),
+            Opcode::Destructure => (1, operand),
+            Opcode::EndTry => (0, 0),
+            Opcode::ReturnValue | Opcode::Return => {
+                let pops = (op == Opcode::ReturnValue) as usize;
+                if height < pops {
+                    return Err(format!("stack underflow at {}", ip));
+                }
+                // returning from the main program leaves no frame to go to
+                if op == Opcode::Return && function.is_none() {
+                    return Err(format!("return outside of a function at {}", ip));
+                }
+                continue;
+            }
+            Opcode::Jump => {
+                jump(operand, height)?;
+                continue;
+            }
+            Opcode::JumpNotTruthy => {
+                let height = height
+                    .checked_sub(1)
+                    .ok_or_else(|| format!("stack underflow at {}", ip))?;
+                jump(operand, height)?;
+                work.push((next, height));
+                continue;
+            }
+            // a finished loop pops its array and index
+            Opcode::IterNext => {
+                let height = height
+                    .checked_sub(2)
+                    .ok_or_else(|| format!("stack underflow at {}", ip))?;
+                jump(operand, height)?;
+                work.push((next, height + 3));
+                continue;
+            }
+            // the handler starts with the error pushed
+            Opcode::Try => {
+                jump(operand, height + 1)?;
+                work.push((next, height));
+                continue;
+            }
+        };
+        let height = height
+            .checked_sub(pops)
+            .ok_or_else(|| format!("stack underflow at {}", ip))?;
+        work.push((next, height + pushes));
+    }
+    Ok(code)
+}
+
+fn write_u32(out: &mut Vec<u8>, value: usize) {
+    out.extend_from_slice(&(value as u32).to_be_bytes());
+}
+
+fn write_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    write_u32(out, bytes.len());
+    out.extend_from_slice(bytes);
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], String> {
+        let end = self
+            .pos
+            .checked_add(n)
+            .filter(|end| *end <= self.bytes.len());
+        let Some(end) = end else {
+            return Err("unexpected end of bytecode".to_string());
+        };
+        let slice = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(slice)
+    }
+
+    fn read_u32(&mut self) -> Result<usize, String> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into().unwrap()) as usize)
+    }
+
+    fn read_bytes(&mut self) -> Result<&'a [u8], String> {
+        let len = self.read_u32()?;
+        self.take(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        code::{make, Instructions, Opcode},
+        compiler::{Bytecode, Compiler},
+        lexer::Lexer,
+        object::{CompiledFunction, Object},
+        parser::Parser,
+        rc::Rc,
+        vm::Vm,
+    };
+
+    use super::{decode, encode, output_path};
+
+    fn compile(input: &str) -> crate::compiler::Bytecode {
+        let prog = Parser::new(Lexer::new(input.as_bytes())).parse().unwrap();
+        let mut compiler = Compiler::new();
+        compiler.compile(&prog).unwrap();
+        compiler.bytecode()
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let inputs = [
+            ("1 + 2 * 3", "7"),
+            ("fn(a) { fn(b) { a * b } }(6)(7)", "42"),
+            ("if (1 > 2) { 1 } else { -1 }", "-1"),
+            ("\"mon\" + \"key\"", "monkey"),
+            ("1.5 * 2", "3.0"),
+        ];
+        for (input, expected) in inputs {
+            let bytecode = compile(input);
+            let bytes = encode(&bytecode).unwrap();
+            let decoded = decode(&bytes).unwrap();
+            assert_eq!(decoded.instructions, bytecode.instructions);
+            assert_eq!(decoded.constants.len(), bytecode.constants.len());
+            assert_eq!(encode(&decoded).unwrap(), bytes);
+            assert_eq!(Vm::new(decoded).run().unwrap().to_string(), expected);
+        }
+    }
+
+    #[test]
+    fn test_function_names() {
+        let bytecode = compile("let add = fn(a, b) { a + b }; fn() { 1 }");
+        let decoded = decode(&encode(&bytecode).unwrap()).unwrap();
+        let names: Vec<_> = decoded
+            .constants
+            .iter()
+            .filter_map(|constant| match constant {
+                Object::CompiledFunction(function) => Some(function.name.as_deref()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(names, [Some("add"), None]);
+
+        // version 1 has no names
+        let mut v1 = b"MKC\0\x01".to_vec();
+        for n in [0, 1] {
+            v1.extend_from_slice(&u32::to_be_bytes(n));
+        }
+        v1.push(1);
+        for n in [0, 0, 1] {
+            v1.extend_from_slice(&u32::to_be_bytes(n));
+        }
+        v1.push(Opcode::Return as u8);
+        let decoded = decode(&v1).unwrap();
+        assert!(matches!(
+            &decoded.constants[..],
+            [Object::CompiledFunction(function)] if function.name.is_none()
+        ));
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        let bytes = encode(&compile("fn(a) { a }(1)")).unwrap();
+        assert!(decode(b"").is_err());
+        assert!(decode(b"MKD\0\x01").is_err());
+        assert!(decode(b"MKC\0\x02").is_err());
+        assert!(decode(b"MKC\0\x04\0\0\0\0\0\0\0\0").is_err());
+        for len in 0..bytes.len() {
+            assert!(decode(&bytes[..len]).is_err(), "truncated at {}", len);
+        }
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert!(decode(&trailing).is_err());
+    }
+
+    fn decode_err(bytecode: &Bytecode) -> String {
+        match decode(&encode(bytecode).unwrap()) {
+            Ok(_) => panic!("malformed bytecode decoded"),
+            Err(err) => err,
+        }
+    }
+
+    #[test]
+    fn test_malformed_instructions() {
+        let function = |instructions: Vec<Vec<u8>>, num_locals| {
+            Object::CompiledFunction(Rc::new(CompiledFunction {
+                instructions: Instructions::from(instructions),
+                num_locals,
+                num_parameters: 0,
+                rest: false,
+                name: None,
+                spans: vec![],
+            }))
+        };
+        let inputs = [
+            (vec![vec![255]], vec![], "unknown opcode 255 at 0"),
+            (
+                vec![make(Opcode::Constant, &[0])[..2].to_vec()],
+                vec![],
+                "truncated operands at 0",
+            ),
+            (
+                vec![make(Opcode::Constant, &[1]), make(Opcode::Pop, &[])],
+                vec![Object::Integer(1)],
+                "constant 1 out of range at 0",
+            ),
+            (vec![make(Opcode::Pop, &[])], vec![], "stack underflow at 0"),
+            (
+                vec![make(Opcode::True, &[]), make(Opcode::Add, &[])],
+                vec![],
+                "stack underflow at 1",
+            ),
+            (
+                vec![make(Opcode::Jump, &[1])],
+                vec![],
+                "jump to 1 isn't an instruction",
+            ),
+            (
+                vec![make(Opcode::GetLocal, &[0])],
+                vec![],
+                "local 0 out of range at 0",
+            ),
+            (
+                vec![make(Opcode::GetBuiltin, &[200])],
+                vec![],
+                "builtin 200 out of range at 0",
+            ),
+            (
+                vec![make(Opcode::Closure, &[0, 0])],
+                vec![Object::Integer(1)],
+                "constant 0 isn't a function at 0",
+            ),
+            (
+                vec![make(Opcode::Return, &[])],
+                vec![],
+                "return outside of a function at 0",
+            ),
+        ];
+        for (instructions, constants, expected) in inputs {
+            let bytecode = Bytecode {
+                instructions: Instructions::from(instructions),
+                constants,
+                spans: vec![],
+            };
+            let err = decode_err(&bytecode);
+            assert_eq!(err, format!("invalid instructions: {}", expected));
+        }
+
+        let inputs = [
+            (
+                function(vec![make(Opcode::GetLocal, &[1])], 1),
+                "local 1 out of range at 0",
+            ),
+            (
+                function(vec![make(Opcode::Null, &[])], 0),
+                "runs past its end",
+            ),
+            (
+                function(vec![make(Opcode::ReturnValue, &[])], 0),
+                "stack underflow at 0",
+            ),
+        ];
+        for (constant, expected) in inputs {
+            let bytecode = Bytecode {
+                instructions: Instructions::default(),
+                constants: vec![constant],
+                spans: vec![],
+            };
+            let err = decode_err(&bytecode);
+            assert_eq!(err, format!("invalid function constant 0: {}", expected));
+        }
+
+        // a closure made with fewer free variables than its function reads
+        let bytecode = Bytecode {
+            instructions: Instructions::from(vec![make(Opcode::Closure, &[0, 0])]),
+            constants: vec![function(
+                vec![make(Opcode::GetFree, &[0]), make(Opcode::ReturnValue, &[])],
+                0,
+            )],
+            spans: vec![],
+        };
+        assert_eq!(
+            decode_err(&bytecode),
+            "function constant 0 needs 1 free variables, its closure has 0"
+        );
+    }
+
+    #[test]
+    fn test_decode_compiled() {
+        let inputs = [
+            "let f = fn(x) { let y = x * 2; fn(z) { y + z } }; f(1)(2)",
+            "for (x in [1, 2, 3]) { if (x == 2) { continue; } if (x == 3) { break; } x }",
+            "fn(a, ...rest) { let [b, c] = rest; a + b + c }(1, 2, 3)",
+            "try { 1 / 0 } catch (e) { e }",
+            "match (2) { 1 => \"one\", 2 => \"two\", _ => [1, {1: 2}][0] }",
+            "let a = 1; a = a + 1; -a; !true; len(\"ab\")",
+            "let f = fn() { let g = fn(n) { if (n > 0) { g(n - 1) } else { 0 } }; g(3) }; f()",
+            "let f = fn() { return 1; }; f(); return 2;",
+        ];
+        for input in inputs {
+            let bytecode = compile(input);
+            assert!(decode(&encode(&bytecode).unwrap()).is_ok(), "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_output_path() {
+        assert_eq!(output_path("fib.mk"), "fib.mkc");
+        assert_eq!(output_path("dir/fib.monkey"), "dir/fib.monkey.mkc");
+    }
+}
@@ -1,19 +1,185 @@
 use core::fmt;
+use std::collections::BTreeMap;
+
+use crate::{
+    ast::Stmt,
+    builtins::Builtin,
+    code::Instructions,
+    env::Env,
+    host::HostFunction,
+    intern::Symbol,
+    rc::Rc,
+    tasks::{Channel, Task},
+    token::Span,
+};
 
 #[derive(Debug, Clone)]
-pub enum Object {
+pub enum Object<'a> {
     Integer(i64),
+    Float(f64),
     Bool(bool),
-    ReturnValue(Box<Object>),
+    ReturnValue(Box<Object<'a>>),
+    // like a return value, unwinds to the innermost loop
+    Break,
+    Continue,
+    Function(Rc<Function<'a>>),
+    CompiledFunction(Rc<CompiledFunction>),
+    Closure(Rc<Closure<'a>>),
+    Builtin(&'static Builtin),
+    Host(Rc<HostFunction<'a>>),
+    Array(Rc<Vec<Object<'a>>>),
+    Str(Rc<str>),
+    Hash(Rc<BTreeMap<HashKey, Object<'a>>>),
+    Channel(Rc<Channel<'a>>),
+    Task(Rc<Task<'a>>),
+    // a runtime error caught by a `try`, with its message
+    Error(Rc<str>),
     Null,
 }
 
-impl fmt::Display for Object {
+// the objects that can be used as keys of a hash
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum HashKey {
+    Integer(i64),
+    Bool(bool),
+    Str(Rc<str>),
+}
+
+impl HashKey {
+    pub fn from_object(obj: &Object) -> Option<HashKey> {
+        match obj {
+            Object::Integer(value) => Some(HashKey::Integer(*value)),
+            Object::Bool(value) => Some(HashKey::Bool(*value)),
+            Object::Str(value) => Some(HashKey::Str(Rc::clone(value))),
+            _ => None,
+        }
+    }
+}
+
+impl<'a> From<HashKey> for Object<'a> {
+    fn from(key: HashKey) -> Self {
+        match key {
+            HashKey::Integer(value) => Object::Integer(value),
+            HashKey::Bool(value) => Object::Bool(value),
+            HashKey::Str(value) => Object::Str(value),
+        }
+    }
+}
+
+impl From<i64> for HashKey {
+    fn from(value: i64) -> Self {
+        HashKey::Integer(value)
+    }
+}
+
+impl From<bool> for HashKey {
+    fn from(value: bool) -> Self {
+        HashKey::Bool(value)
+    }
+}
+
+impl From<String> for HashKey {
+    fn from(value: String) -> Self {
+        HashKey::Str(value.into())
+    }
+}
+
+impl From<&str> for HashKey {
+    fn from(value: &str) -> Self {
+        HashKey::Str(value.into())
+    }
+}
+
+impl fmt::Display for HashKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HashKey::Integer(value) => write!(f, "{}", value),
+            HashKey::Bool(value) => write!(f, "{}", value),
+            HashKey::Str(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Function<'a> {
+    pub parameters: Vec<Symbol>,
+    pub rest: Option<Symbol>,
+    pub body: Rc<Stmt>,
+    pub env: Env<'a>,
+}
+
+impl Function<'_> {
+    // the parameters as they were written, `x, ...rest`
+    pub fn signature(&self) -> String {
+        let parameters = self.parameters.iter().map(|p| p.to_string());
+        let rest = self.rest.as_ref().map(|rest| format!("...{}", rest));
+        parameters.chain(rest).collect::<Vec<_>>().join(", ")
+    }
+}
+
+#[derive(Debug)]
+pub struct CompiledFunction {
+    pub instructions: Instructions,
+    pub num_locals: usize,
+    pub num_parameters: usize,
+    // arguments past the parameters are collected into an array in the
+    // local after them, `fn(a, ...rest)`
+    pub rest: bool,
+    // the `let` binding it, for backtraces, None for anonymous functions
+    pub name: Option<Rc<str>>,
+    // the offset of the first instruction of each statement with the span
+    // of the statement, what the debugger sets breakpoints on lines with,
+    // empty when the bytecode wasn't compiled from source
+    pub spans: Vec<(usize, Span)>,
+}
+
+#[derive(Debug)]
+pub struct Closure<'a> {
+    pub function: Rc<CompiledFunction>,
+    pub free: Vec<Object<'a>>,
+}
+
+impl fmt::Display for Object<'_> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Object::Integer(value) => write!(f, "{}", value),
+            // debug keeps the fraction of whole floats, `1.0` not `1`
+            Object::Float(value) => write!(f, "{:?}", value),
             Object::Bool(value) => write!(f, "{}", value),
             Object::ReturnValue(value) => write!(f, "{}", value),
+            Object::Break => write!(f, "break"),
+            Object::Continue => write!(f, "continue"),
+            Object::Function(function) => {
+                write!(f, "fn({}) {}", function.signature(), function.body)
+            }
+            Object::CompiledFunction(function) => {
+                write!(f, "<compiled fn/{}>", function.num_parameters)
+            }
+            Object::Closure(closure) => write!(f, "<closure/{}>", closure.function.num_parameters),
+            Object::Builtin(builtin) => write!(f, "<builtin {}>", builtin.name),
+            Object::Host(host) => write!(f, "<host fn {}>", host.name),
+            Object::Array(elements) => write!(
+                f,
+                "[{}]",
+                elements
+                    .iter()
+                    .map(|e| e.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            Object::Str(value) => write!(f, "{}", value),
+            Object::Hash(pairs) => write!(
+                f,
+                "{{{}}}",
+                pairs
+                    .iter()
+                    .map(|(key, value)| format!("{}: {}", key, value))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            Object::Channel(_) => write!(f, "<channel>"),
+            Object::Task(_) => write!(f, "<task>"),
+            Object::Error(message) => write!(f, "{}", message),
             Object::Null => write!(f, "nil"),
         }
     }
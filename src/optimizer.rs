@@ -0,0 +1,613 @@
+
+use std::collections::HashSet;
+
+use crate::{
+    code::{make, read_operands, Instructions, Opcode},
+    compiler::Bytecode,
+    object::{CompiledFunction, Object},
+    rc::Rc,
+    token::Span,
+};
+
+#[derive(Debug, Clone)]
+struct Inst {
+    op: Opcode,
+    operands: Vec<usize>,
+    // offset in the unoptimized instructions, jump operands keep pointing at
+    // these until the instructions are encoded again
+    offset: usize,
+}
+
+pub fn optimize(bytecode: Bytecode) -> Bytecode {
+    let mut constants = bytecode.constants;
+    let (instructions, spans) = optimize_instructions(
+        &bytecode.instructions,
+        &bytecode.spans,
+        &mut constants,
+        true,
+    );
+    for i in 0..constants.len() {
+        let Object::CompiledFunction(function) = &constants[i] else {
+            continue;
+        };
+        let function = Rc::clone(function);
+        let (instructions, spans) = optimize_instructions(
+            &function.instructions,
+            &function.spans,
+            &mut constants,
+            false,
+        );
+        constants[i] = Object::CompiledFunction(Rc::new(CompiledFunction {
+            instructions,
+            num_locals: function.num_locals,
+            num_parameters: function.num_parameters,
+            rest: function.rest,
+            name: function.name.clone(),
+            spans,
+        }));
+    }
+    Bytecode {
+        instructions,
+        constants,
+        spans,
+    }
+}
+
+// in the main instructions the last popped value is the result of the
+// program, so a dead pop is only removed there if another one follows
+//
+// the statements' spans move with their first instruction that's left
+fn optimize_instructions(
+    ins: &Instructions,
+    spans: &[(usize, Span)],
+    constants: &mut Vec<Object<'static>>,
+    is_main: bool,
+) -> (Instructions, Vec<(usize, Span)>) {
+    let mut insts = decode(ins);
+    loop {
+        let changed = collapse_jumps(&mut insts)
+            | fold_constants(&mut insts, constants)
+            | remove_dead_pops(&mut insts, is_main)
+            | remove_unreachable(&mut insts);
+        if !changed {
+            break;
+        }
+    }
+    let offsets = offsets(&insts);
+    let mut moved: Vec<(usize, Span)> = vec![];
+    for &(offset, span) in spans {
+        let offset = offsets[find(&insts, offset)];
+        // a statement optimized away leaves its place to the next one
+        if moved.last().is_some_and(|(last, _)| *last == offset) {
+            moved.pop();
+        }
+        moved.push((offset, span));
+    }
+    (encode(&insts, &offsets), moved)
+}
+
+fn decode(ins: &Instructions) -> Vec<Inst> {
+    let mut insts = Vec::new();
+    let mut offset = 0;
+    while offset < ins.len() {
+        let op = Opcode::from_byte(ins.0[offset]).expect("undefined opcode");
+        let (operands, read) = read_operands(&op.definition(), &ins.0[offset + 1..]);
+        insts.push(Inst {
+            op,
+            operands,
+            offset,
+        });
+        offset += 1 + read;
+    }
+    insts
+}
+
+// where each instruction starts once encoded, then where they end
+fn offsets(insts: &[Inst]) -> Vec<usize> {
+    let mut offsets = Vec::with_capacity(insts.len() + 1);
+    let mut len = 0;
+    for inst in insts {
+        offsets.push(len);
+        len += 1 + inst.op.definition().operand_widths.iter().sum::<usize>();
+    }
+    offsets.push(len);
+    offsets
+}
+
+fn encode(insts: &[Inst], offsets: &[usize]) -> Instructions {
+    let mut ins = Instructions::default();
+    for inst in insts {
+        let mut operands = inst.operands.clone();
+        if is_jump(inst.op) {
+            operands[0] = offsets[find(insts, operands[0])];
+        }
+        ins.extend(&make(inst.op, &operands));
+    }
+    ins
+}
+
+// the instructions whose first operand is an offset to go on from
+fn is_jump(op: Opcode) -> bool {
+    matches!(
+        op,
+        Opcode::Jump | Opcode::JumpNotTruthy | Opcode::IterNext | Opcode::Try
+    )
+}
+
+// index of the instruction a jump to `target` lands on, removed instructions
+// fall through to the next remaining one
+fn find(insts: &[Inst], target: usize) -> usize {
+    insts.partition_point(|inst| inst.offset < target)
+}
+
+// points every jump at a remaining instruction and returns their offsets
+fn jump_targets(insts: &mut [Inst]) -> HashSet<usize> {
+    let mut targets = HashSet::new();
+    for i in 0..insts.len() {
+        if !is_jump(insts[i].op) {
+            continue;
+        }
+        if let Some(target) = insts.get(find(insts, insts[i].operands[0])) {
+            insts[i].operands[0] = target.offset;
+        }
+        targets.insert(insts[i].operands[0]);
+    }
+    targets
+}
+
+fn collapse_jumps(insts: &mut Vec<Inst>) -> bool {
+    jump_targets(insts);
+    let mut changed = false;
+    for i in 0..insts.len() {
+        if !is_jump(insts[i].op) {
+            continue;
+        }
+        // bounded in case the jumps form a cycle
+        let mut target = insts[i].operands[0];
+        for _ in 0..insts.len() {
+            match insts.get(find(insts, target)) {
+                Some(next) if next.op == Opcode::Jump && next.operands[0] != target => {
+                    target = next.operands[0];
+                }
+                _ => break,
+            }
+        }
+        if target != insts[i].operands[0] {
+            insts[i].operands[0] = target;
+            changed = true;
+        }
+    }
+
+    // a jump to the instruction right after it does nothing
+    let mut i = 0;
+    while i < insts.len() {
+        if insts[i].op == Opcode::Jump && find(insts, insts[i].operands[0]) == i + 1 {
+            insts.remove(i);
+            changed = true;
+        } else {
+            i += 1;
+        }
+    }
+    changed
+}
+
+fn literal(inst: &Inst, constants: &[Object<'static>]) -> Option<Object<'static>> {
+    match inst.op {
+        Opcode::Constant => match constants[inst.operands[0]] {
+            Object::Integer(value) => Some(Object::Integer(value)),
+            _ => None,
+        },
+        Opcode::True => Some(Object::Bool(true)),
+        Opcode::False => Some(Object::Bool(false)),
+        _ => None,
+    }
+}
+
+fn fold_binary(
+    op: Opcode,
+    left: Object<'static>,
+    right: Object<'static>,
+) -> Option<Object<'static>> {
+    match (left, right) {
+        (Object::Integer(left), Object::Integer(right)) => match op {
+            Opcode::Add => left.checked_add(right).map(Object::Integer),
+            Opcode::Sub => left.checked_sub(right).map(Object::Integer),
+            Opcode::Mul => left.checked_mul(right).map(Object::Integer),
+            Opcode::Div => left.checked_div(right).map(Object::Integer),
+            Opcode::GreaterThan => Some(Object::Bool(left > right)),
+            Opcode::LessThan => Some(Object::Bool(left < right)),
+            Opcode::Equal => Some(Object::Bool(left == right)),
+            Opcode::NotEqual => Some(Object::Bool(left != right)),
+            _ => None,
+        },
+        (Object::Bool(left), Object::Bool(right)) => match op {
+            Opcode::Equal => Some(Object::Bool(left == right)),
+            Opcode::NotEqual => Some(Object::Bool(left != right)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn fold_unary(op: Opcode, operand: Object<'static>) -> Option<Object<'static>> {
+    match (op, operand) {
+        (Opcode::Minus, Object::Integer(value)) => value.checked_neg().map(Object::Integer),
+        (Opcode::Bang, Object::Bool(value)) => Some(Object::Bool(!value)),
+        _ => None,
+    }
+}
+
+fn load_literal(
+    value: Object<'static>,
+    offset: usize,
+    constants: &mut Vec<Object<'static>>,
+) -> Inst {
+    let (op, operands) = match value {
+        Object::Bool(true) => (Opcode::True, vec![]),
+        Object::Bool(false) => (Opcode::False, vec![]),
+        value => {
+            constants.push(value);
+            (Opcode::Constant, vec![constants.len() - 1])
+        }
+    };
+    Inst {
+        op,
+        operands,
+        offset,
+    }
+}
+
+// operations on literals are evaluated here, errors such as a division by
+// zero or an overflow are left for the vm to report
+fn fold_constants(insts: &mut Vec<Inst>, constants: &mut Vec<Object<'static>>) -> bool {
+    let targets = jump_targets(insts);
+    let mut changed = false;
+    let mut i = 0;
+    while i < insts.len() {
+        match fold_at(&insts[i..], &targets, constants) {
+            Some((len, replacement)) => {
+                insts.splice(i..i + len, replacement);
+                changed = true;
+            }
+            None => i += 1,
+        }
+    }
+    changed
+}
+
+// returns how many instructions from the start of `insts` fold into the
+// replacement, only the first of them may be jumped to
+fn fold_at(
+    insts: &[Inst],
+    targets: &HashSet<usize>,
+    constants: &mut Vec<Object<'static>>,
+) -> Option<(usize, Option<Inst>)> {
+    let inner = |inst: &&Inst| !targets.contains(&inst.offset);
+    let first = insts.first()?;
+    let operand = literal(first, constants)?;
+    let next = insts.get(1).filter(inner)?;
+
+    if let (Some(right), Some(op)) = (literal(next, constants), insts.get(2).filter(inner)) {
+        if let Some(value) = fold_binary(op.op, operand.clone(), right) {
+            return Some((3, Some(load_literal(value, first.offset, constants))));
+        }
+    }
+
+    match (operand, next.op) {
+        (Object::Bool(true), Opcode::JumpNotTruthy) => Some((2, None)),
+        (Object::Bool(false), Opcode::JumpNotTruthy) => Some((
+            2,
+            Some(Inst {
+                op: Opcode::Jump,
+                operands: next.operands.clone(),
+                offset: first.offset,
+            }),
+        )),
+        (operand, op) => fold_unary(op, operand)
+            .map(|value| (2, Some(load_literal(value, first.offset, constants)))),
+    }
+}
+
+fn is_pure(op: Opcode) -> bool {
+    matches!(
+        op,
+        Opcode::Constant
+            | Opcode::True
+            | Opcode::False
+            | Opcode::Null
+            | Opcode::GetGlobal
+            | Opcode::GetLocal
+            | Opcode::GetFree
+            | Opcode::GetBuiltin
+    )
+}
+
+fn remove_dead_pops(insts: &mut Vec<Inst>, is_main: bool) -> bool {
+    let targets = jump_targets(insts);
+    let mut changed = false;
+    let mut i = 0;
+    while i + 1 < insts.len() {
+        let dead = is_pure(insts[i].op)
+            && insts[i + 1].op == Opcode::Pop
+            && !targets.contains(&insts[i + 1].offset)
+            && (!is_main
+                || insts[i + 2..]
+                    .iter()
+                    .any(|inst| matches!(inst.op, Opcode::Pop | Opcode::SetGlobal)));
+        if dead {
+            insts.drain(i..i + 2);
+            changed = true;
+        } else {
+            i += 1;
+        }
+    }
+    changed
+}
+
+fn remove_unreachable(insts: &mut Vec<Inst>) -> bool {
+    let targets = jump_targets(insts);
+    let before = insts.len();
+    let mut reachable = true;
+    insts.retain(|inst| {
+        reachable |= targets.contains(&inst.offset);
+        let keep = reachable;
+        if matches!(inst.op, Opcode::ReturnValue | Opcode::Return | Opcode::Jump) {
+            reachable = false;
+        }
+        keep
+    });
+    insts.len() != before
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        compiler::{Bytecode, Compiler},
+        lexer::Lexer,
+        object::Object,
+        parser::Parser,
+        vm::Vm,
+    };
+
+    use super::optimize;
+
+    fn compile(input: &str) -> Bytecode {
+        let prog = Parser::new(Lexer::new(input.as_bytes())).parse().unwrap();
+        let mut compiler = Compiler::new();
+        compiler.compile(&prog).unwrap();
+        compiler.bytecode()
+    }
+
+    fn assert_optimized(input: &str, before: &str, after: &str) {
+        let bytecode = compile(input);
+        assert_eq!(
+            bytecode.instructions.to_string(),
+            before,
+            "before: {}",
+            input
+        );
+        let bytecode = optimize(bytecode);
+        assert_eq!(bytecode.instructions.to_string(), after, "after: {}", input);
+    }
+
+    fn function_disassembly(bytecode: &Bytecode, idx: usize) -> String {
+        match &bytecode.constants[idx] {
+            Object::CompiledFunction(function) => function.instructions.to_string(),
+            obj => panic!("not a function: {}", obj),
+        }
+    }
+
+    #[test]
+    fn test_fold_constants() {
+        assert_optimized(
+            "1 + 2 * 3",
+            "0000 OpConstant 0
+0003 OpConstant 1
+0006 OpConstant 2
+0009 OpMul
+0010 OpAdd
+0011 OpPop
+",
+            "0000 OpConstant 4
+0003 OpPop
+",
+        );
+        assert_optimized(
+            "-5 < 2",
+            "0000 OpConstant 0
+0003 OpMinus
+0004 OpConstant 1
+0007 OpLessThan
+0008 OpPop
+",
+            "0000 OpTrue
+0001 OpPop
+",
+        );
+        assert_optimized(
+            "!(true == false)",
+            "0000 OpTrue
+0001 OpFalse
+0002 OpEqual
+0003 OpBang
+0004 OpPop
+",
+            "0000 OpTrue
+0001 OpPop
+",
+        );
+        // errors are left for the vm
+        assert_optimized(
+            "1 / 0",
+            "0000 OpConstant 0
+0003 OpConstant 1
+0006 OpDiv
+0007 OpPop
+",
+            "0000 OpConstant 0
+0003 OpConstant 1
+0006 OpDiv
+0007 OpPop
+",
+        );
+    }
+
+    #[test]
+    fn test_fold_conditionals() {
+        assert_optimized(
+            "if (1 > 2) { 10 } else { 20 }",
+            "0000 OpConstant 0
+0003 OpConstant 1
+0006 OpGreaterThan
+0007 OpJumpNotTruthy 16
+0010 OpConstant 2
+0013 OpJump 19
+0016 OpConstant 3
+0019 OpPop
+",
+            "0000 OpConstant 3
+0003 OpPop
+",
+        );
+        assert_optimized(
+            "if (true) { 10 }",
+            "0000 OpTrue
+0001 OpJumpNotTruthy 10
+0004 OpConstant 0
+0007 OpJump 11
+0010 OpNull
+0011 OpPop
+",
+            "0000 OpConstant 0
+0003 OpPop
+",
+        );
+    }
+
+    #[test]
+    fn test_remove_dead_pops() {
+        assert_optimized(
+            "let a = 1; a; 2; a",
+            "0000 OpConstant 0
+0003 OpSetGlobal 0
+0006 OpGetGlobal 0
+0009 OpPop
+0010 OpConstant 1
+0013 OpPop
+0014 OpGetGlobal 0
+0017 OpPop
+",
+            "0000 OpConstant 0
+0003 OpSetGlobal 0
+0006 OpGetGlobal 0
+0009 OpPop
+",
+        );
+
+        let bytecode = optimize(compile("fn(a) { a; 1; a }"));
+        assert_eq!(
+            function_disassembly(&bytecode, 1),
+            "0000 OpGetLocal 0
+0002 OpReturnValue
+"
+        );
+    }
+
+    #[test]
+    fn test_collapse_jumps() {
+        assert_optimized(
+            "let a = true; if (a) { if (a) { 1 } else { 2 } } else { 3 }",
+            "0000 OpTrue
+0001 OpSetGlobal 0
+0004 OpGetGlobal 0
+0007 OpJumpNotTruthy 28
+0010 OpGetGlobal 0
+0013 OpJumpNotTruthy 22
+0016 OpConstant 0
+0019 OpJump 25
+0022 OpConstant 1
+0025 OpJump 31
+0028 OpConstant 2
+0031 OpPop
+",
+            "0000 OpTrue
+0001 OpSetGlobal 0
+0004 OpGetGlobal 0
+0007 OpJumpNotTruthy 28
+0010 OpGetGlobal 0
+0013 OpJumpNotTruthy 22
+0016 OpConstant 0
+0019 OpJump 31
+0022 OpConstant 1
+0025 OpJump 31
+0028 OpConstant 2
+0031 OpPop
+",
+        );
+    }
+
+    #[test]
+    fn test_remove_unreachable() {
+        let bytecode = optimize(compile(
+            "fn(a) { if (a) { return 1; } else { return 2; } 3 }",
+        ));
+        assert_eq!(
+            function_disassembly(&bytecode, 3),
+            "0000 OpGetLocal 0
+0002 OpJumpNotTruthy 9
+0005 OpConstant 0
+0008 OpReturnValue
+0009 OpConstant 1
+0012 OpReturnValue
+"
+        );
+    }
+
+    #[test]
+    fn test_spans() {
+        // the folded statement is shorter, the ones after it move up
+        let bytecode = optimize(compile("let a = 1 + 2;\nif (false) { a }\nlet b = a;"));
+        let spans: Vec<_> = bytecode
+            .spans
+            .iter()
+            .map(|(offset, span)| (*offset, span.line))
+            .collect();
+        assert_eq!(spans, [(0, 1), (6, 3)]);
+
+        // and errors in optimized code are still placed
+        let mut vm = Vm::new(optimize(compile(
+            "let f = fn(x) {\n  x + 1;\n  -true\n};\nf(1)",
+        )));
+        assert!(vm.run().is_err());
+        assert_eq!(vm.span().map(|span| span.line), Some(3));
+    }
+
+    #[test]
+    fn test_preserves_results() {
+        let inputs = [
+            "1 + 2 * 3 - 4 / 2",
+            "let a = 1; a; 2;",
+            "5; let a = 1;",
+            "if (1 < 2) { 10 }",
+            "if (1 > 2) { 10 }",
+            "if (!true) { 1 } else { if (false) { 2 } else { 3 } }",
+            "let a = false; if (a) { 1 } else { if (a) { 2 } else { 3 } }",
+            "let f = fn(a) { if (a) { return 1; } 2; 3 }; f(true) + f(false)",
+            "let f = fn(a) { if (a) { return 1; } else { return 2; } 3 }; f(false)",
+            "let fib = fn(n) { if (n < 2) { n } else { fib(n - 1) + fib(n - 2) } }; fib(15)",
+            "let add = fn(a) { fn(b) { a + b + (2 * 3) } }; add(1)(2)",
+            "[1 + 1, -(2)][0 + 1]",
+            "len([1, 2, 3]); first([-1 * 9])",
+            "if (true) { 1 } else { -true }",
+            "-true",
+            "if (1) { 2 }",
+        ];
+        for input in inputs {
+            let plain = Vm::new(compile(input)).run().map(|obj| obj.to_string());
+            let optimized = Vm::new(optimize(compile(input)))
+                .run()
+                .map(|obj| obj.to_string());
+            assert_eq!(plain, optimized, "{}", input);
+        }
+    }
+}
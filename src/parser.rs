@@ -1,20 +1,50 @@
 #![allow(unused, dead_code)]
 
+use core::fmt;
+
 use crate::{
-    ast::{self, Expr, Program, Stmt},
+    ast::{self, Expr, MatchArm, Program, Stmt},
+    diagnostic::Diagnostic,
+    intern::Symbol,
     lexer::Lexer,
-    token::{self, Prec, Token, TokenType},
+    rc::Rc,
+    slots,
+    token::{self, Prec, Span, Token, TokenType},
 };
 
-pub struct Parser<'a> {
-    lexer: Lexer<'a>,
-    curr_token: Token<'a>,
-    peek_token: Token<'a>,
-    errors: Vec<String>,
+// a problem found while parsing, at the token it was found at
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub message: String,
+    pub span: Span,
+    // for a missing token, the one that had to come next
+    pub expected: Option<TokenType>,
+    pub found: TokenType,
+}
+
+impl ParseError {
+    pub fn diagnostic(&self) -> Diagnostic {
+        Diagnostic::error(self.message.clone(), Some(self.span))
+    }
 }
 
-impl<'a> Parser<'a> {
-    pub fn new(mut lexer: Lexer<'a>) -> Self {
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+pub struct Parser {
+    lexer: Lexer,
+    curr_token: Token,
+    peek_token: Token,
+    errors: Vec<ParseError>,
+}
+
+impl Parser {
+    pub fn new(mut lexer: Lexer) -> Self {
         Parser {
             curr_token: lexer.next_token(),
             peek_token: lexer.next_token(),
@@ -24,42 +54,53 @@ impl<'a> Parser<'a> {
     }
 
     fn next_token(&mut self) {
-        self.curr_token = self.peek_token;
-        self.peek_token = self.lexer.next_token();
+        self.curr_token = std::mem::replace(&mut self.peek_token, self.lexer.next_token());
     }
 
-    pub fn parse(&mut self) -> Result<Program<'a>, Vec<String>> {
+    pub fn parse(&mut self) -> Result<Program, Vec<ParseError>> {
         let mut stmts = Vec::new();
 
-        while self.curr_token != token::EOF {
+        while self.curr_token.clone() != token::EOF {
             if let Some(stmt) = self.parse_stmt() {
                 stmts.push(stmt);
             }
             self.next_token();
         }
         if self.errors.is_empty() {
-            Ok(Program { stmts })
+            let mut prog = Program { stmts };
+            slots::resolve_slots(&mut prog);
+            Ok(prog)
         } else {
             Err(self.errors.clone())
         }
     }
 
-    fn parse_stmt(&mut self) -> Option<Stmt<'a>> {
+    fn parse_stmt(&mut self) -> Option<Stmt> {
         match self.curr_token.token_type {
             TokenType::LET => self.parse_let_stmt(),
             TokenType::RETURN => self.parse_return_stmt(),
+            TokenType::DEBUGGER => self.parse_debugger_stmt(),
+            TokenType::FOR => self.parse_for_stmt(),
+            TokenType::BREAK => Some(Stmt::Break {
+                token: self.parse_keyword_stmt(),
+            }),
+            TokenType::CONTINUE => Some(Stmt::Continue {
+                token: self.parse_keyword_stmt(),
+            }),
             _ => self.parse_expr_stmt(),
         }
     }
 
-    fn parse_let_stmt(&mut self) -> Option<Stmt<'a>> {
-        let token = self.curr_token;
-        if !self.advance_if_peek(TokenType::IDENT) {
-            return None;
-        }
-        let name = ast::Expr::Identifier {
-            token: self.curr_token,
-            value: self.curr_token.literal,
+    fn parse_let_stmt(&mut self) -> Option<Stmt> {
+        let token = self.curr_token.clone();
+        let name = if self.is_peek_token(TokenType::LBRACKET) {
+            self.next_token();
+            self.parse_let_pattern()?
+        } else {
+            if !self.advance_if_peek(TokenType::IDENT) {
+                return None;
+            }
+            self.curr_ident()
         };
 
         if !self.advance_if_peek(TokenType::ASSIGN) {
@@ -81,8 +122,24 @@ impl<'a> Parser<'a> {
         })
     }
 
-    fn parse_return_stmt(&mut self) -> Option<Stmt<'a>> {
-        let token = self.curr_token;
+    // `[a, b]` binding the elements of an array, kept as an array literal
+    // of identifiers
+    fn parse_let_pattern(&mut self) -> Option<Expr> {
+        let token = self.curr_token.clone();
+        let elements = self.parse_expr_list(TokenType::RBRACKET);
+        if let Some(other) = elements
+            .iter()
+            .find(|e| !matches!(e, Expr::Identifier { .. }))
+        {
+            let message = format!("can only destructure into names, got {}", other);
+            self.error_at(other.first_token().clone(), message);
+            return None;
+        }
+        Some(Expr::ArrayLiteral { token, elements })
+    }
+
+    fn parse_return_stmt(&mut self) -> Option<Stmt> {
+        let token = self.curr_token.clone();
 
         self.next_token();
         let value = self.parse_expr(Prec::Lowest);
@@ -94,8 +151,49 @@ impl<'a> Parser<'a> {
         value.map(|v| Stmt::Return { token, value: v })
     }
 
-    fn parse_expr_stmt(&mut self) -> Option<Stmt<'a>> {
-        let token = self.curr_token;
+    fn parse_debugger_stmt(&mut self) -> Option<Stmt> {
+        Some(Stmt::Debugger {
+            token: self.parse_keyword_stmt(),
+        })
+    }
+
+    // a statement that is just its keyword, with an optional semicolon
+    fn parse_keyword_stmt(&mut self) -> Token {
+        let token = self.curr_token.clone();
+        if self.is_peek_token(TokenType::SEMICOLON) {
+            self.next_token();
+        }
+        token
+    }
+
+    fn parse_for_stmt(&mut self) -> Option<Stmt> {
+        let token = self.curr_token.clone();
+        if !self.advance_if_peek(TokenType::LPAREN) || !self.advance_if_peek(TokenType::IDENT) {
+            return None;
+        }
+        let name = self.curr_ident();
+        if !self.advance_if_peek(TokenType::IN) {
+            return None;
+        }
+        self.next_token();
+        let iterable = self.parse_expr(Prec::Lowest)?;
+        if !self.advance_if_peek(TokenType::RPAREN) || !self.advance_if_peek(TokenType::LBRACE) {
+            return None;
+        }
+        let body = self.parse_block_stmt();
+        if self.is_peek_token(TokenType::SEMICOLON) {
+            self.next_token();
+        }
+        Some(Stmt::For {
+            token,
+            name,
+            iterable,
+            body: Box::new(body),
+        })
+    }
+
+    fn parse_expr_stmt(&mut self) -> Option<Stmt> {
+        let token = self.curr_token.clone();
         let expr = self.parse_expr(Prec::Lowest);
         // optional semicolon
         if self.is_peek_token(TokenType::SEMICOLON) {
@@ -128,10 +226,24 @@ impl<'a> Parser<'a> {
             "expected next token to be {:?}, instead got {:?}",
             expect, self.peek_token.token_type
         );
-        self.errors.push(error_msg);
+        self.errors.push(ParseError {
+            message: error_msg,
+            span: self.peek_token.span,
+            expected: Some(expect),
+            found: self.peek_token.token_type,
+        });
+    }
+
+    fn error_at(&mut self, token: Token, message: String) {
+        self.errors.push(ParseError {
+            message,
+            span: token.span,
+            expected: None,
+            found: token.token_type,
+        });
     }
 
-    fn parse_expr(&mut self, prec: Prec) -> Option<Expr<'a>> {
+    fn parse_expr(&mut self, prec: Prec) -> Option<Expr> {
         // try as prefix first
         let curr_tt = self.curr_token.token_type;
         let left = match curr_tt {
@@ -142,14 +254,26 @@ impl<'a> Parser<'a> {
             TokenType::FALSE => self.parse_bool_literal(),
             TokenType::IDENT => self.parse_ident(),
             TokenType::INT => self.parse_int_literal(),
+            TokenType::FLOAT => self.parse_float_literal(),
+            TokenType::STRING => self.parse_string_literal(),
+            TokenType::ILLEGAL => {
+                let error = match &*self.curr_token.literal {
+                    [b'"', ..] => "unterminated string literal".to_string(),
+                    literal => format!("illegal token {}", String::from_utf8_lossy(literal)),
+                };
+                self.error_at(self.curr_token.clone(), error);
+                None
+            }
             TokenType::BANG => self.parse_prefix_expr(prec),
             TokenType::MINUS => self.parse_prefix_expr(prec),
+            TokenType::LBRACKET => self.parse_array_literal(),
+            TokenType::LBRACE => self.parse_hash_literal(),
+            TokenType::MATCH => self.parse_match_expr(),
+            TokenType::TRY => self.parse_try_expr(),
             _ => None,
         };
 
-        if left.is_none() {
-            return None;
-        }
+        left.as_ref()?;
         let mut expr = left;
         while !self.is_peek_token(TokenType::SEMICOLON)
             && (prec as u8) < self.peek_token.token_type.precedence() as u8
@@ -166,67 +290,123 @@ impl<'a> Parser<'a> {
                     | TokenType::GT
             );
             let call = matches!(self.peek_token.token_type, TokenType::LPAREN);
+            let index = matches!(self.peek_token.token_type, TokenType::LBRACKET);
+            let ternary = matches!(self.peek_token.token_type, TokenType::QUESTION);
             if infix {
                 self.next_token();
                 expr = expr.and_then(|e| self.parse_infix_expr(e));
             } else if call {
                 self.next_token();
                 expr = expr.and_then(|e| self.parse_call_expr(e))
+            } else if index {
+                self.next_token();
+                expr = expr.and_then(|e| self.parse_index_expr(e))
+            } else if ternary {
+                self.next_token();
+                expr = expr.and_then(|e| self.parse_ternary_expr(e))
             } else {
                 return expr;
             }
         }
+        // assignment binds loosest and groups to the right, `a = b = 1`
+        if matches!(prec, Prec::Lowest) && self.is_peek_token(TokenType::ASSIGN) {
+            self.next_token();
+            return expr.and_then(|target| self.parse_assign_expr(target));
+        }
         expr
     }
 
-    fn parse_ident(&self) -> Option<Expr<'a>> {
-        Some(Expr::Identifier {
-            token: self.curr_token,
-            value: self.curr_token.literal,
+    // the identifier the current token is
+    fn curr_ident(&self) -> Expr {
+        Expr::Identifier {
+            token: self.curr_token.clone(),
+            value: self.curr_token.literal.clone(),
+            symbol: Symbol::intern(&self.curr_token.literal),
+            slot: None,
+        }
+    }
+
+    fn parse_ident(&self) -> Option<Expr> {
+        Some(self.curr_ident())
+    }
+
+    fn parse_int_literal(&mut self) -> Option<Expr> {
+        let (digits, radix) = match &*self.curr_token.literal {
+            [b'0', b'x', digits @ ..] => (digits, 16),
+            [b'0', b'o', digits @ ..] => (digits, 8),
+            [b'0', b'b', digits @ ..] => (digits, 2),
+            digits => (digits, 10),
+        };
+        let value = std::str::from_utf8(digits)
+            .ok()
+            .and_then(|s| i64::from_str_radix(s, radix).ok());
+        let Some(value) = value else {
+            let message = format!("invalid integer literal {}", self.curr_token.clone());
+            self.error_at(self.curr_token.clone(), message);
+            return None;
+        };
+        Some(Expr::IntLiteral {
+            token: self.curr_token.clone(),
+            value,
         })
     }
 
-    fn parse_int_literal(&self) -> Option<Expr<'a>> {
-        std::str::from_utf8(self.curr_token.literal)
+    fn parse_float_literal(&self) -> Option<Expr> {
+        std::str::from_utf8(&self.curr_token.literal)
             .ok()
-            .and_then(|s| s.parse::<i64>().ok())
-            .map(|value| Expr::IntLiteral {
-                token: self.curr_token,
+            .and_then(|s| s.parse::<f64>().ok())
+            .map(|value| Expr::FloatLiteral {
+                token: self.curr_token.clone(),
                 value,
             })
     }
 
-    fn parse_bool_literal(&self) -> Option<Expr<'a>> {
+    fn parse_string_literal(&mut self) -> Option<Expr> {
+        match std::str::from_utf8(&self.curr_token.literal) {
+            Ok(value) => Some(Expr::StringLiteral {
+                token: self.curr_token.clone(),
+                value: Rc::from(value),
+            }),
+            Err(_) => {
+                let message = "string literal isn't valid UTF-8".to_string();
+                self.error_at(self.curr_token.clone(), message);
+                None
+            }
+        }
+    }
+
+    fn parse_bool_literal(&self) -> Option<Expr> {
         Some(Expr::BoolLiteral {
-            token: self.curr_token,
+            token: self.curr_token.clone(),
             value: self.is_curr_token(TokenType::TRUE),
         })
     }
 
-    fn parse_prefix_expr(&mut self, prec: Prec) -> Option<Expr<'a>> {
-        let token = self.curr_token;
+    fn parse_prefix_expr(&mut self, prec: Prec) -> Option<Expr> {
+        let token = self.curr_token.clone();
         self.next_token();
         self.parse_expr(Prec::Prefix).map(|expr| Expr::Prefix {
+            op: token.literal.clone(),
             token,
-            op: token.literal,
             expr: Box::new(expr),
         })
     }
 
-    fn parse_infix_expr(&mut self, left: Expr<'a>) -> Option<Expr<'a>> {
-        let token = self.curr_token;
+    fn parse_infix_expr(&mut self, left: Expr) -> Option<Expr> {
+        let token = self.curr_token.clone();
         let curr_prec = token.token_type.precedence();
         self.next_token();
-        let expr = self.parse_expr(curr_prec).unwrap();
+        let what = format!("an operand for {}", token);
+        let expr = self.parse_required_expr(curr_prec, &what)?;
         Some(Expr::Infix {
+            op: token.literal.clone(),
             token,
             left: Box::new(left),
-            op: token.literal,
             right: Box::new(expr),
         })
     }
 
-    fn parse_group_expr(&mut self) -> Option<Expr<'a>> {
+    fn parse_group_expr(&mut self) -> Option<Expr> {
         self.next_token();
         let expr = self.parse_expr(Prec::Lowest);
         if !self.advance_if_peek(TokenType::RPAREN) {
@@ -235,18 +415,14 @@ impl<'a> Parser<'a> {
         expr
     }
 
-    fn parse_if_expr(&mut self) -> Option<Expr<'a>> {
-        let token = self.curr_token;
+    fn parse_if_expr(&mut self) -> Option<Expr> {
+        let token = self.curr_token.clone();
         if !self.advance_if_peek(TokenType::LPAREN) {
             return None;
         }
 
         self.next_token();
-        let condition = self.parse_expr(Prec::Lowest);
-
-        if condition.is_none() {
-            return None;
-        }
+        let condition = self.parse_expr(Prec::Lowest)?;
 
         if !self.advance_if_peek(TokenType::RPAREN) {
             return None;
@@ -268,15 +444,16 @@ impl<'a> Parser<'a> {
 
         Some(Expr::If {
             token,
-            condition: Box::new(condition.unwrap()),
+            condition: Box::new(condition),
             consequence: Box::new(consequence),
             alternative,
         })
     }
 
-    fn parse_block_stmt(&mut self) -> Stmt<'a> {
-        let token = self.curr_token;
+    fn parse_block_stmt(&mut self) -> Stmt {
+        let token = self.curr_token.clone();
         let mut stmts = Vec::new();
+        self.next_token();
         while !self.is_curr_token(TokenType::RBRACE) && !self.is_curr_token(TokenType::EOF) {
             if let Some(s) = self.parse_stmt() {
                 stmts.push(s);
@@ -287,13 +464,13 @@ impl<'a> Parser<'a> {
         Stmt::Block { token, stmts }
     }
 
-    fn parse_fn_literal(&mut self) -> Option<Expr<'a>> {
-        let token = self.curr_token;
+    fn parse_fn_literal(&mut self) -> Option<Expr> {
+        let token = self.curr_token.clone();
         if !self.advance_if_peek(TokenType::LPAREN) {
             return None;
         }
 
-        let parameters = self.parse_fn_parameters();
+        let (parameters, rest) = self.parse_fn_parameters()?;
 
         if !self.advance_if_peek(TokenType::LBRACE) {
             return None;
@@ -304,72 +481,263 @@ impl<'a> Parser<'a> {
         Some(Expr::FnLiteral {
             token,
             parameters,
-            block: Box::new(block),
+            rest,
+            block: Rc::new(block),
         })
     }
 
-    fn parse_fn_parameters(&mut self) -> Vec<Expr<'a>> {
+    // a rest parameter can only come last
+    #[allow(clippy::type_complexity)]
+    fn parse_fn_parameters(&mut self) -> Option<(Vec<Expr>, Option<Box<Expr>>)> {
+        let mut params = Vec::new();
         if self.is_peek_token(TokenType::RPAREN) {
             self.next_token();
-            return vec![];
+            return Some((params, None));
         }
 
-        self.next_token();
-        let mut params = Vec::new();
-        // first param
-        let ident = Expr::Identifier {
-            token: self.curr_token,
-            value: self.curr_token.literal,
-        };
-        params.push(ident);
-
-        while self.is_peek_token(TokenType::COMMA) {
-            self.next_token();
-            self.next_token();
-            let ident = Expr::Identifier {
-                token: self.curr_token,
-                value: self.curr_token.literal,
-            };
+        loop {
+            let rest = self.is_peek_token(TokenType::ELLIPSIS);
+            if rest {
+                self.next_token();
+            }
+            if !self.advance_if_peek(TokenType::IDENT) {
+                return None;
+            }
+            let ident = self.curr_ident();
+            if rest {
+                if !self.advance_if_peek(TokenType::RPAREN) {
+                    return None;
+                }
+                return Some((params, Some(Box::new(ident))));
+            }
             params.push(ident);
+            if !self.is_peek_token(TokenType::COMMA) {
+                break;
+            }
+            self.next_token();
         }
 
         if !self.advance_if_peek(TokenType::RPAREN) {
-            return vec![];
+            return None;
+        }
+
+        Some((params, None))
+    }
+
+    fn parse_assign_expr(&mut self, name: Expr) -> Option<Expr> {
+        let token = self.curr_token.clone();
+        if !matches!(name, Expr::Identifier { .. }) {
+            self.error_at(
+                name.first_token().clone(),
+                format!("can't assign to {}", name),
+            );
+            return None;
+        }
+        self.next_token();
+        let value = self.parse_required_expr(Prec::Lowest, "a value to assign")?;
+        Some(Expr::Assign {
+            token,
+            name: Box::new(name),
+            value: Box::new(value),
+        })
+    }
+
+    // `cond ? a : b` is sugar for `if (cond) { a } else { b }`
+    fn parse_ternary_expr(&mut self, condition: Expr) -> Option<Expr> {
+        let token = self.curr_token.clone();
+        self.next_token();
+        let consequence = self.parse_required_expr(Prec::Lowest, "an expression after ?")?;
+        if !self.advance_if_peek(TokenType::COLON) {
+            return None;
         }
+        let colon = self.curr_token.clone();
+        self.next_token();
+        let alternative = self.parse_required_expr(Prec::Lowest, "an expression after :")?;
+        let block = |token, expr| Stmt::Block {
+            token,
+            stmts: vec![Stmt::Expr { expr }],
+        };
+        Some(Expr::If {
+            token: token.clone(),
+            condition: Box::new(condition),
+            consequence: Box::new(block(token, consequence)),
+            alternative: Some(Box::new(block(colon, alternative))),
+        })
+    }
 
-        params
+    // `parse_expr` doesn't report a missing expression, this does for the
+    // places where one has to follow
+    fn parse_required_expr(&mut self, prec: Prec, what: &str) -> Option<Expr> {
+        let expr = self.parse_expr(prec);
+        if expr.is_none() {
+            let token = match self.curr_token.token_type {
+                TokenType::EOF => "the end of the input".into(),
+                _ => String::from_utf8_lossy(&self.curr_token.literal),
+            };
+            let message = format!("expected {}, got {}", what, token);
+            self.error_at(self.curr_token.clone(), message);
+        }
+        expr
     }
 
-    fn parse_call_expr(&mut self, fn_expr: Expr<'a>) -> Option<Expr<'a>> {
+    fn parse_call_expr(&mut self, fn_expr: Expr) -> Option<Expr> {
         let arguments = self.parse_call_args();
         Some(Expr::Call {
-            token: self.curr_token,
+            token: self.curr_token.clone(),
             function: Box::new(fn_expr),
             arguments,
         })
     }
 
-    fn parse_call_args(&mut self) -> Vec<Expr<'a>> {
-        if self.is_peek_token(TokenType::RPAREN) {
+    fn parse_call_args(&mut self) -> Vec<Expr> {
+        self.parse_expr_list(TokenType::RPAREN)
+    }
+
+    fn parse_array_literal(&mut self) -> Option<Expr> {
+        let token = self.curr_token.clone();
+        let elements = self.parse_expr_list(TokenType::RBRACKET);
+        Some(Expr::ArrayLiteral { token, elements })
+    }
+
+    fn parse_hash_literal(&mut self) -> Option<Expr> {
+        let token = self.curr_token.clone();
+        let mut pairs = Vec::new();
+        while !self.is_peek_token(TokenType::RBRACE) && !self.is_peek_token(TokenType::EOF) {
+            self.next_token();
+            let key = self.parse_required_expr(Prec::Lowest, "a hash key")?;
+            if !self.advance_if_peek(TokenType::COLON) {
+                return None;
+            }
+            self.next_token();
+            let value = self.parse_expr(Prec::Lowest)?;
+            pairs.push((key, value));
+            if !self.is_peek_token(TokenType::RBRACE) && !self.advance_if_peek(TokenType::COMMA) {
+                return None;
+            }
+        }
+        if !self.advance_if_peek(TokenType::RBRACE) {
+            return None;
+        }
+        Some(Expr::HashLiteral { token, pairs })
+    }
+
+    fn parse_match_expr(&mut self) -> Option<Expr> {
+        let token = self.curr_token.clone();
+        if !self.advance_if_peek(TokenType::LPAREN) {
+            return None;
+        }
+        self.next_token();
+        let subject = self.parse_required_expr(Prec::Lowest, "a value to match")?;
+        if !self.advance_if_peek(TokenType::RPAREN) || !self.advance_if_peek(TokenType::LBRACE) {
+            return None;
+        }
+        let mut arms = Vec::new();
+        while !self.is_peek_token(TokenType::RBRACE) && !self.is_peek_token(TokenType::EOF) {
+            self.next_token();
+            let pattern = if self.curr_token.literal == b"_" {
+                None
+            } else {
+                let pattern = self.parse_required_expr(Prec::Lowest, "a pattern")?;
+                if !is_literal(&pattern) {
+                    let message = format!("match patterns must be literals, got {}", pattern);
+                    self.error_at(pattern.first_token().clone(), message);
+                    return None;
+                }
+                Some(pattern)
+            };
+            if !self.advance_if_peek(TokenType::FATARROW) {
+                return None;
+            }
+            self.next_token();
+            let value = self.parse_required_expr(Prec::Lowest, "a value for the arm")?;
+            arms.push(MatchArm { pattern, value });
+            if !self.is_peek_token(TokenType::RBRACE) && !self.advance_if_peek(TokenType::COMMA) {
+                return None;
+            }
+        }
+        if !self.advance_if_peek(TokenType::RBRACE) {
+            return None;
+        }
+        Some(Expr::Match {
+            token,
+            subject: Box::new(subject),
+            arms,
+        })
+    }
+
+    fn parse_try_expr(&mut self) -> Option<Expr> {
+        let token = self.curr_token.clone();
+        if !self.advance_if_peek(TokenType::LBRACE) {
+            return None;
+        }
+        let body = self.parse_block_stmt();
+        if !self.advance_if_peek(TokenType::CATCH)
+            || !self.advance_if_peek(TokenType::LPAREN)
+            || !self.advance_if_peek(TokenType::IDENT)
+        {
+            return None;
+        }
+        let name = self.parse_ident()?;
+        if !self.advance_if_peek(TokenType::RPAREN) || !self.advance_if_peek(TokenType::LBRACE) {
+            return None;
+        }
+        let handler = self.parse_block_stmt();
+        Some(Expr::Try {
+            token,
+            body: Box::new(body),
+            name: Box::new(name),
+            handler: Box::new(handler),
+        })
+    }
+
+    fn parse_index_expr(&mut self, left: Expr) -> Option<Expr> {
+        let token = self.curr_token.clone();
+        self.next_token();
+        let index = self.parse_expr(Prec::Lowest)?;
+        if !self.advance_if_peek(TokenType::RBRACKET) {
+            return None;
+        }
+        Some(Expr::Index {
+            token,
+            left: Box::new(left),
+            index: Box::new(index),
+        })
+    }
+
+    fn parse_expr_list(&mut self, end: TokenType) -> Vec<Expr> {
+        if self.is_peek_token(end) {
             self.next_token();
             return vec![];
         }
         self.next_token();
-        let mut args = Vec::new();
+        let mut list = Vec::new();
         if let Some(e) = self.parse_expr(Prec::Lowest) {
-            args.push(e);
+            list.push(e);
         }
         while self.is_peek_token(TokenType::COMMA) {
             self.next_token();
             self.next_token();
             if let Some(e) = self.parse_expr(Prec::Lowest) {
-                args.push(e);
+                list.push(e);
             }
         }
-        if !self.advance_if_peek(TokenType::RPAREN) {
+        if !self.advance_if_peek(end) {
             return vec![];
         }
-        args
+        list
+    }
+}
+
+fn is_literal(expr: &Expr) -> bool {
+    match expr {
+        Expr::IntLiteral { .. }
+        | Expr::FloatLiteral { .. }
+        | Expr::BoolLiteral { .. }
+        | Expr::StringLiteral { .. } => true,
+        Expr::Prefix { op, expr, .. } if op == b"-" => {
+            matches!(**expr, Expr::IntLiteral { .. } | Expr::FloatLiteral { .. })
+        }
+        _ => false,
     }
 }
 
@@ -377,14 +745,15 @@ impl<'a> Parser<'a> {
 mod tests {
     use crate::{
         ast::{Expr, Program, Stmt},
+        intern::Symbol,
         lexer::Lexer,
         parser::Prec,
-        token::{Token, TokenType},
+        token::{Text, Token, TokenType},
     };
 
-    use super::Parser;
+    use super::{ParseError, Parser};
 
-    type AssertExpr = fn(&Expr) -> ();
+    type AssertExpr = fn(&Expr);
 
     fn bytes_as_str(value: &[u8]) -> &str {
         std::str::from_utf8(value).unwrap()
@@ -425,7 +794,7 @@ mod tests {
 
     fn assert_ident(expr: &Expr, expected_literal: &[u8]) {
         match expr {
-            Expr::Identifier { token, value } => {
+            Expr::Identifier { value, .. } => {
                 assert_eq!(bytes_as_str(value), bytes_as_str(expected_literal))
             }
             _ => {
@@ -434,7 +803,7 @@ mod tests {
         }
     }
 
-    fn assert_expr_stmt<F: FnOnce(&Expr) -> ()>(stmt: &Stmt, assert: F) {
+    fn assert_expr_stmt<F: FnOnce(&Expr)>(stmt: &Stmt, assert: F) {
         match stmt {
             Stmt::Expr { expr } => assert(expr),
             _ => panic!("not an expression statement"),
@@ -501,9 +870,49 @@ return 2 * 3;
 
         let mut p = Parser::new(Lexer::new(input.as_bytes()));
         let prog = p.parse();
-        assert!(!prog.is_ok());
+        assert!(prog.is_err());
         log_errors(&prog.err().unwrap());
         assert!(p.errors.len() == 3);
+
+        let mut p = Parser::new(Lexer::new("1 +".as_bytes()));
+        let errors = p.parse().unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            errors[0].message,
+            "expected an operand for +, got the end of the input"
+        );
+    }
+
+    #[test]
+    fn test_error_positions() {
+        let input = "let x = 1;\n\tlet = 5;\nlet y = [1, 2;";
+        let errors = Parser::new(Lexer::new(input.as_bytes()))
+            .parse()
+            .unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert_eq!(
+            (
+                errors[0].span.to_string(),
+                errors[0].expected,
+                errors[0].found
+            ),
+            ("2:6".to_string(), Some(TokenType::IDENT), TokenType::ASSIGN)
+        );
+        assert_eq!(
+            errors[0].diagnostic().render(input),
+            "expected next token to be IDENT, instead got ASSIGN\n    \tlet = 5;\n    \t    ^"
+        );
+        assert_eq!(errors[1].span.to_string(), "3:14");
+        assert!(errors[1]
+            .diagnostic()
+            .render(input)
+            .ends_with("let y = [1, 2;\n                 ^"));
+
+        let errors = Parser::new(Lexer::new("1 = 2".as_bytes()))
+            .parse()
+            .unwrap_err();
+        assert_eq!(errors[0].message, "can't assign to 1");
+        assert_eq!((errors[0].span.start, errors[0].expected), (0, None));
     }
 
     #[test]
@@ -512,19 +921,22 @@ return 2 * 3;
             token: Token::new(TokenType::LET, b"let"),
             name: Expr::Identifier {
                 token: Token::new(TokenType::IDENT, b"x"),
-                value: b"x",
+                value: Text::from_static(b"x"),
+                symbol: Symbol::intern(b"x"),
+                slot: None,
             },
             value: Expr::Identifier {
                 token: Token::new(TokenType::IDENT, b"y"),
-                value: b"y",
+                value: Text::from_static(b"y"),
+                symbol: Symbol::intern(b"y"),
+                slot: None,
             },
-
         };
         eprintln!("{a}");
         assert_eq!(format!("{a}"), "let x = y;");
     }
 
-    fn log_errors(p: &[String]) {
+    fn log_errors(p: &[ParseError]) {
         eprintln!("parser has {} errors", p.len());
         for err in p.iter() {
             eprintln!("parser error: {}", err)
@@ -536,7 +948,7 @@ return 2 * 3;
         let input = "foobar;";
         assert_prog(input, |stmts| {
             assert_eq!(stmts.len(), 1);
-            matches!(stmts[0], Stmt:: Expr { expr: Expr::Identifier {token,..}} if token.literal == b"foobar");
+            matches!(&stmts[0], Stmt:: Expr { expr: Expr::Identifier {token,..}} if token.literal == b"foobar");
         });
     }
 
@@ -545,13 +957,41 @@ return 2 * 3;
         let input = "123";
         assert_prog(input, |stmts| {
             assert_eq!(stmts.len(), 1);
-            matches!(stmts[0], Stmt:: Expr { expr: Expr::IntLiteral {value,..}} if value == 123_i64 );
+            matches!(&stmts[0], Stmt:: Expr { expr: Expr::IntLiteral {value,..}} if *value == 123_i64 );
         });
     }
 
+    #[test]
+    fn test_radix_int_literals() {
+        let inputs = [
+            ("0x1F", 31),
+            ("0xff", 255),
+            ("0o17", 15),
+            ("0b1010", 10),
+            ("0", 0),
+        ];
+        for (input, expected) in inputs {
+            assert_prog(input, |stmts| {
+                assert_expr_stmt(&stmts[0], |e| match e {
+                    Expr::IntLiteral { value, .. } => assert_eq!(*value, expected, "{}", input),
+                    _ => panic!("not an int literal {}", e),
+                })
+            });
+        }
+        for input in ["0xg1", "0o8", "0b102", "0x", "0b_1", "99999999999999999999"] {
+            let errors = Parser::new(Lexer::new(input.as_bytes()))
+                .parse()
+                .unwrap_err();
+            assert_eq!(
+                errors[0].message,
+                format!("invalid integer literal {}", input)
+            );
+        }
+    }
+
     #[test]
     fn test_prefix_expr() {
-        let inputs = [("!5", b"!", 5 as i64), ("-10", b"-", 10 as i64)];
+        let inputs = [("!5", b"!", 5_i64), ("-10", b"-", 10_i64)];
         for (input, eop, eexpr) in inputs {
             assert_prog(input, |stmts| {
                 assert_eq!(stmts.len(), 1);
@@ -622,6 +1062,14 @@ return 2 * 3;
                 "add(a + b + c * d / f + g)",
                 "add((((a + b) + ((c * d) / f)) + g))",
             ),
+            (
+                "a * [1, 2, 3, 4][b * c] * d",
+                "((a * ([1, 2, 3, 4][(b * c)])) * d)",
+            ),
+            (
+                "add(a * b[2], b[1], 2 * [1, 2][1])",
+                "add((a * (b[2])), (b[1]), (2 * ([1, 2][1])))",
+            ),
         ];
         for (i, o) in inputs {
             assert_prog(i, |stmts| {
@@ -645,6 +1093,28 @@ return 2 * 3;
         }
     }
 
+    #[test]
+    fn test_string_literal() {
+        assert_prog("\"hello world\";", |stmts| match &stmts[0] {
+            Stmt::Expr {
+                expr: Expr::StringLiteral { value, .. },
+            } => assert_eq!(&**value, "hello world"),
+            stmt => panic!("not a string literal {}", stmt),
+        });
+        assert_prog("\"a\" + \"b\" == \"ab\"", |stmts| {
+            assert_eq!(stmts[0].to_string(), "((\"a\" + \"b\") == \"ab\")");
+        });
+
+        let inputs: [&[u8]; 3] = [b"\"open", b"let a = @;", b"\"\xff\""];
+        for input in inputs {
+            assert!(
+                Parser::new(Lexer::new(input)).parse().is_err(),
+                "{:?}",
+                input
+            );
+        }
+    }
+
     #[test]
     fn test_group_expr() {
         let inputs = [
@@ -673,13 +1143,13 @@ return 2 * 3;
                     alternative,
                 } => {
                     assert_infix_expr(
-                        &condition,
+                        condition,
                         b"<",
                         |e| assert_ident(e, b"x"),
                         |e| assert_ident(e, b"y"),
                     );
 
-                    assert_block_stmt(&consequence, |e| assert_ident(e, b"x"));
+                    assert_block_stmt(consequence, |e| assert_ident(e, b"x"));
                     assert!(alternative.is_none());
                 }
                 _ => panic!("not an If expr, got {:?}", expr),
@@ -700,13 +1170,13 @@ return 2 * 3;
                     alternative,
                 } => {
                     assert_infix_expr(
-                        &condition,
+                        condition,
                         b"<",
                         |e| assert_ident(e, b"x"),
                         |e| assert_ident(e, b"y"),
                     );
 
-                    assert_block_stmt(&consequence, |e| assert_ident(e, b"x"));
+                    assert_block_stmt(consequence, |e| assert_ident(e, b"x"));
                     assert_block_stmt(alternative.as_deref().unwrap(), |e| assert_ident(e, b"y"));
                 }
                 _ => panic!("not an If expr, got {:?}", expr),
@@ -725,6 +1195,7 @@ return 2 * 3;
                     token,
                     parameters,
                     block,
+                    ..
                 } => {
                     assert_eq!(parameters.len(), 2);
                     assert_ident(&parameters[0], b"x");
@@ -760,6 +1231,7 @@ return 2 * 3;
                         token,
                         parameters,
                         block,
+                        ..
                     } => {
                         for i in 0..eparams.len() {
                             assert_ident(&parameters[i], eparams[i]);
@@ -771,6 +1243,29 @@ return 2 * 3;
         }
     }
 
+    #[test]
+    fn test_rest_param() {
+        assert_prog("fn(x, ...xs) {};", |stmts| {
+            assert_expr_stmt(&stmts[0], |s| match s {
+                Expr::FnLiteral {
+                    parameters, rest, ..
+                } => {
+                    assert_eq!(parameters.len(), 1);
+                    assert_ident(&parameters[0], b"x");
+                    assert_ident(rest.as_deref().unwrap(), b"xs");
+                }
+                _ => panic!("not a fn literal"),
+            })
+        });
+        for input in ["fn(...xs, y) {}", "fn(...) {}", "fn(x, 1) {}", "fn(x,) {}"] {
+            assert!(
+                Parser::new(Lexer::new(input.as_bytes())).parse().is_err(),
+                "{}",
+                input
+            );
+        }
+    }
+
     #[test]
     fn test_fn_call() {
         let input = "add(1, 2 * 3, 4 + 5);";
@@ -801,6 +1296,283 @@ return 2 * 3;
         })
     }
 
+    #[test]
+    fn test_array_literal() {
+        assert_prog("[1, 2 * 2, 3 + 3]", |stmts| {
+            assert_expr_stmt(&stmts[0], |e| match e {
+                Expr::ArrayLiteral { elements, .. } => {
+                    assert_eq!(elements.len(), 3);
+                    assert_int_literal(&elements[0], 1);
+                    assert_infix_expr(
+                        &elements[1],
+                        b"*",
+                        |e| assert_int_literal(e, 2),
+                        |e| assert_int_literal(e, 2),
+                    );
+                    assert_infix_expr(
+                        &elements[2],
+                        b"+",
+                        |e| assert_int_literal(e, 3),
+                        |e| assert_int_literal(e, 3),
+                    );
+                }
+                _ => panic!("not an array literal"),
+            })
+        });
+        assert_prog("[]", |stmts| {
+            assert_expr_stmt(&stmts[0], |e| {
+                assert!(matches!(e, Expr::ArrayLiteral { elements, .. } if elements.is_empty()))
+            })
+        });
+    }
+
+    #[test]
+    fn test_index_expr() {
+        assert_prog("myArray[1 + 1]", |stmts| {
+            assert_expr_stmt(&stmts[0], |e| match e {
+                Expr::Index { left, index, .. } => {
+                    assert_ident(left, b"myArray");
+                    assert_infix_expr(
+                        index,
+                        b"+",
+                        |e| assert_int_literal(e, 1),
+                        |e| assert_int_literal(e, 1),
+                    );
+                }
+                _ => panic!("not an index expr"),
+            })
+        });
+    }
+
+    #[test]
+    fn test_try_expr() {
+        assert_prog("try { f(x); } catch (e) { e }", |stmts| {
+            assert_expr_stmt(&stmts[0], |e| match e {
+                Expr::Try {
+                    body,
+                    name,
+                    handler,
+                    ..
+                } => {
+                    assert_eq!(body.to_string(), "{ f(x); }");
+                    assert_ident(name, b"e");
+                    assert_eq!(handler.to_string(), "{ e; }");
+                }
+                _ => panic!("not a try expr"),
+            })
+        });
+        let inputs = [
+            "try f(x) catch (e) { e }",
+            "try { f(x) }",
+            "try { f(x) } catch { e }",
+            "try { f(x) } catch (1) { e }",
+            "try { f(x) } catch (e)",
+        ];
+        for input in inputs {
+            assert!(
+                Parser::new(Lexer::new(input.as_bytes())).parse().is_err(),
+                "{}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_match_expr() {
+        assert_prog(
+            "match (x) { 1 => \"one\", -2.5 => y + 1, _ => 0, }",
+            |stmts| {
+                assert_expr_stmt(&stmts[0], |e| match e {
+                    Expr::Match { subject, arms, .. } => {
+                        assert_ident(subject, b"x");
+                        assert_eq!(arms.len(), 3);
+                        assert_int_literal(arms[0].pattern.as_ref().unwrap(), 1);
+                        assert_eq!(arms[0].value.to_string(), "\"one\"");
+                        assert_eq!(arms[1].to_string(), "(-2.5) => (y + 1)");
+                        assert!(arms[2].pattern.is_none());
+                    }
+                    _ => panic!("not a match expr"),
+                })
+            },
+        );
+        assert_prog("match (x) { }", |stmts| {
+            assert_expr_stmt(&stmts[0], |e| {
+                assert!(matches!(e, Expr::Match { arms, .. } if arms.is_empty()))
+            })
+        });
+        let inputs = [
+            "match x { _ => 1 }",
+            "match (x) { y => 1 }",
+            "match (x) { 1 + 1 => 1 }",
+            "match (x) { 1 => }",
+            "match (x) { 1 -> 2 }",
+            "match (x) { 1 => 2 2 => 3 }",
+            "match (x) { 1 => 2",
+        ];
+        for input in inputs {
+            assert!(
+                Parser::new(Lexer::new(input.as_bytes())).parse().is_err(),
+                "{}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_ternary_expr() {
+        let inputs = [
+            ("a ? b : c", "if (a) { b; } else { c; }"),
+            (
+                "x < 1 ? -1 : x + 1",
+                "if ((x < 1)) { (-1); } else { (x + 1); }",
+            ),
+            (
+                "a ? b : c ? d : e",
+                "if (a) { b; } else { if (c) { d; } else { e; }; }",
+            ),
+            (
+                "a ? b ? c : d : e",
+                "if (a) { if (b) { c; } else { d; }; } else { e; }",
+            ),
+            ("let x = a ? 1 : 2;", "let x = if (a) { 1; } else { 2; };"),
+            ("f(a ? b : c, d)", "f(if (a) { b; } else { c; }, d)"),
+        ];
+        for (input, expected) in inputs {
+            assert_prog(input, |stmts| assert_eq!(stmts[0].to_string(), expected));
+        }
+        for input in ["a ? b", "a ? : c", "a ? b :", "a ? b ; c"] {
+            assert!(
+                Parser::new(Lexer::new(input.as_bytes())).parse().is_err(),
+                "{}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_let_pattern() {
+        assert_prog("let [a, b] = f();", |stmts| match &stmts[0] {
+            Stmt::Let {
+                name: Expr::ArrayLiteral { elements, .. },
+                value,
+                ..
+            } => {
+                assert_ident(&elements[0], b"a");
+                assert_ident(&elements[1], b"b");
+                assert_eq!(value.to_string(), "f()");
+            }
+            stmt => panic!("not a destructuring let {}", stmt),
+        });
+        for input in ["let [a, 1] = x;", "let [a, [b]] = x;", "let [a = x;"] {
+            assert!(
+                Parser::new(Lexer::new(input.as_bytes())).parse().is_err(),
+                "{}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_assign_expr() {
+        let inputs = [
+            ("x = 5;", "(x = 5)"),
+            ("x = y = x + 1", "(x = (y = (x + 1)))"),
+            ("f(x = 1)", "f((x = 1))"),
+            ("(x = 1) * 2", "((x = 1) * 2)"),
+        ];
+        for (input, expected) in inputs {
+            assert_prog(input, |stmts| assert_eq!(stmts[0].to_string(), expected));
+        }
+        assert_prog("x = 5", |stmts| {
+            assert_expr_stmt(&stmts[0], |e| match e {
+                Expr::Assign { name, value, .. } => {
+                    assert_ident(name, b"x");
+                    assert_int_literal(value, 5);
+                }
+                _ => panic!("not an assignment"),
+            })
+        });
+        for input in ["1 = 2", "x + y = 2", "x ="] {
+            assert!(
+                Parser::new(Lexer::new(input.as_bytes())).parse().is_err(),
+                "{}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_for_stmt() {
+        assert_prog("for (x in [1, 2]) { puts(x) }; 3", |stmts| {
+            assert_eq!(stmts.len(), 2);
+            match &stmts[0] {
+                Stmt::For {
+                    name,
+                    iterable,
+                    body,
+                    ..
+                } => {
+                    assert_ident(name, b"x");
+                    assert_eq!(iterable.to_string(), "[1, 2]");
+                    assert_eq!(body.to_string(), "{ puts(x); }");
+                }
+                stmt => panic!("not a for statement {}", stmt),
+            }
+        });
+        assert_prog("for (x in xs) { break; continue }", |stmts| {
+            match &stmts[0] {
+                Stmt::For { body, .. } => assert_eq!(body.to_string(), "{ break; continue; }"),
+                stmt => panic!("not a for statement {}", stmt),
+            }
+        });
+        for input in [
+            "for x in xs { }",
+            "for (1 in xs) { }",
+            "for (x of xs) { }",
+            "for (x in xs) x",
+        ] {
+            assert!(
+                Parser::new(Lexer::new(input.as_bytes())).parse().is_err(),
+                "{}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_hash_literal() {
+        assert_prog("{\"one\": 1, 2: 1 + 1, true: [3]}", |stmts| {
+            assert_expr_stmt(&stmts[0], |e| match e {
+                Expr::HashLiteral { pairs, .. } => {
+                    assert_eq!(pairs.len(), 3);
+                    assert_eq!(pairs[0].0.to_string(), "\"one\"");
+                    assert_int_literal(&pairs[0].1, 1);
+                    assert_int_literal(&pairs[1].0, 2);
+                    assert_infix_expr(
+                        &pairs[1].1,
+                        b"+",
+                        |e| assert_int_literal(e, 1),
+                        |e| assert_int_literal(e, 1),
+                    );
+                    assert_eq!(pairs[2].1.to_string(), "[3]");
+                }
+                _ => panic!("not a hash literal"),
+            })
+        });
+        assert_prog("{}", |stmts| {
+            assert_expr_stmt(&stmts[0], |e| {
+                assert!(matches!(e, Expr::HashLiteral { pairs, .. } if pairs.is_empty()))
+            })
+        });
+        for input in ["{1}", "{1: 2 3: 4}", "{1: 2,", "{: 1}"] {
+            assert!(
+                Parser::new(Lexer::new(input.as_bytes())).parse().is_err(),
+                "{}",
+                input
+            );
+        }
+    }
+
     fn assert_prog<F: Fn(&[Stmt])>(input: &str, assertions: F) {
         let mut p = Parser::new(Lexer::new(input.as_bytes()));
         let prog = p.parse();
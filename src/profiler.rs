@@ -0,0 +1,200 @@
+use std::{
+    collections::HashMap,
+    fmt::Write,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    ast::{self, Expr, Program, Stmt},
+    env::Env,
+    evaluator::Tracer,
+    object::Function,
+    rc::Rc,
+};
+
+// functions are told apart by the body of their literal, so every closure
+// made from one literal shares a profile
+type Key = *const ();
+
+fn key(body: &Rc<Stmt>) -> Key {
+    Rc::as_ptr(body) as Key
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionProfile {
+    pub name: String,
+    pub calls: u64,
+    // statements and expressions evaluated in the function itself, not in
+    // the functions it calls
+    pub nodes: u64,
+    // from the outermost call to its return, so recursion isn't counted twice
+    pub time: Duration,
+}
+
+// a tracer counting where the evaluator spends its time
+#[derive(Debug, Default)]
+pub struct Profiler {
+    names: HashMap<Key, String>,
+    functions: HashMap<Key, FunctionProfile>,
+    // the calls in progress, innermost last, with when they started
+    calls: Vec<(Key, Instant)>,
+    nodes: u64,
+}
+
+impl Profiler {
+    // functions are named after the `let` binding them, if there is one
+    pub fn new(prog: &Program) -> Self {
+        let mut names = HashMap::new();
+        ast::visit_stmts(&prog.stmts, &mut |stmt| {
+            if let Stmt::Let {
+                name: Expr::Identifier { value: name, .. },
+                value: Expr::FnLiteral { block, .. },
+                ..
+            } = stmt
+            {
+                names.insert(key(block), String::from_utf8_lossy(name).into_owned());
+            }
+        });
+        Profiler {
+            names,
+            ..Default::default()
+        }
+    }
+
+    // every statement and expression evaluated, in functions or not
+    pub fn nodes(&self) -> u64 {
+        self.nodes
+    }
+
+    // the functions that were called, the most expensive first
+    pub fn functions(&self) -> Vec<&FunctionProfile> {
+        let mut functions: Vec<_> = self.functions.values().collect();
+        functions.sort_by(|a, b| b.time.cmp(&a.time).then_with(|| a.name.cmp(&b.name)));
+        functions
+    }
+
+    pub fn report(&self) -> String {
+        let functions = self.functions();
+        let width = functions
+            .iter()
+            .map(|function| function.name.chars().count())
+            .max()
+            .unwrap_or(0)
+            .max("function".len());
+
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "{:<width$}  {:>10}  {:>12}  {:>12}",
+            "function", "calls", "nodes", "time"
+        );
+        for function in functions {
+            let _ = writeln!(
+                out,
+                "{:<width$}  {:>10}  {:>12}  {:>10.3}ms",
+                function.name,
+                function.calls,
+                function.nodes,
+                function.time.as_secs_f64() * 1000.0
+            );
+        }
+        let _ = writeln!(out, "{} nodes evaluated", self.nodes);
+        out
+    }
+
+    fn count_node(&mut self) {
+        self.nodes += 1;
+        if let Some((key, _)) = self.calls.last() {
+            if let Some(function) = self.functions.get_mut(key) {
+                function.nodes += 1;
+            }
+        }
+    }
+}
+
+impl<'a> Tracer<'a> for Profiler {
+    fn statement(&mut self, _: &Stmt, _: &mut Env<'a>, _: usize) -> Result<(), &'a str> {
+        self.count_node();
+        Ok(())
+    }
+
+    fn expr(&mut self, _: &Expr) {
+        self.count_node();
+    }
+
+    fn enter(&mut self, function: &Function<'a>) {
+        let key = key(&function.body);
+        let names = &self.names;
+        let profile = self.functions.entry(key).or_insert_with(|| {
+            let name = names
+                .get(&key)
+                .cloned()
+                .unwrap_or_else(|| format!("fn({})", function.signature()));
+            FunctionProfile {
+                name,
+                calls: 0,
+                nodes: 0,
+                time: Duration::ZERO,
+            }
+        });
+        profile.calls += 1;
+        self.calls.push((key, Instant::now()));
+    }
+
+    fn exit(&mut self, _: &Function<'a>) {
+        let Some((key, start)) = self.calls.pop() else {
+            return;
+        };
+        if self.calls.iter().any(|(outer, _)| *outer == key) {
+            return;
+        }
+        if let Some(function) = self.functions.get_mut(&key) {
+            function.time += start.elapsed();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        env::Env, evaluator::Evaluator, lexer::Lexer, parser::Parser, sandbox::SandboxConfig,
+    };
+
+    use super::Profiler;
+
+    #[test]
+    fn test_profile() {
+        let source = "
+            let fib = fn(n) { if (n < 2) { n } else { fib(n - 1) + fib(n - 2) } };
+            let twice = fn(f) { fn(x) { f(f(x)) } };
+            twice(fn(x) { x + fib(5) })(1)
+        ";
+        let prog = Parser::new(Lexer::new(source.as_bytes())).parse().unwrap();
+        let mut profiler = Profiler::new(&prog);
+        let result = Evaluator::new(SandboxConfig::default())
+            .with_tracer(&mut profiler)
+            .eval_program(&prog, &mut Env::new());
+        assert_eq!(result.unwrap().to_string(), "11");
+
+        let functions = profiler.functions();
+        assert_eq!(functions[0].name, "fn(x)");
+        let calls: Vec<_> = functions.iter().map(|f| (&f.name[..], f.calls)).collect();
+        for expected in [("fib", 30), ("twice", 1), ("fn(x)", 1), ("fn(x)", 2)] {
+            assert!(calls.contains(&expected), "{:?}", calls);
+        }
+        let fib = functions.iter().find(|f| f.name == "fib").unwrap();
+        // 16 calls with n < 2 evaluate 9 nodes, the other 14 evaluate 19
+        assert_eq!(fib.nodes, 16 * 9 + 14 * 19);
+        let total: u64 = functions.iter().map(|f| f.nodes).sum();
+        assert!(profiler.nodes() > total);
+
+        let report = profiler.report();
+        assert!(report.starts_with("function "), "{}", report);
+        assert!(report.contains("\nfib   "), "{}", report);
+        assert!(
+            report.ends_with(&format!("{} nodes evaluated\n", profiler.nodes())),
+            "{}",
+            report
+        );
+    }
+}
@@ -0,0 +1,37 @@
+// the shared pointer used by the AST and objects, `Arc` with the `sync`
+// feature so an interpreter and its values can be sent to other threads
+#[cfg(not(feature = "sync"))]
+pub use std::rc::{Rc, Weak};
+#[cfg(feature = "sync")]
+pub use std::sync::{Arc as Rc, Weak};
+
+// the cell for state shared through one, a `RefCell`, or with `sync` a lock
+// with the same interface
+#[cfg(feature = "sync")]
+pub use self::sync::RefCell;
+#[cfg(not(feature = "sync"))]
+pub use std::cell::RefCell;
+
+#[cfg(feature = "sync")]
+mod sync {
+    use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+    // a borrow while the cell is mutably borrowed blocks instead of
+    // panicking, so borrows must not outlive the call that makes them
+    #[derive(Debug, Default)]
+    pub struct RefCell<T>(RwLock<T>);
+
+    impl<T> RefCell<T> {
+        pub fn new(value: T) -> Self {
+            RefCell(RwLock::new(value))
+        }
+
+        pub fn borrow(&self) -> RwLockReadGuard<'_, T> {
+            self.0.read().expect("cell poisoned")
+        }
+
+        pub fn borrow_mut(&self) -> RwLockWriteGuard<'_, T> {
+            self.0.write().expect("cell poisoned")
+        }
+    }
+}
@@ -1,29 +1,660 @@
-use crate::{lexer, parser::Parser, evaluator::eval_program, env::Env};
+use std::{
+    io::{BufRead, IsTerminal},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
 
-pub fn start() {
+use rustyline::{
+    completion::Completer, highlight::Highlighter, hint::Hinter, history::DefaultHistory,
+    validate::Validator, Context, Editor, Helper,
+};
+
+use crate::{
+    ast::Program,
+    builtins::BUILTINS,
+    cli::{self, Options},
+    compiler::Compiler,
+    debugger,
+    diagnostic::{self, Diagnostic},
+    engine::Engine,
+    env::Env,
+    evaluator::Evaluator,
+    fold, lexer,
+    lint::{self, LintConfig},
+    object::Object,
+    parser::Parser,
+    sandbox::{CancellationToken, SandboxConfig},
+    token::KEYWORDS,
+    vm::{Vm, VmConfig, VmState},
+};
+
+// the bindings of a line are kept for the next ones, `warn_unused` warns
+// about the bindings a line makes in functions and doesn't use
+//
+// output is colored when it goes to a terminal and NO_COLOR isn't set, lines
+// run on the engine and under the limits of `opts` until :engine and :limit
+// change them
+pub fn start(opts: &Options) {
+    let stdin = std::io::stdin();
+    let check = (!opts.allow_unused).then(|| LintConfig {
+        unused_globals: false,
+        ..Default::default()
+    });
+    let color = std::io::stdout().is_terminal() && std::env::var_os("NO_COLOR").is_none();
+    let mut session = Session::new(check, color);
+    session.engine = opts.engine;
+    session.sandbox = opts.sandbox;
+    session.fold = opts.fold;
+    {
+        let interrupt = session.interrupt.clone();
+        let evaluating = Arc::clone(&session.evaluating);
+        // with nothing to cancel ctrl-c quits like it would without this
+        let handler = ctrlc::set_handler(move || {
+            if evaluating.load(Ordering::Relaxed) {
+                interrupt.cancel();
+            } else {
+                std::process::exit(130);
+            }
+        });
+        if let Err(err) = handler {
+            eprintln!("ctrl-c won't cancel evaluations: {}", err);
+        }
+    }
+    println!("Try out the RPPL - (Read-parse-print-loop)\n>>");
+    // lines typed on a terminal can be edited and completed with tab, piped
+    // ones are read as they are
+    let mut editor = if stdin.is_terminal() {
+        Editor::<Completions, DefaultHistory>::new().ok()
+    } else {
+        None
+    };
     loop {
-        println!("Try out the RPPL - (Read-parse-print-loop)\n>>");
-        for line in std::io::stdin().lines() {
-            match line {
-                Ok(line) => {
-                    let l = lexer::Lexer::new(line.as_bytes());
-                    let mut p = Parser::new(l);
-                    let mut env = Env::new();
-                    match p.parse() {
-                        Ok(prog) => {
-                            match eval_program(prog, &mut env) {
-                                Ok(value) => println!("{}", value),
-                                Err(err) => println!("Error during eval: {}", err)
-                            }
-                            
-                       }
-                        Err(errors) => {
-                            println!("failed to parse input. Errors:\n {:?}", errors);
-                        }
+        let line = match &mut editor {
+            Some(editor) => {
+                // the env is replaced by :reset
+                editor.set_helper(Some(Completions(session.env.clone())));
+                match editor.readline(">> ") {
+                    Ok(line) => {
+                        let _ = editor.add_history_entry(line.as_str());
+                        line
+                    }
+                    // ctrl-c and ctrl-d quit
+                    Err(_) => return,
+                }
+            }
+            None => {
+                let mut line = String::new();
+                match stdin.lock().read_line(&mut line) {
+                    Ok(0) => return,
+                    Ok(_) => line,
+                    Err(_) => panic!("unknown error"),
+                }
+            }
+        };
+        if let Some(command) = line.trim().strip_prefix(':') {
+            if !session.command(command, &mut stdin.lock()) {
+                return;
+            }
+            continue;
+        }
+        session.eval(line, session.timing);
+    }
+}
+
+// completes the word before the cursor from the bindings of the env
+struct Completions(Env<'static>);
+
+impl Completer for Completions {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        Ok(complete(line, pos, &self.0))
+    }
+}
+
+impl Hinter for Completions {
+    type Hint = String;
+}
+
+impl Highlighter for Completions {}
+
+impl Validator for Completions {}
+
+impl Helper for Completions {}
+
+// what the word before `pos` can be completed to, with where it starts: the
+// commands after a leading `:`, otherwise bindings, keywords and builtins
+fn complete(line: &str, pos: usize, env: &Env) -> (usize, Vec<String>) {
+    let before = &line[..pos];
+    let start = before
+        .char_indices()
+        .rev()
+        .find(|(_, c)| !(c.is_alphanumeric() || *c == '_'))
+        .map_or(0, |(i, c)| i + c.len_utf8());
+    let word = &before[start..];
+    let mut candidates: Vec<String> = if &before[..start] == ":" {
+        // the first word of every line of the help
+        HELP.lines()
+            .filter_map(|line| line[1..].split_whitespace().next())
+            .map(str::to_string)
+            .collect()
+    } else {
+        let names = env
+            .bindings()
+            .map(|(name, _)| String::from_utf8_lossy(&name).into_owned());
+        let builtins = BUILTINS.iter().map(|builtin| builtin.name);
+        names
+            .chain(
+                KEYWORDS
+                    .iter()
+                    .copied()
+                    .chain(builtins)
+                    .map(|name| name.to_string()),
+            )
+            .collect()
+    };
+    candidates.retain(|candidate| candidate.starts_with(word));
+    candidates.sort();
+    candidates.dedup();
+    (start, candidates)
+}
+
+// what the repl keeps from one line to the next
+struct Session {
+    engine: Engine,
+    // the bindings of each engine, they don't see each other's
+    env: Env<'static>,
+    vm: VmState,
+    // how unused bindings are warned about, None when they aren't
+    check: Option<LintConfig>,
+    color: bool,
+    // whether every input is followed by how long it took
+    timing: bool,
+    // the limits every input runs under
+    sandbox: SandboxConfig,
+    // whether constant expressions are folded before running
+    fold: bool,
+    // cancelled by ctrl-c while `evaluating` is set
+    interrupt: CancellationToken,
+    evaluating: Arc<AtomicBool>,
+}
+
+const HELP: &str = "\
+:help             lists these commands
+:quit             exits
+:env              shows every binding
+:reset            drops every binding
+:load <file>      runs <file>, keeping its bindings
+:engine [<name>]  shows or switches what runs the input, `eval` or `vm`
+:time [<expr>]    times <expr>, or every input until it's used again
+:limit [<l> <n>]  shows the limits, or sets `steps` or `timeout` (ms) to <n> or `off`
+:ast <expr>       shows what <expr> parses to
+:tokens <expr>    shows the tokens of <expr>
+:debug <expr>     steps through <expr> compiled for the vm
+";
+
+impl Session {
+    fn new(check: Option<LintConfig>, color: bool) -> Self {
+        Session {
+            engine: Engine::default(),
+            env: Env::new(),
+            vm: VmState::default(),
+            check,
+            color,
+            timing: false,
+            sandbox: SandboxConfig::default(),
+            fold: false,
+            interrupt: CancellationToken::new(),
+            evaluating: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    fn eval(&mut self, line: String, timed: bool) {
+        let mut prog = match Parser::new(lexer::Lexer::new(line.as_bytes())).parse() {
+            Ok(prog) => prog,
+            Err(errors) => {
+                for err in errors {
+                    eprintln!("{}", show(&err.diagnostic(), &line, self.color));
+                }
+                return;
+            }
+        };
+        if let Some(config) = &self.check {
+            for warning in lint::unused(&prog, config) {
+                eprintln!("{}", show(&warning.to_diagnostic(), &line, self.color));
+            }
+        }
+        let start = Instant::now();
+        let (result, steps) = self.run(&mut prog);
+        let time = start.elapsed();
+        match result {
+            Ok(value) => {
+                println!("{}", show_value(&value, self.color));
+                // `_` is the last value, statements like `let` don't change it
+                if !matches!(value, Object::Null) {
+                    match self.engine {
+                        Engine::Eval => self.env.set(b"_", value),
+                        Engine::Vm => self.vm.set(b"_", value),
                     }
                 }
-                Err(_) => panic!("unknown error"),
             }
+            Err(err) => eprintln!("{}", show(&err, &line, self.color)),
+        }
+        if timed {
+            println!("{}", show_time(time, steps));
+        }
+    }
+
+    // runs `prog` on the engine with ctrl-c cancelling it instead of
+    // quitting, with the steps it took
+    fn run(&mut self, prog: &mut Program) -> (Result<Object<'static>, Diagnostic>, u64) {
+        if self.fold {
+            // the repl only warns about unused bindings, not about the
+            // statements this drops
+            fold::fold_program(prog);
         }
+        self.interrupt.reset();
+        self.evaluating.store(true, Ordering::Relaxed);
+        let (result, steps) = match self.engine {
+            Engine::Eval => {
+                let mut evaluator =
+                    Evaluator::new(self.sandbox).with_cancellation(self.interrupt.clone());
+                let result = evaluator.eval_program(prog, &mut self.env);
+                (result.map_err(|err| err.diagnostic()), evaluator.steps())
+            }
+            Engine::Vm => {
+                let config = VmConfig {
+                    sandbox: self.sandbox,
+                    ..Default::default()
+                };
+                self.vm.run(prog, config, &self.interrupt)
+            }
+        };
+        self.evaluating.store(false, Ordering::Relaxed);
+        self.env.heap().collect();
+        (result, steps)
+    }
+
+    // a line starting with `:`, without it, false when it asks to quit
+    fn command<R: BufRead>(&mut self, command: &str, input: &mut R) -> bool {
+        let (name, arg) = command
+            .split_once(char::is_whitespace)
+            .unwrap_or((command, ""));
+        match name {
+            "help" => print!("{}", HELP),
+            "quit" => return false,
+            "env" => match self.engine {
+                Engine::Eval => print!("{}", show_env(self.env.bindings())),
+                Engine::Vm => print!("{}", show_env(self.vm.bindings())),
+            },
+            // builtins aren't bindings, so they're still there after this
+            "reset" => {
+                let heap = self.env.heap();
+                self.env = Env::new();
+                heap.collect();
+                self.vm = VmState::default();
+            }
+            "load" => {
+                let color = self.color;
+                print!("{}", load(arg, color, |prog| self.run(prog).0));
+            }
+            "engine" if arg.is_empty() => println!("engine is {}", self.engine.as_str()),
+            "engine" => match Engine::from_name(arg) {
+                Some(engine) => {
+                    self.engine = engine;
+                    println!("engine is {}", engine.as_str());
+                }
+                None => println!("unknown engine {}, it's `eval` or `vm`", arg),
+            },
+            "time" if arg.is_empty() => {
+                self.timing = !self.timing;
+                let state = if self.timing { "on" } else { "off" };
+                println!("timing is {}", state);
+            }
+            "time" => self.eval(arg.to_string(), true),
+            "limit" => println!("{}", self.limit(arg)),
+            "ast" => print!("{}", show_ast(arg, self.color)),
+            "tokens" => print!("{}", cli::dump_tokens(arg)),
+            "debug" => debug(arg, input, self.color),
+            _ => println!("unknown command :{}, :help lists the commands", name),
+        }
+        true
+    }
+
+    // `steps <n>` or `timeout <ms>`, `off` drops the limit, the limits
+    // afterwards either way
+    fn limit(&mut self, arg: &str) -> String {
+        let words: Vec<_> = arg.split_whitespace().collect();
+        match words[..] {
+            [] => {}
+            [limit @ ("steps" | "timeout"), value] => {
+                let value = if value == "off" {
+                    None
+                } else {
+                    match value.parse() {
+                        Ok(value) => Some(value),
+                        Err(_) => return format!("invalid value {} for {}", value, limit),
+                    }
+                };
+                if limit == "steps" {
+                    self.sandbox.max_steps = value;
+                } else {
+                    self.sandbox.timeout = value.map(Duration::from_millis);
+                }
+            }
+            _ => return "usage: :limit [steps|timeout <n>|off]".to_string(),
+        }
+        let steps = self.sandbox.max_steps.map(|steps| steps.to_string());
+        let timeout = self.sandbox.timeout.map(|t| format!("{}ms", t.as_millis()));
+        format!(
+            "steps {}, timeout {}",
+            steps.as_deref().unwrap_or("off"),
+            timeout.as_deref().unwrap_or("off")
+        )
+    }
+}
+
+// `took 1.250ms, 42 steps`
+fn show_time(time: Duration, steps: u64) -> String {
+    format!(
+        "took {:.3}ms, {} step{}",
+        time.as_secs_f64() * 1000.0,
+        steps,
+        if steps == 1 { "" } else { "s" }
+    )
+}
+
+// the file is parsed and given to `run`, the errors are shown with where in
+// the file they are
+fn load(
+    path: &str,
+    color: bool,
+    run: impl FnOnce(&mut Program) -> Result<Object<'static>, Diagnostic>,
+) -> String {
+    let source = match std::fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(err) => return format!("error: failed to read {}: {}\n", path, err),
+    };
+    let in_file = |diagnostic: &Diagnostic| match diagnostic.span.filter(|span| span.line > 0) {
+        Some(span) => format!("{}:{}: {}\n", path, span, show(diagnostic, &source, color)),
+        None => format!("{}: {}\n", path, show(diagnostic, &source, color)),
+    };
+    let mut prog = match Parser::new(lexer::Lexer::new(source.as_bytes())).parse() {
+        Ok(prog) => prog,
+        Err(errors) => {
+            return errors
+                .iter()
+                .map(|err| in_file(&err.diagnostic()))
+                .collect()
+        }
+    };
+    match run(&mut prog) {
+        Ok(Object::Null) => String::new(),
+        Ok(value) => format!("{}\n", show_value(&value, color)),
+        Err(err) => in_file(&err),
+    }
+}
+
+fn show_ast(source: &str, color: bool) -> String {
+    match Parser::new(lexer::Lexer::new(source.as_bytes())).parse() {
+        Ok(prog) => format!("{:#?}\n", prog.stmts),
+        Err(errors) => errors
+            .iter()
+            .map(|err| format!("{}\n", show(&err.diagnostic(), source, color)))
+            .collect(),
+    }
+}
+
+// `name = value` for every binding
+fn show_env<'a, N: AsRef<[u8]>>(bindings: impl Iterator<Item = (N, Object<'a>)>) -> String {
+    let lines: String = bindings
+        .map(|(name, value)| format!("{} = {}\n", String::from_utf8_lossy(name.as_ref()), value))
+        .collect();
+    if lines.is_empty() {
+        return "no bindings\n".to_string();
+    }
+    lines
+}
+
+// `error: message` and the line of the input it's about
+fn show(diagnostic: &Diagnostic, source: &str, color: bool) -> String {
+    if color {
+        format!(
+            "{}: {}",
+            diagnostic.colored_severity(),
+            diagnostic.render_colored(source)
+        )
+    } else {
+        format!(
+            "{}: {}",
+            diagnostic.severity.as_str(),
+            diagnostic.render(source)
+        )
+    }
+}
+
+fn show_value(value: &Object, color: bool) -> String {
+    if color {
+        diagnostic::paint(&value.to_string(), diagnostic::GREEN)
+    } else {
+        value.to_string()
+    }
+}
+
+// compiles `source` and single-steps it on the vm, commands are read from
+// the same input as the repl
+fn debug<R: BufRead>(source: &str, input: &mut R, color: bool) {
+    let prog = match Parser::new(lexer::Lexer::new(source.as_bytes())).parse() {
+        Ok(prog) => prog,
+        Err(errors) => {
+            for err in errors {
+                println!("{}", show(&err.diagnostic(), source, color));
+            }
+            return;
+        }
+    };
+    let mut compiler = Compiler::new();
+    if let Err(err) = compiler.compile(&prog) {
+        println!("Error during compilation: {}", err);
+        return;
+    }
+    let mut vm = Vm::new(compiler.bytecode());
+    if let Err(err) = debugger::debug(&mut vm, input, &mut std::io::stdout()) {
+        println!("Error during debugging: {}", err);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::{
+        cli,
+        engine::Engine,
+        env::Env,
+        evaluator::{eval_program, Evaluator},
+        lexer::Lexer,
+        parser::Parser,
+        sandbox::{CancellationToken, SandboxConfig},
+    };
+
+    use super::{complete, load, show_ast, show_env, show_time, Session};
+
+    #[test]
+    fn test_show_env() {
+        let mut env = Env::new();
+        assert_eq!(show_env(env.bindings()), "no bindings\n");
+        let source = "let b = [1, \"two\"]; let a = fn(x) { x };";
+        let prog = Parser::new(Lexer::new(source.as_bytes())).parse().unwrap();
+        eval_program(&prog, &mut env).unwrap();
+        assert_eq!(show_env(env.bindings()), "a = fn(x) { x; }\nb = [1, two]\n");
+    }
+
+    #[test]
+    fn test_show_source() {
+        assert_eq!(
+            cli::dump_tokens("let x = 1;"),
+            "1:1 LET let\n1:5 IDENT x\n1:7 ASSIGN =\n1:9 INT 1\n1:10 SEMICOLON ;\n"
+        );
+        assert!(show_ast("1 + 2", false).starts_with("[\n    Expr {\n"));
+        assert!(show_ast("let = 1", false).starts_with("error: "));
+    }
+
+    #[test]
+    fn test_load() {
+        let dir = std::env::temp_dir().join(format!("monkey-load-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let lib = dir.join("lib.mk");
+        std::fs::write(&lib, "let double = fn(x) { x * 2 };\ndouble(1)\n").unwrap();
+        let broken = dir.join("broken.mk");
+        std::fs::write(&broken, "let a = 1;\nlet b = a + true;\n").unwrap();
+
+        let mut session = Session::new(None, false);
+        let mut run = |path: &str| load(path, false, |prog| session.run(prog).0);
+        assert_eq!(run(lib.to_str().unwrap()), "2\n");
+        let path = broken.to_str().unwrap();
+        assert_eq!(
+            run(path),
+            format!(
+                "{}:2:11: error: operand can only be applied to numbers\n    let b = a + true;\n              ^\n",
+                path
+            )
+        );
+        assert!(run(dir.join("missing.mk").to_str().unwrap()).starts_with("error: failed to read "));
+        assert_eq!(
+            session.env.get(b"double").unwrap().to_string(),
+            "fn(x) { (x * 2); }"
+        );
+        // what ran before the error is kept
+        assert!(session.env.contains(b"a"));
+
+        // the vm places its errors at the statement they happened in
+        session.engine = Engine::Vm;
+        let mut run = |path: &str| load(path, false, |prog| session.run(prog).0);
+        assert_eq!(run(lib.to_str().unwrap()), "2\n");
+        assert_eq!(
+            run(path),
+            format!(
+                "{}:2:1: error: operand can only be applied to numbers\n    let b = a + true;\n    ^^^\n",
+                path
+            )
+        );
+
+        // what ctrl-c does
+        let token = CancellationToken::new();
+        token.cancel();
+        let mut evaluator = Evaluator::new(SandboxConfig::default()).with_cancellation(token);
+        let mut env = Env::new();
+        assert_eq!(
+            load(lib.to_str().unwrap(), false, |prog| evaluator
+                .eval_program(prog, &mut env)
+                .map_err(|err| err.diagnostic())),
+            format!("{}: error: evaluation cancelled\n", lib.to_str().unwrap())
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_show_time() {
+        assert_eq!(
+            show_time(Duration::from_micros(1250), 42),
+            "took 1.250ms, 42 steps"
+        );
+        assert_eq!(show_time(Duration::ZERO, 1), "took 0.000ms, 1 step");
+    }
+
+    #[test]
+    fn test_complete() {
+        let mut env = Env::new();
+        let prog = Parser::new(Lexer::new("let length = 1; let lets = 2;".as_bytes()))
+            .parse()
+            .unwrap();
+        eval_program(&prog, &mut env).unwrap();
+
+        assert_eq!(
+            complete("le", 2, &env),
+            (
+                0,
+                vec!["len".into(), "length".into(), "let".into(), "lets".into()]
+            )
+        );
+        assert_eq!(complete("1 + lengt", 9, &env), (4, vec!["length".into()]));
+        assert_eq!(
+            complete("puts(tr) + x", 7, &env),
+            (5, vec!["true".into(), "try".into()])
+        );
+        assert_eq!(
+            complete(":t", 2, &env),
+            (1, vec!["time".into(), "tokens".into()])
+        );
+        assert_eq!(complete(":", 1, &env).1.len(), 11);
+        assert!(complete("zz", 2, &env).1.is_empty());
+    }
+
+    #[test]
+    fn test_last_value() {
+        let mut session = Session::new(None, false);
+        session.eval("1 + 2".to_string(), false);
+        session.eval("let a = _ * 2;".to_string(), false);
+        assert_eq!(session.env.get(b"_").unwrap().to_string(), "3");
+        session.eval("_ + a".to_string(), false);
+        assert_eq!(session.env.get(b"_").unwrap().to_string(), "9");
+        // errors leave it alone too
+        session.eval("_ + true".to_string(), false);
+        assert_eq!(session.env.get(b"_").unwrap().to_string(), "9");
+    }
+
+    #[test]
+    fn test_limit() {
+        let mut session = Session::new(None, false);
+        assert_eq!(session.limit(""), "steps off, timeout off");
+        assert_eq!(session.limit("steps 100"), "steps 100, timeout off");
+        assert_eq!(session.limit("timeout 50"), "steps 100, timeout 50ms");
+        assert_eq!(session.limit("steps lots"), "invalid value lots for steps");
+        assert!(session.limit("memory 1").starts_with("usage: "));
+
+        let looping = "let f = fn(n) { f(n + 1) }; f(0)";
+        let mut prog = Parser::new(Lexer::new(looping.as_bytes())).parse().unwrap();
+        let (result, _) = session.run(&mut prog);
+        assert_eq!(result.unwrap_err().message, "step limit exceeded");
+        session.engine = Engine::Vm;
+        let (result, steps) = session.run(&mut prog);
+        assert_eq!(result.unwrap_err().message, "step limit exceeded");
+        assert_eq!(steps, 101);
+
+        assert_eq!(session.limit("steps off"), "steps off, timeout 50ms");
+    }
+
+    #[test]
+    fn test_engine() {
+        let mut session = Session::new(None, false);
+        session.eval("let a = 1;".to_string(), false);
+        assert!(session.command("engine vm", &mut "".as_bytes()));
+        assert_eq!(session.engine, Engine::Vm);
+        // the bindings made on the evaluator stay there
+        session.eval("a".to_string(), false);
+        session.eval("let add = fn(x, y) { x + y };".to_string(), false);
+        session.eval("add(20, 1)".to_string(), false);
+        session.eval("let b = _ * 2;".to_string(), false);
+        // compiling errors don't define anything
+        session.eval("let c = 1; break;".to_string(), false);
+        assert_eq!(
+            show_env(session.vm.bindings()),
+            "_ = 21\nadd = <closure/2>\nb = 42\n"
+        );
+        assert_eq!(show_env(session.env.bindings()), "a = 1\n");
+
+        assert!(session.command("engine jit", &mut "".as_bytes()));
+        assert_eq!(session.engine, Engine::Vm);
+        assert!(session.command("reset", &mut "".as_bytes()));
+        assert_eq!(show_env(session.vm.bindings()), "no bindings\n");
     }
 }
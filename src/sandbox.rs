@@ -0,0 +1,434 @@
+use std::{
+    mem::size_of,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::{
+    object::{Closure, Function, HashKey, Object},
+    rc::Rc,
+    tasks::{Channel, Task},
+};
+
+// the deadline and cancellation are only polled every so many steps,
+// reading the clock on every node would dominate evaluation time
+const DEADLINE_CHECK_INTERVAL: u64 = 1024;
+
+// the default call depth, the vm's frame limit, the evaluator grows its stack
+// to reach it
+const MAX_CALL_DEPTH: usize = 1024;
+
+// what a builtin needs from the outside world to run
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Capability {
+    Io,
+    Time,
+    Random,
+    Concurrency,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capabilities {
+    pub io: bool,
+    pub time: bool,
+    pub random: bool,
+    pub concurrency: bool,
+}
+
+impl Capabilities {
+    pub const ALL: Capabilities = Capabilities {
+        io: true,
+        time: true,
+        random: true,
+        concurrency: true,
+    };
+    pub const NONE: Capabilities = Capabilities {
+        io: false,
+        time: false,
+        random: false,
+        concurrency: false,
+    };
+
+    pub fn allows(&self, capability: Capability) -> bool {
+        match capability {
+            Capability::Io => self.io,
+            Capability::Time => self.time,
+            Capability::Random => self.random,
+            Capability::Concurrency => self.concurrency,
+        }
+    }
+}
+
+impl Default for Capabilities {
+    fn default() -> Self {
+        Capabilities::ALL
+    }
+}
+
+// every resource control shared by the evaluator and the vm, the default
+// only limits how deep calls nest, as deep as the vm's frames go, and allows
+// every builtin
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SandboxConfig {
+    pub max_steps: Option<u64>,
+    pub max_call_depth: Option<usize>,
+    pub max_allocations: Option<u64>,
+    pub max_bytes: Option<usize>,
+    pub capabilities: Capabilities,
+    pub timeout: Option<Duration>,
+}
+
+impl Default for SandboxConfig {
+    fn default() -> Self {
+        SandboxConfig {
+            max_steps: None,
+            max_call_depth: Some(MAX_CALL_DEPTH),
+            max_allocations: None,
+            max_bytes: None,
+            capabilities: Capabilities::ALL,
+            timeout: None,
+        }
+    }
+}
+
+// lets another thread stop an evaluation, clones share the same flag
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        CancellationToken::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    // lets the token be used for another evaluation
+    pub fn reset(&self) {
+        self.0.store(false, Ordering::Relaxed);
+    }
+}
+
+// tracks a single run against a sandbox config
+pub(crate) struct Meter {
+    pub config: SandboxConfig,
+    pub cancel: Option<CancellationToken>,
+    steps: u64,
+    allocations: u64,
+    bytes: usize,
+    deadline: Option<Instant>,
+}
+
+impl Meter {
+    pub fn new(config: SandboxConfig) -> Self {
+        Meter {
+            config,
+            cancel: None,
+            steps: 0,
+            allocations: 0,
+            bytes: 0,
+            deadline: None,
+        }
+    }
+
+    pub fn start(&mut self) -> Result<(), &'static str> {
+        self.steps = 0;
+        self.allocations = 0;
+        self.bytes = 0;
+        self.deadline = self.config.timeout.map(|t| Instant::now() + t);
+        if self.cancelled() {
+            return Err("evaluation cancelled");
+        }
+        Ok(())
+    }
+
+    // unlike the deadline this is only an atomic load, so loops check it on
+    // every iteration
+    pub fn check_cancelled(&self) -> Result<(), &'static str> {
+        if self.cancelled() {
+            return Err("evaluation cancelled");
+        }
+        Ok(())
+    }
+
+    fn cancelled(&self) -> bool {
+        self.cancel
+            .as_ref()
+            .is_some_and(|token| token.is_cancelled())
+    }
+
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    #[inline]
+    pub fn tick(&mut self) -> Result<(), &'static str> {
+        self.steps += 1;
+        if self.config.max_steps.is_some_and(|max| self.steps > max) {
+            return Err("step limit exceeded");
+        }
+        if !self.steps.is_multiple_of(DEADLINE_CHECK_INTERVAL) {
+            return Ok(());
+        }
+        if self
+            .deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+        {
+            return Err("timeout exceeded");
+        }
+        if self.cancelled() {
+            return Err("evaluation cancelled");
+        }
+        Ok(())
+    }
+
+    // true when nothing but the call depth is limited, code that can't be
+    // interrupted, like jit compiled functions, may only run then
+    #[cfg(feature = "jit")]
+    pub fn is_unmetered(&self) -> bool {
+        self.config.max_steps.is_none()
+            && self.config.timeout.is_none()
+            && self.config.max_allocations.is_none()
+            && self.config.max_bytes.is_none()
+            && self.cancel.is_none()
+    }
+
+    pub fn check_depth(&self, depth: usize) -> Result<(), &'static str> {
+        if self.config.max_call_depth.is_some_and(|max| depth >= max) {
+            return Err("call depth limit exceeded");
+        }
+        Ok(())
+    }
+
+    pub fn check_capability(&self, capability: Option<Capability>) -> Result<(), &'static str> {
+        match capability {
+            Some(capability) if !self.config.capabilities.allows(capability) => {
+                Err("builtin not allowed in sandbox")
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // charges a value that was just created, values that are shared already
+    // were paid for when they were created
+    pub fn allocate(&mut self, obj: &Object) -> Result<(), &'static str> {
+        let Some(size) = fresh_allocation_size(obj) else {
+            return Ok(());
+        };
+        self.allocations += 1;
+        self.bytes += size;
+        if self
+            .config
+            .max_allocations
+            .is_some_and(|max| self.allocations > max)
+        {
+            return Err("allocation limit exceeded");
+        }
+        if self.config.max_bytes.is_some_and(|max| self.bytes > max) {
+            return Err("memory limit exceeded");
+        }
+        Ok(())
+    }
+}
+
+fn fresh_allocation_size(obj: &Object) -> Option<usize> {
+    match obj {
+        Object::Array(elements) if Rc::strong_count(elements) == 1 => {
+            Some(size_of::<Vec<Object>>() + elements.len() * size_of::<Object>())
+        }
+        Object::Str(s) | Object::Error(s) if Rc::strong_count(s) == 1 => Some(s.len()),
+        Object::Hash(pairs) if Rc::strong_count(pairs) == 1 => {
+            Some(pairs.len() * (size_of::<HashKey>() + size_of::<Object>()))
+        }
+        Object::Function(function) if Rc::strong_count(function) == 1 => {
+            Some(size_of::<Function>())
+        }
+        Object::Closure(closure) if Rc::strong_count(closure) == 1 => {
+            Some(size_of::<Closure>() + closure.free.len() * size_of::<Object>())
+        }
+        Object::Channel(channel) if Rc::strong_count(channel) == 1 => Some(size_of::<Channel>()),
+        Object::Task(task) if Rc::strong_count(task) == 1 => Some(size_of::<Task>()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::{
+        compiler::Compiler,
+        env::Env,
+        evaluator::Evaluator,
+        lexer::Lexer,
+        parser::Parser,
+        vm::{Vm, VmConfig},
+    };
+
+    use super::{CancellationToken, Capabilities, SandboxConfig, MAX_CALL_DEPTH};
+
+    // runs input on both engines, which have to agree on the outcome
+    fn run(input: &str, config: SandboxConfig) -> Result<String, String> {
+        let prog = Parser::new(Lexer::new(input.as_bytes())).parse().unwrap();
+        let evaluated = Evaluator::new(config)
+            .eval_program(&prog, &mut Env::new())
+            .map(|obj| obj.to_string())
+            .map_err(|err| err.to_string());
+
+        let mut compiler = Compiler::new();
+        compiler.compile(&prog).unwrap();
+        let vm_config = VmConfig {
+            sandbox: config,
+            ..Default::default()
+        };
+        let run = Vm::with_config(compiler.bytecode(), vm_config)
+            .run()
+            .map(|obj| obj.to_string());
+
+        assert_eq!(evaluated, run, "{}", input);
+        evaluated
+    }
+
+    const FIB: &str =
+        "let fib = fn(n) { if (n < 2) { n } else { fib(n - 1) + fib(n - 2) } }; fib(15);";
+
+    #[test]
+    fn test_defaults() {
+        let config = SandboxConfig::default();
+        assert_eq!(config.max_steps, None);
+        assert_eq!(config.max_call_depth, Some(MAX_CALL_DEPTH));
+        assert_eq!(config.max_allocations, None);
+        assert_eq!(config.max_bytes, None);
+        assert_eq!(config.capabilities, Capabilities::ALL);
+        assert_eq!(config.timeout, None);
+        assert_eq!(run(FIB, config).unwrap(), "610");
+    }
+
+    #[test]
+    fn test_max_steps() {
+        let config = SandboxConfig {
+            max_steps: Some(100),
+            ..Default::default()
+        };
+        assert_eq!(run(FIB, config).unwrap_err(), "step limit exceeded");
+        assert_eq!(run("1 + 2", config).unwrap(), "3");
+    }
+
+    #[test]
+    fn test_max_call_depth() {
+        let countdown = "let f = fn(n) { if (n == 0) { 0 } else { 1 + f(n - 1) } };";
+        let config = SandboxConfig {
+            max_call_depth: Some(10),
+            ..Default::default()
+        };
+        assert_eq!(run(&format!("{} f(9)", countdown), config).unwrap(), "9");
+        assert_eq!(
+            run(&format!("{} f(10)", countdown), config).unwrap_err(),
+            "call depth limit exceeded"
+        );
+    }
+
+    #[test]
+    fn test_max_allocations() {
+        let config = SandboxConfig {
+            max_allocations: Some(3),
+            ..Default::default()
+        };
+        assert_eq!(run("[[1], [2]]", config).unwrap(), "[[1], [2]]");
+        assert_eq!(
+            run("[[1], [2], [3]]", config).unwrap_err(),
+            "allocation limit exceeded"
+        );
+        // builtins returning a value that already exists don't allocate
+        let input = "let a = [[1]]; first(a); first(a); first(a)";
+        assert_eq!(run(input, config).unwrap(), "[1]");
+        let input = "let a = push([], 1); let b = push(a, 2); push(b, 3)";
+        assert_eq!(run(input, config).unwrap_err(), "allocation limit exceeded");
+    }
+
+    #[test]
+    fn test_max_bytes() {
+        let config = SandboxConfig {
+            max_bytes: Some(1024),
+            ..Default::default()
+        };
+        assert_eq!(run("[1, 2, 3]", config).unwrap(), "[1, 2, 3]");
+        let grow = "let grow = fn(a, n) { if (n == 0) { a } else { grow(push(a, n), n - 1) } };";
+        assert_eq!(
+            run(&format!("{} len(grow([], 100))", grow), config).unwrap_err(),
+            "memory limit exceeded"
+        );
+    }
+
+    #[test]
+    fn test_capabilities() {
+        let config = SandboxConfig {
+            capabilities: Capabilities::NONE,
+            ..Default::default()
+        };
+        assert_eq!(
+            run("puts(1)", config).unwrap_err(),
+            "builtin not allowed in sandbox"
+        );
+        assert_eq!(run("len([1, 2])", config).unwrap(), "2");
+        assert_eq!(
+            run("spawn(fn() { 1 })", config).unwrap_err(),
+            "builtin not allowed in sandbox"
+        );
+
+        let config = SandboxConfig {
+            capabilities: Capabilities {
+                io: true,
+                ..Capabilities::NONE
+            },
+            ..Default::default()
+        };
+        assert_eq!(run("puts()", config).unwrap(), "nil");
+        assert_eq!(
+            run("recv(channel())", config).unwrap_err(),
+            "builtin not allowed in sandbox"
+        );
+
+        let config = SandboxConfig {
+            capabilities: Capabilities {
+                concurrency: true,
+                ..Capabilities::NONE
+            },
+            ..Default::default()
+        };
+        assert_eq!(run("join(spawn(fn() { 1 }))", config).unwrap(), "1");
+    }
+
+    #[test]
+    fn test_timeout() {
+        let config = SandboxConfig {
+            timeout: Some(Duration::ZERO),
+            ..Default::default()
+        };
+        assert_eq!(run(FIB, config).unwrap_err(), "timeout exceeded");
+
+        let config = SandboxConfig {
+            timeout: Some(Duration::from_secs(60)),
+            ..Default::default()
+        };
+        assert_eq!(run(FIB, config).unwrap(), "610");
+    }
+
+    #[test]
+    fn test_cancellation_reset() {
+        let token = CancellationToken::new();
+        let clone = token.clone();
+        clone.cancel();
+        assert!(token.is_cancelled());
+        token.reset();
+        assert!(!clone.is_cancelled());
+    }
+}
@@ -0,0 +1,850 @@
+use core::{fmt, marker::PhantomData};
+use std::collections::BTreeMap;
+
+use ::serde::{
+    de::{
+        self,
+        value::{MapDeserializer, SeqDeserializer, StrDeserializer},
+        DeserializeOwned, DeserializeSeed, IntoDeserializer, MapAccess, SeqAccess, Visitor,
+    },
+    forward_to_deserialize_any,
+    ser::{self, Serialize, SerializeMap, SerializeSeq},
+    Deserialize, Deserializer,
+};
+
+use crate::{
+    object::{HashKey, Object},
+    rc::Rc,
+};
+
+/// Error converting between Rust values and Monkey objects.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Error(String);
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl ser::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Error(msg.to_string())
+    }
+}
+
+impl de::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Error(msg.to_string())
+    }
+}
+
+/// Converts a serializable Rust value into a Monkey object.
+///
+/// Structs and maps become hashes, sequences and tuples become arrays,
+/// `None` and `()` become `nil`. Unit enum variants become their name as a
+/// string, other variants a hash with the name as its only key.
+///
+/// ```
+/// use interpreter_book_in_rust::{serde::to_object, Interpreter};
+///
+/// let mut interp = Interpreter::new();
+/// interp.set_global("limits", to_object(&[10, 20]).unwrap());
+/// assert_eq!(interp.eval("last(limits)").unwrap().to_string(), "20");
+/// ```
+pub fn to_object<'a, T: Serialize + ?Sized>(value: &T) -> Result<Object<'a>, Error> {
+    value.serialize(Serializer(PhantomData))
+}
+
+/// Converts a Monkey object back into a Rust value, using the same mapping
+/// as [`to_object`].
+pub fn from_object<T: DeserializeOwned>(obj: &Object) -> Result<T, Error> {
+    T::deserialize(obj)
+}
+
+fn variant<'a>(name: &'static str, value: Object<'a>) -> Object<'a> {
+    let pairs = BTreeMap::from([(HashKey::Str(name.into()), value)]);
+    Object::Hash(Rc::new(pairs))
+}
+
+struct Serializer<'a>(PhantomData<Object<'a>>);
+
+impl<'a> ser::Serializer for Serializer<'a> {
+    type Ok = Object<'a>;
+    type Error = Error;
+    type SerializeSeq = SerializeArray<'a>;
+    type SerializeTuple = SerializeArray<'a>;
+    type SerializeTupleStruct = SerializeArray<'a>;
+    type SerializeTupleVariant = SerializeArray<'a>;
+    type SerializeMap = SerializeHash<'a>;
+    type SerializeStruct = SerializeHash<'a>;
+    type SerializeStructVariant = SerializeHash<'a>;
+
+    fn serialize_bool(self, v: bool) -> Result<Object<'a>, Error> {
+        Ok(Object::Bool(v))
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<Object<'a>, Error> {
+        self.serialize_i64(v.into())
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<Object<'a>, Error> {
+        self.serialize_i64(v.into())
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<Object<'a>, Error> {
+        self.serialize_i64(v.into())
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<Object<'a>, Error> {
+        Ok(Object::Integer(v))
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<Object<'a>, Error> {
+        self.serialize_i64(v.into())
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<Object<'a>, Error> {
+        self.serialize_i64(v.into())
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<Object<'a>, Error> {
+        self.serialize_i64(v.into())
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<Object<'a>, Error> {
+        i64::try_from(v)
+            .map(Object::Integer)
+            .map_err(|_| Error(format!("integer {} out of range", v)))
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<Object<'a>, Error> {
+        self.serialize_f64(v.into())
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<Object<'a>, Error> {
+        Ok(Object::Float(v))
+    }
+
+    fn serialize_char(self, v: char) -> Result<Object<'a>, Error> {
+        Ok(Object::Str(v.to_string().into()))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<Object<'a>, Error> {
+        Ok(Object::Str(v.into()))
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<Object<'a>, Error> {
+        v.serialize(self)
+    }
+
+    fn serialize_none(self) -> Result<Object<'a>, Error> {
+        Ok(Object::Null)
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<Object<'a>, Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Object<'a>, Error> {
+        Ok(Object::Null)
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Object<'a>, Error> {
+        Ok(Object::Null)
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+    ) -> Result<Object<'a>, Error> {
+        Ok(Object::Str(variant.into()))
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<Object<'a>, Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        name: &'static str,
+        value: &T,
+    ) -> Result<Object<'a>, Error> {
+        Ok(variant(name, value.serialize(self)?))
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<SerializeArray<'a>, Error> {
+        Ok(SerializeArray {
+            elements: Vec::with_capacity(len.unwrap_or(0)),
+            variant: None,
+        })
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<SerializeArray<'a>, Error> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<SerializeArray<'a>, Error> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<SerializeArray<'a>, Error> {
+        Ok(SerializeArray {
+            elements: Vec::with_capacity(len),
+            variant: Some(variant),
+        })
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<SerializeHash<'a>, Error> {
+        Ok(SerializeHash {
+            pairs: BTreeMap::new(),
+            key: None,
+            variant: None,
+        })
+    }
+
+    fn serialize_struct(self, _name: &'static str, len: usize) -> Result<SerializeHash<'a>, Error> {
+        self.serialize_map(Some(len))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<SerializeHash<'a>, Error> {
+        Ok(SerializeHash {
+            pairs: BTreeMap::new(),
+            key: None,
+            variant: Some(variant),
+        })
+    }
+}
+
+struct SerializeArray<'a> {
+    elements: Vec<Object<'a>>,
+    variant: Option<&'static str>,
+}
+
+impl<'a> SerializeArray<'a> {
+    fn push<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.elements.push(to_object(value)?);
+        Ok(())
+    }
+
+    fn finish(self) -> Result<Object<'a>, Error> {
+        let array = Object::Array(Rc::new(self.elements));
+        Ok(match self.variant {
+            Some(name) => variant(name, array),
+            None => array,
+        })
+    }
+}
+
+impl<'a> ser::SerializeSeq for SerializeArray<'a> {
+    type Ok = Object<'a>;
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Object<'a>, Error> {
+        self.finish()
+    }
+}
+
+impl<'a> ser::SerializeTuple for SerializeArray<'a> {
+    type Ok = Object<'a>;
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Object<'a>, Error> {
+        self.finish()
+    }
+}
+
+impl<'a> ser::SerializeTupleStruct for SerializeArray<'a> {
+    type Ok = Object<'a>;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Object<'a>, Error> {
+        self.finish()
+    }
+}
+
+impl<'a> ser::SerializeTupleVariant for SerializeArray<'a> {
+    type Ok = Object<'a>;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Object<'a>, Error> {
+        self.finish()
+    }
+}
+
+struct SerializeHash<'a> {
+    pairs: BTreeMap<HashKey, Object<'a>>,
+    key: Option<HashKey>,
+    variant: Option<&'static str>,
+}
+
+impl<'a> SerializeHash<'a> {
+    fn finish(self) -> Result<Object<'a>, Error> {
+        let hash = Object::Hash(Rc::new(self.pairs));
+        Ok(match self.variant {
+            Some(name) => variant(name, hash),
+            None => hash,
+        })
+    }
+}
+
+impl<'a> ser::SerializeMap for SerializeHash<'a> {
+    type Ok = Object<'a>;
+    type Error = Error;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), Error> {
+        let key = to_object(key)?;
+        match HashKey::from_object(&key) {
+            Some(key) => self.key = Some(key),
+            None => return Err(Error(format!("unusable as hash key: {}", key))),
+        }
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        let key = self
+            .key
+            .take()
+            .expect("serialize_value called before serialize_key");
+        self.pairs.insert(key, to_object(value)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Object<'a>, Error> {
+        self.finish()
+    }
+}
+
+impl<'a> ser::SerializeStruct for SerializeHash<'a> {
+    type Ok = Object<'a>;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        self.pairs
+            .insert(HashKey::Str(key.into()), to_object(value)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Object<'a>, Error> {
+        self.finish()
+    }
+}
+
+impl<'a> ser::SerializeStructVariant for SerializeHash<'a> {
+    type Ok = Object<'a>;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        self.pairs
+            .insert(HashKey::Str(key.into()), to_object(value)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Object<'a>, Error> {
+        self.finish()
+    }
+}
+
+impl<'de, 'b, 'a> de::Deserializer<'de> for &'b Object<'a> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self {
+            Object::Integer(value) => visitor.visit_i64(*value),
+            Object::Float(value) => visitor.visit_f64(*value),
+            Object::Bool(value) => visitor.visit_bool(*value),
+            Object::Str(value) => visitor.visit_str(value),
+            Object::Null => visitor.visit_unit(),
+            Object::Array(elements) => {
+                let mut seq = SeqDeserializer::new(elements.iter());
+                let value = visitor.visit_seq(&mut seq)?;
+                seq.end()?;
+                Ok(value)
+            }
+            Object::Hash(pairs) => {
+                let mut map = MapDeserializer::new(pairs.iter());
+                let value = visitor.visit_map(&mut map)?;
+                map.end()?;
+                Ok(value)
+            }
+            _ => Err(Error(format!("cannot convert {} to a rust value", self))),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self {
+            Object::Null => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        match self {
+            Object::Str(name) => visitor.visit_enum(StrDeserializer::<Error>::new(name)),
+            Object::Hash(pairs) if pairs.len() == 1 => match pairs.iter().next() {
+                Some((HashKey::Str(name), value)) => visitor.visit_enum(Enum { name, value }),
+                _ => Err(Error("enum variant names must be strings".to_string())),
+            },
+            _ => Err(Error(format!(
+                "expected a string or a hash with one key for an enum, got {}",
+                self
+            ))),
+        }
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct seq tuple tuple_struct map struct
+        identifier ignored_any
+    }
+}
+
+impl<'de, 'b, 'a> IntoDeserializer<'de, Error> for &'b Object<'a> {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+impl<'de> de::Deserializer<'de> for &HashKey {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self {
+            HashKey::Integer(value) => visitor.visit_i64(*value),
+            HashKey::Bool(value) => visitor.visit_bool(*value),
+            HashKey::Str(value) => visitor.visit_str(value),
+        }
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct newtype_struct seq tuple
+        tuple_struct map struct enum identifier ignored_any
+    }
+}
+
+impl<'de> IntoDeserializer<'de, Error> for &HashKey {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+// a variant with data, stored as a hash from its name to the data
+struct Enum<'b, 'a> {
+    name: &'b str,
+    value: &'b Object<'a>,
+}
+
+impl<'de> de::EnumAccess<'de> for Enum<'_, '_> {
+    type Error = Error;
+    type Variant = Self;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(self, seed: V) -> Result<(V::Value, Self), Error> {
+        let name = seed.deserialize(StrDeserializer::<Error>::new(self.name))?;
+        Ok((name, self))
+    }
+}
+
+impl<'de> de::VariantAccess<'de> for Enum<'_, '_> {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<(), Error> {
+        match self.value {
+            Object::Null => Ok(()),
+            value => Err(Error(format!("unexpected data {} for unit variant", value))),
+        }
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value, Error> {
+        seed.deserialize(self.value)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value, Error> {
+        de::Deserializer::deserialize_seq(self.value, visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        de::Deserializer::deserialize_map(self.value, visitor)
+    }
+}
+
+/// Objects serialize as the values they hold, so results can be sent as
+/// JSON for example:
+///
+/// | Object  | serde data model | JSON                 |
+/// |---------|------------------|----------------------|
+/// | integer | `i64`            | number               |
+/// | float   | `f64`            | number with a `.`    |
+/// | boolean | `bool`           | `true` or `false`    |
+/// | string  | string           | string               |
+/// | `nil`   | unit             | `null`               |
+/// | array   | sequence         | array                |
+/// | hash    | map              | object               |
+///
+/// Functions, channels, tasks and caught errors have no such value and fail
+/// to serialize. JSON object keys are strings, so a hash with integer or
+/// boolean keys comes back with string ones.
+///
+/// ```
+/// use interpreter_book_in_rust::{eval, Object};
+///
+/// let value = eval(r#"{"n": [1, 2.5, true]}"#).unwrap();
+/// let json = serde_json::to_string(&value).unwrap();
+/// assert_eq!(json, r#"{"n":[1,2.5,true]}"#);
+/// let read: Object = serde_json::from_str(&json).unwrap();
+/// assert_eq!(read.to_string(), value.to_string());
+/// ```
+impl Serialize for Object<'_> {
+    fn serialize<S: ser::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Object::Integer(value) => serializer.serialize_i64(*value),
+            Object::Float(value) => serializer.serialize_f64(*value),
+            Object::Bool(value) => serializer.serialize_bool(*value),
+            Object::Str(value) => serializer.serialize_str(value),
+            Object::Null => serializer.serialize_unit(),
+            Object::Array(elements) => {
+                let mut seq = serializer.serialize_seq(Some(elements.len()))?;
+                for element in elements.iter() {
+                    seq.serialize_element(element)?;
+                }
+                seq.end()
+            }
+            Object::Hash(pairs) => {
+                let mut map = serializer.serialize_map(Some(pairs.len()))?;
+                for (key, value) in pairs.iter() {
+                    map.serialize_entry(key, value)?;
+                }
+                map.end()
+            }
+            _ => Err(ser::Error::custom(format!("cannot serialize {}", self))),
+        }
+    }
+}
+
+impl Serialize for HashKey {
+    fn serialize<S: ser::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            HashKey::Integer(value) => serializer.serialize_i64(*value),
+            HashKey::Bool(value) => serializer.serialize_bool(*value),
+            HashKey::Str(value) => serializer.serialize_str(value),
+        }
+    }
+}
+
+/// Reads back what [`Object`]'s `Serialize` writes, unsigned integers past
+/// `i64::MAX` fail.
+impl<'de, 'a> Deserialize<'de> for Object<'a> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(ObjectVisitor(PhantomData))
+    }
+}
+
+impl<'de> Deserialize<'de> for HashKey {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let key = Object::deserialize(deserializer)?;
+        HashKey::from_object(&key)
+            .ok_or_else(|| de::Error::custom(format!("unusable as hash key: {}", key)))
+    }
+}
+
+struct ObjectVisitor<'a>(PhantomData<Object<'a>>);
+
+impl<'de, 'a> Visitor<'de> for ObjectVisitor<'a> {
+    type Value = Object<'a>;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a number, boolean, string, null, sequence or map")
+    }
+
+    fn visit_bool<E: de::Error>(self, v: bool) -> Result<Object<'a>, E> {
+        Ok(Object::Bool(v))
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> Result<Object<'a>, E> {
+        Ok(Object::Integer(v))
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<Object<'a>, E> {
+        i64::try_from(v)
+            .map(Object::Integer)
+            .map_err(|_| E::custom(format!("integer {} out of range", v)))
+    }
+
+    fn visit_f64<E: de::Error>(self, v: f64) -> Result<Object<'a>, E> {
+        Ok(Object::Float(v))
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<Object<'a>, E> {
+        Ok(Object::Str(v.into()))
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<Object<'a>, E> {
+        Ok(Object::Null)
+    }
+
+    fn visit_none<E: de::Error>(self) -> Result<Object<'a>, E> {
+        Ok(Object::Null)
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<Object<'a>, D::Error> {
+        Object::deserialize(deserializer)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Object<'a>, A::Error> {
+        let mut elements = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        while let Some(element) = seq.next_element()? {
+            elements.push(element);
+        }
+        Ok(Object::Array(Rc::new(elements)))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Object<'a>, A::Error> {
+        let mut pairs = BTreeMap::new();
+        while let Some((key, value)) = map.next_entry()? {
+            pairs.insert(key, value);
+        }
+        Ok(Object::Hash(Rc::new(pairs)))
+    }
+}
+
+// the bytes of tokens and names in the ast as strings, for formats like json
+// where an array of numbers would be hard to read, the source they came
+// from is utf-8 so nothing is lost
+pub(crate) mod text {
+    use ::serde::{Deserialize, Deserializer, Serializer};
+
+    use crate::token::Text;
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&String::from_utf8_lossy(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Text, D::Error> {
+        String::deserialize(deserializer).map(|text| Text::from(text.as_bytes()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, HashMap};
+
+    use serde::{Deserialize, Serialize};
+
+    use super::{from_object, to_object, Error};
+    use crate::{
+        ast::Program,
+        eval,
+        lexer::Lexer,
+        object::{HashKey, Object},
+        parser::Parser,
+        Interpreter,
+    };
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    enum Mode {
+        Fast,
+        Retry(u8),
+        Window(i64, i64),
+        Limit { steps: u32 },
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Config {
+        name: String,
+        verbose: bool,
+        depth: Option<u16>,
+        limits: Vec<i64>,
+        modes: Vec<Mode>,
+        weights: HashMap<i64, char>,
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let config = Config {
+            name: "demo".to_string(),
+            verbose: true,
+            depth: None,
+            limits: vec![1, -2],
+            modes: vec![
+                Mode::Fast,
+                Mode::Retry(3),
+                Mode::Window(0, 10),
+                Mode::Limit { steps: 100 },
+            ],
+            weights: HashMap::from([(2, 'b'), (1, 'a')]),
+        };
+        let obj = to_object(&config).unwrap();
+        assert_eq!(
+            obj.to_string(),
+            "{depth: nil, limits: [1, -2], modes: [Fast, {Retry: 3}, {Window: [0, 10]}, \
+             {Limit: {steps: 100}}], name: demo, verbose: true, weights: {1: a, 2: b}}"
+        );
+        assert_eq!(from_object::<Config>(&obj).unwrap(), config);
+    }
+
+    #[test]
+    fn test_scripts() {
+        let mut interp = Interpreter::new();
+        interp.set_global("limits", to_object(&(1, 2, 3)).unwrap());
+        let obj = interp
+            .eval("let double = fn(x) { x * 2 }; [double(first(limits)), len(limits) > 2]")
+            .unwrap();
+        assert_eq!(from_object::<(i64, bool)>(&obj).unwrap(), (2, true));
+
+        interp.set_global("rate", to_object(&0.5).unwrap());
+        let obj = interp.eval("[rate * 3, len(limits)]").unwrap();
+        assert_eq!(from_object::<(f64, f64)>(&obj).unwrap(), (1.5, 3.0));
+    }
+
+    #[test]
+    fn test_errors() {
+        let err = |msg: &str| Error(msg.to_string());
+        assert_eq!(
+            to_object(&u64::MAX).unwrap_err(),
+            err("integer 18446744073709551615 out of range")
+        );
+        assert_eq!(
+            to_object(&BTreeMap::from([(vec![1], 1)])).unwrap_err(),
+            err("unusable as hash key: [1]")
+        );
+
+        let obj = to_object(&[300, 1]).unwrap();
+        assert!(from_object::<Vec<u8>>(&obj).is_err());
+        assert!(from_object::<[i64; 1]>(&obj).is_err());
+        assert!(from_object::<Config>(&obj).is_err());
+        assert!(from_object::<Mode>(&to_object(&"Slow").unwrap()).is_err());
+        assert!(from_object::<i64>(&Interpreter::new().eval("fn(x) { x }").unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_ast_json() {
+        let source = "let add = fn(a, b) { a + b };\nadd(1, -2.5);\n\"two\nlines\"";
+        let prog = Parser::new(Lexer::new(source.as_bytes())).parse().unwrap();
+        let json = serde_json::to_string(&prog).unwrap();
+        assert!(
+            json.starts_with(
+                r#"{"stmts":[{"Let":{"token":{"token_type":"LET","literal":"let","span":{"line":1,"column":1,"start":0,"end":3}},"name":{"Identifier":"#
+            ),
+            "{}",
+            json
+        );
+        assert!(
+            json.contains(r#""op":"+","right":{"Identifier":"#),
+            "{}",
+            json
+        );
+
+        let read: Program = serde_json::from_str(&json).unwrap();
+        assert_eq!(read, prog);
+        assert_eq!(read.to_string(), prog.to_string());
+        assert_eq!(read.stmts[1].first_token().span.to_string(), "2:1");
+    }
+
+    #[test]
+    fn test_object_json() {
+        let value =
+            eval(r#"[1, -2.5, 2.0, true, "hi", puts(), [{"a": {}}], {1: 2, true: [3]}]"#).unwrap();
+        let json = serde_json::to_string(&value).unwrap();
+        assert_eq!(
+            json,
+            r#"[1,-2.5,2.0,true,"hi",null,[{"a":{}}],{"1":2,"true":[3]}]"#
+        );
+        let read: Object = serde_json::from_str(&json).unwrap();
+        // json has only string keys
+        assert_eq!(
+            read.to_string(),
+            r#"[1, -2.5, 2.0, true, hi, nil, [{a: {}}], {1: 2, true: [3]}]"#
+        );
+        let Object::Array(elements) = &read else {
+            panic!("{}", read);
+        };
+        let Object::Hash(pairs) = &elements[7] else {
+            panic!("{}", read);
+        };
+        assert!(pairs.keys().all(|key| matches!(key, HashKey::Str(_))));
+        assert_eq!(serde_json::to_string(&read).unwrap(), json);
+
+        // a hash deserialized from a non-string key format keeps its keys
+        let obj = to_object(&BTreeMap::from([(1, true)])).unwrap();
+        let read = from_object::<Object>(&obj).unwrap();
+        assert_eq!(read.to_string(), "{1: true}");
+
+        let err = serde_json::to_string(&eval("fn(x) { x }").unwrap()).unwrap_err();
+        assert_eq!(err.to_string(), "cannot serialize fn(x) { x; }");
+        let err = serde_json::from_str::<Object>("18446744073709551615").unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("integer 18446744073709551615 out of range"));
+    }
+}
@@ -0,0 +1,324 @@
+use crate::{
+    ast::{Expr, Program, Stmt},
+    intern::Symbol,
+    rc::Rc,
+};
+
+// where the binding of an identifier is kept, `depth` envs out from the one
+// it's evaluated in, at `index` among that env's slots
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+pub struct Slot {
+    pub depth: usize,
+    pub index: usize,
+}
+
+// gives the identifiers bound in a function or a block the slot of the env
+// the evaluator binds them in, so they're found by index instead of by name
+//
+// the envs are the ones the evaluator makes: one with the parameters of a
+// call, one with the variable of each loop iteration and one per block run,
+// a `let` and a caught error are bound in the env they run in
+//
+// names bound outside of every function and block are left to be looked up
+// by name, other programs and the host bind them too, and so are the names
+// nothing around an identifier binds, like builtins
+//
+// an identifier can be evaluated before its scope binds it, `a` in
+// `{ let b = a; let a = 2; }`, the slot is empty then and the evaluator
+// looks it up by name instead
+pub fn resolve_slots(prog: &mut Program) {
+    let mut slots = Slots::default();
+    // the names are collected first, then the identifiers given slots
+    for resolving in [false, true] {
+        slots.resolving = resolving;
+        slots.opened = 0;
+        slots.enter();
+        prog.stmts.iter_mut().for_each(|stmt| slots.stmt(stmt));
+        slots.leave();
+    }
+}
+
+#[derive(Default)]
+struct Slots {
+    // the names each scope binds, in the order the scopes open, the first
+    // is the program's own
+    scopes: Vec<Vec<Symbol>>,
+    // the scopes around what's resolved, innermost last
+    open: Vec<usize>,
+    // how many scopes opened so far
+    opened: usize,
+    resolving: bool,
+}
+
+impl Slots {
+    fn enter(&mut self) {
+        if !self.resolving {
+            self.scopes.push(vec![]);
+        }
+        self.open.push(self.opened);
+        self.opened += 1;
+    }
+
+    fn leave(&mut self) {
+        self.open.pop();
+    }
+
+    // parameters take a slot each, the evaluator binds them by position,
+    // anything else one per name
+    fn declare(&mut self, ident: &mut Expr, parameter: bool) {
+        let Expr::Identifier { symbol, slot, .. } = ident else {
+            // the names a `let` destructures an array into
+            if let Expr::ArrayLiteral { elements, .. } = ident {
+                elements.iter_mut().for_each(|e| self.declare(e, parameter));
+            }
+            return;
+        };
+        let scope = self.open[self.open.len() - 1];
+        if scope == 0 {
+            return;
+        }
+        let names = &mut self.scopes[scope];
+        if self.resolving {
+            let index = names.iter().rposition(|name| name == symbol);
+            *slot = index.map(|index| Slot { depth: 0, index });
+        } else if parameter || !names.contains(symbol) {
+            names.push(symbol.clone());
+        }
+    }
+
+    fn lookup(&mut self, ident: &mut Expr) {
+        let Expr::Identifier { symbol, slot, .. } = ident else {
+            return;
+        };
+        // a later parameter of the same name hides an earlier one
+        *slot = self
+            .open
+            .iter()
+            .rev()
+            .take_while(|&&scope| scope != 0)
+            .enumerate()
+            .find_map(|(depth, &scope)| {
+                let index = self.scopes[scope].iter().rposition(|name| name == symbol)?;
+                Some(Slot { depth, index })
+            });
+    }
+
+    fn stmt(&mut self, stmt: &mut Stmt) {
+        match stmt {
+            Stmt::Let { name, value, .. } => {
+                self.expr(value);
+                self.declare(name, false);
+            }
+            Stmt::Return { value: expr, .. } | Stmt::Expr { expr } => self.expr(expr),
+            Stmt::Block { stmts, .. } => {
+                self.enter();
+                stmts.iter_mut().for_each(|stmt| self.stmt(stmt));
+                self.leave();
+            }
+            Stmt::For {
+                name,
+                iterable,
+                body,
+                ..
+            } => {
+                self.expr(iterable);
+                self.enter();
+                self.declare(name, false);
+                self.stmt(body);
+                self.leave();
+            }
+            Stmt::Debugger { .. } | Stmt::Break { .. } | Stmt::Continue { .. } => {}
+        }
+    }
+
+    fn expr(&mut self, expr: &mut Expr) {
+        match expr {
+            Expr::Identifier { .. } if self.resolving => self.lookup(expr),
+            Expr::Identifier { .. } => {}
+            Expr::IntLiteral { .. }
+            | Expr::FloatLiteral { .. }
+            | Expr::BoolLiteral { .. }
+            | Expr::StringLiteral { .. } => {}
+            Expr::FnLiteral {
+                parameters,
+                rest,
+                block,
+                ..
+            } => {
+                // a body already shared with closures is left to be looked
+                // up by name, the same in both passes
+                let Some(block) = Rc::get_mut(block) else {
+                    return;
+                };
+                self.enter();
+                for param in parameters.iter_mut().chain(rest.as_deref_mut()) {
+                    self.declare(param, true);
+                }
+                self.stmt(block);
+                self.leave();
+            }
+            Expr::Call {
+                function,
+                arguments,
+                ..
+            } => {
+                self.expr(function);
+                arguments.iter_mut().for_each(|arg| self.expr(arg));
+            }
+            Expr::Prefix { expr, .. } => self.expr(expr),
+            Expr::Infix { left, right, .. } => {
+                self.expr(left);
+                self.expr(right);
+            }
+            Expr::If {
+                condition,
+                consequence,
+                alternative,
+                ..
+            } => {
+                self.expr(condition);
+                self.stmt(consequence);
+                if let Some(alternative) = alternative {
+                    self.stmt(alternative);
+                }
+            }
+            Expr::Try {
+                body,
+                name,
+                handler,
+                ..
+            } => {
+                self.stmt(body);
+                self.declare(name, false);
+                self.stmt(handler);
+            }
+            Expr::ArrayLiteral { elements, .. } => elements.iter_mut().for_each(|e| self.expr(e)),
+            Expr::Index { left, index, .. } => {
+                self.expr(left);
+                self.expr(index);
+            }
+            Expr::HashLiteral { pairs, .. } => {
+                for (key, value) in pairs {
+                    self.expr(key);
+                    self.expr(value);
+                }
+            }
+            Expr::Match { subject, arms, .. } => {
+                self.expr(subject);
+                for arm in arms {
+                    if let Some(pattern) = &mut arm.pattern {
+                        self.expr(pattern);
+                    }
+                    self.expr(&mut arm.value);
+                }
+            }
+            Expr::Assign { name, value, .. } => {
+                self.expr(value);
+                self.expr(name);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        ast::Program,
+        evaluator::eval_program,
+        flat::{FlatExpr, FlatProgram},
+        lexer::Lexer,
+        parser::Parser,
+        Env,
+    };
+
+    use super::Slot;
+
+    fn parse(input: &str) -> Program {
+        Parser::new(Lexer::new(input.as_bytes())).parse().unwrap()
+    }
+
+    #[test]
+    fn test_resolve_slots() {
+        let prog = parse(
+            "let a = 1;
+            let f = fn(b, c) { let d = b + c; fn() { d = a + b; len(d) } };
+            for (x in [1]) { let y = x; y }",
+        );
+        let flat = FlatProgram::new(&prog);
+        let found: Vec<_> = flat
+            .exprs()
+            .filter_map(|(_, expr)| match expr {
+                FlatExpr::Identifier { value, slot, .. } => Some((
+                    std::str::from_utf8(value).unwrap(),
+                    slot.map(|Slot { depth, index }| (depth, index)),
+                )),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            found,
+            [
+                ("a", None),
+                ("f", None),
+                ("b", Some((0, 0))),
+                ("c", Some((0, 1))),
+                ("d", Some((0, 0))),
+                ("b", Some((1, 0))),
+                ("c", Some((1, 1))),
+                // the closure's own parameters and body are two scopes
+                ("d", Some((2, 0))),
+                ("a", None),
+                ("b", Some((3, 0))),
+                ("len", None),
+                ("d", Some((2, 0))),
+                // the loop variable is in a scope around the body's
+                ("x", Some((0, 0))),
+                ("y", Some((0, 0))),
+                ("x", Some((1, 0))),
+                ("y", Some((0, 0))),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_same_results() {
+        let inputs = [
+            // used before its own scope binds it, the outer binding is seen
+            ("let a = 1; if (true) { let b = a; let a = 2; [a, b] }", "[2, 1]"),
+            (
+                "let f = fn() { let even = fn(n) { if (n == 0) { true } else { odd(n - 1) } }; let odd = fn(n) { if (n == 0) { false } else { even(n - 1) } }; even(10) }; f()",
+                "true",
+            ),
+            (
+                "let counter = fn() { let n = 0; fn() { n = n + 1 } }; let c = counter(); c(); c()",
+                "2",
+            ),
+            // each iteration's body is a scope of its own
+            (
+                "let f = fn() { let fs = []; for (x in [1, 2]) { let y = x * 10; fs = push(fs, fn() { y }) }; [fs[0](), fs[1]()] }; f()",
+                "[10, 20]",
+            ),
+            (
+                "let f = fn(x) { let out = []; for (x in [1, 2]) { out = push(out, x) }; push(out, x) }; f(9)",
+                "[1, 2, 9]",
+            ),
+            (
+                "let f = fn() { let e = 1; let caught = try { 1 / 0 } catch (e) { e }; [caught, e] }; f()",
+                "[division by zero, 1]",
+            ),
+            ("fn(a, a) { a }(1, 2)", "2"),
+            ("fn(a, ...rest) { let [b, c] = rest; a + b + c }(1, 2, 3)", "6"),
+            ("fn(x) { let x = x + 1; x }(1)", "2"),
+            ("fn() { let x = 1; if (true) { x = 2 }; x }()", "2"),
+            (
+                "let f = fn() { let n = 0; for (i in [1, 2, 3]) { n = n + i }; n }; f()",
+                "6",
+            ),
+        ];
+        for (input, expected) in inputs {
+            let result = eval_program(&parse(input), &mut Env::new());
+            assert_eq!(result.unwrap().to_string(), expected, "{}", input);
+        }
+    }
+}
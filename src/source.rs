@@ -0,0 +1,141 @@
+use std::ops::Range;
+
+// a position in the source as people count it, both fields start at 1 and
+// columns count characters rather than bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Location {
+    pub line: usize,
+    pub column: usize,
+}
+
+// the source text with the byte offset of every line start, so positions
+// can be looked up without rescanning the text each time
+#[derive(Debug, Clone)]
+pub struct SourceMap {
+    text: String,
+    line_starts: Vec<usize>,
+}
+
+impl SourceMap {
+    pub fn new(text: impl Into<String>) -> Self {
+        let text = text.into();
+        let line_starts = std::iter::once(0)
+            .chain(text.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        SourceMap { text, line_starts }
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn line_count(&self) -> usize {
+        self.line_starts.len()
+    }
+
+    // the text of a line without its line ending
+    pub fn line(&self, line: usize) -> Option<&str> {
+        let range = self.line_range(line)?;
+        let text = &self.text[range];
+        Some(text.strip_suffix('\r').unwrap_or(text))
+    }
+
+    // byte range of a line, not counting the `\n`
+    fn line_range(&self, line: usize) -> Option<Range<usize>> {
+        let start = *self.line_starts.get(line.checked_sub(1)?)?;
+        let end = self
+            .line_starts
+            .get(line)
+            .map_or(self.text.len(), |next| next - 1);
+        Some(start..end)
+    }
+
+    // None for offsets past the end of the text or inside a character
+    pub fn location(&self, offset: usize) -> Option<Location> {
+        let line = self.line_starts.partition_point(|&start| start <= offset);
+        let before = self.text.get(self.line_starts[line - 1]..offset)?;
+        Some(Location {
+            line,
+            column: before.chars().count() + 1,
+        })
+    }
+
+    pub fn span_location(&self, span: Range<usize>) -> Option<(Location, Location)> {
+        Some((self.location(span.start)?, self.location(span.end)?))
+    }
+
+    // the column may point just past the last character of a line, where
+    // the line ending or the end of the text is
+    pub fn offset(&self, location: Location) -> Option<usize> {
+        let range = self.line_range(location.line)?;
+        let line = &self.text[range.clone()];
+        let column = location.column.checked_sub(1)?;
+        match line.char_indices().nth(column) {
+            Some((i, _)) => Some(range.start + i),
+            None if column == line.chars().count() => Some(range.end),
+            None => None,
+        }
+    }
+
+    pub fn span(&self, start: Location, end: Location) -> Option<Range<usize>> {
+        Some(self.offset(start)?..self.offset(end)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::{Location, SourceMap};
+
+    fn loc(line: usize, column: usize) -> Location {
+        Location { line, column }
+    }
+
+    #[test]
+    fn test_location() {
+        let map = SourceMap::new("let a = 1;\nlet b = a;\n\nb");
+        assert_eq!(map.line_count(), 4);
+        let cases = [
+            (0, loc(1, 1)),
+            (4, loc(1, 5)),
+            (10, loc(1, 11)),
+            (11, loc(2, 1)),
+            (19, loc(2, 9)),
+            (22, loc(3, 1)),
+            (23, loc(4, 1)),
+            (24, loc(4, 2)),
+        ];
+        for (offset, location) in cases {
+            assert_eq!(map.location(offset), Some(location), "{}", offset);
+            assert_eq!(map.offset(location), Some(offset), "{:?}", location);
+        }
+        assert_eq!(map.location(25), None);
+        assert_eq!(map.offset(loc(1, 12)), None);
+        assert_eq!(map.offset(loc(5, 1)), None);
+        assert_eq!(map.offset(loc(0, 1)), None);
+        assert_eq!(map.offset(loc(1, 0)), None);
+
+        assert_eq!(map.span_location(4..5), Some((loc(1, 5), loc(1, 6))));
+        assert_eq!(map.span(loc(2, 5), loc(2, 6)), Some(15..16));
+    }
+
+    #[test]
+    fn test_lines() {
+        let map = SourceMap::new("a\r\nbc\n");
+        assert_eq!(map.line(1), Some("a"));
+        assert_eq!(map.line(2), Some("bc"));
+        assert_eq!(map.line(3), Some(""));
+        assert_eq!(map.line(4), None);
+        assert_eq!(map.line(0), None);
+        assert_eq!(map.text(), "a\r\nbc\n");
+    }
+
+    #[test]
+    fn test_multibyte_columns() {
+        let map = SourceMap::new("é = 1;\nfoo");
+        assert_eq!(map.location(2), Some(loc(1, 2)));
+        assert_eq!(map.location(1), None);
+        assert_eq!(map.offset(loc(1, 3)), Some(3));
+        assert_eq!(map.location(8), Some(loc(2, 1)));
+    }
+}
@@ -0,0 +1,372 @@
+
+use std::collections::HashMap;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SymbolScope {
+    Global,
+    Local,
+    Free,
+    Builtin,
+    // the function being compiled, by the name it's bound to
+    Function,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Symbol {
+    pub name: Vec<u8>,
+    pub scope: SymbolScope,
+    pub index: usize,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct SymbolTable {
+    pub outer: Option<Box<SymbolTable>>,
+    pub free_symbols: Vec<Symbol>,
+    store: HashMap<Vec<u8>, Symbol>,
+    num_definitions: usize,
+    // the first slot of the innermost block, names defined before it are
+    // shadowed rather than redefined
+    block_start: usize,
+    // the first global the outermost open block defined, see `is_scoped`
+    scoped_from: Option<usize>,
+}
+
+// what a block hides while it's compiled, see `enter_block`
+#[derive(Debug, Clone)]
+pub struct BlockScope {
+    store: HashMap<Vec<u8>, Symbol>,
+    start: usize,
+    scoped_from: Option<usize>,
+}
+
+impl SymbolTable {
+    pub fn new() -> Self {
+        SymbolTable::default()
+    }
+
+    pub fn new_enclosed(outer: SymbolTable) -> Self {
+        SymbolTable {
+            outer: Some(Box::new(outer)),
+            ..Default::default()
+        }
+    }
+
+    pub fn num_definitions(&self) -> usize {
+        self.num_definitions
+    }
+
+    // the names defined in this table, not in the ones enclosing it
+    pub fn symbols(&self) -> impl Iterator<Item = &Symbol> {
+        self.store.values()
+    }
+
+    pub fn define(&mut self, name: &[u8]) -> Symbol {
+        let scope = if self.outer.is_some() {
+            SymbolScope::Local
+        } else {
+            SymbolScope::Global
+        };
+        // redefining a name in the same block reuses its slot
+        if let Some(existing) = self.store.get(name) {
+            if existing.scope == scope && existing.index >= self.block_start {
+                return existing.clone();
+            }
+        }
+        let symbol = Symbol {
+            name: name.to_vec(),
+            scope,
+            index: self.num_definitions,
+        };
+        self.num_definitions += 1;
+        self.store.insert(name.to_vec(), symbol.clone());
+        symbol
+    }
+
+    // the names a block defines get slots of their own and stop resolving
+    // once the block is left with what this returns
+    pub fn enter_block(&mut self) -> BlockScope {
+        let start = std::mem::replace(&mut self.block_start, self.num_definitions);
+        let scoped_from = self.scoped_from;
+        self.scoped_from.get_or_insert(self.num_definitions);
+        BlockScope {
+            store: self.store.clone(),
+            start,
+            scoped_from,
+        }
+    }
+
+    pub fn leave_block(&mut self, block: BlockScope) {
+        let store = std::mem::replace(&mut self.store, block.store);
+        // what the block captured stays captured for the rest of the function
+        for (name, symbol) in store {
+            if symbol.scope == SymbolScope::Free {
+                self.store.entry(name).or_insert(symbol);
+            }
+        }
+        self.block_start = block.start;
+        self.scoped_from = block.scoped_from;
+    }
+
+    // a global defined in a block, a block in a loop binds it again on each
+    // run, so the functions made in the block capture it like a local
+    // instead of loading it when they're called
+    pub fn is_scoped(&self, symbol: &Symbol) -> bool {
+        symbol.scope == SymbolScope::Global
+            && self.scoped_from.is_some_and(|from| symbol.index >= from)
+    }
+
+    // lets a function refer to itself by the name it's bound to when that
+    // isn't a global, which is only set once the function is made
+    pub fn define_function_name(&mut self, name: &[u8]) -> Symbol {
+        let symbol = Symbol {
+            name: name.to_vec(),
+            scope: SymbolScope::Function,
+            index: 0,
+        };
+        self.store.insert(name.to_vec(), symbol.clone());
+        symbol
+    }
+
+    pub fn define_builtin(&mut self, index: usize, name: &[u8]) -> Symbol {
+        let symbol = Symbol {
+            name: name.to_vec(),
+            scope: SymbolScope::Builtin,
+            index,
+        };
+        self.store.insert(name.to_vec(), symbol.clone());
+        symbol
+    }
+
+    fn define_free(&mut self, original: Symbol) -> Symbol {
+        let symbol = Symbol {
+            name: original.name.clone(),
+            scope: SymbolScope::Free,
+            index: self.free_symbols.len(),
+        };
+        self.free_symbols.push(original);
+        self.store.insert(symbol.name.clone(), symbol.clone());
+        symbol
+    }
+
+    pub fn resolve(&mut self, name: &[u8]) -> Option<Symbol> {
+        if let Some(symbol) = self.store.get(name) {
+            return Some(symbol.clone());
+        }
+        let outer = self.outer.as_mut()?;
+        let symbol = outer.resolve(name)?;
+        if outer.is_scoped(&symbol) {
+            return Some(self.define_free(symbol));
+        }
+        match symbol.scope {
+            SymbolScope::Global | SymbolScope::Builtin => Some(symbol),
+            SymbolScope::Local | SymbolScope::Free | SymbolScope::Function => {
+                Some(self.define_free(symbol))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Symbol, SymbolScope, SymbolTable};
+
+    fn symbol(name: &[u8], scope: SymbolScope, index: usize) -> Symbol {
+        Symbol {
+            name: name.to_vec(),
+            scope,
+            index,
+        }
+    }
+
+    #[test]
+    fn test_define() {
+        let mut global = SymbolTable::new();
+        assert_eq!(global.define(b"a"), symbol(b"a", SymbolScope::Global, 0));
+        assert_eq!(global.define(b"b"), symbol(b"b", SymbolScope::Global, 1));
+        // redefinition keeps the original slot
+        assert_eq!(global.define(b"a"), symbol(b"a", SymbolScope::Global, 0));
+
+        let mut first_local = SymbolTable::new_enclosed(global);
+        assert_eq!(
+            first_local.define(b"c"),
+            symbol(b"c", SymbolScope::Local, 0)
+        );
+        assert_eq!(
+            first_local.define(b"d"),
+            symbol(b"d", SymbolScope::Local, 1)
+        );
+
+        let mut second_local = SymbolTable::new_enclosed(first_local);
+        assert_eq!(
+            second_local.define(b"e"),
+            symbol(b"e", SymbolScope::Local, 0)
+        );
+        assert_eq!(
+            second_local.define(b"f"),
+            symbol(b"f", SymbolScope::Local, 1)
+        );
+    }
+
+    #[test]
+    fn test_resolve_global() {
+        let mut global = SymbolTable::new();
+        global.define(b"a");
+        global.define(b"b");
+
+        assert_eq!(
+            global.resolve(b"a"),
+            Some(symbol(b"a", SymbolScope::Global, 0))
+        );
+        assert_eq!(
+            global.resolve(b"b"),
+            Some(symbol(b"b", SymbolScope::Global, 1))
+        );
+        assert_eq!(global.resolve(b"c"), None);
+    }
+
+    #[test]
+    fn test_resolve_nested_local() {
+        let mut global = SymbolTable::new();
+        global.define(b"a");
+        global.define(b"b");
+
+        let mut first_local = SymbolTable::new_enclosed(global);
+        first_local.define(b"c");
+        first_local.define(b"d");
+
+        let mut second_local = SymbolTable::new_enclosed(first_local);
+        second_local.define(b"e");
+        second_local.define(b"f");
+
+        let expected = [
+            symbol(b"a", SymbolScope::Global, 0),
+            symbol(b"b", SymbolScope::Global, 1),
+            symbol(b"c", SymbolScope::Free, 0),
+            symbol(b"d", SymbolScope::Free, 1),
+            symbol(b"e", SymbolScope::Local, 0),
+            symbol(b"f", SymbolScope::Local, 1),
+        ];
+        for sym in expected {
+            assert_eq!(second_local.resolve(&sym.name), Some(sym));
+        }
+        assert_eq!(
+            second_local.free_symbols,
+            vec![
+                symbol(b"c", SymbolScope::Local, 0),
+                symbol(b"d", SymbolScope::Local, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_block() {
+        let mut global = SymbolTable::new();
+        global.define(b"a");
+        let block = global.enter_block();
+        assert_eq!(global.define(b"a"), symbol(b"a", SymbolScope::Global, 1));
+        assert_eq!(global.define(b"a"), symbol(b"a", SymbolScope::Global, 1));
+        global.define(b"b");
+        global.leave_block(block);
+
+        assert_eq!(
+            global.resolve(b"a"),
+            Some(symbol(b"a", SymbolScope::Global, 0))
+        );
+        assert_eq!(global.resolve(b"b"), None);
+        // the block's slots stay taken
+        assert_eq!(global.define(b"c"), symbol(b"c", SymbolScope::Global, 3));
+    }
+
+    #[test]
+    fn test_scoped_globals() {
+        let mut global = SymbolTable::new();
+        global.define(b"a");
+        let block = global.enter_block();
+        global.define(b"b");
+
+        let mut local = SymbolTable::new_enclosed(global);
+        assert_eq!(
+            local.resolve(b"a"),
+            Some(symbol(b"a", SymbolScope::Global, 0))
+        );
+        assert_eq!(
+            local.resolve(b"b"),
+            Some(symbol(b"b", SymbolScope::Free, 0))
+        );
+        assert_eq!(
+            local.free_symbols,
+            vec![symbol(b"b", SymbolScope::Global, 1)]
+        );
+
+        let mut global = *local.outer.take().unwrap();
+        global.leave_block(block);
+        assert!(!global.is_scoped(&symbol(b"b", SymbolScope::Global, 1)));
+    }
+
+    #[test]
+    fn test_function_name() {
+        let mut local = SymbolTable::new_enclosed(SymbolTable::new());
+        local.define_function_name(b"f");
+        assert_eq!(
+            local.resolve(b"f"),
+            Some(symbol(b"f", SymbolScope::Function, 0))
+        );
+
+        let mut inner = SymbolTable::new_enclosed(local);
+        assert_eq!(
+            inner.resolve(b"f"),
+            Some(symbol(b"f", SymbolScope::Free, 0))
+        );
+
+        // a parameter of the same name hides it
+        let mut local = *inner.outer.take().unwrap();
+        assert_eq!(local.define(b"f"), symbol(b"f", SymbolScope::Local, 0));
+    }
+
+    #[test]
+    fn test_resolve_unresolvable_free() {
+        let mut global = SymbolTable::new();
+        global.define(b"a");
+
+        let mut first_local = SymbolTable::new_enclosed(global);
+        first_local.define(b"c");
+
+        let mut second_local = SymbolTable::new_enclosed(first_local);
+        second_local.define(b"e");
+
+        assert_eq!(
+            second_local.resolve(b"a"),
+            Some(symbol(b"a", SymbolScope::Global, 0))
+        );
+        assert_eq!(
+            second_local.resolve(b"c"),
+            Some(symbol(b"c", SymbolScope::Free, 0))
+        );
+        assert_eq!(
+            second_local.resolve(b"e"),
+            Some(symbol(b"e", SymbolScope::Local, 0))
+        );
+        assert_eq!(second_local.resolve(b"b"), None);
+        assert_eq!(second_local.resolve(b"d"), None);
+    }
+
+    #[test]
+    fn test_define_resolve_builtins() {
+        let mut global = SymbolTable::new();
+        global.define_builtin(0, b"a");
+        global.define_builtin(1, b"c");
+
+        let first_local = SymbolTable::new_enclosed(global);
+        let mut second_local = SymbolTable::new_enclosed(first_local);
+
+        assert_eq!(
+            second_local.resolve(b"a"),
+            Some(symbol(b"a", SymbolScope::Builtin, 0))
+        );
+        assert_eq!(
+            second_local.resolve(b"c"),
+            Some(symbol(b"c", SymbolScope::Builtin, 1))
+        );
+        assert!(second_local.free_symbols.is_empty());
+    }
+}
@@ -0,0 +1,121 @@
+use std::{collections::VecDeque, sync::Mutex};
+
+use crate::{builtins::Builtin, object::Object, rc::Rc};
+
+// spawn, join and recv have to run Monkey functions, which a builtin can't,
+// so the engines implement them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TaskOp {
+    Spawn,
+    Join,
+    Recv,
+}
+
+impl TaskOp {
+    pub fn of(builtin: &Builtin) -> Option<TaskOp> {
+        match builtin.name {
+            "spawn" => Some(TaskOp::Spawn),
+            "join" => Some(TaskOp::Join),
+            "recv" => Some(TaskOp::Recv),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Channel<'a>(Mutex<VecDeque<Object<'a>>>);
+
+impl<'a> Channel<'a> {
+    pub fn send(&self, value: Object<'a>) {
+        self.0.lock().expect("channel poisoned").push_back(value);
+    }
+
+    pub fn try_recv(&self) -> Option<Object<'a>> {
+        self.0.lock().expect("channel poisoned").pop_front()
+    }
+}
+
+#[derive(Debug)]
+enum TaskState<'a> {
+    Pending,
+    Running,
+    Done(Object<'a>),
+}
+
+#[derive(Debug)]
+pub struct Task<'a> {
+    pub function: Object<'a>,
+    state: Mutex<TaskState<'a>>,
+}
+
+impl<'a> Task<'a> {
+    // marks the task as running, false if it was started before
+    pub fn start(&self) -> bool {
+        let mut state = self.state.lock().expect("task poisoned");
+        if !matches!(*state, TaskState::Pending) {
+            return false;
+        }
+        *state = TaskState::Running;
+        true
+    }
+
+    pub fn finish(&self, value: Object<'a>) {
+        *self.state.lock().expect("task poisoned") = TaskState::Done(value);
+    }
+
+    // None while the task hasn't finished
+    pub fn result(&self) -> Option<Object<'a>> {
+        match &*self.state.lock().expect("task poisoned") {
+            TaskState::Done(value) => Some(value.clone()),
+            _ => None,
+        }
+    }
+}
+
+// tasks run one at a time and to completion, a task starts once something
+// waits on it, through `join` or a `recv` with nothing to receive, or when
+// the program that spawned it ends
+#[derive(Debug, Default)]
+pub struct Scheduler<'a> {
+    queue: VecDeque<Rc<Task<'a>>>,
+}
+
+impl<'a> Scheduler<'a> {
+    pub fn spawn(&mut self, args: &[Object<'a>]) -> Result<Object<'a>, &'static str> {
+        let function = match args {
+            [function @ (Object::Function(_)
+            | Object::Closure(_)
+            | Object::Builtin(_)
+            | Object::Host(_))] => function.clone(),
+            [_] => return Err("argument to `spawn` must be a function"),
+            _ => return Err("wrong number of arguments"),
+        };
+        let task = Rc::new(Task {
+            function,
+            state: Mutex::new(TaskState::Pending),
+        });
+        self.queue.push_back(Rc::clone(&task));
+        Ok(Object::Task(task))
+    }
+
+    // starts the oldest task that hasn't been started by a `join` yet
+    pub fn start_next(&mut self) -> Option<Rc<Task<'a>>> {
+        std::iter::from_fn(|| self.queue.pop_front()).find(|task| task.start())
+    }
+}
+
+pub fn join_arg<'a>(args: &[Object<'a>]) -> Result<Rc<Task<'a>>, &'static str> {
+    match args {
+        [Object::Task(task)] => Ok(Rc::clone(task)),
+        [_] => Err("argument to `join` must be a task"),
+        _ => Err("wrong number of arguments"),
+    }
+}
+
+pub fn recv_arg<'a>(args: &[Object<'a>]) -> Result<Rc<Channel<'a>>, &'static str> {
+    match args {
+        [Object::Channel(channel)] => Ok(Rc::clone(channel)),
+        [_] => Err("argument to `recv` must be a channel"),
+        _ => Err("wrong number of arguments"),
+    }
+}
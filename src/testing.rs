@@ -0,0 +1,303 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use crate::{
+    coverage::Coverage,
+    env::Env,
+    evaluator::Evaluator,
+    host::{HostFunction, MaybeSync},
+    interpreter::Error,
+    lexer::Lexer,
+    object::Object,
+    parser::Parser,
+    rc::Rc,
+    sandbox::SandboxConfig,
+};
+
+pub const SUFFIX: &str = "_test.mk";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TestResult {
+    pub name: String,
+    // why the test failed, with both sides of a failed `assert_eq`
+    pub outcome: Result<(), String>,
+}
+
+// the test scripts under `path` in a stable order, `path` may also name a
+// single script
+pub fn discover(path: &Path) -> io::Result<Vec<PathBuf>> {
+    if path.is_file() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+    let mut found = Vec::new();
+    for entry in fs::read_dir(path)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            found.extend(discover(&path)?);
+        } else if path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.ends_with(SUFFIX))
+        {
+            found.push(path);
+        }
+    }
+    found.sort();
+    Ok(found)
+}
+
+// what the runner's builtins record while a script runs
+#[derive(Default)]
+struct State<'a> {
+    tests: Vec<(String, Object<'a>)>,
+    failure: Option<String>,
+}
+
+// runs the script to collect the tests it declares with `test(name, fn)`,
+// then calls each test with a fresh evaluator, so limits apply per test and
+// a failure can't leave anything behind for the next one
+pub fn run_tests(
+    source: &str,
+    config: SandboxConfig,
+    coverage: Option<&Coverage>,
+) -> Result<Vec<TestResult>, Error> {
+    let prog = Parser::new(Lexer::new(source.as_bytes()))
+        .parse()
+        .map_err(Error::Parse)?;
+    let state = Rc::new(Mutex::new(State::default()));
+    let mut globals = Env::new();
+    bind_builtins(&mut globals, &state);
+    let evaluator = || match coverage {
+        Some(coverage) => Evaluator::new(config).with_tracer(coverage),
+        None => Evaluator::new(config),
+    };
+    evaluator()
+        .eval_program(&prog, &mut globals)
+        .map_err(|err| Error::Runtime(err.diagnostic()))?;
+
+    let tests = std::mem::take(&mut lock(&state).tests);
+    let results = tests
+        .into_iter()
+        .map(|(name, function)| {
+            let result = evaluator().call(function, vec![], &globals);
+            let failure = lock(&state).failure.take();
+            let outcome = match result {
+                Ok(_) => Ok(()),
+                Err(err) => Err(failure.unwrap_or_else(|| err.to_string())),
+            };
+            TestResult { name, outcome }
+        })
+        .collect();
+    Ok(results)
+}
+
+fn lock<'s, 'a>(state: &'s Mutex<State<'a>>) -> std::sync::MutexGuard<'s, State<'a>> {
+    state.lock().expect("test state poisoned")
+}
+
+fn bind_builtins<'a>(env: &mut Env<'a>, state: &Rc<Mutex<State<'a>>>) {
+    let collect = Rc::clone(state);
+    bind(env, "test", move |args| match args {
+        [name, function @ (Object::Function(_) | Object::Builtin(_) | Object::Host(_))] => {
+            lock(&collect)
+                .tests
+                .push((name.to_string(), function.clone()));
+            Ok(Object::Null)
+        }
+        [_, _] => Err("argument to `test` must be a function"),
+        _ => Err("wrong number of arguments"),
+    });
+
+    let failures = Rc::clone(state);
+    bind(env, "assert", move |args| match args {
+        [Object::Bool(true)] => Ok(Object::Null),
+        [Object::Bool(false)] => {
+            lock(&failures).failure = Some("assertion failed".to_string());
+            Err("assertion failed")
+        }
+        [_] => Err("argument to `assert` must be a boolean"),
+        _ => Err("wrong number of arguments"),
+    });
+
+    let failures = Rc::clone(state);
+    bind(env, "assert_eq", move |args| match args {
+        [left, right] if equal(left, right) => Ok(Object::Null),
+        [left, right] => {
+            let message = format!("assertion failed: {} != {}", left, right);
+            lock(&failures).failure = Some(message);
+            Err("assertion failed")
+        }
+        _ => Err("wrong number of arguments"),
+    });
+}
+
+fn bind<'a, F>(env: &mut Env<'a>, name: &'static str, func: F)
+where
+    F: Fn(&[Object<'a>]) -> Result<Object<'a>, &'static str> + MaybeSync + 'a,
+{
+    let func = Box::new(func);
+    env.set(
+        name.as_bytes(),
+        Object::Host(Rc::new(HostFunction { name, func })),
+    );
+}
+
+// compares values structurally, functions and other handles only equal
+// themselves
+fn equal<'a>(left: &Object<'a>, right: &Object<'a>) -> bool {
+    match (left, right) {
+        (Object::Integer(left), Object::Integer(right)) => left == right,
+        (Object::Float(left), Object::Float(right)) => left == right,
+        (Object::Bool(left), Object::Bool(right)) => left == right,
+        (Object::Str(left), Object::Str(right)) | (Object::Error(left), Object::Error(right)) => {
+            left == right
+        }
+        (Object::Null, Object::Null) => true,
+        (Object::Array(left), Object::Array(right)) => {
+            left.len() == right.len() && left.iter().zip(right.iter()).all(|(l, r)| equal(l, r))
+        }
+        (Object::Hash(left), Object::Hash(right)) => {
+            left.len() == right.len()
+                && left
+                    .iter()
+                    .zip(right.iter())
+                    .all(|((lk, lv), (rk, rv))| lk == rk && equal(lv, rv))
+        }
+        (Object::Function(left), Object::Function(right)) => Rc::ptr_eq(left, right),
+        (Object::Closure(left), Object::Closure(right)) => Rc::ptr_eq(left, right),
+        (Object::Builtin(left), Object::Builtin(right)) => std::ptr::eq(*left, *right),
+        (Object::Host(left), Object::Host(right)) => Rc::ptr_eq(left, right),
+        (Object::Channel(left), Object::Channel(right)) => Rc::ptr_eq(left, right),
+        (Object::Task(left), Object::Task(right)) => Rc::ptr_eq(left, right),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::{coverage::Coverage, interpreter::Error, sandbox::SandboxConfig};
+
+    use super::{discover, run_tests, TestResult};
+
+    fn run(source: &str) -> Result<Vec<TestResult>, Error> {
+        run_tests(source, SandboxConfig::default(), None)
+    }
+
+    fn result(name: &str, outcome: Result<(), &str>) -> TestResult {
+        TestResult {
+            name: name.to_string(),
+            outcome: outcome.map_err(str::to_string),
+        }
+    }
+
+    #[test]
+    fn test_run_tests() {
+        let source = "
+            let add = fn(a, b) { a + b };
+            test(1, fn() { assert_eq(add(1, 2), 3) });
+            test(2, fn() { assert_eq(add(1, 2), 4) });
+            test(3, fn() { assert(add(1, 1) > 2) });
+            test(4, fn() { add(1, true) });
+            test([5], fn() { assert_eq([1, [true]], [1, [true]]); assert(true) });
+        ";
+        assert_eq!(
+            run(source).unwrap(),
+            [
+                result("1", Ok(())),
+                result("2", Err("assertion failed: 3 != 4")),
+                result("3", Err("assertion failed")),
+                result("4", Err("operand can only be applied to numbers")),
+                result("[5]", Ok(())),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_limits_per_test() {
+        let source = "
+            let count = fn(n) { if (n > 0) { 1 + count(n - 1) } else { 0 } };
+            test(1, fn() { assert_eq(count(5), 5) });
+            test(2, fn() { assert_eq(count(5), 5) });
+            test(3, fn() { assert_eq(count(50), 50) });
+        ";
+        let config = SandboxConfig {
+            max_call_depth: Some(10),
+            ..Default::default()
+        };
+        assert_eq!(
+            run_tests(source, config, None).unwrap(),
+            [
+                result("1", Ok(())),
+                result("2", Ok(())),
+                result("3", Err("call depth limit exceeded")),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_coverage() {
+        let source = "let f = fn(n) {
+            if (n > 0) {
+                1
+            } else {
+                2
+            }
+        };
+        test(1, fn() {
+            assert_eq(f(1), 1)
+        });
+        test(2, fn() {
+            assert_eq(f(0), 2)
+        });";
+        let coverage = Coverage::new(source);
+        run_tests(source, SandboxConfig::default(), Some(&coverage)).unwrap();
+        assert!(coverage.missed().is_empty(), "{:?}", coverage.missed());
+        assert_eq!(coverage.executed().len(), 8);
+    }
+
+    #[test]
+    fn test_script_errors() {
+        assert!(matches!(run("let = 1;"), Err(Error::Parse(_))));
+        let err = run("test(1, 2)").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "runtime error: argument to `test` must be a function"
+        );
+        assert_eq!(
+            err.span().map(|span| span.to_string()).as_deref(),
+            Some("1:1")
+        );
+        assert_eq!(
+            run("assert(1)").unwrap_err().to_string(),
+            "runtime error: argument to `assert` must be a boolean"
+        );
+        assert_eq!(run("let a = 1;").unwrap(), []);
+    }
+
+    #[test]
+    fn test_discover() {
+        let dir = std::env::temp_dir().join(format!("monkey-discover-{}", std::process::id()));
+        fs::create_dir_all(dir.join("nested")).unwrap();
+        for file in ["b_test.mk", "a_test.mk", "helper.mk", "nested/c_test.mk"] {
+            fs::write(dir.join(file), "").unwrap();
+        }
+        let found = discover(&dir).unwrap();
+        let single = discover(&dir.join("helper.mk")).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            found,
+            [
+                dir.join("a_test.mk"),
+                dir.join("b_test.mk"),
+                dir.join("nested/c_test.mk")
+            ]
+        );
+        assert_eq!(single, [dir.join("helper.mk")]);
+    }
+}
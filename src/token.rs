@@ -1,26 +1,35 @@
 #![allow(unused)]
 
 use core::fmt;
+use std::ops::{Deref, Range};
+
+use crate::rc::Rc;
 
 pub const EOF: Token = Token::new(TokenType::EOF, b"\0");
 
 #[derive(Debug, Copy, Clone)]
 pub enum Prec {
     Lowest,
+    Ternary,
     Equals,
     LtOrGt,
     Sum,
     Product,
     Prefix,
     FnCall,
+    Index,
 }
 
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
 pub enum TokenType {
     // Unknown token
     ILLEGAL,
     EOF,
     INT,
+    FLOAT,
+    STRING,
     // Operators
     BANG,
     MINUS,
@@ -35,11 +44,17 @@ pub enum TokenType {
     // Delimiters
     COMMA,
     SEMICOLON,
+    COLON,
+    QUESTION,
+    FATARROW,
+    ELLIPSIS,
     // Misc
     LPAREN,
     RPAREN,
     LBRACE,
     RBRACE,
+    LBRACKET,
+    RBRACKET,
     // Identifiers + literals
     IDENT,
     FUNCTION,
@@ -49,6 +64,14 @@ pub enum TokenType {
     TRUE,
     ELSE,
     FALSE,
+    DEBUGGER,
+    FOR,
+    IN,
+    BREAK,
+    CONTINUE,
+    MATCH,
+    TRY,
+    CATCH,
 }
 
 impl TokenType {
@@ -63,34 +86,168 @@ impl TokenType {
             Self::FSLASH => Prec::Product,
             Self::MUL => Prec::Product,
             Self::LPAREN => Prec::FnCall,
+            Self::LBRACKET => Prec::Index,
+            Self::QUESTION => Prec::Ternary,
             _ => Prec::Lowest,
         }
     }
 }
 
-impl<'a> std::fmt::Display for Token<'a> {
+impl std::fmt::Display for Token {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", std::str::from_utf8(self.literal).unwrap())
+        write!(f, "{}", std::str::from_utf8(&self.literal).unwrap())
+    }
+}
+
+// the text of a token, a range of the source it was read from, which every
+// token read from it shares, so the ast owns its text without a copy per
+// token and without borrowing the source
+#[derive(Clone)]
+pub struct Text(Repr);
+
+#[derive(Clone)]
+enum Repr {
+    Static(&'static [u8]),
+    // the range is kept as u32s, tokens are in every node of the ast and
+    // a source over 4gb isn't something to run
+    Shared(Rc<[u8]>, u32, u32),
+}
+
+impl Text {
+    pub const fn from_static(text: &'static [u8]) -> Self {
+        Text(Repr::Static(text))
+    }
+
+    pub fn shared(source: &Rc<[u8]>, range: Range<usize>) -> Self {
+        debug_assert!(source.get(range.clone()).is_some());
+        Text(Repr::Shared(
+            Rc::clone(source),
+            range.start as u32,
+            range.end as u32,
+        ))
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        match &self.0 {
+            Repr::Static(text) => text,
+            Repr::Shared(source, start, end) => &source[*start as usize..*end as usize],
+        }
     }
 }
 
-pub type Literal<'a> = &'a [u8];
+impl Deref for Text {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.as_bytes()
+    }
+}
+
+// text that isn't part of a source is copied into one of its own
+impl From<&[u8]> for Text {
+    fn from(text: &[u8]) -> Self {
+        Text::shared(&Rc::from(text), 0..text.len())
+    }
+}
+
+impl fmt::Debug for Text {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.as_bytes().fmt(f)
+    }
+}
+
+impl PartialEq for Text {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_bytes() == other.as_bytes()
+    }
+}
 
-#[derive(Debug, Copy, Clone, PartialEq)]
-pub struct Token<'a> {
+impl PartialEq<[u8]> for Text {
+    fn eq(&self, other: &[u8]) -> bool {
+        self.as_bytes() == other
+    }
+}
+
+impl PartialEq<&[u8]> for Text {
+    fn eq(&self, other: &&[u8]) -> bool {
+        self.as_bytes() == *other
+    }
+}
+
+impl<const N: usize> PartialEq<[u8; N]> for Text {
+    fn eq(&self, other: &[u8; N]) -> bool {
+        self.as_bytes() == other
+    }
+}
+
+impl<const N: usize> PartialEq<&[u8; N]> for Text {
+    fn eq(&self, other: &&[u8; N]) -> bool {
+        self.as_bytes() == *other
+    }
+}
+
+// where a token was read from, the line and column of its first character
+// count from 1 and columns count characters rather than bytes, `start..end`
+// are the bytes of the whole token, quotes included
+//
+// tokens that weren't read by a lexer have the default span, on line 0
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+pub struct Span {
+    pub line: usize,
+    pub column: usize,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+pub struct Token {
     pub token_type: TokenType,
-    pub literal: &'a [u8],
+    // the text as a string, like the source it was read from
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde::text"))]
+    pub literal: Text,
+    pub span: Span,
 }
 
-impl<'a> Token<'a> {
-    pub const fn new(token_type: TokenType, literal: Literal<'a>) -> Self {
+impl Token {
+    pub const fn new(token_type: TokenType, literal: &'static [u8]) -> Self {
+        Token::with_text(token_type, Text::from_static(literal))
+    }
+
+    pub const fn with_text(token_type: TokenType, literal: Text) -> Self {
         Token {
             token_type,
             literal,
+            span: Span {
+                line: 0,
+                column: 0,
+                start: 0,
+                end: 0,
+            },
         }
     }
 }
 
+// the same token wherever it was read, so reparsed programs compare equal
+impl PartialEq for Token {
+    fn eq(&self, other: &Self) -> bool {
+        self.token_type == other.token_type && self.literal == other.literal
+    }
+}
+
+// the identifiers `lookup_ident` doesn't read as IDENT
+pub const KEYWORDS: &[&str] = &[
+    "fn", "let", "true", "false", "return", "if", "else", "debugger", "for", "in", "break",
+    "continue", "match", "try", "catch",
+];
+
 pub fn lookup_ident(ident: &[u8]) -> TokenType {
     match ident {
         b"fn" => TokenType::FUNCTION,
@@ -100,6 +257,14 @@ pub fn lookup_ident(ident: &[u8]) -> TokenType {
         b"return" => TokenType::RETURN,
         b"if" => TokenType::IF,
         b"else" => TokenType::ELSE,
+        b"debugger" => TokenType::DEBUGGER,
+        b"for" => TokenType::FOR,
+        b"in" => TokenType::IN,
+        b"break" => TokenType::BREAK,
+        b"continue" => TokenType::CONTINUE,
+        b"match" => TokenType::MATCH,
+        b"try" => TokenType::TRY,
+        b"catch" => TokenType::CATCH,
         _ => TokenType::IDENT,
     }
 }
@@ -0,0 +1,1625 @@
+
+use std::collections::BTreeMap;
+
+use crate::{
+    ast::Program,
+    builtins::{Builtin, BUILTINS},
+    code::{read_u16, Instructions, Opcode},
+    compiler::{Bytecode, Compiler},
+    diagnostic::{self, Diagnostic},
+    evaluator::Truthiness,
+    object::{Closure, CompiledFunction, HashKey, Object},
+    rc::Rc,
+    sandbox::{CancellationToken, Meter, SandboxConfig},
+    symbol_table::{SymbolScope, SymbolTable},
+    tasks::{self, Scheduler, Task, TaskOp},
+    token::Span,
+};
+
+#[cfg(feature = "jit")]
+use crate::jit::{Exit, Jit};
+
+const MAX_FRAMES: usize = 1024;
+// room for the arguments, locals and temporaries of every frame, so deep
+// recursion runs into the frame or call depth limit before the stack
+const STACK_SIZE: usize = MAX_FRAMES * 8;
+const OVERFLOW: &str = "integer overflow";
+// OpGetGlobal/OpSetGlobal take a u16 operand
+const GLOBALS_SIZE: usize = 65536;
+
+#[derive(Debug, Clone, Copy)]
+pub struct VmConfig {
+    pub stack_size: usize,
+    pub max_frames: usize,
+    pub globals_size: usize,
+    pub sandbox: SandboxConfig,
+    pub truthiness: Truthiness,
+}
+
+impl Default for VmConfig {
+    fn default() -> Self {
+        VmConfig {
+            stack_size: STACK_SIZE,
+            max_frames: MAX_FRAMES,
+            globals_size: GLOBALS_SIZE,
+            sandbox: SandboxConfig::default(),
+            truthiness: Truthiness::default(),
+        }
+    }
+}
+
+struct Frame {
+    closure: Rc<Closure<'static>>,
+    ip: usize,
+    base_pointer: usize,
+}
+
+// where an error in the body of a `try` goes, see `catch`
+struct Handler {
+    frames: usize,
+    sp: usize,
+    ip: usize,
+}
+
+pub struct Vm {
+    constants: Vec<Object<'static>>,
+    stack: Vec<Object<'static>>,
+    sp: usize,
+    globals: Vec<Object<'static>>,
+    frames: Vec<Frame>,
+    max_frames: usize,
+    meter: Meter,
+    truthiness: Truthiness,
+    started: bool,
+    tasks: Scheduler<'static>,
+    last_popped: Object<'static>,
+    // the `try` bodies running, innermost last
+    handlers: Vec<Handler>,
+    // the handlers a task started below them can't use, they're the
+    // caller's
+    handler_floor: usize,
+    // set by the errors a `try` doesn't catch, like the sandbox's limits
+    aborted: bool,
+    #[cfg(feature = "jit")]
+    jit: Jit,
+    // the function and how many calls of it native code had nested when it
+    // ran out of frames, they're part of the backtrace
+    #[cfg(feature = "jit")]
+    native_frames: Option<(Rc<CompiledFunction>, usize)>,
+}
+
+impl Vm {
+    pub fn new(bytecode: Bytecode) -> Self {
+        Vm::with_config(bytecode, VmConfig::default())
+    }
+
+    pub fn with_config(bytecode: Bytecode, config: VmConfig) -> Self {
+        let globals = vec![Object::Null; config.globals_size];
+        Vm::with_globals(bytecode, config, globals)
+    }
+
+    // starts with the globals an earlier vm left, see `into_globals`, so code
+    // compiled against the same symbol table sees what that one defined
+    pub fn with_globals(
+        bytecode: Bytecode,
+        config: VmConfig,
+        globals: Vec<Object<'static>>,
+    ) -> Self {
+        let main_fn = CompiledFunction {
+            instructions: bytecode.instructions,
+            num_locals: 0,
+            num_parameters: 0,
+            rest: false,
+            name: None,
+            spans: bytecode.spans,
+        };
+        let main_closure = Closure {
+            function: Rc::new(main_fn),
+            free: Vec::new(),
+        };
+        Vm {
+            constants: bytecode.constants,
+            stack: vec![Object::Null; config.stack_size],
+            sp: 0,
+            globals,
+            frames: vec![Frame {
+                closure: Rc::new(main_closure),
+                ip: 0,
+                base_pointer: 0,
+            }],
+            max_frames: config.max_frames,
+            meter: Meter::new(config.sandbox),
+            truthiness: config.truthiness,
+            started: false,
+            tasks: Scheduler::default(),
+            last_popped: Object::Null,
+            handlers: Vec::new(),
+            handler_floor: 0,
+            aborted: false,
+            #[cfg(feature = "jit")]
+            jit: Jit::new(),
+            #[cfg(feature = "jit")]
+            native_frames: None,
+        }
+    }
+
+    pub fn with_cancellation(mut self, token: CancellationToken) -> Self {
+        self.meter.cancel = Some(token);
+        self
+    }
+
+    // the instructions the run executed so far
+    pub fn steps(&self) -> u64 {
+        self.meter.steps()
+    }
+
+    // the calls in progress as `in f`, like the evaluator's backtrace, after
+    // an error they're the calls it happened in
+    pub fn backtrace(&self) -> Vec<String> {
+        let frames = self.frames[1..]
+            .iter()
+            .rev()
+            .map(|frame| &frame.closure.function);
+        #[cfg(feature = "jit")]
+        let frames = self
+            .native_frames
+            .iter()
+            .flat_map(|(function, calls)| std::iter::repeat_n(function, *calls))
+            .chain(frames);
+        diagnostic::calls(frames.map(|function| match &function.name {
+            Some(name) => name.to_string(),
+            None => format!("fn/{}", function.num_parameters),
+        }))
+    }
+
+    // where the statement of the instruction that last ran starts, after an
+    // error the one it happened in, None for code the compiler placed nowhere
+    pub fn span(&self) -> Option<Span> {
+        let ip = self.ip();
+        let spans = &self.function().spans;
+        spans
+            .iter()
+            .rev()
+            .find(|(start, _)| *start < ip)
+            .map(|(_, span)| *span)
+    }
+
+    pub fn into_globals(self) -> Vec<Object<'static>> {
+        self.globals
+    }
+
+    // runs until the main instructions are exhausted and returns the value
+    // of the last expression statement, like `eval_program` does
+    pub fn run(&mut self) -> Result<Object<'static>, String> {
+        loop {
+            if let Some(result) = self.step()? {
+                while let Some(task) = self.tasks.start_next() {
+                    self.run_task(&task)?;
+                }
+                return Ok(result);
+            }
+        }
+    }
+
+    // calls `function` with `args` instead of running the main program, like
+    // a host calling back into monkey, the function is one the program made
+    // or a value it can call
+    pub fn call_value(
+        &mut self,
+        function: Object<'static>,
+        args: Vec<Object<'static>>,
+    ) -> Result<Object<'static>, String> {
+        if !self.started {
+            self.started = true;
+            self.meter.start().map_err(|err| self.abort(err))?;
+        }
+        let num_args = args.len();
+        self.push(function)?;
+        for arg in args {
+            self.push(arg)?;
+        }
+        self.call(num_args)?;
+        while self.frames.len() > 1 {
+            self.step()?;
+        }
+        let result = self.pop();
+        while let Some(task) = self.tasks.start_next() {
+            self.run_task(&task)?;
+        }
+        Ok(result)
+    }
+
+    // executes a single instruction, returns the result of the program once
+    // there is nothing left to execute
+    #[inline]
+    pub fn step(&mut self) -> Result<Option<Object<'static>>, String> {
+        match self.execute() {
+            Err(err) => self.catch(err).map(|()| None),
+            result => result,
+        }
+    }
+
+    // errors that end the run however many `try` bodies it's in, like the
+    // evaluator's aborts
+    fn abort(&mut self, err: &'static str) -> String {
+        self.aborted = true;
+        err.to_string()
+    }
+
+    // hands an error to the innermost `try` of the run, which goes on from
+    // its handler with the error on the stack
+    fn catch(&mut self, err: String) -> Result<(), String> {
+        if self.aborted || self.handlers.len() <= self.handler_floor {
+            return Err(err);
+        }
+        let handler = self.handlers.pop().expect("checked handler");
+        self.frames.truncate(handler.frames);
+        while self.sp > handler.sp {
+            self.pop();
+        }
+        self.frames.last_mut().expect("vm has no frame").ip = handler.ip;
+        let error = Object::Error(err.into());
+        self.meter.allocate(&error).map_err(|err| self.abort(err))?;
+        self.push(error)
+    }
+
+    #[inline]
+    fn execute(&mut self) -> Result<Option<Object<'static>>, String> {
+        if !self.started {
+            self.started = true;
+            self.meter.start().map_err(|err| self.abort(err))?;
+        }
+        self.meter.tick().map_err(|err| self.abort(err))?;
+        let Some(op) = self.fetch()? else {
+            return Ok(Some(std::mem::replace(&mut self.last_popped, Object::Null)));
+        };
+        match op {
+            Opcode::Constant => {
+                let idx = self.read_u16_operand();
+                self.push(self.constants[idx].clone())?;
+            }
+            Opcode::Pop => {
+                self.last_popped = self.pop();
+            }
+            Opcode::True => self.push(Object::Bool(true))?,
+            Opcode::False => self.push(Object::Bool(false))?,
+            Opcode::Add
+            | Opcode::Sub
+            | Opcode::Mul
+            | Opcode::Div
+            | Opcode::Equal
+            | Opcode::NotEqual
+            | Opcode::GreaterThan
+            | Opcode::LessThan => {
+                let right = self.pop();
+                let left = self.pop();
+                let value = binary_op(op, left, right)?;
+                self.meter.allocate(&value).map_err(|err| self.abort(err))?;
+                self.push(value)?;
+            }
+            Opcode::Bang => match self.truthiness.test(&self.pop()) {
+                Some(value) => self.push(Object::Bool(!value))?,
+                None => return Err("operator '!' only applies to boolean types".to_string()),
+            },
+            Opcode::Minus => match self.pop() {
+                Object::Integer(value) => {
+                    self.push(Object::Integer(value.checked_neg().ok_or(OVERFLOW)?))?
+                }
+                Object::Float(value) => self.push(Object::Float(-value))?,
+                _ => return Err("operator '-' only applies to numbers".to_string()),
+            },
+            Opcode::Call => {
+                let num_args = self.read_u8_operand();
+                self.call(num_args)?;
+            }
+            Opcode::ReturnValue => {
+                let value = self.pop();
+                if self.frames.len() == 1 {
+                    return Ok(Some(value));
+                }
+                self.return_from_frame();
+                self.push(value)?;
+            }
+            Opcode::Return => {
+                self.return_from_frame();
+                self.push(Object::Null)?;
+            }
+            Opcode::GetLocal => {
+                let idx = self.read_u8_operand();
+                let base_pointer = self.frame().base_pointer;
+                self.push(self.stack[base_pointer + idx].clone())?;
+            }
+            Opcode::Closure => {
+                let idx = self.read_u16_operand();
+                let num_free = self.read_u8_operand();
+                self.push_closure(idx, num_free)?;
+            }
+            Opcode::GetFree => {
+                let idx = self.read_u8_operand();
+                let value = self.frame().closure.free[idx].clone();
+                self.push(value)?;
+            }
+            Opcode::Null => self.push(Object::Null)?,
+            Opcode::Jump => {
+                let target = self.read_u16_operand();
+                self.frames.last_mut().expect("vm has no frame").ip = target;
+            }
+            Opcode::JumpNotTruthy => {
+                let target = self.read_u16_operand();
+                match self.truthiness.test(&self.pop()) {
+                    Some(true) => {}
+                    Some(false) => {
+                        self.frames.last_mut().expect("vm has no frame").ip = target;
+                    }
+                    None => return Err("conditional expression isn't a boolean".to_string()),
+                }
+            }
+            Opcode::SetGlobal => {
+                let idx = self.read_u16_operand();
+                let value = self.pop();
+                *self.global_mut(idx)? = value;
+                // a trailing let leaves the program without a value, same
+                // as in the evaluator
+                self.last_popped = Object::Null;
+            }
+            Opcode::GetGlobal => {
+                let idx = self.read_u16_operand();
+                let value = self.global_mut(idx)?.clone();
+                self.push(value)?;
+            }
+            Opcode::SetLocal => {
+                let idx = self.read_u8_operand();
+                let base_pointer = self.frame().base_pointer;
+                self.stack[base_pointer + idx] = self.pop();
+            }
+            Opcode::Array => {
+                let len = self.read_u16_operand();
+                let elements = self.stack[self.sp - len..self.sp].to_vec();
+                self.sp -= len;
+                let array = Object::Array(Rc::new(elements));
+                self.meter.allocate(&array).map_err(|err| self.abort(err))?;
+                self.push(array)?;
+            }
+            Opcode::Index => {
+                let index = self.pop();
+                let left = self.pop();
+                self.push(index_op(left, index)?)?;
+            }
+            Opcode::GetBuiltin => {
+                let idx = self.read_u8_operand();
+                self.push(Object::Builtin(&BUILTINS[idx]))?;
+            }
+            Opcode::Hash => {
+                let len = self.read_u16_operand();
+                let mut pairs = BTreeMap::new();
+                for pair in self.stack[self.sp - 2 * len..self.sp].chunks(2) {
+                    let key = HashKey::from_object(&pair[0]).ok_or("unusable as hash key")?;
+                    pairs.insert(key, pair[1].clone());
+                }
+                self.sp -= 2 * len;
+                let hash = Object::Hash(Rc::new(pairs));
+                self.meter.allocate(&hash).map_err(|err| self.abort(err))?;
+                self.push(hash)?;
+            }
+            Opcode::Iter => match self.pop() {
+                array @ Object::Array(_) => {
+                    self.push(array)?;
+                    self.push(Object::Integer(0))?;
+                }
+                _ => return Err("for loops can only iterate over arrays".to_string()),
+            },
+            Opcode::IterNext => {
+                let target = self.read_u16_operand();
+                let (Object::Array(elements), Object::Integer(idx)) =
+                    (&self.stack[self.sp - 2], &self.stack[self.sp - 1])
+                else {
+                    return Err("OpIterNext without an array".to_string());
+                };
+                match elements.get(*idx as usize).cloned() {
+                    Some(element) => {
+                        self.stack[self.sp - 1] = Object::Integer(idx + 1);
+                        self.push(element)?;
+                    }
+                    None => {
+                        self.pop();
+                        self.pop();
+                        self.frames.last_mut().expect("vm has no frame").ip = target;
+                    }
+                }
+            }
+            Opcode::Try => {
+                let ip = self.read_u16_operand();
+                self.handlers.push(Handler {
+                    frames: self.frames.len(),
+                    sp: self.sp,
+                    ip,
+                });
+            }
+            Opcode::EndTry => {
+                self.handlers.pop();
+            }
+            Opcode::Matches => {
+                // values that can't be compared just don't match
+                let pattern = self.pop();
+                let subject = self.stack[self.sp - 1].clone();
+                let equal = binary_op(Opcode::Equal, subject, pattern);
+                self.push(Object::Bool(matches!(equal, Ok(Object::Bool(true)))))?;
+            }
+            Opcode::Destructure => {
+                let len = self.read_u8_operand();
+                let Object::Array(elements) = self.pop() else {
+                    return Err("only arrays can be destructured".to_string());
+                };
+                // names past the end of the array are bound to nil
+                for i in (0..len).rev() {
+                    self.push(elements.get(i).cloned().unwrap_or(Object::Null))?;
+                }
+            }
+            Opcode::CurrentClosure => {
+                let closure = Rc::clone(&self.frame().closure);
+                self.push(Object::Closure(closure))?;
+            }
+        }
+        Ok(None)
+    }
+
+    // the `try` bodies of the frame end with it
+    fn return_from_frame(&mut self) {
+        let frame = self.frames.pop().expect("no frame to return from");
+        self.sp = frame.base_pointer - 1;
+        while self
+            .handlers
+            .last()
+            .is_some_and(|handler| handler.frames > self.frames.len())
+        {
+            self.handlers.pop();
+        }
+    }
+
+    pub fn function(&self) -> &CompiledFunction {
+        &self.frame().closure.function
+    }
+
+    pub fn ip(&self) -> usize {
+        self.frame().ip
+    }
+
+    pub fn depth(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn stack(&self) -> &[Object<'static>] {
+        &self.stack[..self.sp]
+    }
+
+    pub fn locals(&self) -> &[Object<'static>] {
+        let frame = self.frame();
+        &self.stack[frame.base_pointer..frame.base_pointer + frame.closure.function.num_locals]
+    }
+
+    fn global_mut(&mut self, idx: usize) -> Result<&mut Object<'static>, String> {
+        self.globals
+            .get_mut(idx)
+            .ok_or_else(|| "globals overflow".to_string())
+    }
+
+    fn frame(&self) -> &Frame {
+        self.frames.last().expect("vm has no frame")
+    }
+
+    fn fetch(&mut self) -> Result<Option<Opcode>, String> {
+        let frame = self.frames.last_mut().expect("vm has no frame");
+        let Some(&byte) = frame.closure.function.instructions.0.get(frame.ip) else {
+            return Ok(None);
+        };
+        frame.ip += 1;
+        Opcode::from_byte(byte)
+            .map(Some)
+            .ok_or_else(|| format!("undefined opcode {}", byte))
+    }
+
+    fn read_u8_operand(&mut self) -> usize {
+        let frame = self.frames.last_mut().expect("vm has no frame");
+        let operand = frame.closure.function.instructions.0[frame.ip];
+        frame.ip += 1;
+        operand as usize
+    }
+
+    fn read_u16_operand(&mut self) -> usize {
+        let frame = self.frames.last_mut().expect("vm has no frame");
+        let operand = read_u16(&frame.closure.function.instructions.0[frame.ip..]);
+        frame.ip += 2;
+        operand as usize
+    }
+
+    fn push(&mut self, obj: Object<'static>) -> Result<(), String> {
+        if self.sp >= self.stack.len() {
+            return Err(self.abort("stack overflow"));
+        }
+        self.stack[self.sp] = obj;
+        self.sp += 1;
+        Ok(())
+    }
+
+    fn pop(&mut self) -> Object<'static> {
+        self.sp -= 1;
+        std::mem::replace(&mut self.stack[self.sp], Object::Null)
+    }
+
+    fn call(&mut self, num_args: usize) -> Result<(), String> {
+        let closure = match &self.stack[self.sp - 1 - num_args] {
+            Object::Closure(closure) => Rc::clone(closure),
+            Object::Builtin(builtin) => return self.call_builtin(builtin, num_args),
+            Object::Host(host) => {
+                let result = (host.func)(&self.stack[self.sp - num_args..self.sp])?;
+                self.meter
+                    .allocate(&result)
+                    .map_err(|err| self.abort(err))?;
+                self.sp -= num_args + 1;
+                return self.push(result);
+            }
+            _ => return Err("not a function".to_string()),
+        };
+        let arity = closure.function.num_parameters;
+        if num_args < arity || num_args > arity && !closure.function.rest {
+            return Err("wrong number of arguments".to_string());
+        }
+        if self.frames.len() >= self.max_frames {
+            return Err(self.abort("frame overflow"));
+        }
+        // the main program runs in a frame of its own
+        self.meter
+            .check_depth(self.frames.len() - 1)
+            .map_err(|err| self.abort(err))?;
+        let mut num_args = num_args;
+        if closure.function.rest {
+            let rest = self.stack[self.sp - (num_args - arity)..self.sp].to_vec();
+            self.sp -= num_args - arity;
+            let rest = Object::Array(Rc::new(rest));
+            self.meter.allocate(&rest).map_err(|err| self.abort(err))?;
+            self.push(rest)?;
+            num_args = arity + 1;
+        }
+        let base_pointer = self.sp - num_args;
+        let num_locals = closure.function.num_locals;
+        if base_pointer + num_locals > self.stack.len() {
+            return Err(self.abort("stack overflow"));
+        }
+        #[cfg(feature = "jit")]
+        if self.call_native(&closure, num_args)? {
+            return Ok(());
+        }
+        self.frames.push(Frame {
+            closure,
+            ip: 0,
+            base_pointer,
+        });
+        self.sp = base_pointer + num_locals;
+        Ok(())
+    }
+
+    #[cfg(feature = "jit")]
+    pub fn jit(&self) -> &Jit {
+        &self.jit
+    }
+
+    // runs the call as native code if the function is hot, compiled and gets
+    // integer arguments, returns false to leave it to the vm
+    #[cfg(feature = "jit")]
+    fn call_native(&mut self, closure: &Closure<'static>, num_args: usize) -> Result<bool, String> {
+        // native code can't stop for the step, time or allocation budgets
+        if !self.meter.is_unmetered() {
+            return Ok(false);
+        }
+        let Some(native) = self
+            .jit
+            .lookup(&closure.function, &self.constants, &self.globals)
+        else {
+            return Ok(false);
+        };
+        if let Some(idx) = native.self_global {
+            match &self.globals[idx] {
+                Object::Closure(global) if Rc::ptr_eq(&global.function, &closure.function) => {}
+                _ => return Ok(false),
+            }
+        }
+        let mut args = Vec::with_capacity(num_args);
+        for arg in &self.stack[self.sp - num_args..self.sp] {
+            match arg {
+                Object::Integer(value) => args.push(*value),
+                _ => return Ok(false),
+            }
+        }
+
+        // native calls don't use the vm stack but are held to the same budget,
+        // each taking the slots for the closure and its locals
+        let frames_left = self.max_frames - self.frames.len() - 1;
+        let depth_left = self
+            .meter
+            .config
+            .max_call_depth
+            .map_or(usize::MAX, |max| max - self.frames.len());
+        let stack_left = (self.stack.len() - self.sp).saturating_sub(native.max_height)
+            / (1 + closure.function.num_locals);
+        let frames = frames_left.min(depth_left).min(stack_left);
+        match native.call(&args, frames) {
+            Ok(value) => {
+                self.sp -= num_args + 1;
+                self.push(Object::Integer(value))?;
+                Ok(true)
+            }
+            Err(Exit::Overflow) => Err(OVERFLOW.to_string()),
+            Err(Exit::OutOfFrames) => {
+                // the call itself and the ones nested in it
+                self.native_frames = Some((Rc::clone(&closure.function), frames + 1));
+                Err(self.abort(if stack_left < frames_left.min(depth_left) {
+                    "stack overflow"
+                } else if depth_left < frames_left {
+                    "call depth limit exceeded"
+                } else {
+                    "frame overflow"
+                }))
+            }
+        }
+    }
+
+    fn call_builtin(&mut self, builtin: &'static Builtin, num_args: usize) -> Result<(), String> {
+        self.meter
+            .check_capability(builtin.capability)
+            .map_err(|err| self.abort(err))?;
+        let args = self.sp - num_args..self.sp;
+        let result = match TaskOp::of(builtin) {
+            Some(op) => {
+                let args = self.stack[args].to_vec();
+                self.apply_task_op(op, &args)?
+            }
+            None => (builtin.func)(&self.stack[args])?,
+        };
+        self.meter
+            .allocate(&result)
+            .map_err(|err| self.abort(err))?;
+        self.sp -= num_args + 1;
+        self.push(result)
+    }
+
+    // tasks run above the caller's stack, which stays as it is until they're
+    // done
+    fn apply_task_op(
+        &mut self,
+        op: TaskOp,
+        args: &[Object<'static>],
+    ) -> Result<Object<'static>, String> {
+        match op {
+            TaskOp::Spawn => Ok(self.tasks.spawn(args)?),
+            TaskOp::Join => {
+                let task = tasks::join_arg(args)?;
+                if task.start() {
+                    self.run_task(&task)?;
+                }
+                Ok(task.result().ok_or("task is still running")?)
+            }
+            TaskOp::Recv => {
+                let channel = tasks::recv_arg(args)?;
+                loop {
+                    if let Some(value) = channel.try_recv() {
+                        return Ok(value);
+                    }
+                    let Some(task) = self.tasks.start_next() else {
+                        return Err("recv on an empty channel with no tasks left".to_string());
+                    };
+                    self.run_task(&task)?;
+                }
+            }
+        }
+    }
+
+    fn run_task(&mut self, task: &Task<'static>) -> Result<(), String> {
+        let depth = self.frames.len();
+        let floor = std::mem::replace(&mut self.handler_floor, self.handlers.len());
+        let result = self.run_task_frames(task, depth);
+        self.handler_floor = floor;
+        result
+    }
+
+    fn run_task_frames(&mut self, task: &Task<'static>, depth: usize) -> Result<(), String> {
+        self.push(task.function.clone())?;
+        self.call(0)?;
+        while self.frames.len() > depth {
+            self.step()?;
+        }
+        task.finish(self.pop());
+        Ok(())
+    }
+
+    fn push_closure(&mut self, idx: usize, num_free: usize) -> Result<(), String> {
+        let Object::CompiledFunction(function) = &self.constants[idx] else {
+            return Err(format!("not a function: {}", self.constants[idx]));
+        };
+        let function = Rc::clone(function);
+        let free = self.stack[self.sp - num_free..self.sp].to_vec();
+        self.sp -= num_free;
+        let closure = Object::Closure(Rc::new(Closure { function, free }));
+        self.meter
+            .allocate(&closure)
+            .map_err(|err| self.abort(err))?;
+        self.push(closure)
+    }
+}
+
+fn binary_op(
+    op: Opcode,
+    left: Object<'static>,
+    right: Object<'static>,
+) -> Result<Object<'static>, String> {
+    match (left, right) {
+        (Object::Integer(left), Object::Integer(right)) => Ok(match op {
+            Opcode::Add => Object::Integer(left.checked_add(right).ok_or(OVERFLOW)?),
+            Opcode::Sub => Object::Integer(left.checked_sub(right).ok_or(OVERFLOW)?),
+            Opcode::Mul => Object::Integer(left.checked_mul(right).ok_or(OVERFLOW)?),
+            Opcode::Div if right == 0 => return Err("division by zero".to_string()),
+            Opcode::Div => Object::Integer(left.checked_div(right).ok_or(OVERFLOW)?),
+            Opcode::GreaterThan => Object::Bool(left > right),
+            Opcode::LessThan => Object::Bool(left < right),
+            Opcode::Equal => Object::Bool(left == right),
+            Opcode::NotEqual => Object::Bool(left != right),
+            _ => return Err("operator not supported for given types".to_string()),
+        }),
+        (Object::Float(left), Object::Float(right)) => float_op(op, left, right),
+        (Object::Integer(left), Object::Float(right)) => float_op(op, left as f64, right),
+        (Object::Float(left), Object::Integer(right)) => float_op(op, left, right as f64),
+        (Object::Bool(left), Object::Bool(right)) => Ok(match op {
+            Opcode::Equal => Object::Bool(left == right),
+            Opcode::NotEqual => Object::Bool(left != right),
+            _ => return Err("operator not supported for given types".to_string()),
+        }),
+        (Object::Str(left), Object::Str(right)) => Ok(match op {
+            Opcode::Add => Object::Str([&*left, &*right].concat().into()),
+            Opcode::Equal => Object::Bool(left == right),
+            Opcode::NotEqual => Object::Bool(left != right),
+            _ => return Err("operator not supported for given types".to_string()),
+        }),
+        _ => Err("operand can only be applied to numbers".to_string()),
+    }
+}
+
+fn float_op(op: Opcode, left: f64, right: f64) -> Result<Object<'static>, String> {
+    Ok(match op {
+        Opcode::Add => Object::Float(left + right),
+        Opcode::Sub => Object::Float(left - right),
+        Opcode::Mul => Object::Float(left * right),
+        Opcode::Div => Object::Float(left / right),
+        Opcode::GreaterThan => Object::Bool(left > right),
+        Opcode::LessThan => Object::Bool(left < right),
+        Opcode::Equal => Object::Bool(left == right),
+        Opcode::NotEqual => Object::Bool(left != right),
+        _ => return Err("operator not supported for given types".to_string()),
+    })
+}
+
+fn index_op(left: Object<'static>, index: Object<'static>) -> Result<Object<'static>, String> {
+    match (left, index) {
+        (Object::Array(elements), Object::Integer(idx)) => Ok(usize::try_from(idx)
+            .ok()
+            .and_then(|idx| elements.get(idx).cloned())
+            .unwrap_or(Object::Null)),
+        (Object::Hash(pairs), index) => match HashKey::from_object(&index) {
+            Some(key) => Ok(pairs.get(&key).cloned().unwrap_or(Object::Null)),
+            None => Err("unusable as hash key".to_string()),
+        },
+        _ => Err("index operator not supported".to_string()),
+    }
+}
+
+// the globals of the programs run on the vm one after the other, like the
+// lines of the repl, each is compiled with the symbol table and constants
+// the previous ones left
+pub struct VmState {
+    symbol_table: SymbolTable,
+    constants: Vec<Object<'static>>,
+    globals: Vec<Object<'static>>,
+}
+
+impl Default for VmState {
+    fn default() -> Self {
+        // a new compiler's table has the builtins
+        let (_, symbol_table) = Compiler::new().into_parts();
+        VmState {
+            symbol_table,
+            constants: vec![],
+            globals: vec![Object::Null; VmConfig::default().globals_size],
+        }
+    }
+}
+
+impl VmState {
+    // compiling errors leave the state as it was, running ones keep what
+    // was assigned before them
+    pub fn run(
+        &mut self,
+        prog: &Program,
+        config: VmConfig,
+        interrupt: &CancellationToken,
+    ) -> (Result<Object<'static>, Diagnostic>, u64) {
+        let mut compiler = Compiler::with_state(self.symbol_table.clone(), self.constants.clone());
+        if let Err(err) = compiler.compile(prog) {
+            return (Err(Diagnostic::error(err, None)), 0);
+        }
+        let (bytecode, symbol_table) = compiler.into_parts();
+        self.symbol_table = symbol_table;
+        self.constants = bytecode.constants.clone();
+        let globals = std::mem::take(&mut self.globals);
+        let mut vm =
+            Vm::with_globals(bytecode, config, globals).with_cancellation(interrupt.clone());
+        let result = vm
+            .run()
+            .map_err(|err| Diagnostic::error(err, vm.span()).with_notes(vm.backtrace()));
+        let steps = vm.steps();
+        self.globals = vm.into_globals();
+        (result, steps)
+    }
+
+    // calls a function one of the programs made, see `Vm::call_value`
+    pub fn call(
+        &mut self,
+        function: Object<'static>,
+        args: Vec<Object<'static>>,
+        config: VmConfig,
+        interrupt: &CancellationToken,
+    ) -> Result<Object<'static>, Diagnostic> {
+        let bytecode = Bytecode {
+            instructions: Instructions(vec![]),
+            constants: self.constants.clone(),
+            spans: vec![],
+        };
+        let globals = std::mem::take(&mut self.globals);
+        let mut vm =
+            Vm::with_globals(bytecode, config, globals).with_cancellation(interrupt.clone());
+        let result = vm
+            .call_value(function, args)
+            .map_err(|err| Diagnostic::error(err, vm.span()).with_notes(vm.backtrace()));
+        self.globals = vm.into_globals();
+        result
+    }
+
+    pub fn set(&mut self, name: &[u8], value: Object<'static>) {
+        let symbol = self.symbol_table.define(name);
+        if let Some(global) = self.globals.get_mut(symbol.index) {
+            *global = value;
+        }
+    }
+
+    pub fn get(&self, name: &[u8]) -> Option<Object<'static>> {
+        let symbol = self
+            .symbol_table
+            .symbols()
+            .find(|symbol| symbol.scope == SymbolScope::Global && symbol.name == name)?;
+        Some(self.globals[symbol.index].clone())
+    }
+
+    // like `Env::bindings`
+    pub fn bindings(&self) -> impl Iterator<Item = (Vec<u8>, Object<'static>)> + '_ {
+        let mut symbols: Vec<_> = self
+            .symbol_table
+            .symbols()
+            .filter(|symbol| symbol.scope == SymbolScope::Global)
+            .collect();
+        symbols.sort_by(|a, b| a.name.cmp(&b.name));
+        symbols
+            .into_iter()
+            .map(|symbol| (symbol.name.clone(), self.globals[symbol.index].clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use crate::{
+        compiler::{Bytecode, Compiler},
+        env::Env,
+        evaluator::eval_program,
+        lexer::Lexer,
+        object::Object,
+        optimizer,
+        parser::Parser,
+        sandbox::SandboxConfig,
+    };
+
+    use super::{Vm, VmConfig, GLOBALS_SIZE, MAX_FRAMES};
+
+    fn compile(input: &str) -> Bytecode {
+        let prog = Parser::new(Lexer::new(input.as_bytes())).parse().unwrap();
+        let mut compiler = Compiler::new();
+        compiler.compile(&prog).unwrap();
+        compiler.bytecode()
+    }
+
+    fn run(input: &str) -> Result<Object<'static>, String> {
+        let prog = Parser::new(Lexer::new(input.as_bytes()))
+            .parse()
+            .expect("failed to parse program");
+        let mut compiler = Compiler::new();
+        compiler.compile(&prog)?;
+        Vm::new(compiler.bytecode()).run()
+    }
+
+    fn assert_int(input: &str, expected: i64) {
+        match run(input) {
+            Ok(Object::Integer(value)) => assert_eq!(value, expected, "{}", input),
+            other => panic!("expected {} for {}, got {:?}", expected, input, other),
+        }
+    }
+
+    fn assert_bool(input: &str, expected: bool) {
+        match run(input) {
+            Ok(Object::Bool(value)) => assert_eq!(value, expected, "{}", input),
+            other => panic!("expected {} for {}, got {:?}", expected, input, other),
+        }
+    }
+
+    #[test]
+    fn test_integer_arithmetic() {
+        let inputs = [
+            ("1", 1),
+            ("1 + 2", 3),
+            ("1 - 2", -1),
+            ("4 / 2", 2),
+            ("50 / 2 * 2 + 10 - 5", 55),
+            ("5 * (2 + 10)", 60),
+            ("-5", -5),
+            ("-50 + 100 + -50", 0),
+            ("(5 + 10 * 2 + 15 / 3) * 2 + -10", 50),
+        ];
+        for (input, expected) in inputs {
+            assert_int(input, expected);
+        }
+    }
+
+    #[test]
+    fn test_boolean_expressions() {
+        let inputs = [
+            ("true", true),
+            ("1 < 2", true),
+            ("1 > 2", false),
+            ("1 == 1", true),
+            ("1 != 2", true),
+            ("true != false", true),
+            ("(1 < 2) == true", true),
+            ("!true", false),
+            ("!!false", false),
+        ];
+        for (input, expected) in inputs {
+            assert_bool(input, expected);
+        }
+    }
+
+    #[test]
+    fn test_global_let_stmts() {
+        let inputs = [
+            ("let one = 1; one", 1),
+            ("let one = 1; let two = 2; one + two", 3),
+            ("let one = 1; let two = one + one; one + two", 3),
+            ("let one = 1; let one = 2; one", 2),
+        ];
+        for (input, expected) in inputs {
+            assert_int(input, expected);
+        }
+        assert!(matches!(run("5; let a = 1;"), Ok(Object::Null)));
+    }
+
+    #[test]
+    fn test_local_bindings() {
+        let inputs = [
+            ("let one = fn() { let one = 1; one }; one();", 1),
+            (
+                "let oneAndTwo = fn() { let one = 1; let two = 2; one + two; }; oneAndTwo();",
+                3,
+            ),
+            (
+                "let firstFoobar = fn() { let foobar = 50; foobar; };
+                 let secondFoobar = fn() { let foobar = 100; foobar; };
+                 firstFoobar() + secondFoobar();",
+                150,
+            ),
+            (
+                "let globalSeed = 50;
+                 let minusOne = fn() { let num = 1; globalSeed - num; }
+                 let minusTwo = fn() { let num = 2; globalSeed - num; }
+                 minusOne() + minusTwo();",
+                97,
+            ),
+            (
+                "let sum = fn(a, b) { let c = a + b; c; };
+                 let outer = fn() { sum(1, 2) + sum(3, 4); };
+                 outer();",
+                10,
+            ),
+            (
+                "let fib = fn(n) { if (n < 2) { n } else { fib(n - 1) + fib(n - 2) } }; fib(15);",
+                610,
+            ),
+            (
+                "let newAdder = fn(a, b) { let c = a + b; fn(d) { c + d } };
+                 let adder = newAdder(1, 2);
+                 adder(8);",
+                11,
+            ),
+        ];
+        for (input, expected) in inputs {
+            assert_int(input, expected);
+        }
+    }
+
+    #[test]
+    fn test_arrays() {
+        let inputs = [
+            ("[]", "[]"),
+            ("[1, 2, 3]", "[1, 2, 3]"),
+            ("[1 + 2, 3 * 4, 5 + 6]", "[3, 12, 11]"),
+            ("[1, 2, 3][1]", "2"),
+            ("[[1, 1, 1]][0][0]", "1"),
+            ("[1, 2, 3][99]", "nil"),
+            ("[1][-1]", "nil"),
+            ("let f = fn(a) { a[0] }; f([7])", "7"),
+        ];
+        for (input, expected) in inputs {
+            assert_eq!(run(input).unwrap().to_string(), expected);
+        }
+        assert_eq!(run("1[0]").unwrap_err(), "index operator not supported");
+    }
+
+    fn run_optimized(input: &str) -> Result<Object<'static>, String> {
+        let prog = Parser::new(Lexer::new(input.as_bytes()))
+            .parse()
+            .expect("failed to parse program");
+        let mut compiler = Compiler::new();
+        compiler.compile(&prog)?;
+        Vm::new(optimizer::optimize(compiler.bytecode())).run()
+    }
+
+    fn run_with_config(input: &str, config: VmConfig) -> Result<Object<'static>, String> {
+        let prog = Parser::new(Lexer::new(input.as_bytes()))
+            .parse()
+            .expect("failed to parse program");
+        let mut compiler = Compiler::new();
+        compiler.compile(&prog)?;
+        Vm::with_config(compiler.bytecode(), config).run()
+    }
+
+    #[test]
+    fn test_config_limits() {
+        let looping = "let f = fn(n) { f(n + 1) }; f(0);";
+        assert_eq!(run(looping).unwrap_err(), "frame overflow");
+        let config = VmConfig {
+            stack_size: 1 << 16,
+            max_frames: 100,
+            ..Default::default()
+        };
+        assert_eq!(
+            run_with_config(looping, config).unwrap_err(),
+            "frame overflow"
+        );
+
+        let countdown = "let f = fn(n) { if (n == 0) { 0 } else { f(n - 1) } }; f(98);";
+        assert!(matches!(
+            run_with_config(countdown, config),
+            Ok(Object::Integer(0))
+        ));
+
+        let config = VmConfig {
+            stack_size: 4,
+            ..Default::default()
+        };
+        assert_eq!(
+            run_with_config("[1, 2, 3, 4, 5]", config).unwrap_err(),
+            "stack overflow"
+        );
+
+        let config = VmConfig {
+            globals_size: 2,
+            ..Default::default()
+        };
+        assert!(run_with_config("let a = 1; let b = 2; a + b", config).is_ok());
+        assert_eq!(
+            run_with_config("let a = 1; let b = 2; let c = 3;", config).unwrap_err(),
+            "globals overflow"
+        );
+    }
+
+    #[test]
+    fn test_globals_carried_over() {
+        // the builtins are defined by a new compiler
+        let (_, mut symbol_table) = Compiler::new().into_parts();
+        let mut constants = vec![];
+        let mut globals = vec![Object::Null; GLOBALS_SIZE];
+        let mut results = vec![];
+        for input in ["let a = 20;", "let add = fn(b) { a + b };", "add(22)"] {
+            let prog = Parser::new(Lexer::new(input.as_bytes())).parse().unwrap();
+            let mut compiler = Compiler::with_state(symbol_table, constants);
+            compiler.compile(&prog).unwrap();
+            let bytecode;
+            (bytecode, symbol_table) = compiler.into_parts();
+            constants = bytecode.constants.clone();
+            let mut vm = Vm::with_globals(bytecode, VmConfig::default(), globals);
+            results.push(vm.run().unwrap().to_string());
+            assert!(vm.steps() > 0);
+            globals = vm.into_globals();
+        }
+        assert_eq!(results, ["nil", "nil", "42"]);
+    }
+
+    #[test]
+    fn test_backtrace() {
+        let input = "
+            let check = fn(n) { if (n > 2) { n + true } else { check(n + 1) } };
+            let run = fn() { check(0) };
+            fn(f) { f() }(run)
+        ";
+        let mut vm = Vm::new(compile(input));
+        assert!(vm.run().is_err());
+        assert_eq!(vm.backtrace(), ["in check (4 times)", "in run", "in fn/1"]);
+
+        let config = VmConfig {
+            max_frames: 100,
+            ..Default::default()
+        };
+        let mut vm = Vm::with_config(compile("let f = fn(n) { f(n + 1) }; f(0)"), config);
+        assert_eq!(vm.run().unwrap_err(), "frame overflow");
+        assert_eq!(vm.backtrace(), ["in f (99 times)"]);
+
+        // at the default limits the frames run out before the stack does
+        let recursive = "let f = fn(n) { if (n == 0) { 0 } else { 1 + f(n - 1) } };";
+        let mut vm = Vm::new(compile(&format!("{} f(1000)", recursive)));
+        assert!(matches!(vm.run(), Ok(Object::Integer(1000))));
+        let mut vm = Vm::new(compile(&format!("{} f(5000)", recursive)));
+        assert_eq!(vm.run().unwrap_err(), "frame overflow");
+        assert_eq!(vm.backtrace(), [format!("in f ({} times)", MAX_FRAMES - 1)]);
+
+        let mut vm = Vm::new(compile("1 + 2"));
+        vm.run().unwrap();
+        assert!(vm.backtrace().is_empty());
+    }
+
+    #[test]
+    fn test_conditionals() {
+        let inputs = [
+            ("if (true) { 10 }", 10),
+            ("if (true) { 10 } else { 20 }", 10),
+            ("if (false) { 10 } else { 20 } ", 20),
+            ("if (1 < 2) { 10 }", 10),
+            ("if (1 > 2) { 10 } else { 20 }", 20),
+            ("if (1 < 2) { 10 } else { 20 } + 5", 15),
+            ("fn(x) { if (x > 1) { return 1; } 2 }(5)", 1),
+            ("fn(x) { if (x > 1) { return 1; } 2 }(0)", 2),
+        ];
+        for (input, expected) in inputs {
+            assert_int(input, expected);
+        }
+        assert!(matches!(run("if (1 > 2) { 10 }"), Ok(Object::Null)));
+        assert!(matches!(run("if (false) { 10 }"), Ok(Object::Null)));
+        assert!(matches!(run("if (true) { }"), Ok(Object::Null)));
+        assert!(run("if (1) { 10 }").is_err());
+    }
+
+    #[test]
+    fn test_calling_functions() {
+        let inputs = [
+            ("fn() { 5 + 10; }()", 15),
+            ("fn() { return 99; 100; }()", 99),
+            ("fn() { fn() { 1; } }()()", 1),
+            ("fn(a) { a; }(4)", 4),
+            ("fn(a, b) { a + b; }(1, 2)", 3),
+        ];
+        for (input, expected) in inputs {
+            assert_int(input, expected);
+        }
+        assert!(matches!(run("fn() { }()"), Ok(Object::Null)));
+    }
+
+    #[test]
+    fn test_calling_functions_with_wrong_arguments() {
+        let inputs = [
+            "fn() { 1; }(1);",
+            "fn(a) { a; }();",
+            "fn(a, b) { a + b; }(1);",
+        ];
+        for input in inputs {
+            assert_eq!(run(input).unwrap_err(), "wrong number of arguments");
+        }
+        assert_eq!(run("5(1)").unwrap_err(), "not a function");
+    }
+
+    #[test]
+    fn test_closures() {
+        let inputs = [
+            ("fn(a) { fn(b) { a + b } }(1)(2)", 3),
+            ("fn(a) { fn(b) { fn(c) { a + b + c } } }(1)(2)(3)", 6),
+            (
+                "fn(a, b) { fn(c) { fn(d) { a + b + c + d } } }(1, 2)(3)(4)",
+                10,
+            ),
+        ];
+        for (input, expected) in inputs {
+            assert_int(input, expected);
+        }
+    }
+
+    #[test]
+    fn test_runtime_errors() {
+        let inputs = ["5 + true", "-true", "!5", "true + false"];
+        for input in inputs {
+            assert!(run(input).is_err(), "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_integer_overflow() {
+        let inputs = [
+            "9223372036854775807 + 1",
+            "-9223372036854775807 - 2",
+            "4611686018427387904 * 2",
+            "(-9223372036854775807 - 1) / -1",
+            "-(-9223372036854775807 - 1)",
+        ];
+        for input in inputs {
+            assert_eq!(run(input).unwrap_err(), "integer overflow", "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_matches_evaluator() {
+        let inputs = [
+            "fn(x) { x * 2 }(21)",
+            "fn(a) { fn(b) { a + b } }(1)(2)",
+            "fn(a) { fn(b) { fn(c) { a * b - c } } }(4)(5)(6)",
+            "fn(f) { f(3) }(fn(x) { x + 1 })",
+            "fn() { return 1; 2 }()",
+            "fn() { }()",
+            "fn(a) { a }(1, 2)",
+            "{{}: (0)()}",
+            "fn(a) { !a }(1)",
+            "if (1 > 2) { 1 }",
+            "if (2 > 1) { } else { 2 }",
+            "fn(n) { if (n > 0) { return n * 2; } else { return 0 - n; } }(-3)",
+            "if (if (false) { 1 }) { 2 }",
+            "if (true) { if (true) { return 10; } return 1; }",
+            "let a = 5; let b = a * 2; b",
+            "let f = fn(x) { let y = x * 2; y + 1 }; f(4)",
+            "let x = 1; if (true) { let x = 2; x + 1 } + x",
+            "fn() { let n = 1; if (true) { let n = 5; } n }()",
+            "fn() { if (true) { let n = 5; fn() { n } } }()()",
+            "let a = 1;",
+            "let fact = fn(n) { if (n < 2) { 1 } else { n * fact(n - 1) } }; fact(10)",
+            "\"mon\" + \"key\"",
+            "let s = \"a\"; s + s == \"aa\"",
+            "\"a\" != \"b\"",
+            "\"a\" * \"b\"",
+            "2.5 * 2 - 0.5",
+            "1 < 1.5 == (1.5 > 1)",
+            "-1.5 + 1",
+            "1.0 + true",
+            "5 / 0",
+            "let f = fn(n) { 10 / n }; f(2) + f(0)",
+            "let abs = fn(x) { x < 0 ? -x : x }; abs(-3) + abs(4)",
+            "9223372036854775807 + 1",
+            "let min = -9223372036854775807 - 1; [min / -1]",
+            "x",
+            "let h = {\"a\": 1, 2: [3], true: fn() { 4 }}; [h[\"a\"], h[2][0], h[true](), h[\"b\"]]",
+            "{[1]: 2}",
+            "{1: 2}[[1]]",
+            "let x = 1; x = x + 1; x",
+            "let f = fn() { let x = 1; x = x * 5; x }; f()",
+            "y = 1",
+            "len = 1",
+            "let n = 0; for (x in [1, 2, 3]) { n = n + x }; n",
+            "for (x in [1, 2, 3]) { x }",
+            "for (x in 5) { }",
+            "let x = 5; for (x in [1]) { } x",
+            "let f = fn(xs) { for (x in xs) { if (x > 2) { return x * 10; } } 0 }; f([1, 2, 3])",
+            "let n = 0; for (x in [1, 2, 3, 4]) { if (x == 3) { break; } n = n + x; } n",
+            "let n = 0; for (x in [1, 2, 3, 4]) { if (x == 2) { continue; } n = n + x; } n",
+            "let n = 0; for (x in [1, 2]) { for (y in [1, 2]) { break; } n = n + 1; } n",
+            "let fs = []; for (i in [1, 2, 3]) { fs = push(fs, fn() { i }); }; [fs[0](), fs[2]()]",
+            "let g = fn() { for (i in [7]) { return fn() { i }; } }; g()()",
+            "match (2) { 1 => \"one\", 2 => \"two\", _ => \"many\" }",
+            "match (5) { 1 => \"one\", _ => \"many\" }",
+            "match (5) { 1 => \"one\" }",
+            "match ([1]) { 1 => 1, \"a\" => 2, _ => 3 }",
+            "let f = fn(x) { match (x) { 0 => 0, _ => x + f(x - 1) } }; f(10)",
+            "try { 1 / 0 } catch (e) { e }",
+            "try { 1 + 1 } catch (e) { 0 }",
+            "let e = 1; try { 1 / 0 } catch (e) { e }; e",
+            "let f = fn() { try { return 1; } catch (e) { 2 }; 3 }; f()",
+            "let f = fn() { len(1) }; try { f() } catch (e) { 0 }",
+            "let r = 0; for (x in [1, 0, 2]) { r = r + try { 4 / x } catch (e) { 10 } }; r",
+            "let n = 0; for (x in [1, 2, 3]) { try { if (x == 2) { break; } n = n + x } catch (e) { 0 } }; try { 1 / 0 } catch (e) { n }",
+            "try { try { 1 / 0 } catch (e) { len(e) } } catch (e) { e }",
+            "let [a, b, c] = [1, 2]; [a, b, c]",
+            "let [a, a] = [1, 2]; a",
+            "let [a] = 1;",
+            "let f = fn() { let [a, b] = [3, 4]; a * b }; f()",
+            "fn(a, ...rest) { [a, rest] }(1, 2, 3)",
+            "fn(a, ...rest) { rest }(1)",
+            "fn(a, ...rest) { a }()",
+            "let f = fn() { let fact = fn(n) { if (n < 2) { 1 } else { n * fact(n - 1) } }; fact(5) }; f()",
+            "if (true) { let g = fn(n) { if (n == 0) { 0 } else { g(n - 1) } }; g(3) }",
+        ];
+        for input in inputs {
+            let prog = Parser::new(Lexer::new(input.as_bytes())).parse().unwrap();
+            let expected = eval_program(&prog, &mut Env::new())
+                .map(|obj| obj.to_string())
+                .map_err(|err| err.to_string());
+            let got = run(input).map(|obj| obj.to_string());
+            assert_eq!(got, expected, "{}", input);
+
+            let optimized = run_optimized(input).map(|obj| obj.to_string());
+            assert_eq!(optimized, expected, "optimized: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_unsupported() {
+        let inputs = [
+            (
+                "let counter = fn() { let n = 0; fn() { n = n + 1 } }; counter()()",
+                "compiling assignment to `n` captured from an enclosing function is not supported",
+            ),
+            ("break;", "break outside of a loop"),
+            (
+                "for (x in [1]) { fn() { continue; } }",
+                "continue outside of a loop",
+            ),
+            ("if (false) { x }", "variable not found: x"),
+        ];
+        for (input, expected) in inputs {
+            assert_eq!(run(input).unwrap_err(), expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_try_does_not_catch_limits() {
+        let config = VmConfig {
+            sandbox: SandboxConfig {
+                max_steps: Some(1000),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let input = "let f = fn(n) { f(n + 1) }; try { f(0) } catch (e) { e }";
+        assert_eq!(
+            run_with_config(input, config).unwrap_err(),
+            "step limit exceeded"
+        );
+        assert_eq!(
+            run("let f = fn(n) { f(n + 1) }; try { f(0) } catch (e) { e }").unwrap_err(),
+            "frame overflow"
+        );
+    }
+
+    // generated expressions refer to bindings by index, `render` maps them to
+    // a name in scope so every program compiles
+    #[derive(Debug, Clone)]
+    enum Gen {
+        Int(i64),
+        Float(f64),
+        Bool(bool),
+        Str(&'static str),
+        Var(usize),
+        Prefix(&'static str, Box<Gen>),
+        Infix(Box<Gen>, &'static str, Box<Gen>),
+        Array(Vec<Gen>),
+        Index(Box<Gen>, Box<Gen>),
+        If(Box<Gen>, Box<Gen>, Option<Box<Gen>>),
+        Fn(Box<Gen>),
+        Call(Box<Gen>, Vec<Gen>),
+        Builtin(&'static str, Vec<Gen>),
+        Hash(Vec<(Gen, Gen)>),
+        For(Box<Gen>, Box<Gen>),
+        Match(Box<Gen>, Vec<(Gen, Gen)>, Option<Box<Gen>>),
+        Try(Box<Gen>, Box<Gen>),
+    }
+
+    fn render(gen: &Gen, scope: &mut Vec<String>) -> String {
+        match gen {
+            Gen::Int(value) => value.to_string(),
+            Gen::Float(value) => format!("{:?}", value),
+            Gen::Bool(value) => value.to_string(),
+            Gen::Str(value) => format!("{:?}", value),
+            Gen::Var(_) if scope.is_empty() => "0".to_string(),
+            Gen::Var(i) => scope[i % scope.len()].clone(),
+            Gen::Prefix(op, e) => format!("({}{})", op, render(e, scope)),
+            Gen::Infix(l, op, r) => format!("({} {} {})", render(l, scope), op, render(r, scope)),
+            Gen::Array(elements) => format!("[{}]", render_list(elements, scope)),
+            Gen::Index(l, i) => format!("{}[{}]", render(l, scope), render(i, scope)),
+            Gen::If(c, t, e) => {
+                let head = format!("if ({}) {{ {} }}", render(c, scope), render(t, scope));
+                match e {
+                    Some(e) => format!("{} else {{ {} }}", head, render(e, scope)),
+                    None => head,
+                }
+            }
+            Gen::Fn(body) => {
+                let param = name("p", scope.len());
+                scope.push(param.clone());
+                let body = render(body, scope);
+                scope.pop();
+                format!("fn({}) {{ {} }}", param, body)
+            }
+            Gen::Call(f, args) => format!("({})({})", render(f, scope), render_list(args, scope)),
+            Gen::Builtin(name, args) => format!("{}({})", name, render_list(args, scope)),
+            Gen::Hash(pairs) => {
+                let pairs = pairs
+                    .iter()
+                    .map(|(k, v)| format!("{}: {}", render(k, scope), render(v, scope)))
+                    .collect::<Vec<_>>();
+                format!("{{{}}}", pairs.join(", "))
+            }
+            // the values of the body are collected, a loop itself is no value
+            Gen::For(iterable, body) => {
+                let iterable = render(iterable, scope);
+                let var = name("v", scope.len());
+                scope.push(var.clone());
+                let body = render(body, scope);
+                scope.pop();
+                format!(
+                    "fn() {{ let acc = []; for ({} in {}) {{ acc = push(acc, {}); }}; acc }}()",
+                    var, iterable, body
+                )
+            }
+            Gen::Match(subject, arms, default) => {
+                let mut arms = arms
+                    .iter()
+                    .map(|(p, v)| format!("{} => {}", render(p, scope), render(v, scope)))
+                    .collect::<Vec<_>>();
+                if let Some(default) = default {
+                    arms.push(format!("_ => {}", render(default, scope)));
+                }
+                format!(
+                    "match ({}) {{ {} }}",
+                    render(subject, scope),
+                    arms.join(", ")
+                )
+            }
+            Gen::Try(body, handler) => {
+                let body = render(body, scope);
+                let var = name("e", scope.len());
+                scope.push(var.clone());
+                let handler = render(handler, scope);
+                scope.pop();
+                format!("try {{ {} }} catch ({}) {{ {} }}", body, var, handler)
+            }
+        }
+    }
+
+    // identifiers can't contain digits
+    fn name(prefix: &str, i: usize) -> String {
+        format!("{}{}", prefix, (b'a' + i as u8) as char)
+    }
+
+    fn render_list(gens: &[Gen], scope: &mut Vec<String>) -> String {
+        let items = gens.iter().map(|g| render(g, scope)).collect::<Vec<_>>();
+        items.join(", ")
+    }
+
+    fn gen_expr() -> impl Strategy<Value = Gen> {
+        let leaf = prop_oneof![
+            (-5..20i64).prop_map(Gen::Int),
+            // overflows are errors on both engines
+            Just(Gen::Int(i64::MAX)),
+            (-4..8i32).prop_map(|v| Gen::Float(v as f64 / 2.0)),
+            any::<bool>().prop_map(Gen::Bool),
+            prop::sample::select(vec!["", "a", "bc"]).prop_map(Gen::Str),
+            any::<usize>().prop_map(Gen::Var),
+        ];
+        // match patterns are literals
+        let pattern = prop_oneof![
+            (-2..3i64).prop_map(Gen::Int),
+            any::<bool>().prop_map(Gen::Bool),
+            prop::sample::select(vec!["", "a"]).prop_map(Gen::Str),
+        ];
+        leaf.prop_recursive(4, 48, 3, move |inner| {
+            let list = prop::collection::vec(inner.clone(), 0..3);
+            let pairs = prop::collection::vec((inner.clone(), inner.clone()), 0..3);
+            let arms = prop::collection::vec((pattern.clone(), inner.clone()), 0..3);
+            prop_oneof![
+                (prop::sample::select(vec!["-", "!"]), inner.clone())
+                    .prop_map(|(op, e)| Gen::Prefix(op, Box::new(e))),
+                (
+                    inner.clone(),
+                    prop::sample::select(vec!["+", "-", "*", "<", ">", "==", "!="]),
+                    inner.clone()
+                )
+                    .prop_map(|(l, op, r)| Gen::Infix(
+                        Box::new(l),
+                        op,
+                        Box::new(r)
+                    )),
+                list.clone().prop_map(Gen::Array),
+                (inner.clone(), inner.clone())
+                    .prop_map(|(l, i)| Gen::Index(Box::new(l), Box::new(i))),
+                (
+                    inner.clone(),
+                    inner.clone(),
+                    prop::option::of(inner.clone())
+                )
+                    .prop_map(|(c, t, e)| Gen::If(
+                        Box::new(c),
+                        Box::new(t),
+                        e.map(Box::new)
+                    )),
+                inner.clone().prop_map(|body| Gen::Fn(Box::new(body))),
+                (inner.clone(), list.clone()).prop_map(|(f, args)| Gen::Call(Box::new(f), args)),
+                (
+                    prop::sample::select(vec!["len", "first", "last", "rest", "push"]),
+                    list
+                )
+                    .prop_map(|(name, args)| Gen::Builtin(name, args)),
+                pairs.prop_map(Gen::Hash),
+                (inner.clone(), inner.clone())
+                    .prop_map(|(i, body)| Gen::For(Box::new(i), Box::new(body))),
+                (inner.clone(), arms, prop::option::of(inner.clone())).prop_map(
+                    |(s, arms, default)| Gen::Match(Box::new(s), arms, default.map(Box::new))
+                ),
+                (inner.clone(), inner)
+                    .prop_map(|(body, handler)| Gen::Try(Box::new(body), Box::new(handler))),
+            ]
+        })
+    }
+
+    // a few global lets followed by an expression using them
+    fn gen_program() -> impl Strategy<Value = String> {
+        (prop::collection::vec(gen_expr(), 0..4), gen_expr()).prop_map(|(lets, last)| {
+            let mut scope = vec![];
+            let mut source = String::new();
+            for (i, value) in lets.iter().enumerate() {
+                source.push_str(&format!(
+                    "let {} = {}; ",
+                    name("g", i),
+                    render(value, &mut scope)
+                ));
+                scope.push(name("g", i));
+            }
+            source + &render(&last, &mut scope)
+        })
+    }
+
+    // functions are represented differently by the two engines
+    fn normalize(obj: &Object) -> String {
+        match obj {
+            Object::Function(_) | Object::CompiledFunction(_) | Object::Closure(_) => {
+                "<fn>".to_string()
+            }
+            Object::Array(elements) => {
+                let elements = elements.iter().map(normalize).collect::<Vec<_>>();
+                format!("[{}]", elements.join(", "))
+            }
+            Object::Hash(pairs) => {
+                let pairs = pairs
+                    .iter()
+                    .map(|(key, value)| format!("{}: {}", key, normalize(value)))
+                    .collect::<Vec<_>>();
+                format!("{{{}}}", pairs.join(", "))
+            }
+            obj => obj.to_string(),
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn test_matches_evaluator_on_generated_programs(input in gen_program()) {
+            let prog = Parser::new(Lexer::new(input.as_bytes())).parse().unwrap();
+            let expected = eval_program(&prog, &mut Env::new())
+                .map(|obj| normalize(&obj))
+                .map_err(|err| err.to_string());
+            let got = run(&input).map(|obj| normalize(&obj));
+            prop_assert_eq!(&got, &expected, "{}", input);
+
+            let mut compiler = Compiler::new();
+            compiler.compile(&prog).unwrap();
+            let optimized = Vm::new(optimizer::optimize(compiler.bytecode()))
+                .run()
+                .map(|obj| normalize(&obj));
+            prop_assert_eq!(optimized, expected, "optimized: {}", input);
+        }
+    }
+}
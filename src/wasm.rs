@@ -0,0 +1,246 @@
+// bindings for running the interpreter in a browser, build with
+// `cargo build --lib --target wasm32-unknown-unknown --features wasm`
+use std::{
+    io::{self, Write},
+    sync::Mutex,
+};
+
+use wasm_bindgen::prelude::*;
+
+use crate::{
+    diagnostic::json_diagnostic,
+    lexer::Lexer,
+    parser::{ParseError, Parser},
+    rc::Rc,
+    token::Span,
+    Error, Interpreter,
+};
+
+const FILE: &str = "<input>";
+
+// a problem with the source, as a plain object for javascript, the position
+// fields are undefined when it isn't about a place in the source
+#[wasm_bindgen(getter_with_clone, js_name = Diagnostic)]
+#[derive(Debug, Clone, PartialEq)]
+pub struct JsDiagnostic {
+    pub severity: String,
+    pub code: String,
+    pub message: String,
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+    pub start: Option<usize>,
+    pub end: Option<usize>,
+}
+
+impl JsDiagnostic {
+    fn error(code: &str, message: String, span: Option<Span>) -> Self {
+        JsDiagnostic {
+            severity: "error".to_string(),
+            code: code.to_string(),
+            message,
+            line: span.map(|span| span.line),
+            column: span.map(|span| span.column),
+            start: span.map(|span| span.start),
+            end: span.map(|span| span.end),
+        }
+    }
+
+    fn parse_errors(errors: &[ParseError]) -> Vec<Self> {
+        errors
+            .iter()
+            .map(|err| JsDiagnostic::error("parse-error", err.to_string(), Some(err.span)))
+            .collect()
+    }
+}
+
+// what `parseStr` returns, `program` is undefined when there are diagnostics
+#[wasm_bindgen(getter_with_clone)]
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseResult {
+    pub program: Option<String>,
+    pub diagnostics: Vec<JsDiagnostic>,
+}
+
+// what `evalStr` returns, `value` is undefined when there are diagnostics,
+// `output` has what `puts` printed either way
+#[wasm_bindgen(getter_with_clone)]
+#[derive(Debug, Clone, PartialEq)]
+pub struct EvalResult {
+    pub value: Option<String>,
+    pub output: String,
+    pub diagnostics: Vec<JsDiagnostic>,
+}
+
+// parses `source` and returns the program printed back, with the parse errors
+// as diagnostics instead of a thrown string
+#[wasm_bindgen(js_name = parseStr)]
+pub fn parse_str(source: &str) -> ParseResult {
+    match Parser::new(Lexer::new(source.as_bytes())).parse() {
+        Ok(prog) => ParseResult {
+            program: Some(prog.to_string()),
+            diagnostics: vec![],
+        },
+        Err(errors) => ParseResult {
+            program: None,
+            diagnostics: JsDiagnostic::parse_errors(&errors),
+        },
+    }
+}
+
+// evaluates `source` in a fresh interpreter, there's no stdout in a browser
+// so what `puts` prints is collected into the result
+#[wasm_bindgen(js_name = evalStr)]
+pub fn eval_str(source: &str) -> EvalResult {
+    let output = Output::default();
+    let result = Interpreter::new()
+        .with_output(output.clone())
+        .eval(source)
+        .map(|value| value.to_string());
+    let output = output.0.lock().map_or_else(
+        |_| String::new(),
+        |out| String::from_utf8_lossy(&out).into_owned(),
+    );
+    match result {
+        Ok(value) => EvalResult {
+            value: Some(value),
+            output,
+            diagnostics: vec![],
+        },
+        Err(Error::Parse(errors)) => EvalResult {
+            value: None,
+            output,
+            diagnostics: JsDiagnostic::parse_errors(&errors),
+        },
+        Err(Error::Runtime(diagnostic)) => EvalResult {
+            value: None,
+            output,
+            diagnostics: vec![JsDiagnostic::error(
+                "runtime-error",
+                diagnostic.message,
+                diagnostic.span,
+            )],
+        },
+    }
+}
+
+#[derive(Clone, Default)]
+struct Output(Rc<Mutex<Vec<u8>>>);
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut out = self
+            .0
+            .lock()
+            .map_err(|_| io::Error::other("output poisoned"))?;
+        out.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// returns the parsed program printed back, or the parse errors one per line
+#[wasm_bindgen]
+pub fn parse(source: &str) -> Result<String, String> {
+    Parser::new(Lexer::new(source.as_bytes()))
+        .parse()
+        .map(|prog| prog.to_string())
+        .map_err(|errors| {
+            let messages: Vec<_> = errors.iter().map(|err| err.to_string()).collect();
+            messages.join("\n")
+        })
+}
+
+// evaluates `source` in a fresh interpreter and returns the printed result
+#[wasm_bindgen]
+pub fn eval(source: &str) -> Result<String, String> {
+    Interpreter::new()
+        .eval(source)
+        .map(|value| value.to_string())
+        .map_err(|err| err.to_string())
+}
+
+// a json array with the same diagnostics as `--diagnostics json`
+#[wasm_bindgen]
+pub fn diagnostics(source: &str) -> String {
+    let diagnostics = match Parser::new(Lexer::new(source.as_bytes())).parse() {
+        Ok(_) => vec![],
+        Err(errors) => errors
+            .iter()
+            .map(|err| {
+                json_diagnostic(
+                    FILE,
+                    Some(err.span),
+                    "error",
+                    "parse-error",
+                    &err.to_string(),
+                )
+            })
+            .collect(),
+    };
+    format!("[{}]", diagnostics.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{diagnostics, eval, eval_str, parse, parse_str, JsDiagnostic};
+
+    #[test]
+    fn test_bindings() {
+        assert_eq!(
+            parse("let x = 1 + 2 * 3;").unwrap(),
+            "let x = (1 + (2 * 3));"
+        );
+        assert!(parse("let = 1;").is_err());
+        assert_eq!(eval("let f = fn(x) { x * 2 }; f(21)").unwrap(), "42");
+        assert_eq!(
+            eval("1 + true").unwrap_err(),
+            "runtime error: operand can only be applied to numbers"
+        );
+        assert_eq!(diagnostics("1 + 2"), "[]");
+        assert!(diagnostics("let = 1;")
+            .starts_with(r#"[{"file":"<input>","span":{"line":1,"column":5,"start":4,"end":5}"#));
+    }
+
+    #[test]
+    fn test_structured_results() {
+        let parsed = parse_str("let x = 1 + 2 * 3;");
+        assert_eq!(parsed.program.as_deref(), Some("let x = (1 + (2 * 3));"));
+        assert!(parsed.diagnostics.is_empty());
+
+        let parsed = parse_str("let = 1;");
+        assert_eq!(parsed.program, None);
+        let diagnostic = &parsed.diagnostics[0];
+        assert_eq!(
+            (&diagnostic.severity[..], &diagnostic.code[..]),
+            ("error", "parse-error")
+        );
+        assert_eq!((diagnostic.line, diagnostic.column), (Some(1), Some(5)));
+        assert_eq!((diagnostic.start, diagnostic.end), (Some(4), Some(5)));
+
+        let evaluated = eval_str("puts(\"hi\", 1); let f = fn(x) { x * 2 }; f(21)");
+        assert_eq!(evaluated.value.as_deref(), Some("42"));
+        assert_eq!(evaluated.output, "hi\n1\n");
+        assert!(evaluated.diagnostics.is_empty());
+
+        // what was printed before the error is kept
+        let evaluated = eval_str("puts(1); 1 + true");
+        assert_eq!(evaluated.value, None);
+        assert_eq!(evaluated.output, "1\n");
+        assert_eq!(
+            evaluated.diagnostics,
+            [JsDiagnostic {
+                severity: "error".to_string(),
+                code: "runtime-error".to_string(),
+                message: "operand can only be applied to numbers".to_string(),
+                line: Some(1),
+                column: Some(12),
+                start: Some(11),
+                end: Some(12),
+            }]
+        );
+        assert_eq!(eval_str("let = 1;").diagnostics[0].code, "parse-error");
+    }
+}
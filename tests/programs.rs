@@ -0,0 +1,100 @@
+// runs every `tests/programs/*.mk` script and compares what it printed with
+// the `.expected` file next to it, `UPDATE_EXPECT=1 cargo test` rewrites them
+use std::{
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+fn scripts() -> Vec<PathBuf> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/programs");
+    let mut scripts = fs::read_dir(dir)
+        .expect("failed to read tests/programs")
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "mk"))
+        .collect::<Vec<_>>();
+    scripts.sort();
+    scripts
+}
+
+// stdout, then stderr and the exit code if the script failed
+fn run(script: &Path) -> String {
+    let relative = script.strip_prefix(env!("CARGO_MANIFEST_DIR")).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_interpreter-book-in-rust"))
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .arg(relative)
+        .output()
+        .expect("failed to run interpreter");
+    let mut actual = String::from_utf8_lossy(&output.stdout).into_owned();
+    if !output.status.success() {
+        actual.push_str(&String::from_utf8_lossy(&output.stderr));
+        actual.push_str(&format!(
+            "exit code: {}\n",
+            output.status.code().unwrap_or(-1)
+        ));
+    }
+    actual
+}
+
+#[test]
+fn test_programs() {
+    let update = std::env::var_os("UPDATE_EXPECT").is_some();
+    let mut failures = vec![];
+    for script in scripts() {
+        let actual = run(&script);
+        let expected_path = script.with_extension("expected");
+        if update {
+            fs::write(&expected_path, &actual).unwrap();
+            continue;
+        }
+        let expected = fs::read_to_string(&expected_path).unwrap_or_default();
+        if actual != expected {
+            failures.push(format!(
+                "{}\n--- expected\n{}--- actual\n{}",
+                script.display(),
+                expected,
+                actual
+            ));
+        }
+    }
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
+
+// without a script, piped input is run as one instead of starting the repl
+#[test]
+fn test_stdin() {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_interpreter-book-in-rust"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("failed to run interpreter");
+    let mut stdin = child.stdin.take().unwrap();
+    stdin
+        .write_all(b"puts(1 + 1);\nlet x = 2;\nx * 21\n")
+        .unwrap();
+    drop(stdin);
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "2\n42\n");
+    assert!(output.stderr.is_empty());
+}
+
+// the vm reports runtime errors at the statement they happened in
+#[test]
+fn test_vm_error_position() {
+    let script = "let f = fn(n) {\n  n + true\n};\nf(1);\n";
+    for (format, location) in [
+        ("text", "<eval>:2:3: runtime-error"),
+        ("json", "\"span\":{\"line\":2,\"column\":3"),
+    ] {
+        let output = Command::new(env!("CARGO_BIN_EXE_interpreter-book-in-rust"))
+            .args(["--engine", "vm", "--diagnostics", format, "-e", script])
+            .output()
+            .expect("failed to run interpreter");
+        assert_eq!(output.status.code(), Some(1));
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains(location), "{}", stderr);
+    }
+}
@@ -0,0 +1,9 @@
+let map = fn(arr, f) {
+    let iter = fn(arr, acc) {
+        if (len(arr) == 0) { acc } else { iter(rest(arr), push(acc, f(first(arr)))) }
+    };
+    iter(arr, [])
+};
+let xs = map([1, 2, 3, 4], fn(x) { x * x });
+puts(xs, len(xs), first(xs), last(xs), xs[10]);
+rest(xs)
@@ -0,0 +1,6 @@
+let check = fn(n) {
+  if (n > 2) { n + true } else { check(n + 1) }
+};
+let run = fn() { check(0) };
+puts(1);
+run();
@@ -0,0 +1,23 @@
+let jobs = channel();
+let results = channel();
+
+let produce = fn(n) {
+    if (n > 0) {
+        send(jobs, n);
+        produce(n - 1)
+    }
+};
+let work = fn(n) {
+    if (n > 0) {
+        let job = recv(jobs);
+        send(results, job * job);
+        work(n - 1)
+    }
+};
+
+spawn(fn() { produce(4) });
+let worker = spawn(fn() { work(4); puts(100) });
+join(worker);
+puts([recv(results), recv(results), recv(results), recv(results)]);
+spawn(fn() { puts(300) });
+puts(200);
@@ -0,0 +1,5 @@
+let new_adder = fn(x) { fn(y) { x + y } };
+let add_two = new_adder(2);
+puts(add_two(3));
+let compose = fn(f, g) { fn(x) { g(f(x)) } };
+compose(add_two, fn(x) { x * 10 })(1)
@@ -0,0 +1,3 @@
+let max = fn(a, b) { if (a > b) { a } else { b } };
+puts(max(3, 7), max(9, -1));
+if (1 > 2) { 10 }
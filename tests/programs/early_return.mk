@@ -0,0 +1,6 @@
+let f = fn(x) {
+    if (x > 10) { return x; }
+    if (x > 5) { if (x > 7) { return x * 2; } return x * 3; }
+    0
+};
+[f(11), f(8), f(6), f(1)]
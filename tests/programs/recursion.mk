@@ -0,0 +1,4 @@
+let fib = fn(n) { if (n < 2) { n } else { fib(n - 1) + fib(n - 2) } };
+puts(fib(10));
+let fact = fn(n) { if (n < 2) { 1 } else { n * fact(n - 1) } };
+fact(10)
@@ -0,0 +1,5 @@
+let greet = fn(name) { "Hello, " + name + "!" };
+let names = ["monkey", "world"];
+puts(greet(first(names)), greet(last(names)));
+puts(len("monkey"), "" == "", "a" != "b");
+greet("") + " " + greet("you")
@@ -0,0 +1,3 @@
+puts(1);
+let x = 5 + true;
+puts(2);